@@ -1,5 +1,3 @@
-mod contraction;
-
 use abstutil::Timer;
 use geom::Distance;
 use map_model::{Map, PathRequest, Position};
@@ -22,13 +20,9 @@ struct Flags {
     #[structopt(long = "enable_profiler")]
     pub enable_profiler: bool,
 
-    /// Previously calculated CH to load
-    #[structopt(long = "load_ch")]
-    pub load_ch: Option<String>,
-
-    /// Calculate a CH and save here
+    /// Prepare contraction hierarchies if the map doesn't have them, and save the map
     #[structopt(long = "save_ch")]
-    pub save_ch: Option<String>,
+    pub save_ch: bool,
 }
 
 fn main() {
@@ -36,18 +30,14 @@ fn main() {
     let mut timer = Timer::new("benchmark pathfinding");
     let mut rng = XorShiftRng::from_seed([RNG_SEED; 16]);
 
-    let map: Map = abstutil::read_binary(&flags.map, &mut timer).unwrap();
+    let mut map: Map = abstutil::read_binary(&flags.map, &mut timer).unwrap();
     println!(); // TODO Because Timer manages newlines poorly
 
-    if let Some(path) = flags.save_ch {
-        contraction::build_ch(path, &map, &mut timer);
+    if flags.save_ch {
+        map.use_contraction_hierarchies(true, &mut timer);
+        map.save();
         return;
     }
-    let maybe_ch: Option<contraction::CHGraph> = if let Some(path) = flags.load_ch {
-        Some(abstutil::read_binary(&path, &mut timer).unwrap())
-    } else {
-        None
-    };
 
     let requests: Vec<PathRequest> = (0..NUM_PATHS)
         .map(|_| {
@@ -86,14 +76,14 @@ fn main() {
             .unwrap();
     }
 
-    if let Some(ref ch) = maybe_ch {
-        timer.start_iter("compute paths using CH", requests.len());
-        for req in &requests {
-            timer.next();
-            ch.pathfind(req);
-        }
+    // Whatever the map was saved with
+    timer.start_iter("compute paths using the map's pathfinder", requests.len());
+    for req in &requests {
+        timer.next();
+        map.pathfind(req.clone());
     }
 
+    map.use_contraction_hierarchies(false, &mut timer);
     timer.start_iter("compute paths using simplified approach", requests.len());
     for req in &requests {
         timer.next();
@@ -0,0 +1,87 @@
+use geom::{GPSBounds, LonLat};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+
+// Where to find the data for one city, and how to interpret each source. Everything besides the
+// bounds and elevation is optional. Paths are relative to the importing crate's directory.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CityConfig {
+    pub name: String,
+    // Data outside the box containing these points is skipped
+    pub bounds: Vec<LonLat>,
+
+    // SRTM .hgt files covering every map in the city
+    pub elevation: Vec<String>,
+    // JSON with map_model::SpeedLimitDefaults
+    pub speed_limit_defaults: Option<String>,
+    pub gtfs: Option<String>,
+    pub neighborhoods: Option<NeighborhoodSource>,
+    pub traffic_signals: Option<TrafficSignalSource>,
+    pub residential_buildings: Option<ResidentialSource>,
+    pub parking: Option<ParkingSource>,
+    pub census_tracts: Option<CensusSource>,
+}
+
+// GeoJSON with a polygon per neighborhood
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NeighborhoodSource {
+    pub path: String,
+    // The property with the neighborhood's name
+    pub name: String,
+}
+
+// KML with a point per traffic signal
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TrafficSignalSource {
+    pub path: String,
+    // Only shapes with all of these attribute values are signals. Empty means every shape.
+    pub only_if: BTreeMap<String, String>,
+}
+
+// KML with a point per residential building permit
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResidentialSource {
+    pub path: String,
+    // The attribute with the number of units in the building
+    pub units: String,
+}
+
+// ExtraShapes, converted from KML by this crate's binary. Each shape runs along one side of a
+// road.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ParkingSource {
+    pub path: String,
+    // The attribute describing on-street parking
+    pub category: String,
+    // Values of that attribute meaning there's no parking lane
+    pub no_parking: Vec<String>,
+}
+
+// KML with census tract polygons, each with estimates for that tract
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CensusSource {
+    pub household_vehicles: String,
+    pub commute_times: String,
+    pub commute_modes: String,
+    // The attribute with the tract's name
+    pub label: String,
+}
+
+impl CityConfig {
+    pub fn load(path: &str) -> Result<CityConfig, io::Error> {
+        abstutil::read_json(path)
+    }
+
+    pub fn gps_bounds(&self) -> GPSBounds {
+        GPSBounds::from(&self.bounds)
+    }
+}
+
+impl TrafficSignalSource {
+    pub fn matches(&self, attributes: &BTreeMap<String, String>) -> bool {
+        self.only_if
+            .iter()
+            .all(|(k, v)| attributes.get(k) == Some(v))
+    }
+}
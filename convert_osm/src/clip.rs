@@ -2,6 +2,7 @@ use abstutil::{retain_btreemap, Timer};
 use clipping::CPolygon;
 use geom::{PolyLine, Polygon, Pt2D};
 use map_model::{raw_data, IntersectionType};
+use std::collections::HashSet;
 
 pub fn clip_map(map: &mut raw_data::Map, timer: &mut Timer) {
     timer.start("clipping map to boundary");
@@ -90,12 +91,30 @@ pub fn clip_map(map: &mut raw_data::Map, timer: &mut Timer) {
         }
     }
 
+    // Restrictions involving something that got clipped away can't match anything later.
+    let ways: HashSet<i64> = map.roads.values().map(|r| r.osm_way_id).collect();
+    map.turn_restrictions.retain(|tr| {
+        let via_in = match tr.via {
+            raw_data::RestrictionVia::Node(pt) => Pt2D::from_gps(pt, &bounds)
+                .map(|pt| boundary_poly.contains_pt(pt))
+                .unwrap_or(false),
+            raw_data::RestrictionVia::Way(id) => ways.contains(&id),
+        };
+        via_in && ways.contains(&tr.from_way) && ways.contains(&tr.to_way)
+    });
+
     map.buildings.retain(|b| {
         bounds
             .must_convert(&b.points)
             .into_iter()
             .all(|pt| boundary_poly.contains_pt(pt))
     });
+    map.parking_lots.retain(|p| {
+        bounds
+            .must_convert(&p.points)
+            .into_iter()
+            .all(|pt| boundary_poly.contains_pt(pt))
+    });
 
     let mut result_areas = Vec::new();
     for orig_area in map.areas.drain(..) {
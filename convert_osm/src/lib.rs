@@ -1,12 +1,21 @@
 mod clip;
 mod neighborhoods;
 mod osm;
+mod pbf;
 mod remove_disconnected;
 mod split_ways;
 mod srtm;
+mod traffic_controls;
+
+pub use crate::osm::osm_to_raw_roads;
+pub use crate::srtm::Elevation;
+pub use crate::traffic_controls::{
+    Control, ControlNode, Matcher, MAX_DIST_BTWN_INTERSECTION_AND_CONTROL,
+    MAX_DIST_BTWN_INTERSECTION_AND_CROSSING,
+};
 
-use crate::srtm::Elevation;
 use abstutil::Timer;
+use city_config::{CityConfig, ParkingSource, ResidentialSource, TrafficSignalSource};
 use geom::{Distance, FindClosest, GPSBounds, LonLat, PolyLine, Pt2D};
 use kml::ExtraShapes;
 use map_model::{raw_data, IntersectionType, LANE_THICKNESS};
@@ -21,33 +30,13 @@ const MAX_DIST_BTWN_BLDG_PERMIT_AND_BLDG: Distance = Distance::const_meters(10.0
 #[derive(StructOpt, Debug)]
 #[structopt(name = "convert_osm")]
 pub struct Flags {
-    /// OSM XML file to read
+    /// OSM file to read, either XML or .osm.pbf
     #[structopt(long = "osm")]
     pub osm: String,
 
-    /// HGT with elevation data
-    #[structopt(long = "elevation")]
-    pub elevation: String,
-
-    /// KML with traffic signals. Optional.
-    #[structopt(long = "traffic_signals", default_value = "")]
-    pub traffic_signals: String,
-
-    /// KML with residential building permits. Optional.
-    #[structopt(long = "residential_buildings", default_value = "")]
-    pub residential_buildings: String,
-
-    /// ExtraShapes file with blockface, produced using the kml crate. Optional.
-    #[structopt(long = "parking_shapes", default_value = "")]
-    pub parking_shapes: String,
-
-    /// GTFS directory. Optional.
-    #[structopt(long = "gtfs", default_value = "")]
-    pub gtfs: String,
-
-    /// Neighborhood GeoJSON path. Optional.
-    #[structopt(long = "neighborhoods", default_value = "")]
-    pub neighborhoods: String,
+    /// City config JSON, listing the other data sources
+    #[structopt(long = "city")]
+    pub city: String,
 
     /// Osmosis clipping polgon
     #[structopt(long = "clip")]
@@ -63,13 +52,23 @@ pub struct Flags {
 }
 
 pub fn convert(flags: &Flags, timer: &mut abstutil::Timer) -> raw_data::Map {
-    let elevation = Elevation::new(&flags.elevation).expect("loading .hgt failed");
-
-    let mut map =
-        split_ways::split_up_roads(osm::osm_to_raw_roads(&flags.osm, timer), &elevation, timer);
+    let (roads, buildings, parking_lots, areas, turn_restrictions, control_nodes) =
+        osm::osm_to_raw_roads(&flags.osm, timer);
+    let mut map = split_ways::split_up_roads(
+        (roads, buildings, parking_lots, areas, turn_restrictions),
+        timer,
+    );
     map.boundary_polygon = read_osmosis_polygon(&flags.clip);
     clip::clip_map(&mut map, timer);
     remove_disconnected::remove_disconnected_roads(&mut map, timer);
+    let city = CityConfig::load(&flags.city).expect("loading city config failed");
+    // Clipping moves points around, so do these afterwards.
+    use_elevation(&mut map, &city.elevation, timer);
+    traffic_controls::apply(&mut map, control_nodes, timer);
+    if let Some(ref path) = city.speed_limit_defaults {
+        map.speed_limit_defaults =
+            abstutil::read_json(path).expect("loading speed limit defaults failed");
+    }
 
     if flags.fast_dev {
         return map;
@@ -77,22 +76,22 @@ pub fn convert(flags: &Flags, timer: &mut abstutil::Timer) -> raw_data::Map {
     // Do this after removing stuff.
     let gps_bounds = map.get_gps_bounds();
 
-    if !flags.residential_buildings.is_empty() {
-        handle_residences(&mut map, &gps_bounds, &flags.residential_buildings, timer);
+    if let Some(ref src) = city.residential_buildings {
+        handle_residences(&mut map, &gps_bounds, src, timer);
     }
-    if !flags.parking_shapes.is_empty() {
-        use_parking_hints(&mut map, &gps_bounds, &flags.parking_shapes, timer);
+    if let Some(ref src) = city.parking {
+        use_parking_hints(&mut map, &gps_bounds, src, timer);
     }
-    if !flags.traffic_signals.is_empty() {
-        handle_traffic_signals(&mut map, &gps_bounds, &flags.traffic_signals, timer);
+    if let Some(ref src) = city.traffic_signals {
+        handle_traffic_signals(&mut map, &gps_bounds, src, timer);
     }
-    if !flags.gtfs.is_empty() {
+    if let Some(ref path) = city.gtfs {
         timer.start("load GTFS");
-        map.bus_routes = gtfs::load(&flags.gtfs).unwrap();
+        map.bus_routes = gtfs::load(path).unwrap();
         timer.stop("load GTFS");
     }
 
-    if !flags.neighborhoods.is_empty() {
+    if let Some(ref src) = city.neighborhoods {
         timer.start("convert neighborhood polygons");
         let map_name = Path::new(&flags.output)
             .file_stem()
@@ -100,22 +99,50 @@ pub fn convert(flags: &Flags, timer: &mut abstutil::Timer) -> raw_data::Map {
             .to_os_string()
             .into_string()
             .unwrap();
-        neighborhoods::convert(&flags.neighborhoods, map_name, &gps_bounds);
+        neighborhoods::convert(src, map_name, &gps_bounds);
         timer.stop("convert neighborhood polygons");
     }
 
     map
 }
 
+fn use_elevation(map: &mut raw_data::Map, paths: &[String], timer: &mut Timer) {
+    timer.start("look up elevation");
+    let elevation = Elevation::new(paths).expect("loading .hgt failed");
+    // Partly flat elevation would make cliffs at the edge of the data, which slow down walking
+    // and biking a lot. The whole map being flat is less wrong.
+    if let Err(err) = elevation.check_coverage(&map.get_gps_bounds()) {
+        timer.warn(format!("{}. Ignoring elevation for the whole map.", err));
+        for i in map.intersections.values_mut() {
+            i.elevation = Distance::ZERO;
+        }
+        for r in map.roads.values_mut() {
+            r.elevations = vec![Distance::ZERO; r.points.len()];
+        }
+        timer.stop("look up elevation");
+        return;
+    }
+    // Everything's covered now.
+    let lookup = |pt: LonLat| elevation.get(pt).unwrap();
+    for i in map.intersections.values_mut() {
+        i.elevation = lookup(i.point);
+    }
+    for r in map.roads.values_mut() {
+        r.elevations = r.points.iter().map(|pt| lookup(*pt)).collect();
+    }
+    timer.stop("look up elevation");
+}
+
 fn use_parking_hints(
     map: &mut raw_data::Map,
     gps_bounds: &GPSBounds,
-    path: &str,
+    src: &ParkingSource,
     timer: &mut Timer,
 ) {
     timer.start("apply parking hints");
-    println!("Loading blockface shapes from {}", path);
-    let shapes: ExtraShapes = abstutil::read_binary(path, timer).expect("loading blockface failed");
+    println!("Loading parking shapes from {}", src.path);
+    let shapes: ExtraShapes =
+        abstutil::read_binary(&src.path, timer).expect("loading parking shapes failed");
 
     // Match shapes with the nearest road + direction (true for forwards)
     let mut closest: FindClosest<(raw_data::StableRoadID, bool)> =
@@ -136,15 +163,16 @@ fn use_parking_hints(
             }
         }
         if pts.len() > 1 {
-            // The blockface line endpoints will be close to other roads, so match based on the
+            // The line endpoints will be close to other roads, so match based on the
             // middle of the blockface.
-            // TODO Long blockfaces sometimes cover two roads. Should maybe find ALL matches within
+            // TODO Long shapes sometimes cover two roads. Should maybe find ALL matches within
             // the threshold distance?
             let middle = PolyLine::new(pts).middle();
             if let Some(((r, fwds), _)) = closest.closest_pt(middle, LANE_THICKNESS * 5.0) {
-                let category = s.attributes.get("PARKING_CATEGORY");
-                let has_parking = category != Some(&"None".to_string())
-                    && category != Some(&"No Parking Allowed".to_string());
+                let has_parking = match s.attributes.get(&src.category) {
+                    Some(category) => !src.no_parking.contains(category),
+                    None => true,
+                };
                 // Blindly override prior values.
                 if fwds {
                     map.roads.get_mut(&r).unwrap().parking_lane_fwd = has_parking;
@@ -160,34 +188,34 @@ fn use_parking_hints(
 fn handle_traffic_signals(
     map: &mut raw_data::Map,
     gps_bounds: &GPSBounds,
-    path: &str,
+    src: &TrafficSignalSource,
     timer: &mut Timer,
 ) {
     timer.start("handle traffic signals");
-    for shape in kml::load(path, gps_bounds, timer)
+    let matcher = traffic_controls::Matcher::new(map, gps_bounds);
+    for shape in kml::load(&src.path, gps_bounds, timer)
         .expect("loading traffic signals failed")
         .shapes
         .into_iter()
     {
-        // See https://www.seattle.gov/Documents/Departments/SDOT/GIS/Traffic_Signals_OD.pdf
+        if !src.matches(&shape.attributes) {
+            continue;
+        }
         if shape.points.len() > 1 {
             panic!("Traffic signal has multiple points: {:?}", shape);
         }
-        let pt = shape.points[0];
-        if gps_bounds.contains(pt) {
-            // TODO use a quadtree or some better way to match signals to the closest
-            // intersection
-            let closest_intersection = map
-                .intersections
-                .values_mut()
-                .min_by_key(|i| pt.gps_dist_meters(i.point))
-                .unwrap();
-            let dist = pt.gps_dist_meters(closest_intersection.point);
-            if dist <= MAX_DIST_BTWN_INTERSECTION_AND_SIGNAL {
-                if closest_intersection.intersection_type == IntersectionType::TrafficSignal {
-                    println!("WARNING: {:?} already has a traffic signal, but there's another one that's {} from it", closest_intersection, dist);
-                }
-                closest_intersection.intersection_type = IntersectionType::TrafficSignal;
+        // These points aren't necessarily on a road, so look further away. OSM may have already
+        // found the same signal.
+        if let Some((i, _)) = matcher.find(
+            map,
+            shape.points[0],
+            None,
+            MAX_DIST_BTWN_INTERSECTION_AND_SIGNAL,
+            MAX_DIST_BTWN_INTERSECTION_AND_SIGNAL,
+        ) {
+            let intersection = map.intersections.get_mut(&i).unwrap();
+            if intersection.intersection_type != IntersectionType::Border {
+                intersection.intersection_type = IntersectionType::TrafficSignal;
             }
         }
     }
@@ -197,7 +225,7 @@ fn handle_traffic_signals(
 fn handle_residences(
     map: &mut raw_data::Map,
     gps_bounds: &GPSBounds,
-    path: &str,
+    src: &ResidentialSource,
     timer: &mut Timer,
 ) {
     timer.start("match residential permits with buildings");
@@ -208,7 +236,7 @@ fn handle_residences(
         closest.add_gps(idx, &b.points, gps_bounds);
     }
 
-    let shapes = kml::load(path, gps_bounds, timer)
+    let shapes = kml::load(&src.path, gps_bounds, timer)
         .expect("loading residential buildings failed")
         .shapes;
     timer.start_iter("handle residential permits", shapes.len());
@@ -226,7 +254,7 @@ fn handle_residences(
         }
         if let Some(num) = shape
             .attributes
-            .get("net_units")
+            .get(&src.units)
             .and_then(|n| usize::from_str_radix(n, 10).ok())
         {
             if let Some((idx, _)) = closest.closest_pt(
@@ -1,15 +1,19 @@
 use abstutil;
+use city_config::NeighborhoodSource;
 use geojson::{GeoJson, PolygonType, Value};
 use geom::{GPSBounds, LonLat};
 use map_model::NeighborhoodBuilder;
 
-pub fn convert(geojson_path: &str, map_name: String, gps_bounds: &GPSBounds) {
-    println!("Extracting neighborhoods from {}...", geojson_path);
-    let document: GeoJson = abstutil::read_json(geojson_path).unwrap();
+pub fn convert(src: &NeighborhoodSource, map_name: String, gps_bounds: &GPSBounds) {
+    println!("Extracting neighborhoods from {}...", src.path);
+    let document: GeoJson = abstutil::read_json(&src.path).unwrap();
     match document {
         GeoJson::FeatureCollection(c) => {
             for f in c.features.into_iter() {
-                let name = f.properties.unwrap()["name"].as_str().unwrap().to_string();
+                let name = f.properties.unwrap()[&src.name]
+                    .as_str()
+                    .unwrap()
+                    .to_string();
                 match f.geometry.unwrap().value {
                     Value::Polygon(p) => {
                         convert_polygon(p, name, map_name.clone(), gps_bounds);
@@ -1,58 +1,85 @@
+use crate::pbf;
+use crate::traffic_controls::ControlNode;
 use abstutil::{FileWithProgress, Timer};
 use geom::LonLat;
 use map_model::{raw_data, AreaType};
 use osm_xml;
 use std::collections::{BTreeMap, HashMap};
 
+// What's needed from an OSM file, whatever format it's in
+pub struct Document {
+    // Only nodes that something else refers to are needed.
+    pub nodes: HashMap<i64, LonLat>,
+    // Just for the nodes above that have any tags
+    pub node_tags: HashMap<i64, BTreeMap<String, String>>,
+    // Ways with nodes that couldn't be found are left out.
+    pub ways: Vec<Way>,
+    pub relations: Vec<Relation>,
+}
+
+pub struct Way {
+    pub id: i64,
+    pub pts: Vec<LonLat>,
+    pub tags: BTreeMap<String, String>,
+}
+
+pub struct Relation {
+    pub id: i64,
+    pub tags: BTreeMap<String, String>,
+    // With the role of each member
+    pub members: Vec<(Member, String)>,
+}
+
+#[derive(Debug)]
+pub enum Member {
+    Node(i64),
+    Way(i64),
+    Relation(i64),
+}
+
+// .osm.pbf files are streamed; anything else is parsed as XML.
 pub fn osm_to_raw_roads(
     osm_path: &str,
     timer: &mut Timer,
 ) -> (
     Vec<raw_data::Road>,
     Vec<raw_data::Building>,
+    Vec<raw_data::ParkingLot>,
     Vec<raw_data::Area>,
+    Vec<raw_data::TurnRestriction>,
+    Vec<ControlNode>,
 ) {
-    let (reader, done) = FileWithProgress::new(osm_path).unwrap();
-    let doc = osm_xml::OSM::parse(reader).expect("OSM parsing failed");
+    let doc = if osm_path.ends_with(".pbf") {
+        pbf::read(osm_path, timer).expect("OSM PBF parsing failed")
+    } else {
+        read_xml(osm_path, timer)
+    };
     println!(
         "OSM doc has {} nodes, {} ways, {} relations",
         doc.nodes.len(),
         doc.ways.len(),
         doc.relations.len()
     );
-    done(timer);
 
     let mut id_to_way: HashMap<i64, Vec<LonLat>> = HashMap::new();
     let mut roads: Vec<raw_data::Road> = Vec::new();
     let mut buildings: Vec<raw_data::Building> = Vec::new();
+    let mut parking_lots: Vec<raw_data::ParkingLot> = Vec::new();
     let mut areas: Vec<raw_data::Area> = Vec::new();
+    let mut turn_restrictions: Vec<raw_data::TurnRestriction> = Vec::new();
     timer.start_iter("processing OSM ways", doc.ways.len());
-    for way in doc.ways.values() {
+    for way in doc.ways {
         timer.next();
 
-        let mut valid = true;
-        let mut pts = Vec::new();
-        for node_ref in &way.nodes {
-            match doc.resolve_reference(node_ref) {
-                osm_xml::Reference::Node(node) => {
-                    pts.push(LonLat::new(node.lon, node.lat));
-                }
-                // Don't handle nested ways/relations yet
-                _ => {
-                    valid = false;
-                }
-            }
-        }
-        if !valid {
-            continue;
-        }
-        let tags = tags_to_map(&way.tags);
+        let tags = way.tags;
+        let pts = way.pts;
         if is_road(&tags) {
             roads.push(raw_data::Road {
                 osm_way_id: way.id,
                 points: pts,
-                osm_tags: tags,
                 // We'll fill this out later
+                elevations: Vec::new(),
+                osm_tags: tags,
                 i1: raw_data::StableIntersectionID(0),
                 i2: raw_data::StableIntersectionID(0),
                 parking_lane_fwd: false,
@@ -65,6 +92,12 @@ pub fn osm_to_raw_roads(
                 osm_tags: tags,
                 num_residential_units: None,
             });
+        } else if is_parking_lot(&tags) {
+            parking_lots.push(raw_data::ParkingLot {
+                osm_way_id: way.id,
+                points: pts,
+                osm_tags: tags,
+            });
         } else if let Some(at) = get_area_type(&tags) {
             areas.push(raw_data::Area {
                 area_type: at,
@@ -79,18 +112,18 @@ pub fn osm_to_raw_roads(
     }
 
     timer.start_iter("processing OSM relations", doc.relations.len());
-    for rel in doc.relations.values() {
+    for rel in &doc.relations {
         timer.next();
-        let tags = tags_to_map(&rel.tags);
-        if let Some(at) = get_area_type(&tags) {
+        let tags = &rel.tags;
+        if let Some(at) = get_area_type(tags) {
             if tags.get("type") == Some(&"multipolygon".to_string()) {
                 let mut ok = true;
                 let mut pts_per_way: Vec<Vec<LonLat>> = Vec::new();
-                for member in &rel.members {
-                    match *member {
-                        osm_xml::Member::Way(osm_xml::UnresolvedReference::Way(id), ref role) => {
+                for (member, role) in &rel.members {
+                    match member {
+                        Member::Way(id) => {
                             // If the way is clipped out, that's fine
-                            if let Some(pts) = id_to_way.get(&id) {
+                            if let Some(pts) = id_to_way.get(id) {
                                 if role == "outer" {
                                     pts_per_way.push(pts.to_vec());
                                 } else {
@@ -123,10 +156,151 @@ pub fn osm_to_raw_roads(
                     }
                 }
             }
+        } else if tags.get("type") == Some(&"restriction".to_string()) {
+            if let Some(tr) = get_turn_restriction(rel, &doc.nodes) {
+                turn_restrictions.push(tr);
+            }
+        }
+    }
+
+    let control_nodes = doc
+        .node_tags
+        .iter()
+        .filter_map(|(id, tags)| ControlNode::parse(doc.nodes[id], tags))
+        .collect();
+
+    (
+        roads,
+        buildings,
+        parking_lots,
+        areas,
+        turn_restrictions,
+        control_nodes,
+    )
+}
+
+fn read_xml(path: &str, timer: &mut Timer) -> Document {
+    let (reader, done) = FileWithProgress::new(path).unwrap();
+    let doc = osm_xml::OSM::parse(reader).expect("OSM parsing failed");
+    done(timer);
+
+    let mut ways = Vec::new();
+    for way in doc.ways.values() {
+        let mut valid = true;
+        let mut pts = Vec::new();
+        for node_ref in &way.nodes {
+            match doc.resolve_reference(node_ref) {
+                osm_xml::Reference::Node(node) => {
+                    pts.push(LonLat::new(node.lon, node.lat));
+                }
+                // Don't handle nested ways/relations yet
+                _ => {
+                    valid = false;
+                }
+            }
+        }
+        if valid {
+            ways.push(Way {
+                id: way.id,
+                pts,
+                tags: tags_to_map(&way.tags),
+            });
         }
     }
 
-    (roads, buildings, areas)
+    let relations = doc
+        .relations
+        .values()
+        .map(|rel| Relation {
+            id: rel.id,
+            tags: tags_to_map(&rel.tags),
+            members: rel
+                .members
+                .iter()
+                .map(|member| {
+                    let (r, role) = match member {
+                        osm_xml::Member::Node(r, role) => (r, role),
+                        osm_xml::Member::Way(r, role) => (r, role),
+                        osm_xml::Member::Relation(r, role) => (r, role),
+                    };
+                    let member = match *r {
+                        osm_xml::UnresolvedReference::Node(id) => Member::Node(id),
+                        osm_xml::UnresolvedReference::Way(id) => Member::Way(id),
+                        osm_xml::UnresolvedReference::Relation(id) => Member::Relation(id),
+                    };
+                    (member, role.clone())
+                })
+                .collect(),
+        })
+        .collect();
+
+    Document {
+        nodes: doc
+            .nodes
+            .values()
+            .map(|node| (node.id, LonLat::new(node.lon, node.lat)))
+            .collect(),
+        node_tags: doc
+            .nodes
+            .values()
+            .filter(|node| !node.tags.is_empty())
+            .map(|node| (node.id, tags_to_map(&node.tags)))
+            .collect(),
+        ways,
+        relations,
+    }
+}
+
+fn get_turn_restriction(
+    rel: &Relation,
+    nodes: &HashMap<i64, LonLat>,
+) -> Option<raw_data::TurnRestriction> {
+    let restriction = rel
+        .tags
+        .get("restriction")
+        .and_then(|r| raw_data::RestrictionType::new(r))?;
+    let mut from_way = None;
+    let mut to_way = None;
+    let mut via = None;
+    for (member, role) in &rel.members {
+        match member {
+            Member::Way(id) => match role.as_ref() {
+                "from" => {
+                    from_way = Some(*id);
+                }
+                "to" => {
+                    to_way = Some(*id);
+                }
+                "via" => {
+                    via = Some(raw_data::RestrictionVia::Way(*id));
+                }
+                _ => {}
+            },
+            Member::Node(id) => {
+                if role == "via" {
+                    // If the node is clipped out, so is the restriction.
+                    via = Some(raw_data::RestrictionVia::Node(*nodes.get(id)?));
+                }
+            }
+            Member::Relation(_) => {}
+        }
+    }
+    match (from_way, via, to_way) {
+        (Some(from_way), Some(via), Some(to_way)) => Some(raw_data::TurnRestriction {
+            osm_relation_id: rel.id,
+            restriction,
+            from_way,
+            via,
+            to_way,
+        }),
+        _ => {
+            println!(
+                "Turn restriction {} is missing members, ignoring it",
+                rel.id
+            );
+            None
+        }
+    }
 }
 
 fn tags_to_map(raw_tags: &[osm_xml::Tag]) -> BTreeMap<String, String> {
@@ -179,6 +353,11 @@ fn is_bldg(tags: &BTreeMap<String, String>) -> bool {
     tags.contains_key("building")
 }
 
+// Garages are tagged as buildings too; those are handled as buildings.
+fn is_parking_lot(tags: &BTreeMap<String, String>) -> bool {
+    tags.get("amenity") == Some(&"parking".to_string())
+}
+
 fn get_area_type(tags: &BTreeMap<String, String>) -> Option<AreaType> {
     if tags.get("leisure") == Some(&"park".to_string()) {
         return Some(AreaType::Park);
@@ -0,0 +1,89 @@
+use crate::osm::{Document, Member, Relation, Way};
+use abstutil::Timer;
+use geom::LonLat;
+use osmpbfreader::{Error, OsmId, OsmObj, OsmPbfReader, Tags};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+
+// Streams through the file twice, first for ways and relations, then for just the nodes they
+// refer to. Regional extracts have far too many nodes to keep all of them.
+pub fn read(path: &str, timer: &mut Timer) -> Result<Document, Error> {
+    println!("Reading {}", path);
+    let mut pbf = OsmPbfReader::new(File::open(path)?);
+
+    timer.start("read ways and relations from PBF");
+    let mut raw_ways: Vec<(i64, Vec<i64>, BTreeMap<String, String>)> = Vec::new();
+    let mut relations: Vec<Relation> = Vec::new();
+    let mut needed_nodes: HashSet<i64> = HashSet::new();
+    for obj in pbf.iter() {
+        match obj? {
+            OsmObj::Node(_) => {}
+            OsmObj::Way(way) => {
+                let node_ids: Vec<i64> = way.nodes.iter().map(|id| id.0).collect();
+                needed_nodes.extend(node_ids.iter().cloned());
+                raw_ways.push((way.id.0, node_ids, tags_to_map(&way.tags)));
+            }
+            OsmObj::Relation(rel) => {
+                let mut members = Vec::new();
+                for r in &rel.refs {
+                    let member = match r.member {
+                        OsmId::Node(id) => {
+                            needed_nodes.insert(id.0);
+                            Member::Node(id.0)
+                        }
+                        OsmId::Way(id) => Member::Way(id.0),
+                        OsmId::Relation(id) => Member::Relation(id.0),
+                    };
+                    members.push((member, r.role.to_string()));
+                }
+                relations.push(Relation {
+                    id: rel.id.0,
+                    tags: tags_to_map(&rel.tags),
+                    members,
+                });
+            }
+        }
+    }
+    timer.stop("read ways and relations from PBF");
+
+    timer.start("read nodes from PBF");
+    pbf.rewind()?;
+    let mut nodes: HashMap<i64, LonLat> = HashMap::new();
+    let mut node_tags: HashMap<i64, BTreeMap<String, String>> = HashMap::new();
+    for obj in pbf.iter() {
+        if let OsmObj::Node(node) = obj? {
+            if needed_nodes.contains(&node.id.0) {
+                nodes.insert(node.id.0, LonLat::new(node.lon(), node.lat()));
+                if !node.tags.is_empty() {
+                    node_tags.insert(node.id.0, tags_to_map(&node.tags));
+                }
+            }
+        }
+    }
+    timer.stop("read nodes from PBF");
+
+    // Extracts can cut ways off partway. Like the XML reader, skip those.
+    let ways = raw_ways
+        .into_iter()
+        .filter_map(|(id, node_ids, tags)| {
+            let pts = node_ids
+                .iter()
+                .map(|n| nodes.get(n).cloned())
+                .collect::<Option<Vec<LonLat>>>()?;
+            Some(Way { id, pts, tags })
+        })
+        .collect();
+
+    Ok(Document {
+        nodes,
+        node_tags,
+        ways,
+        relations,
+    })
+}
+
+fn tags_to_map(tags: &Tags) -> BTreeMap<String, String> {
+    tags.iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
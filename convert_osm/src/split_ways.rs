@@ -1,16 +1,16 @@
-use crate::srtm;
 use abstutil::Timer;
 use geom::{Distance, HashablePt2D, LonLat};
 use map_model::{raw_data, IntersectionType};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 pub fn split_up_roads(
-    (mut roads, buildings, areas): (
+    (mut roads, buildings, parking_lots, areas, turn_restrictions): (
         Vec<raw_data::Road>,
         Vec<raw_data::Building>,
+        Vec<raw_data::ParkingLot>,
         Vec<raw_data::Area>,
+        Vec<raw_data::TurnRestriction>,
     ),
-    elevation: &srtm::Elevation,
     timer: &mut Timer,
 ) -> raw_data::Map {
     timer.start("splitting up roads");
@@ -61,16 +61,20 @@ pub fn split_up_roads(
 
     let mut map = raw_data::Map::blank();
     map.buildings = buildings;
+    map.parking_lots = parking_lots;
     map.areas = areas;
+    map.turn_restrictions = turn_restrictions;
     // All of the roundabout points will just keep moving the intersection
     for (pt, id) in &pt_to_intersection {
         map.intersections.insert(
             *id,
             raw_data::Intersection {
                 point: LonLat::new(pt.x(), pt.y()),
-                elevation: Distance::meters(elevation.get(pt.x(), pt.y())),
+                // Filled out later
+                elevation: Distance::ZERO,
                 intersection_type: IntersectionType::StopSign,
                 label: None,
+                approach_signs: BTreeMap::new(),
             },
         );
     }
@@ -80,9 +84,10 @@ pub fn split_up_roads(
             *id,
             raw_data::Intersection {
                 point: *pt,
-                elevation: Distance::meters(elevation.get(pt.longitude, pt.latitude)),
+                elevation: Distance::ZERO,
                 intersection_type: IntersectionType::StopSign,
                 label: None,
+                approach_signs: BTreeMap::new(),
             },
         );
     }
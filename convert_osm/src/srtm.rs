@@ -1,42 +1,172 @@
 use byteorder::{BigEndian, ReadBytesExt};
+use geom::{Distance, GPSBounds, LonLat};
 use std::fs::File;
-use std::io;
+use std::io::{BufReader, Error, ErrorKind};
+use std::path::Path;
 
-// Assuming the 3-second arcs
-const GRID_DIM: usize = 1201;
+// SRTM marks samples it couldn't measure with this
+const VOID: i16 = -32768;
 
+// Each .hgt file covers one degree of longitude and latitude.
 pub struct Elevation {
-    lon_offset: f64,
-    lat_offset: f64,
+    tiles: Vec<Tile>,
+}
+
+struct Tile {
+    // The southwest corner, from the file name
+    lon: f64,
+    lat: f64,
+    // Samples per row and column. 1201 for 3-arcsecond data, 3601 for 1-arcsecond.
+    dim: usize,
+    // Rows go from north to south, and each row from west to east.
     data: Vec<i16>,
 }
 
 impl Elevation {
-    pub fn new(path: &str) -> Result<Elevation, io::Error> {
-        println!("Opening {}", path);
-        let mut f = File::open(path).unwrap();
+    pub fn new(paths: &[String]) -> Result<Elevation, Error> {
+        let mut tiles = Vec::new();
+        for path in paths {
+            tiles.push(Tile::load(path)?);
+        }
+        Ok(Elevation { tiles })
+    }
+
+    // Every part of the map must be covered by some tile.
+    pub fn check_coverage(&self, bounds: &GPSBounds) -> Result<(), String> {
+        let corners = bounds.get_corners();
+        let (min, max) = (corners[0], corners[2]);
+        let mut missing = Vec::new();
+        for lon in (min.longitude.floor() as i64)..=(max.longitude.floor() as i64) {
+            for lat in (min.latitude.floor() as i64)..=(max.latitude.floor() as i64) {
+                let middle = LonLat::new(lon as f64 + 0.5, lat as f64 + 0.5);
+                if self.find_tile(middle).is_none() {
+                    missing.push(tile_name(lon, lat));
+                }
+            }
+        }
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(format!(
+                "The elevation data doesn't cover the whole map. Missing {}",
+                missing.join(", ")
+            ))
+        }
+    }
+
+    // None if no tile covers the point
+    pub fn get(&self, pt: LonLat) -> Option<Distance> {
+        self.find_tile(pt)
+            .map(|tile| Distance::meters(tile.get(pt)))
+    }
+
+    fn find_tile(&self, pt: LonLat) -> Option<&Tile> {
+        self.tiles.iter().find(|t| {
+            pt.longitude >= t.lon
+                && pt.longitude <= t.lon + 1.0
+                && pt.latitude >= t.lat
+                && pt.latitude <= t.lat + 1.0
+        })
+    }
+}
 
-        let mut e = Elevation {
-            // TODO dont hardcode
-            lon_offset: -122.0,
-            lat_offset: 47.0,
-            data: Vec::with_capacity(GRID_DIM.pow(2)),
+impl Tile {
+    fn load(path: &str) -> Result<Tile, Error> {
+        println!("Opening {}", path);
+        let (lon, lat) = parse_name(path).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} isn't named like N47W122.hgt", path),
+            )
+        })?;
+        let f = File::open(path)?;
+        let len = f.metadata()?.len() as usize;
+        let dim = if len == 1201 * 1201 * 2 {
+            1201
+        } else if len == 3601 * 3601 * 2 {
+            3601
+        } else {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} has {} bytes, which isn't a 1 or 3-arcsecond grid",
+                    path, len
+                ),
+            ));
         };
-        // TODO off by one?
-        for _ in 0..GRID_DIM.pow(2) {
-            e.data.push(f.read_i16::<BigEndian>().unwrap());
+        let mut data = vec![0; dim * dim];
+        BufReader::new(f).read_i16_into::<BigEndian>(&mut data)?;
+        Ok(Tile {
+            lon,
+            lat,
+            dim,
+            data,
+        })
+    }
+
+    // Bilinear interpolation between the four surrounding samples, skipping voids
+    fn get(&self, pt: LonLat) -> f64 {
+        let cells = (self.dim - 1) as f64;
+        let x = (pt.longitude - self.lon) * cells;
+        let y = (self.lat + 1.0 - pt.latitude) * cells;
+        let col = (x.floor() as usize).min(self.dim - 2);
+        let row = (y.floor() as usize).min(self.dim - 2);
+        let dx = x - (col as f64);
+        let dy = y - (row as f64);
+
+        let mut total = 0.0;
+        let mut total_weight = 0.0;
+        for (r, c, weight) in vec![
+            (row, col, (1.0 - dx) * (1.0 - dy)),
+            (row, col + 1, dx * (1.0 - dy)),
+            (row + 1, col, (1.0 - dx) * dy),
+            (row + 1, col + 1, dx * dy),
+        ] {
+            let value = self.data[r * self.dim + c];
+            if value != VOID {
+                total += f64::from(value) * weight;
+                total_weight += weight;
+            }
+        }
+        if total_weight == 0.0 {
+            0.0
+        } else {
+            total / total_weight
         }
-        Ok(e)
     }
+}
 
-    // TODO plumb through u16 everyhere
-    pub fn get(&self, lon: f64, lat: f64) -> f64 {
-        // TODO assert the (lon, lat) match the offsets
-        // TODO not tons of confidence in any of this.
-        // TODO interpolate from the 4 matching tiles?
-        let x = ((lon - self.lon_offset).abs() * (GRID_DIM as f64)) as usize;
-        let y = ((lat - self.lat_offset).abs() * (GRID_DIM as f64)) as usize;
-        let i = x + (y * GRID_DIM);
-        f64::from(self.data[i])
+// Files are named after their southwest corner, like N47W122.hgt.
+fn parse_name(path: &str) -> Option<(f64, f64)> {
+    let name = Path::new(path).file_stem()?.to_str()?.to_uppercase();
+    if name.len() != 7 || !name.is_ascii() {
+        return None;
     }
+    let lat = name[1..3].parse::<f64>().ok()?;
+    let lon = name[4..7].parse::<f64>().ok()?;
+    let lat = match &name[0..1] {
+        "N" => lat,
+        "S" => -lat,
+        _ => {
+            return None;
+        }
+    };
+    let lon = match &name[3..4] {
+        "E" => lon,
+        "W" => -lon,
+        _ => {
+            return None;
+        }
+    };
+    Some((lon, lat))
+}
+
+fn tile_name(lon: i64, lat: i64) -> String {
+    format!(
+        "{}{:02}{}{:03}.hgt",
+        if lat >= 0 { "N" } else { "S" },
+        lat.abs(),
+        if lon >= 0 { "E" } else { "W" },
+        lon.abs()
+    )
 }
@@ -0,0 +1,195 @@
+use abstutil::{MultiMap, Timer};
+use geom::{Distance, FindClosest, GPSBounds, LonLat, Pt2D};
+use map_model::{raw_data, IntersectionType};
+use std::collections::BTreeMap;
+
+// Signs and signals are placed on the road a little before the intersection they control.
+pub const MAX_DIST_BTWN_INTERSECTION_AND_CONTROL: Distance = Distance::const_meters(30.0);
+// Crossings at an intersection are just across the road from it. Anything further out is a
+// mid-block crossing, which says nothing about the intersection.
+pub const MAX_DIST_BTWN_INTERSECTION_AND_CROSSING: Distance = Distance::const_meters(15.0);
+// OSM nodes lie on their ways, so this just absorbs rounding.
+const MAX_DIST_BTWN_ROAD_AND_NODE: Distance = Distance::const_meters(1.0);
+
+// An OSM node that says how some nearby intersection is controlled
+pub struct ControlNode {
+    pub pt: LonLat,
+    pub control: Control,
+    // From the direction tag, relative to the way the node is on. None means unknown.
+    pub forwards: Option<bool>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Control {
+    TrafficSignal,
+    Stop,
+    // stop=all
+    AllWayStop,
+    GiveWay,
+    // crossing=traffic_signals
+    PedestrianSignal,
+}
+
+impl ControlNode {
+    pub fn parse(pt: LonLat, tags: &BTreeMap<String, String>) -> Option<ControlNode> {
+        let control = match tags.get("highway").map(|x| x.as_str()) {
+            Some("traffic_signals") => Control::TrafficSignal,
+            Some("stop") => {
+                if tags.get("stop") == Some(&"all".to_string()) {
+                    Control::AllWayStop
+                } else {
+                    Control::Stop
+                }
+            }
+            Some("give_way") => Control::GiveWay,
+            // Sometimes tagged on the crossing node alone, without highway=crossing
+            _ if tags.get("crossing") == Some(&"traffic_signals".to_string()) => {
+                Control::PedestrianSignal
+            }
+            // Other crossings (zebra, uncontrolled, unmarked) don't change anything; crosswalks
+            // already have priority at stop signs.
+            _ => {
+                return None;
+            }
+        };
+        let forwards = match tags.get("direction").map(|x| x.as_str()) {
+            Some("forward") => Some(true),
+            Some("backward") => Some(false),
+            _ => None,
+        };
+        Some(ControlNode {
+            pt,
+            control,
+            forwards,
+        })
+    }
+}
+
+// Finds the intersection that something near a road controls, and the road it's on.
+pub struct Matcher {
+    gps_bounds: GPSBounds,
+    closest: FindClosest<raw_data::StableRoadID>,
+}
+
+impl Matcher {
+    pub fn new(map: &raw_data::Map, gps_bounds: &GPSBounds) -> Matcher {
+        let mut closest = FindClosest::new(&gps_bounds.to_bounds());
+        for (id, r) in &map.roads {
+            closest.add_gps(*id, &r.points, gps_bounds);
+        }
+        Matcher {
+            gps_bounds: gps_bounds.clone(),
+            closest,
+        }
+    }
+
+    // Without a direction, picks the closer end of the road.
+    pub fn find(
+        &self,
+        map: &raw_data::Map,
+        pt: LonLat,
+        forwards: Option<bool>,
+        max_dist_to_road: Distance,
+        max_dist_to_intersection: Distance,
+    ) -> Option<(raw_data::StableIntersectionID, raw_data::StableRoadID)> {
+        let (r, _) = self
+            .closest
+            .closest_pt(Pt2D::from_gps(pt, &self.gps_bounds)?, max_dist_to_road)?;
+        let road = &map.roads[&r];
+        let i = match forwards {
+            Some(true) => road.i2,
+            Some(false) => road.i1,
+            None => {
+                if pt.gps_dist_meters(map.intersections[&road.i1].point)
+                    <= pt.gps_dist_meters(map.intersections[&road.i2].point)
+                {
+                    road.i1
+                } else {
+                    road.i2
+                }
+            }
+        };
+        if pt.gps_dist_meters(map.intersections[&i].point) <= max_dist_to_intersection {
+            Some((i, r))
+        } else {
+            None
+        }
+    }
+}
+
+// Run after clipping, so only nodes near the remaining roads match anything.
+pub fn apply(map: &mut raw_data::Map, nodes: Vec<ControlNode>, timer: &mut Timer) {
+    timer.start("match traffic control nodes to intersections");
+    let matcher = Matcher::new(map, &map.get_gps_bounds());
+    let mut roads_per_intersection: MultiMap<
+        raw_data::StableIntersectionID,
+        raw_data::StableRoadID,
+    > = MultiMap::new();
+    for (id, r) in &map.roads {
+        roads_per_intersection.insert(r.i1, *id);
+        roads_per_intersection.insert(r.i2, *id);
+    }
+
+    let mut matches = 0;
+    for node in nodes {
+        let max_dist_to_intersection = if node.control == Control::PedestrianSignal {
+            MAX_DIST_BTWN_INTERSECTION_AND_CROSSING
+        } else {
+            MAX_DIST_BTWN_INTERSECTION_AND_CONTROL
+        };
+        let (i, r) = match matcher.find(
+            map,
+            node.pt,
+            node.forwards,
+            MAX_DIST_BTWN_ROAD_AND_NODE,
+            max_dist_to_intersection,
+        ) {
+            Some(pair) => pair,
+            None => {
+                continue;
+            }
+        };
+        let intersection = map.intersections.get_mut(&i).unwrap();
+        if intersection.intersection_type == IntersectionType::Border {
+            continue;
+        }
+        matches += 1;
+        // A sign right on the intersection's node, without a direction, could be facing any of
+        // the roads. Assume all of them.
+        let all_roads = node.control == Control::AllWayStop
+            || (node.control == Control::Stop
+                && node.forwards.is_none()
+                && node.pt == intersection.point);
+        match node.control {
+            // Cars have to wait for pedestrians crossing at the intersection, so it's signalized
+            // for everyone.
+            Control::TrafficSignal | Control::PedestrianSignal => {
+                intersection.intersection_type = IntersectionType::TrafficSignal;
+            }
+            Control::Stop | Control::AllWayStop if all_roads => {
+                for road in roads_per_intersection.get(i) {
+                    intersection
+                        .approach_signs
+                        .insert(*road, raw_data::ApproachSign::Stop);
+                }
+            }
+            Control::Stop | Control::AllWayStop => {
+                intersection
+                    .approach_signs
+                    .insert(r, raw_data::ApproachSign::Stop);
+            }
+            Control::GiveWay => {
+                // Don't downgrade a stop sign
+                intersection
+                    .approach_signs
+                    .entry(r)
+                    .or_insert(raw_data::ApproachSign::GiveWay);
+            }
+        }
+    }
+    timer.note(format!(
+        "{} traffic control nodes matched intersections",
+        matches
+    ));
+    timer.stop("match traffic control nodes to intersections");
+}
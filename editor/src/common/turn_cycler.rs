@@ -88,9 +88,9 @@ impl TurnCyclerState {
             }
             State::ShowIntersection(i) => {
                 if self.shift_key_held {
-                    if let Some(signal) = ui.primary.map.maybe_get_traffic_signal(i) {
-                        let (cycle, mut time_left) =
-                            signal.current_cycle_and_remaining_time(ui.primary.sim.time());
+                    if ui.primary.map.maybe_get_traffic_signal(i).is_some() {
+                        let (cycle_idx, mut time_left) =
+                            ui.primary.sim.current_signal_cycle(i, &ui.primary.map);
                         if ui.primary.sim.is_in_overtime(i, &ui.primary.map) {
                             // TODO Hacky way of indicating overtime. Should make a 3-case enum.
                             time_left = Duration::seconds(-1.0);
@@ -101,7 +101,7 @@ impl TurnCyclerState {
                             draw_map: &ui.primary.draw_map,
                             sim: &ui.primary.sim,
                         };
-                        draw_signal_diagram(i, cycle.idx, Some(time_left), g, &ctx);
+                        draw_signal_diagram(i, cycle_idx, Some(time_left), g, &ctx);
                     }
                 }
             }
@@ -5,21 +5,33 @@ use crate::common::CommonState;
 use crate::game::{GameState, Mode};
 use crate::helpers::ID;
 use crate::render::{
-    DrawCtx, DrawIntersection, DrawLane, DrawMap, DrawOptions, DrawTurn, Renderable,
+    DrawCtx, DrawIntersection, DrawLane, DrawMap, DrawOptions, DrawRoad, DrawTurn, Renderable,
     MIN_ZOOM_FOR_DETAIL,
 };
 use crate::ui::{ShowEverything, UI};
 use abstutil::Timer;
-use ezgui::{Color, EventCtx, EventLoopMode, GfxCtx, Key, ModalMenu, Text, Wizard, WrappedWizard};
-use map_model::{IntersectionID, Lane, LaneID, LaneType, Map, MapEdits, Road, TurnID, TurnType};
+use ezgui::{
+    Color, EventCtx, EventLoopMode, GfxCtx, Key, LogScroller, ModalMenu, Text, Wizard,
+    WrappedWizard,
+};
+use geom::Speed;
+use map_model::raw_data::RestrictionType;
+use map_model::{
+    EditCmd, IntersectionID, IntersectionType, Lane, LaneID, LaneType, Map, MapEdits, Road, RoadID,
+    TurnID, TurnType,
+};
 use std::collections::{BTreeSet, HashMap};
 
 pub enum EditMode {
     ViewingDiffs(CommonState, ModalMenu),
     Saving(Wizard),
     Loading(Wizard),
+    Comparing(Wizard),
+    ViewingComparison(LogScroller),
     EditingStopSign(stop_signs::StopSignEditor),
     EditingTrafficSignal(traffic_signals::TrafficSignalEditor),
+    // Turning from the road at the intersection
+    EditingTurnRestrictions(Wizard, RoadID, IntersectionID),
 }
 
 impl EditMode {
@@ -36,6 +48,9 @@ impl EditMode {
                         (Some(Key::Escape), "quit"),
                         (Some(Key::S), "save edits"),
                         (Some(Key::L), "load different edits"),
+                        (Some(Key::D), "compare with other edits"),
+                        (Some(Key::U), "undo"),
+                        (Some(Key::Y), "redo"),
                     ],
                     CommonState::modal_menu_entries(),
                 ]
@@ -53,12 +68,33 @@ impl EditMode {
                     let edits = state.ui.primary.map.get_edits();
                     txt.add_line(edits.edits_name.clone());
                     txt.add_line(format!("{} lanes", edits.lane_overrides.len()));
+                    txt.add_line(format!("{} reversed lanes", edits.reversed_lanes.len()));
+                    txt.add_line(format!(
+                        "{} speed limits",
+                        edits.speed_limit_overrides.len()
+                    ));
+                    txt.add_line(format!("{} closed roads", edits.closed_roads.len()));
+                    txt.add_line(format!(
+                        "{} intersection types",
+                        edits.intersection_type_overrides.len()
+                    ));
                     txt.add_line(format!("{} stop signs ", edits.stop_sign_overrides.len()));
                     txt.add_line(format!(
                         "{} traffic signals",
                         edits.traffic_signal_overrides.len()
                     ));
-                    txt.add_line("Right-click a lane or intersection to start editing".to_string());
+                    txt.add_line(format!(
+                        "{} off-street parking",
+                        edits.offstreet_parking_overrides.len()
+                    ));
+                    txt.add_line(format!(
+                        "{} turn restrictions",
+                        edits.turn_restriction_overrides.len()
+                    ));
+                    txt.add_line(
+                        "Right-click a lane, intersection, or building to start editing"
+                            .to_string(),
+                    );
                 }
                 menu.handle_event(ctx, Some(txt));
 
@@ -90,6 +126,24 @@ impl EditMode {
                 } else if menu.action("load different edits") {
                     state.mode = Mode::Edit(EditMode::Loading(Wizard::new()));
                     return EventLoopMode::InputOnly;
+                } else if menu.action("compare with other edits") {
+                    state.mode = Mode::Edit(EditMode::Comparing(Wizard::new()));
+                    return EventLoopMode::InputOnly;
+                }
+                if menu.action("undo") {
+                    let mut new_edits = state.ui.primary.map.get_edits().clone();
+                    if new_edits.can_undo() {
+                        new_edits.undo();
+                        apply_map_edits(&mut state.ui, ctx, new_edits);
+                    }
+                    return EventLoopMode::InputOnly;
+                } else if menu.action("redo") {
+                    let mut new_edits = state.ui.primary.map.get_edits().clone();
+                    if new_edits.can_redo() {
+                        new_edits.redo();
+                        apply_map_edits(&mut state.ui, ctx, new_edits);
+                    }
+                    return EventLoopMode::InputOnly;
                 }
 
                 if let Some(ID::Lane(id)) = state.ui.primary.current_selection {
@@ -102,14 +156,116 @@ impl EditMode {
                                 .input
                                 .contextual_action(Key::Space, &format!("toggle to {:?}", new_type))
                             {
-                                let mut new_edits = state.ui.primary.map.get_edits().clone();
-                                new_edits.lane_overrides.insert(lane.id, new_type);
-                                apply_map_edits(&mut state.ui, ctx, new_edits);
+                                execute_edit(
+                                    &mut state.ui,
+                                    ctx,
+                                    EditCmd::LaneType(lane.id, Some(new_type)),
+                                );
+                                return EventLoopMode::InputOnly;
+                            }
+                        }
+                    }
+
+                    if state.ui.primary.map.can_reverse_lane(id)
+                        && ctx
+                            .input
+                            .contextual_action(Key::R, "reverse lane direction")
+                    {
+                        let reversed = state
+                            .ui
+                            .primary
+                            .map
+                            .get_edits()
+                            .reversed_lanes
+                            .contains(&id);
+                        execute_edit(&mut state.ui, ctx, EditCmd::ReverseLane(id, !reversed));
+                        return EventLoopMode::InputOnly;
+                    }
+
+                    if lane.is_driving()
+                        && ctx
+                            .input
+                            .contextual_action(Key::N, "edit turn restrictions from here")
+                    {
+                        state.mode = Mode::Edit(EditMode::EditingTurnRestrictions(
+                            Wizard::new(),
+                            lane.parent,
+                            lane.dst_i,
+                        ));
+                        return EventLoopMode::InputOnly;
+                    }
+
+                    let r = lane.parent;
+                    let closed = state.ui.primary.map.get_edits().closed_roads.contains(&r);
+                    if ctx.input.contextual_action(
+                        Key::C,
+                        if closed {
+                            "reopen road to cars"
+                        } else {
+                            "close road to cars"
+                        },
+                    ) {
+                        execute_edit(&mut state.ui, ctx, EditCmd::CloseRoad(r, !closed));
+                        return EventLoopMode::InputOnly;
+                    }
+
+                    let road = state.ui.primary.map.get_r(r);
+                    for (key, steps, verb) in vec![
+                        (Key::RightBracket, 1.0, "raise"),
+                        (Key::LeftBracket, -1.0, "lower"),
+                    ] {
+                        if let Some(speed) = step_speed_limit(road.get_speed_limit(), steps) {
+                            if ctx.input.contextual_action(
+                                key,
+                                &format!("{} speed limit to {} mph", verb, to_mph(speed)),
+                            ) {
+                                let value = if to_mph(speed) == to_mph(road.speed_limit_from_osm())
+                                {
+                                    None
+                                } else {
+                                    Some(speed)
+                                };
+                                execute_edit(&mut state.ui, ctx, EditCmd::SpeedLimit(r, value));
+                                return EventLoopMode::InputOnly;
                             }
                         }
                     }
                 }
                 if let Some(ID::Intersection(id)) = state.ui.primary.current_selection {
+                    let new_type = match state.ui.primary.map.get_i(id).intersection_type {
+                        IntersectionType::StopSign => Some(IntersectionType::TrafficSignal),
+                        IntersectionType::TrafficSignal => Some(IntersectionType::StopSign),
+                        IntersectionType::Border => None,
+                    };
+                    if let Some(it) = new_type {
+                        if ctx
+                            .input
+                            .contextual_action(Key::T, &format!("convert to {:?}", it))
+                        {
+                            let overridden = state
+                                .ui
+                                .primary
+                                .map
+                                .get_edits()
+                                .intersection_type_overrides
+                                .contains_key(&id);
+                            // Any old policy was for the other type.
+                            execute_edit(
+                                &mut state.ui,
+                                ctx,
+                                EditCmd::Many(vec![
+                                    EditCmd::StopSign(id, None),
+                                    EditCmd::TrafficSignal(id, None),
+                                    EditCmd::IntersectionType(
+                                        id,
+                                        if overridden { None } else { Some(it) },
+                                    ),
+                                ]),
+                            );
+                            return EventLoopMode::InputOnly;
+                        }
+                    }
+
                     if state.ui.primary.map.maybe_get_stop_sign(id).is_some()
                         && ctx
                             .input
@@ -129,6 +285,44 @@ impl EditMode {
                         ));
                     }
                 }
+                if let Some(ID::Building(id)) = state.ui.primary.current_selection {
+                    let osm_stalls = state
+                        .ui
+                        .primary
+                        .map
+                        .get_b(id)
+                        .osm_num_parking_stalls()
+                        .unwrap_or(0);
+                    let current = state
+                        .ui
+                        .primary
+                        .map
+                        .get_edits()
+                        .offstreet_parking_overrides
+                        .get(&id)
+                        .cloned()
+                        .unwrap_or(osm_stalls);
+                    let mut new_stalls = None;
+                    if ctx
+                        .input
+                        .contextual_action(Key::RightBracket, "add 10 off-street parking stalls")
+                    {
+                        new_stalls = Some(current + 10);
+                    }
+                    if current > 0
+                        && ctx.input.contextual_action(
+                            Key::LeftBracket,
+                            "remove 10 off-street parking stalls",
+                        )
+                    {
+                        new_stalls = Some(current.saturating_sub(10));
+                    }
+                    if let Some(n) = new_stalls {
+                        let value = if n == osm_stalls { None } else { Some(n) };
+                        execute_edit(&mut state.ui, ctx, EditCmd::OffstreetParking(id, value));
+                        return EventLoopMode::InputOnly;
+                    }
+                }
             }
             Mode::Edit(EditMode::Saving(ref mut wizard)) => {
                 if save_edits(
@@ -153,6 +347,21 @@ impl EditMode {
                     state.mode = Mode::Edit(EditMode::new(ctx, &mut state.ui));
                 }
             }
+            Mode::Edit(EditMode::Comparing(ref mut wizard)) => {
+                if let Some(scroller) = compare_edits(
+                    &mut wizard.wrap(ctx.input, ctx.canvas),
+                    &state.ui.primary.map,
+                ) {
+                    state.mode = Mode::Edit(EditMode::ViewingComparison(scroller));
+                } else if wizard.aborted() {
+                    state.mode = Mode::Edit(EditMode::new(ctx, &mut state.ui));
+                }
+            }
+            Mode::Edit(EditMode::ViewingComparison(ref mut scroller)) => {
+                if scroller.event(ctx.input) {
+                    state.mode = Mode::Edit(EditMode::new(ctx, &mut state.ui));
+                }
+            }
             Mode::Edit(EditMode::EditingStopSign(ref mut editor)) => {
                 if editor.event(ctx, &mut state.ui) {
                     state.mode = Mode::Edit(EditMode::new(ctx, &mut state.ui));
@@ -163,6 +372,19 @@ impl EditMode {
                     state.mode = Mode::Edit(EditMode::new(ctx, &mut state.ui));
                 }
             }
+            Mode::Edit(EditMode::EditingTurnRestrictions(ref mut wizard, from, i)) => {
+                if let Some(cmd) = edit_turn_restriction(
+                    &mut wizard.wrap(ctx.input, ctx.canvas),
+                    &state.ui.primary.map,
+                    from,
+                    i,
+                ) {
+                    execute_edit(&mut state.ui, ctx, cmd);
+                    state.mode = Mode::Edit(EditMode::new(ctx, &mut state.ui));
+                } else if wizard.aborted() {
+                    state.mode = Mode::Edit(EditMode::new(ctx, &mut state.ui));
+                }
+            }
             _ => unreachable!(),
         }
 
@@ -197,15 +419,11 @@ impl EditMode {
                 if g.canvas.cam_zoom >= MIN_ZOOM_FOR_DETAIL {
                     g.enable_hatching();
 
-                    for l in edits.lane_overrides.keys() {
-                        ctx.draw_map.get_l(*l).draw(g, &opts, &ctx);
+                    for l in edited_lanes(ctx.map) {
+                        ctx.draw_map.get_l(l).draw(g, &opts, &ctx);
                     }
-                    for i in edits
-                        .stop_sign_overrides
-                        .keys()
-                        .chain(edits.traffic_signal_overrides.keys())
-                    {
-                        ctx.draw_map.get_i(*i).draw(g, &opts, &ctx);
+                    for i in edited_intersections(edits) {
+                        ctx.draw_map.get_i(i).draw(g, &opts, &ctx);
                     }
 
                     g.disable_hatching();
@@ -228,17 +446,13 @@ impl EditMode {
                     }
                 } else {
                     let color = state.ui.cs.get_def("unzoomed map diffs", Color::RED);
-                    for l in edits.lane_overrides.keys() {
-                        g.draw_polygon(color, &ctx.map.get_parent(*l).get_thick_polygon().unwrap());
+                    for l in edited_lanes(ctx.map) {
+                        g.draw_polygon(color, &ctx.map.get_parent(l).get_thick_polygon().unwrap());
                     }
 
-                    for i in edits
-                        .stop_sign_overrides
-                        .keys()
-                        .chain(edits.traffic_signal_overrides.keys())
-                    {
-                        opts.override_colors.insert(ID::Intersection(*i), color);
-                        ctx.draw_map.get_i(*i).draw(g, &opts, &ctx);
+                    for i in edited_intersections(edits) {
+                        opts.override_colors.insert(ID::Intersection(i), color);
+                        ctx.draw_map.get_i(i).draw(g, &opts, &ctx);
                     }
                 }
 
@@ -246,7 +460,9 @@ impl EditMode {
                 menu.draw(g);
             }
             Mode::Edit(EditMode::Saving(ref wizard))
-            | Mode::Edit(EditMode::Loading(ref wizard)) => {
+            | Mode::Edit(EditMode::Loading(ref wizard))
+            | Mode::Edit(EditMode::Comparing(ref wizard))
+            | Mode::Edit(EditMode::EditingTurnRestrictions(ref wizard, _, _)) => {
                 state.ui.draw(
                     g,
                     DrawOptions::new(),
@@ -257,6 +473,15 @@ impl EditMode {
                 // TODO Still draw the diffs, yo
                 wizard.draw(g);
             }
+            Mode::Edit(EditMode::ViewingComparison(ref scroller)) => {
+                state.ui.draw(
+                    g,
+                    DrawOptions::new(),
+                    &state.ui.primary.sim,
+                    &ShowEverything::new(),
+                );
+                scroller.draw(g);
+            }
             Mode::Edit(EditMode::EditingStopSign(ref editor)) => {
                 editor.draw(g, state);
             }
@@ -288,11 +513,142 @@ fn save_edits(mut wizard: WrappedWizard, map: &mut Map) -> Option<()> {
             edits.edits_name = name;
             map.apply_edits(edits, &mut Timer::new("name map edits"));
         }
-        map.get_edits().save();
+        map.get_edits().save(map);
     }
     Some(())
 }
 
+fn edit_turn_restriction(
+    wizard: &mut WrappedWizard,
+    map: &Map,
+    from: RoadID,
+    i: IntersectionID,
+) -> Option<EditCmd> {
+    let mut choices: Vec<(String, RoadID)> = map
+        .get_i(i)
+        .roads
+        .iter()
+        .filter(|r| **r != from)
+        .map(|r| {
+            let current = match map.get_turn_restriction(from, *r) {
+                Some(RestrictionType::BanTurns) => "banned",
+                Some(RestrictionType::OnlyAllowTurns) => "the only way allowed",
+                None => "allowed",
+            };
+            (
+                format!(
+                    "{} ({}), currently {}",
+                    map.get_r(*r).get_name(),
+                    r,
+                    current
+                ),
+                *r,
+            )
+        })
+        .collect();
+    // Restrictions through a via road can only be lifted or restored.
+    let mut via_way: BTreeSet<RoadID> = BTreeSet::new();
+    for (via, _, to) in &map.get_r(from).complicated_turn_restrictions {
+        if !via_way.insert(*to) {
+            continue;
+        }
+        let current = if map
+            .get_edits()
+            .turn_restriction_overrides
+            .contains_key(&(from, *to))
+        {
+            "lifted"
+        } else {
+            "restricted"
+        };
+        choices.push((
+            format!(
+                "{} ({}) via {}, currently {}",
+                map.get_r(*to).get_name(),
+                to,
+                map.get_r(*via).get_name(),
+                current
+            ),
+            *to,
+        ));
+    }
+    let (_, to) = wizard.choose_something_no_keys::<RoadID>(
+        &format!(
+            "Turning from {} onto which road?",
+            map.get_r(from).get_name()
+        ),
+        Box::new(move || choices.clone()),
+    )?;
+
+    let ban = "ban turns onto this road";
+    let only = "only allow turns onto this road";
+    let allow = "allow turns, ignoring OSM";
+    let revert = "use whatever OSM says";
+    let options = if via_way.contains(&to) {
+        vec![allow, revert]
+    } else {
+        vec![ban, only, allow, revert]
+    };
+    let choice = wizard.choose_string(&format!("Turning from {} onto {}", from, to), options)?;
+    Some(match choice.as_str() {
+        x if x == ban => EditCmd::TurnRestriction(from, to, Some(RestrictionType::BanTurns)),
+        x if x == only => EditCmd::TurnRestriction(from, to, Some(RestrictionType::OnlyAllowTurns)),
+        x if x == allow => EditCmd::TurnRestriction(from, to, None),
+        _ => EditCmd::RevertTurnRestriction(from, to),
+    })
+}
+
+fn edited_lanes(map: &Map) -> BTreeSet<LaneID> {
+    let edits = map.get_edits();
+    let mut lanes: BTreeSet<LaneID> = edits.lane_overrides.keys().cloned().collect();
+    lanes.extend(edits.reversed_lanes.iter().cloned());
+    for r in edits
+        .speed_limit_overrides
+        .keys()
+        .chain(edits.closed_roads.iter())
+    {
+        lanes.extend(map.get_r(*r).all_lanes());
+    }
+    lanes
+}
+
+fn edited_intersections(edits: &MapEdits) -> BTreeSet<IntersectionID> {
+    edits
+        .stop_sign_overrides
+        .keys()
+        .chain(edits.traffic_signal_overrides.keys())
+        .chain(edits.intersection_type_overrides.keys())
+        .cloned()
+        .collect()
+}
+
+// Speed limits are edited in steps of 5mph.
+fn step_speed_limit(current: Speed, steps: f64) -> Option<Speed> {
+    let mph = (to_mph(current) / 5.0).round() * 5.0 + 5.0 * steps;
+    if mph < 5.0 {
+        None
+    } else {
+        Some(Speed::miles_per_hour(mph))
+    }
+}
+
+fn to_mph(speed: Speed) -> f64 {
+    (speed.inner_meters_per_second() / 0.44704).round()
+}
+
+fn compare_edits(wizard: &mut WrappedWizard, map: &Map) -> Option<LogScroller> {
+    let other = load_edits(map, wizard, "Compare the current edits with which?")?;
+    let current = map.get_edits();
+    let mut lines = current.diff(&other);
+    if lines.is_empty() {
+        lines.push("No differences".to_string());
+    }
+    Some(LogScroller::new(
+        format!("{} vs {}", current.edits_name, other.edits_name),
+        lines,
+    ))
+}
+
 // For lane editing
 
 fn next_valid_type(r: &Road, l: &Lane) -> Option<LaneType> {
@@ -350,11 +706,27 @@ fn can_change_lane_type(r: &Road, l: &Lane, lt: LaneType) -> bool {
     true
 }
 
+// Records the command, so it can be undone.
+fn execute_edit(ui: &mut UI, ctx: &mut EventCtx, cmd: EditCmd) {
+    let mut new_edits = ui.primary.map.get_edits().clone();
+    new_edits.execute(cmd);
+    apply_map_edits(ui, ctx, new_edits);
+}
+
 pub fn apply_map_edits(ui: &mut UI, ctx: &mut EventCtx, edits: MapEdits) {
     let mut timer = Timer::new("apply map edits");
     ui.primary.current_flags.sim_flags.edits_name = edits.edits_name.clone();
     let (lanes_changed, turns_deleted, turns_added) = ui.primary.map.apply_edits(edits, &mut timer);
 
+    // Reversing lanes moves the road's center line.
+    let roads_changed: BTreeSet<RoadID> = lanes_changed
+        .iter()
+        .map(|l| ui.primary.map.get_l(*l).parent)
+        .collect();
+    for r in roads_changed {
+        ui.primary.draw_map.roads[r.0] =
+            DrawRoad::new(ui.primary.map.get_r(r), &ui.cs, ctx.prerender);
+    }
     for l in lanes_changed {
         ui.primary.draw_map.lanes[l.0] = DrawLane::new(
             ui.primary.map.get_l(l),
@@ -409,14 +781,26 @@ pub fn apply_map_edits(ui: &mut UI, ctx: &mut EventCtx, edits: MapEdits) {
 fn load_edits(map: &Map, wizard: &mut WrappedWizard, query: &str) -> Option<MapEdits> {
     // TODO Exclude current?
     let map_name = map.get_name().to_string();
-    wizard
-        .choose_something_no_keys::<MapEdits>(
-            query,
-            Box::new(move || {
-                let mut list = abstutil::load_all_objects("edits", &map_name);
-                list.push(("no_edits".to_string(), MapEdits::new(map_name.clone())));
-                list
-            }),
-        )
-        .map(|(_, e)| e)
+    let (name, _) = wizard.choose_something_no_keys::<String>(
+        query,
+        Box::new(move || {
+            let mut list = abstutil::list_all_objects("edits", &map_name);
+            list.push(("no_edits".to_string(), "no_edits".to_string()));
+            list
+        }),
+    )?;
+    match MapEdits::load(map, &name, &mut Timer::new("load map edits")) {
+        Ok(edits) => Some(edits),
+        Err(err) => {
+            if wizard.acknowledge(LogScroller::new(
+                format!("Couldn't load edits {}", name),
+                vec![err, "".to_string(), "Press ENTER to continue".to_string()],
+            )) {
+                // Nothing changes
+                Some(map.get_edits().clone())
+            } else {
+                None
+            }
+        }
+    }
 }
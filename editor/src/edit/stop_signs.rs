@@ -1,12 +1,12 @@
 use crate::common::CommonState;
-use crate::edit::apply_map_edits;
+use crate::edit::execute_edit;
 use crate::game::GameState;
 use crate::helpers::ID;
 use crate::render::{DrawIntersection, DrawOptions, DrawTurn};
 use crate::ui::{ShowEverything, UI};
 use ezgui::{Color, EventCtx, GeomBatch, GfxCtx, Key, ModalMenu, Text};
 use geom::Polygon;
-use map_model::{IntersectionID, RoadID, TurnID, TurnPriority};
+use map_model::{EditCmd, IntersectionID, RoadID, TurnID, TurnPriority};
 use std::collections::HashMap;
 
 pub struct StopSignEditor {
@@ -94,24 +94,18 @@ impl StopSignEditor {
                 .contextual_action(Key::Space, &format!("toggle to {:?}", next_priority))
             {
                 sign.change(t, next_priority, &ui.primary.map);
-                let mut new_edits = ui.primary.map.get_edits().clone();
-                new_edits.stop_sign_overrides.insert(self.id, sign);
-                apply_map_edits(ui, ctx, new_edits);
+                execute_edit(ui, ctx, EditCmd::StopSign(self.id, Some(sign)));
             }
         } else if let Some(r) = self.selected_sign {
             if ctx.input.contextual_action(Key::Space, "toggle stop sign") {
                 let mut sign = ui.primary.map.get_stop_sign(self.id).clone();
                 sign.flip_sign(r, &ui.primary.map);
-                let mut new_edits = ui.primary.map.get_edits().clone();
-                new_edits.stop_sign_overrides.insert(self.id, sign);
-                apply_map_edits(ui, ctx, new_edits);
+                execute_edit(ui, ctx, EditCmd::StopSign(self.id, Some(sign)));
             }
         } else if self.menu.action("quit") {
             return true;
         } else if self.menu.action("reset to default") {
-            let mut new_edits = ui.primary.map.get_edits().clone();
-            new_edits.stop_sign_overrides.remove(&self.id);
-            apply_map_edits(ui, ctx, new_edits);
+            execute_edit(ui, ctx, EditCmd::StopSign(self.id, None));
         }
         false
     }
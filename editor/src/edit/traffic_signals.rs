@@ -1,5 +1,5 @@
 use crate::common::CommonState;
-use crate::edit::apply_map_edits;
+use crate::edit::execute_edit;
 use crate::game::GameState;
 use crate::helpers::ID;
 use crate::render::{draw_signal_cycle, draw_signal_diagram, DrawCtx, DrawOptions, DrawTurn};
@@ -7,7 +7,9 @@ use crate::ui::{ShowEverything, UI};
 use abstutil::Timer;
 use ezgui::{Color, EventCtx, GeomBatch, GfxCtx, Key, ModalMenu, Wizard, WrappedWizard};
 use geom::Duration;
-use map_model::{ControlTrafficSignal, Cycle, IntersectionID, Map, TurnID, TurnPriority, TurnType};
+use map_model::{
+    ControlTrafficSignal, Cycle, EditCmd, IntersectionID, Map, TurnID, TurnPriority, TurnType,
+};
 
 // TODO Warn if there are empty cycles or if some turn is completely absent from the signal.
 pub struct TrafficSignalEditor {
@@ -17,6 +19,8 @@ pub struct TrafficSignalEditor {
     // The Wizard states are nested under here to remember things like current_cycle and keep
     // drawing stuff. Better way to represent nested states?
     cycle_duration_wizard: Option<Wizard>,
+    max_extension_wizard: Option<Wizard>,
+    offset_wizard: Option<Wizard>,
     preset_wizard: Option<Wizard>,
     icon_selected: Option<TurnID>,
 }
@@ -29,6 +33,9 @@ impl TrafficSignalEditor {
             vec![
                 (Some(Key::Escape), "quit"),
                 (Some(Key::D), "change cycle duration"),
+                (Some(Key::E), "change cycle max extension"),
+                (Some(Key::O), "change signal offset"),
+                (Some(Key::A), "toggle actuated timing"),
                 (Some(Key::P), "choose a preset signal"),
                 (Some(Key::R), "reset to original"),
                 (Some(Key::K), "move current cycle up"),
@@ -46,6 +53,8 @@ impl TrafficSignalEditor {
             i: id,
             current_cycle: 0,
             cycle_duration_wizard: None,
+            max_extension_wizard: None,
+            offset_wizard: None,
             preset_wizard: None,
             icon_selected: None,
         }
@@ -85,12 +94,56 @@ impl TrafficSignalEditor {
                     ),
                 )
             {
-                signal.cycles[self.current_cycle].duration = Duration::seconds(new_duration as f64);
-                changed = true;
+                // A cycle with no duration would never run.
+                if new_duration > 0 {
+                    signal.cycles[self.current_cycle].duration =
+                        Duration::seconds(new_duration as f64);
+                    changed = true;
+                }
                 self.cycle_duration_wizard = None;
             } else if self.cycle_duration_wizard.as_ref().unwrap().aborted() {
                 self.cycle_duration_wizard = None;
             }
+        } else if self.max_extension_wizard.is_some() {
+            if let Some(new_extension) = self
+                .max_extension_wizard
+                .as_mut()
+                .unwrap()
+                .wrap(ctx.input, ctx.canvas)
+                .input_usize_prefilled(
+                    "If actuated, how much longer can this cycle run while agents are waiting?",
+                    format!(
+                        "{}",
+                        signal.cycles[self.current_cycle]
+                            .max_extension
+                            .inner_seconds() as usize
+                    ),
+                )
+            {
+                signal.cycles[self.current_cycle].max_extension =
+                    Duration::seconds(new_extension as f64);
+                changed = true;
+                self.max_extension_wizard = None;
+            } else if self.max_extension_wizard.as_ref().unwrap().aborted() {
+                self.max_extension_wizard = None;
+            }
+        } else if self.offset_wizard.is_some() {
+            if let Some(new_offset) = self
+                .offset_wizard
+                .as_mut()
+                .unwrap()
+                .wrap(ctx.input, ctx.canvas)
+                .input_usize_prefilled(
+                    "How many seconds after midnight should the first cycle start?",
+                    format!("{}", signal.offset.inner_seconds() as usize),
+                )
+            {
+                signal.offset = Duration::seconds(new_offset as f64);
+                changed = true;
+                self.offset_wizard = None;
+            } else if self.offset_wizard.as_ref().unwrap().aborted() {
+                self.offset_wizard = None;
+            }
         } else if self.preset_wizard.is_some() {
             if let Some(new_signal) = choose_preset(
                 &ui.primary.map,
@@ -159,6 +212,13 @@ impl TrafficSignalEditor {
 
             if self.menu.action("change cycle duration") {
                 self.cycle_duration_wizard = Some(Wizard::new());
+            } else if self.menu.action("change cycle max extension") {
+                self.max_extension_wizard = Some(Wizard::new());
+            } else if self.menu.action("change signal offset") {
+                self.offset_wizard = Some(Wizard::new());
+            } else if self.menu.action("toggle actuated timing") {
+                signal.actuated = !signal.actuated;
+                changed = true;
             } else if self.menu.action("choose a preset signal") {
                 self.preset_wizard = Some(Wizard::new());
             } else if self.menu.action("reset to original") {
@@ -215,13 +275,8 @@ impl TrafficSignalEditor {
 
         if changed {
             let orig = ControlTrafficSignal::new(&ui.primary.map, self.i, &mut Timer::throwaway());
-            let mut new_edits = ui.primary.map.get_edits().clone();
-            if orig == signal {
-                new_edits.traffic_signal_overrides.remove(&self.i);
-            } else {
-                new_edits.traffic_signal_overrides.insert(self.i, signal);
-            }
-            apply_map_edits(ui, ctx, new_edits);
+            let value = if orig == signal { None } else { Some(signal) };
+            execute_edit(ui, ctx, EditCmd::TrafficSignal(self.i, value));
         }
 
         false
@@ -278,6 +333,10 @@ impl TrafficSignalEditor {
 
         if let Some(ref wizard) = self.cycle_duration_wizard {
             wizard.draw(g);
+        } else if let Some(ref wizard) = self.max_extension_wizard {
+            wizard.draw(g);
+        } else if let Some(ref wizard) = self.offset_wizard {
+            wizard.draw(g);
         } else if let Some(ref wizard) = self.preset_wizard {
             wizard.draw(g);
         }
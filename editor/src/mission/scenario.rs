@@ -9,7 +9,9 @@ use ezgui::{
 };
 use geom::{Distance, Duration, Line, Pt2D};
 use map_model::{IntersectionID, Map, Neighborhood};
-use sim::{BorderSpawnOverTime, OriginDestination, Scenario, SeedParkedCars, SpawnOverTime};
+use sim::{
+    BorderRef, BorderSpawnOverTime, OriginDestination, Scenario, SeedParkedCars, SpawnOverTime,
+};
 use std::collections::BTreeMap;
 
 pub enum ScenarioEditor {
@@ -170,7 +172,13 @@ impl ScenarioEditor {
                         .offset(Distance::meters(-50.0), Distance::ZERO);
                     let dst = match s.goal {
                         OriginDestination::Neighborhood(ref n) => mapping[n].center,
-                        OriginDestination::Border(i) => ui.primary.map.get_i(i).point,
+                        OriginDestination::Border(border) => {
+                            match border.resolve(&ui.primary.map) {
+                                Some(i) => ui.primary.map.get_i(i).point,
+                                // Not in this version of the map
+                                None => continue,
+                            }
+                        }
                     }
                     .offset(Distance::meters(50.0), Distance::ZERO);
                     // TODO Draw a self-loop or something
@@ -248,8 +256,7 @@ fn edit_scenario(map: &Map, scenario: &mut Scenario, mut wizard: WrappedWizard)
                     "Where should the agents start?",
                 )?,
                 goal: choose_origin_destination(map, &mut wizard, "Where should the agents go?")?,
-                percent_biking: wizard
-                    .input_percent("What percent of the walking trips will bike instead?")?,
+                percent_biking: wizard.input_percent("What percent of the agents own a bike?")?,
                 percent_use_transit: wizard.input_percent(
                     "What percent of the walking trips will consider taking transit?",
                 )?,
@@ -265,6 +272,7 @@ fn edit_scenario(map: &Map, scenario: &mut Scenario, mut wizard: WrappedWizard)
                 stop_time: input_time(&mut wizard, "Stop spawning when?")?,
                 // TODO validate it's a border!
                 start_from_border: choose_intersection(
+                    map,
                     &mut wizard,
                     "Which border should the agents spawn at?",
                 )?,
@@ -337,12 +345,14 @@ fn input_weighted_usize(wizard: &mut WrappedWizard, query: &str) -> Option<Weigh
 }
 
 // TODO Validate the intersection exists? Let them pick it with the cursor?
-fn choose_intersection(wizard: &mut WrappedWizard, query: &str) -> Option<IntersectionID> {
-    wizard.input_something(
-        query,
-        None,
-        Box::new(|line| usize::from_str_radix(&line, 10).ok().map(IntersectionID)),
-    )
+fn choose_intersection(map: &Map, wizard: &mut WrappedWizard, query: &str) -> Option<BorderRef> {
+    wizard
+        .input_something(
+            query,
+            None,
+            Box::new(|line| usize::from_str_radix(&line, 10).ok().map(IntersectionID)),
+        )
+        .map(|i| BorderRef::Original(map.original_intersection(i)))
 }
 
 fn choose_origin_destination(
@@ -355,6 +365,6 @@ fn choose_origin_destination(
     if wizard.choose_string(query, vec![neighborhood, border])? == neighborhood {
         choose_neighborhood(map, wizard, query).map(OriginDestination::Neighborhood)
     } else {
-        choose_intersection(wizard, query).map(OriginDestination::Border)
+        choose_intersection(map, wizard, query).map(OriginDestination::Border)
     }
 }
@@ -150,9 +150,9 @@ impl Renderable for DrawIntersection {
                         .map(|(_, t)| *t != ctx.sim.time())
                         .unwrap_or(true);
                     if recalc {
-                        let (cycle, t) = signal.current_cycle_and_remaining_time(ctx.sim.time());
+                        let (idx, t) = ctx.sim.current_signal_cycle(self.id, ctx.map);
                         let mut batch = GeomBatch::new();
-                        draw_signal_cycle(cycle, Some(t), &mut batch, ctx);
+                        draw_signal_cycle(&signal.cycles[idx], Some(t), &mut batch, ctx);
                         *maybe_redraw = Some((g.prerender.upload(batch), ctx.sim.time()));
                     }
                     g.redraw(&maybe_redraw.as_ref().unwrap().0);
@@ -173,6 +173,13 @@ impl DrawMap {
             timer.next();
             areas.push(DrawArea::new(a, cs, &mut all_areas));
         }
+        // Lots can't be selected, so they don't need their own Renderable.
+        for lot in map.all_parking_lots() {
+            all_areas.push(
+                cs.get_def("parking lot", Color::rgb(238, 238, 238)),
+                lot.polygon.clone(),
+            );
+        }
         let draw_all_areas = prerender.upload(all_areas);
 
         let boundary_polygon = prerender.upload_borrowed(vec![(
@@ -71,6 +71,7 @@ impl SandboxMode {
                         // TODO This should probably be a debug thing instead
                         (Some(Key::L), "show/hide route for all agents"),
                         (Some(Key::A), "show/hide active traffic"),
+                        (Some(Key::C), "show/hide pedestrian crowds"),
                         (Some(Key::T), "start time traveling"),
                     ],
                     CommonState::modal_menu_entries(),
@@ -132,6 +133,9 @@ impl SandboxMode {
                 }
                 match mode.show_activity {
                     show_activity::ShowActivity::Inactive => {}
+                    show_activity::ShowActivity::Crowds(_, _) => {
+                        txt.add_line("Showing pedestrian crowds".to_string());
+                    }
                     _ => {
                         txt.add_line("Showing active traffic".to_string());
                     }
@@ -2,21 +2,39 @@ use crate::render::MIN_ZOOM_FOR_DETAIL;
 use crate::ui::UI;
 use ezgui::{Color, EventCtx, GfxCtx, ModalMenu};
 use geom::{Bounds, Distance, Duration, Polygon, Pt2D};
-use map_model::{RoadID, Traversable};
+use map_model::{IntersectionID, LaneID, RoadID, Traversable, LANE_THICKNESS};
 use std::collections::HashMap;
 
 pub enum ShowActivity {
     Inactive,
     Unzoomed(Duration, RoadHeatmap),
     Zoomed(Duration, Heatmap),
+    Crowds(Duration, CrowdHeatmap),
 }
 
 impl ShowActivity {
     pub fn event(&mut self, ctx: &mut EventCtx, ui: &mut UI, menu: &mut ModalMenu) {
         let zoomed = ctx.canvas.cam_zoom >= MIN_ZOOM_FOR_DETAIL;
 
+        if menu.action("show/hide pedestrian crowds") {
+            *self = match self {
+                ShowActivity::Crowds(_, _) => ShowActivity::Inactive,
+                _ => ShowActivity::Crowds(ui.primary.sim.time(), CrowdHeatmap::new(ui)),
+            };
+            return;
+        }
+
         // If we survive past this, recompute current state.
         match self {
+            ShowActivity::Crowds(time, _) => {
+                // Switch over to showing all traffic
+                if !menu.action("show/hide active traffic") {
+                    if *time != ui.primary.sim.time() {
+                        *self = ShowActivity::Crowds(ui.primary.sim.time(), CrowdHeatmap::new(ui));
+                    }
+                    return;
+                }
+            }
             ShowActivity::Inactive => {
                 if !menu.action("show/hide active traffic") {
                     return;
@@ -60,6 +78,9 @@ impl ShowActivity {
             ShowActivity::Unzoomed(_, ref road_heatmap) => {
                 road_heatmap.draw(g, ui);
             }
+            ShowActivity::Crowds(_, ref crowds) => {
+                crowds.draw(g, ui);
+            }
             ShowActivity::Inactive => {}
         }
     }
@@ -184,3 +205,57 @@ impl RoadHeatmap {
         }
     }
 }
+
+// Where pedestrians are backing up
+pub struct CrowdHeatmap {
+    // People per square meter
+    density_per_sidewalk: Vec<(LaneID, f64)>,
+    waiting_per_intersection: Vec<(IntersectionID, usize)>,
+    max_waiting: usize,
+}
+
+impl CrowdHeatmap {
+    fn new(ui: &UI) -> CrowdHeatmap {
+        let map = &ui.primary.map;
+        let (per_sidewalk, waiting) = ui.primary.sim.get_pedestrian_crowds(map);
+        CrowdHeatmap {
+            density_per_sidewalk: per_sidewalk
+                .into_iter()
+                .map(|(l, count)| {
+                    let area = map.get_l(l).length().inner_meters() * LANE_THICKNESS.inner_meters();
+                    (l, (count as f64) / area)
+                })
+                .collect(),
+            max_waiting: waiting.values().max().cloned().unwrap_or(0),
+            waiting_per_intersection: waiting.into_iter().collect(),
+        }
+    }
+
+    fn draw(&self, g: &mut GfxCtx, ui: &UI) {
+        for (l, density) in &self.density_per_sidewalk {
+            // People start slowing each other down well before the sidewalk is packed.
+            let color = if *density <= 0.3 {
+                Color::rgb(255, 255, 0)
+            } else if *density <= 1.0 {
+                Color::rgb(255, 128, 0)
+            } else {
+                Color::RED
+            };
+            g.draw_polygon(
+                color,
+                &ui.primary
+                    .map
+                    .get_l(*l)
+                    .lane_center_pts
+                    .make_polygons(LANE_THICKNESS),
+            );
+        }
+        for (i, count) in &self.waiting_per_intersection {
+            let percent = (*count as f32) / (self.max_waiting as f32);
+            g.draw_polygon(
+                Color::RED.alpha(0.2 + percent * 0.6),
+                &ui.primary.map.get_i(*i).polygon,
+            );
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{trim_f64, Duration, Speed};
+use serde_derive::{Deserialize, Serialize};
+use std::{f64, fmt, ops};
+
+// In meters per second squared. Can be negative.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub struct Acceleration(f64);
+
+impl Acceleration {
+    pub const ZERO: Acceleration = Acceleration::const_meters_per_second_squared(0.0);
+
+    pub fn meters_per_second_squared(value: f64) -> Acceleration {
+        if !value.is_finite() {
+            panic!("Bad Acceleration {}", value);
+        }
+
+        Acceleration(trim_f64(value))
+    }
+
+    pub const fn const_meters_per_second_squared(value: f64) -> Acceleration {
+        Acceleration(value)
+    }
+
+    // TODO Remove if possible.
+    pub fn inner_meters_per_second_squared(self) -> f64 {
+        self.0
+    }
+}
+
+impl ops::Mul<Duration> for Acceleration {
+    type Output = Speed;
+
+    fn mul(self, other: Duration) -> Speed {
+        Speed::meters_per_second(self.0 * other.inner_seconds())
+    }
+}
+
+impl ops::Div<Acceleration> for Speed {
+    type Output = Duration;
+
+    fn div(self, other: Acceleration) -> Duration {
+        if other.0 == 0.0 {
+            panic!("Can't divide {} / {}", self, other);
+        }
+        Duration::seconds(self.inner_meters_per_second() / other.0)
+    }
+}
+
+impl fmt::Display for Acceleration {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}m/s^2", self.0)
+    }
+}
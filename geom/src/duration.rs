@@ -219,3 +219,14 @@ impl ops::Div<Duration> for Duration {
         self.0 / other.0
     }
 }
+
+impl ops::Div<f64> for Duration {
+    type Output = Duration;
+
+    fn div(self, scalar: f64) -> Duration {
+        if scalar == 0.0 {
+            panic!("Can't divide {} / {}", self, scalar);
+        }
+        Duration::seconds(self.0 / scalar)
+    }
+}
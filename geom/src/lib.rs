@@ -1,3 +1,4 @@
+mod acceleration;
 mod angle;
 mod bounds;
 mod circle;
@@ -11,6 +12,7 @@ mod polyline;
 mod pt;
 mod speed;
 
+pub use crate::acceleration::Acceleration;
 pub use crate::angle::Angle;
 pub use crate::bounds::{Bounds, GPSBounds};
 pub use crate::circle::Circle;
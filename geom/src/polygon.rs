@@ -114,6 +114,20 @@ impl Polygon {
         Bounds::from(&self.points)
     }
 
+    // In square meters
+    pub fn area(&self) -> f64 {
+        self.triangles()
+            .into_iter()
+            .map(|tri| {
+                ((tri.pt1.x() * (tri.pt2.y() - tri.pt3.y())
+                    + tri.pt2.x() * (tri.pt3.y() - tri.pt1.y())
+                    + tri.pt3.x() * (tri.pt1.y() - tri.pt2.y()))
+                    / 2.0)
+                    .abs()
+            })
+            .sum()
+    }
+
     pub fn translate(&self, dx: Distance, dy: Distance) -> Polygon {
         Polygon {
             points: self.points.iter().map(|pt| pt.offset(dx, dy)).collect(),
@@ -1,16 +1,42 @@
 use abstutil::elapsed_seconds;
-use failure::Error;
-use geom::LonLat;
+use failure::{err_msg, Error};
+use geom::{Duration, LonLat};
 use itertools::Itertools;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::fs::File;
 use std::time::Instant;
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub struct Route {
+    // Routes with more than one stop pattern are split up; the less common patterns get a suffix.
     pub name: String,
     pub stops: Vec<LonLat>,
+    // Every trip serving this route, in order of departure. They all visit the same stops.
+    pub trips: Vec<Trip>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct Trip {
+    // One per stop of the route
+    pub stop_times: Vec<StopTime>,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+pub struct StopTime {
+    // Since midnight of the service day; can go past 24 hours. Agencies often only list times at
+    // timepoints.
+    pub arrival: Option<Duration>,
+    pub departure: Option<Duration>,
+    // Buses aren't supposed to leave a timepoint early.
+    pub timepoint: bool,
+}
+
+impl Trip {
+    pub fn first_departure(&self) -> Option<Duration> {
+        let first = &self.stop_times[0];
+        first.departure.or(first.arrival)
+    }
 }
 
 pub fn load(dir_path: &str) -> Result<Vec<Route>, Error> {
@@ -37,30 +63,95 @@ pub fn load(dir_path: &str) -> Result<Vec<Route>, Error> {
         trip_id_to_route_id.insert(rec[2].to_string(), rec[0].to_string());
     }
 
-    // Each route has many trips. Ignore all but the first and assume the list of stops is the
-    // same. Also assume that records with the same trip are contiguous and that stop_sequence is
-    // monotonic.
-    let mut route_ids_used: HashSet<String> = HashSet::new();
+    // Each route has many trips. Short-turns and express variants visit different stops, so group
+    // trips by their list of stops. Assume that records with the same trip are contiguous and that
+    // stop_sequence is monotonic.
+    let mut patterns_per_route: HashMap<String, Vec<usize>> = HashMap::new();
     let mut results: Vec<Route> = Vec::new();
 
     let mut reader = csv::Reader::from_reader(File::open(format!("{}/stop_times.txt", dir_path))?);
+    // This column is optional. When it's missing, every stop with a time is exact.
+    let timepoint_col = reader.headers()?.iter().position(|h| h == "timepoint");
     for (key, group) in reader
         .records()
         .group_by(|rec| rec.as_ref().unwrap()[0].to_string())
         .into_iter()
     {
         let route_id = trip_id_to_route_id[&key].to_string();
-        if route_ids_used.contains(&route_id) {
-            continue;
+        let mut stops: Vec<LonLat> = Vec::new();
+        let mut stop_times: Vec<StopTime> = Vec::new();
+        for rec in group {
+            let rec = rec?;
+            stops.push(stop_id_to_pt[&rec[3]]);
+            let arrival = parse_time(&rec[1])?;
+            let departure = parse_time(&rec[2])?;
+            let exact = match timepoint_col {
+                Some(col) => &rec[col] != "0",
+                None => true,
+            };
+            let timepoint = exact && (arrival.is_some() || departure.is_some());
+            stop_times.push(StopTime {
+                arrival,
+                departure,
+                timepoint,
+            });
+        }
+        let trip = Trip { stop_times };
+
+        let patterns = patterns_per_route
+            .entry(route_id.clone())
+            .or_insert_with(Vec::new);
+        if let Some(idx) = patterns
+            .iter()
+            .cloned()
+            .find(|idx| results[*idx].stops == stops)
+        {
+            results[idx].trips.push(trip);
+        } else {
+            patterns.push(results.len());
+            results.push(Route {
+                name: route_id_to_name[&route_id].to_string(),
+                stops,
+                trips: vec![trip],
+            });
         }
-        route_ids_used.insert(route_id.clone());
+    }
 
-        results.push(Route {
-            name: route_id_to_name[&route_id].to_string(),
-            stops: group.map(|rec| stop_id_to_pt[&rec.unwrap()[3]]).collect(),
-        });
+    // The pattern with the most trips keeps the route's name.
+    let mut num_variants = 0;
+    for patterns in patterns_per_route.values_mut() {
+        patterns.sort_by_key(|idx| std::cmp::Reverse(results[*idx].trips.len()));
+        for (n, idx) in patterns.iter().enumerate().skip(1) {
+            results[*idx].name = format!("{} variant {}", results[*idx].name, n + 1);
+            num_variants += 1;
+        }
+    }
+
+    for route in results.iter_mut() {
+        route.trips.retain(|trip| trip.first_departure().is_some());
+        route
+            .trips
+            .sort_by_key(|trip| trip.first_departure().unwrap());
+    }
+    if num_variants > 0 {
+        println!(
+            "Split off {} variants of routes that visit different stops",
+            num_variants
+        );
     }
 
     println!("Loading GTFS took {}s", elapsed_seconds(timer));
     Ok(results)
 }
+
+// HH:MM:SS, or blank
+fn parse_time(value: &str) -> Result<Option<Duration>, Error> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    match Duration::parse(value) {
+        Some(t) => Ok(Some(t)),
+        None => Err(err_msg(format!("Bad GTFS time {}", value))),
+    }
+}
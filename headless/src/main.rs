@@ -1,7 +1,9 @@
 use abstutil::Timer;
 use geom::Duration;
-use sim::{GetDrawAgents, Scenario, SimFlags};
+use map_model::Map;
+use sim::{GetDrawAgents, Scenario, Sim, SimFlags};
 use std::path::Path;
+use std::time::Instant;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
@@ -25,10 +27,27 @@ struct Flags {
     /// Every 0.1s, pretend to draw everything to make sure there are no bugs.
     #[structopt(long = "paranoia")]
     paranoia: bool,
+
+    /// When done, write every finished trip to this path. CSV if it ends in .csv, otherwise JSON.
+    #[structopt(long = "export_trips")]
+    export_trips: Option<String>,
+
+    /// Before the real run, simulate the scenario this many times, each time routing cars using
+    /// the travel times observed so far. Only applies when generating a scenario.
+    #[structopt(long = "assignment_iterations", default_value = "0")]
+    assignment_iterations: usize,
+
+    /// Measure how fast the sim processes events, not counting setup. Can't be combined with
+    /// --paranoia or --save_at, which would skew the timing.
+    #[structopt(long = "benchmark")]
+    benchmark: bool,
 }
 
 fn main() {
     let flags = Flags::from_args();
+    if flags.benchmark && (flags.paranoia || flags.save_at.is_some()) {
+        panic!("--benchmark can't be combined with --paranoia or --save_at");
+    }
 
     let save_at = if let Some(ref time_str) = flags.save_at {
         if let Some(t) = Duration::parse(time_str) {
@@ -43,7 +62,7 @@ fn main() {
     // TODO not the ideal way to distinguish what thing we loaded
     let load = flags.sim_flags.load.clone();
     let mut timer = Timer::new("setup headless");
-    let (map, mut sim, mut rng) = flags.sim_flags.load(None, &mut timer);
+    let (mut map, mut sim, mut rng) = flags.sim_flags.load(None, &mut timer);
 
     if load.starts_with(Path::new("../data/raw_maps/"))
         || load.starts_with(Path::new("../data/maps/"))
@@ -53,6 +72,15 @@ fn main() {
         } else {
             Scenario::small_run(&map)
         };
+        for iter in 0..flags.assignment_iterations {
+            timer.start(&format!("traffic assignment round {}", iter + 1));
+            let mut assignment_sim = Sim::new(&map, format!("assignment_{}", iter + 1), None);
+            // Same demand every round
+            s.instantiate(&mut assignment_sim, &map, &mut rng.clone(), &mut timer);
+            assignment_sim.run_until_done(&map, |_, _| {}, None);
+            average_travel_times(&mut map, &assignment_sim, iter, &mut timer);
+            timer.stop(&format!("traffic assignment round {}", iter + 1));
+        }
         s.instantiate(&mut sim, &map, &mut rng, &mut timer);
     }
     timer.done();
@@ -67,6 +95,9 @@ fn main() {
     let enable_profiler = flags.enable_profiler;
     let paranoia = flags.paranoia;
     let timer = Timer::new("run sim until done");
+    let events_before = sim.num_events_processed();
+    let mut benchmark = sim.start_benchmark();
+    let started = Instant::now();
     sim.run_until_done(
         &map,
         move |sim, map| {
@@ -84,9 +115,43 @@ fn main() {
         },
         None,
     );
+    let elapsed = abstutil::elapsed_seconds(started);
     timer.done();
+    if flags.benchmark {
+        let events = sim.num_events_processed() - events_before;
+        println!(
+            "Processed {} events in {:.1}s ({} events/s)",
+            abstutil::prettyprint_usize(events),
+            elapsed,
+            abstutil::prettyprint_usize((events as f64 / elapsed) as usize)
+        );
+        println!("Sim speed: {}", sim.measure_speed(&mut benchmark, true));
+    }
     println!("{:?}", sim.get_score());
+    if let Some(ref path) = flags.export_trips {
+        if path.ends_with(".csv") {
+            sim.save_finished_trips_csv(path).unwrap();
+        } else {
+            sim.save_finished_trips_json(path).unwrap();
+        }
+        println!("Wrote finished trips to {}", path);
+    }
     if flags.enable_profiler && save_at.is_none() {
         cpuprofiler::PROFILER.lock().unwrap().stop().unwrap();
     }
 }
+
+// The method of successive averages. Each round moves the costs towards what the latest round
+// observed, by less and less, to converge on something near user equilibrium.
+fn average_travel_times(map: &mut Map, sim: &Sim, iter: usize, timer: &mut Timer) {
+    let weight = 1.0 / ((iter + 1) as f64);
+    let mut times = map.get_observed_travel_times().clone();
+    for (dr, observed) in sim.get_observed_travel_times() {
+        let blended = match times.get(&dr) {
+            Some(old) => *old + weight * (observed - *old),
+            None => observed,
+        };
+        times.insert(dr, blended);
+    }
+    map.set_observed_travel_times(times, timer);
+}
@@ -67,6 +67,16 @@ for poly in `ls data/polygons/`; do
 	fi
 done
 
+# A small piece of Montlake in both formats, to check the PBF reader against the XML one
+if [ ! -f data/input/pbf_test.osm.pbf ]; then
+	osmosis \
+		--read-xml enableDateParsing=no file=data/input/montlake.osm \
+		--bounding-box top=47.6420 left=-122.3100 bottom=47.6380 right=-122.3040 completeWays=yes \
+		--tee 2 \
+		--write-xml data/input/pbf_test.osm \
+		--write-pbf data/input/pbf_test.osm.pbf
+fi
+
 if [ ! -f data/shapes/blockface ]; then
 	# From http://data-seattlecitygis.opendata.arcgis.com/datasets/blockface
 	get_if_needed https://opendata.arcgis.com/datasets/a1458ad1abca41869b81f7c0db0cd777_0.kml data/input/blockface.kml;
@@ -74,6 +84,7 @@ if [ ! -f data/shapes/blockface ]; then
 	cd kml
 	time cargo run --release -- \
 		--input=../data/input/blockface.kml \
+		--city=../data/cities/seattle.json \
 		--output=../data/shapes/blockface
 	cd ..
 fi
@@ -102,14 +113,9 @@ for poly in `ls ../data/polygons/`; do
 	rm -rf ../data/neighborhoods/$name ../data/maps/${name}.abst;
 	RUST_BACKTRACE=1 cargo run --release -- \
 		--osm=../data/input/$name.osm \
-		--elevation=../data/input/N47W122.hgt \
-		--traffic_signals=../data/input/traffic_signals.kml \
-		--residential_buildings=../data/input/residential_buildings.kml \
-		--parking_shapes=../data/shapes/blockface \
-		--gtfs=../data/input/google_transit_2018_18_08 \
-		--neighborhoods=../data/input/neighborhoods.geojson \
+		--city=../data/cities/seattle.json \
 		--clip=../data/polygons/$name.poly \
 		--output=../data/raw_maps/$name.abst
 done
 
-# To run manually: cargo run -- --osm=../data/input/montlake.osm --elevation=../data/input/N47W122.hgt --traffic_signals=../data/input/traffic_signals.kml --residential_buildings=../data/input/residential_buildings.kml --parking_shapes=../data/shapes/blockface --gtfs=../data/input/google_transit_2018_18_08 --neighborhoods=../data/input/neighborhoods.geojson --clip=../data/polygons/montlake.poly --output=../data/raw_maps/montlake.abst --fast_dev
+# To run manually: cargo run -- --osm=../data/input/montlake.osm --city=../data/cities/seattle.json --clip=../data/polygons/montlake.poly --output=../data/raw_maps/montlake.abst --fast_dev
@@ -1,4 +1,4 @@
-use geom::GPSBounds;
+use city_config::CityConfig;
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -8,6 +8,10 @@ struct Flags {
     #[structopt(long = "input")]
     pub input: String,
 
+    /// City config JSON, for the bounds
+    #[structopt(long = "city")]
+    pub city: String,
+
     /// Output (serialized ExtraShapes) to write
     #[structopt(long = "output")]
     pub output: String,
@@ -15,10 +19,11 @@ struct Flags {
 
 fn main() {
     let flags = Flags::from_args();
+    let city = CityConfig::load(&flags.city).unwrap();
 
     let shapes = kml::load(
         &flags.input,
-        &GPSBounds::seattle_bounds(),
+        &city.gps_bounds(),
         &mut abstutil::Timer::new("extracting shapes from KML"),
     )
     .unwrap();
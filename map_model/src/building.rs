@@ -23,6 +23,14 @@ pub struct FrontPath {
     pub line: Line,
 }
 
+// A garage or lot, entered from a driving lane
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OffstreetParking {
+    pub num_stalls: usize,
+    // Cars enter and exit here
+    pub driving_pos: Position,
+}
+
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum BuildingType {
     Residence,
@@ -40,6 +48,7 @@ pub struct Building {
     pub num_residential_units: Option<usize>,
 
     pub front_path: FrontPath,
+    pub parking: Option<OffstreetParking>,
 }
 
 impl Building {
@@ -51,6 +60,17 @@ impl Building {
         self.front_path.sidewalk.lane()
     }
 
+    // Based on OSM tags. Doesn't mean there's a driving lane nearby to actually use the stalls.
+    pub fn osm_num_parking_stalls(&self) -> Option<usize> {
+        if self.osm_tags.get("building") == Some(&"parking".to_string())
+            || self.osm_tags.get("amenity") == Some(&"parking".to_string())
+        {
+            Some(guess_num_parking_stalls(&self.osm_tags, &self.polygon))
+        } else {
+            None
+        }
+    }
+
     pub fn get_name(&self) -> String {
         self.osm_tags
             .get("name")
@@ -65,3 +85,20 @@ impl Building {
             .unwrap_or_else(|| "???".to_string())
     }
 }
+
+// For garages and lots. Uses the capacity tag if it's there.
+pub(crate) fn guess_num_parking_stalls(
+    tags: &BTreeMap<String, String>,
+    polygon: &Polygon,
+) -> usize {
+    if let Some(n) = tags.get("capacity").and_then(|c| c.parse::<usize>().ok()) {
+        return n;
+    }
+    // Guess about 30 square meters per stall, including aisles.
+    let levels = tags
+        .get("building:levels")
+        .and_then(|l| l.parse::<usize>().ok())
+        .unwrap_or(1)
+        .max(1);
+    levels * (polygon.area() / 30.0) as usize
+}
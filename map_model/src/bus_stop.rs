@@ -1,5 +1,6 @@
 use crate::{LaneID, Position};
 use abstutil;
+use geom::Duration;
 use serde_derive::{Deserialize, Serialize};
 use std::fmt;
 
@@ -44,4 +45,16 @@ pub struct BusRoute {
     pub id: BusRouteID,
     pub name: String,
     pub stops: Vec<BusStopID>,
+    // In order of departure. If this is empty, buses just loop around the route forever.
+    pub schedule: Vec<ScheduledRun>,
+}
+
+// One scheduled trip of a bus from the first stop of a route to the last.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ScheduledRun {
+    // When the bus should leave each stop of the route. Interpolated between timepoints if the
+    // schedule doesn't say.
+    pub departures: Vec<Duration>,
+    // Buses hold at timepoints if they're early.
+    pub timepoints: Vec<bool>,
 }
@@ -0,0 +1,377 @@
+mod perma;
+
+use crate::edits::perma::PermanentMapEdits;
+use crate::raw_data::RestrictionType;
+use crate::{
+    BuildingID, ControlStopSign, ControlTrafficSignal, IntersectionID, IntersectionType, LaneID,
+    LaneType, Map, RoadID,
+};
+use abstutil::{deserialize_btreemap, serialize_btreemap, Timer};
+use geom::Speed;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MapEdits {
+    pub(crate) map_name: String,
+    pub edits_name: String,
+    pub lane_overrides: BTreeMap<LaneID, LaneType>,
+    // Lanes pointing the opposite way from OSM. Only the lanes closest to the middle of the road
+    // get reversed, so nothing has to move. Reversing all of one side's lanes (besides the
+    // sidewalk) makes a road one-way, or flips a one-way road.
+    // TODO Adding and removing lanes isn't supported yet. LaneIDs index into the map, and the
+    // pathfinders, simulation and rendering all hold onto them, so that needs its own change.
+    #[serde(default)]
+    pub reversed_lanes: BTreeSet<LaneID>,
+    #[serde(default)]
+    pub speed_limit_overrides: BTreeMap<RoadID, Speed>,
+    // Driving lanes on these roads can't be used. Bike and bus lanes and sidewalks still can.
+    #[serde(default)]
+    pub closed_roads: BTreeSet<RoadID>,
+    // Only between stop signs and traffic signals. Only contains intersections that differ from
+    // OSM, so the original type is always the other one.
+    #[serde(default)]
+    pub intersection_type_overrides: BTreeMap<IntersectionID, IntersectionType>,
+    // TODO Storing the entire thing is maybe a bit dramatic, but works for now.
+    pub stop_sign_overrides: BTreeMap<IntersectionID, ControlStopSign>,
+    pub traffic_signal_overrides: BTreeMap<IntersectionID, ControlTrafficSignal>,
+    // Number of off-street parking stalls. 0 removes a garage.
+    #[serde(default)]
+    pub offstreet_parking_overrides: BTreeMap<BuildingID, usize>,
+    // Replaces whatever OSM says about turning from the first road onto the second, including
+    // restrictions via another road. None lifts all restrictions.
+    #[serde(
+        default,
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub turn_restriction_overrides: BTreeMap<(RoadID, RoadID), Option<RestrictionType>>,
+
+    // Every command executed so far, paired with the command that undoes it. Changing the
+    // overrides above directly bypasses this. Edits saved before there was any history don't
+    // have these.
+    #[serde(default)]
+    history: Vec<(EditCmd, EditCmd)>,
+    // Undone commands, most recent last
+    #[serde(default)]
+    redo_stack: Vec<EditCmd>,
+}
+
+// Each command sets or clears one override. None means to use whatever OSM says.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum EditCmd {
+    LaneType(LaneID, Option<LaneType>),
+    ReverseLane(LaneID, bool),
+    SpeedLimit(RoadID, Option<Speed>),
+    CloseRoad(RoadID, bool),
+    IntersectionType(IntersectionID, Option<IntersectionType>),
+    StopSign(IntersectionID, Option<ControlStopSign>),
+    TrafficSignal(IntersectionID, Option<ControlTrafficSignal>),
+    OffstreetParking(BuildingID, Option<usize>),
+    TurnRestriction(RoadID, RoadID, Option<RestrictionType>),
+    RevertTurnRestriction(RoadID, RoadID),
+    // Undone and redone together
+    Many(Vec<EditCmd>),
+}
+
+impl EditCmd {
+    // Returns the command that undoes this one.
+    fn apply(&self, edits: &mut MapEdits) -> EditCmd {
+        match self {
+            EditCmd::LaneType(id, value) => {
+                EditCmd::LaneType(*id, set(&mut edits.lane_overrides, *id, *value))
+            }
+            EditCmd::ReverseLane(id, value) => {
+                let old = if *value {
+                    !edits.reversed_lanes.insert(*id)
+                } else {
+                    edits.reversed_lanes.remove(id)
+                };
+                EditCmd::ReverseLane(*id, old)
+            }
+            EditCmd::SpeedLimit(id, value) => {
+                EditCmd::SpeedLimit(*id, set(&mut edits.speed_limit_overrides, *id, *value))
+            }
+            EditCmd::CloseRoad(id, value) => {
+                let old = if *value {
+                    !edits.closed_roads.insert(*id)
+                } else {
+                    edits.closed_roads.remove(id)
+                };
+                EditCmd::CloseRoad(*id, old)
+            }
+            EditCmd::IntersectionType(id, value) => EditCmd::IntersectionType(
+                *id,
+                set(&mut edits.intersection_type_overrides, *id, *value),
+            ),
+            EditCmd::StopSign(id, value) => {
+                EditCmd::StopSign(*id, set(&mut edits.stop_sign_overrides, *id, value.clone()))
+            }
+            EditCmd::TrafficSignal(id, value) => EditCmd::TrafficSignal(
+                *id,
+                set(&mut edits.traffic_signal_overrides, *id, value.clone()),
+            ),
+            EditCmd::OffstreetParking(id, value) => EditCmd::OffstreetParking(
+                *id,
+                set(&mut edits.offstreet_parking_overrides, *id, *value),
+            ),
+            EditCmd::TurnRestriction(from, to, value) => turn_restriction_undo(
+                edits
+                    .turn_restriction_overrides
+                    .insert((*from, *to), *value),
+                *from,
+                *to,
+            ),
+            EditCmd::RevertTurnRestriction(from, to) => turn_restriction_undo(
+                edits.turn_restriction_overrides.remove(&(*from, *to)),
+                *from,
+                *to,
+            ),
+            EditCmd::Many(cmds) => {
+                let mut undo: Vec<EditCmd> = cmds.iter().map(|cmd| cmd.apply(edits)).collect();
+                undo.reverse();
+                EditCmd::Many(undo)
+            }
+        }
+    }
+}
+
+fn turn_restriction_undo(
+    old: Option<Option<RestrictionType>>,
+    from: RoadID,
+    to: RoadID,
+) -> EditCmd {
+    match old {
+        Some(rt) => EditCmd::TurnRestriction(from, to, rt),
+        None => EditCmd::RevertTurnRestriction(from, to),
+    }
+}
+
+// Returns the old value.
+fn set<K: Ord, V>(map: &mut BTreeMap<K, V>, key: K, value: Option<V>) -> Option<V> {
+    match value {
+        Some(v) => map.insert(key, v),
+        None => map.remove(&key),
+    }
+}
+
+impl MapEdits {
+    pub fn new(map_name: String) -> MapEdits {
+        MapEdits {
+            map_name,
+            // Something has to fill this out later
+            edits_name: "no_edits".to_string(),
+            lane_overrides: BTreeMap::new(),
+            reversed_lanes: BTreeSet::new(),
+            speed_limit_overrides: BTreeMap::new(),
+            closed_roads: BTreeSet::new(),
+            intersection_type_overrides: BTreeMap::new(),
+            stop_sign_overrides: BTreeMap::new(),
+            traffic_signal_overrides: BTreeMap::new(),
+            offstreet_parking_overrides: BTreeMap::new(),
+            turn_restriction_overrides: BTreeMap::new(),
+            history: Vec::new(),
+            redo_stack: Vec::new(),
+        }
+    }
+
+    pub fn execute(&mut self, cmd: EditCmd) {
+        let undo = cmd.apply(self);
+        self.history.push((cmd, undo));
+        self.redo_stack.clear();
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_stack.is_empty()
+    }
+
+    pub fn undo(&mut self) {
+        if let Some((cmd, undo)) = self.history.pop() {
+            undo.apply(self);
+            self.redo_stack.push(cmd);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(cmd) = self.redo_stack.pop() {
+            let undo = cmd.apply(self);
+            self.history.push((cmd, undo));
+        }
+    }
+
+    // Describes what changed from these edits to the other ones. IDs only mean something for the
+    // same map, so edits for another map are just called out.
+    pub fn diff(&self, other: &MapEdits) -> Vec<String> {
+        if self.map_name != other.map_name {
+            return vec![format!(
+                "{} is for {}, not {}",
+                other.edits_name, other.map_name, self.map_name
+            )];
+        }
+        let mut lines = Vec::new();
+        diff_maps(
+            &self.lane_overrides,
+            &other.lane_overrides,
+            |l| format!("{}", l),
+            |lt| format!("{:?}", lt),
+            &mut lines,
+        );
+        for l in self.reversed_lanes.difference(&other.reversed_lanes) {
+            lines.push(format!("{} isn't reversed anymore", l));
+        }
+        for l in other.reversed_lanes.difference(&self.reversed_lanes) {
+            lines.push(format!("{} is reversed", l));
+        }
+        diff_maps(
+            &self.speed_limit_overrides,
+            &other.speed_limit_overrides,
+            |r| format!("Speed limit of {}", r),
+            |speed| format!("{}", speed),
+            &mut lines,
+        );
+        for r in self.closed_roads.difference(&other.closed_roads) {
+            lines.push(format!("{} is reopened to cars", r));
+        }
+        for r in other.closed_roads.difference(&self.closed_roads) {
+            lines.push(format!("{} is closed to cars", r));
+        }
+        diff_maps(
+            &self.intersection_type_overrides,
+            &other.intersection_type_overrides,
+            |i| format!("{}", i),
+            |it| format!("{:?}", it),
+            &mut lines,
+        );
+        // These are too big to describe, so just say which ones changed.
+        diff_maps(
+            &self.stop_sign_overrides,
+            &other.stop_sign_overrides,
+            |i| format!("Stop sign at {}", i),
+            |_| "edited".to_string(),
+            &mut lines,
+        );
+        diff_maps(
+            &self.traffic_signal_overrides,
+            &other.traffic_signal_overrides,
+            |i| format!("Traffic signal at {}", i),
+            |_| "edited".to_string(),
+            &mut lines,
+        );
+        diff_maps(
+            &self.offstreet_parking_overrides,
+            &other.offstreet_parking_overrides,
+            |b| format!("Off-street parking at {}", b),
+            |n| format!("{} stalls", n),
+            &mut lines,
+        );
+        diff_maps(
+            &self.turn_restriction_overrides,
+            &other.turn_restriction_overrides,
+            |(from, to)| format!("Turn restriction from {} to {}", from, to),
+            |rt| format!("{:?}", rt),
+            &mut lines,
+        );
+        lines
+    }
+
+    // The edits must be the ones currently applied to the map.
+    pub fn save(&self, map: &Map) {
+        abstutil::save_object(
+            "edits",
+            &self.map_name,
+            &self.edits_name,
+            &PermanentMapEdits::new(self, map),
+        );
+    }
+
+    // Matches saved edits up with this version of the map. Anything that doesn't match is skipped
+    // with a warning.
+    pub fn load(map: &Map, edits_name: &str, timer: &mut Timer) -> Result<MapEdits, String> {
+        if edits_name == "no_edits" {
+            return Ok(MapEdits::new(map.get_name().to_string()));
+        }
+        let path = format!("../data/edits/{}/{}.json", map.get_name(), edits_name);
+        let err = match abstutil::read_json::<PermanentMapEdits>(&path) {
+            Ok(edits) => {
+                return Ok(edits.resolve(map, timer));
+            }
+            Err(err) => err,
+        };
+        // Edits saved before they referred to OSM use IDs directly, which only match the version
+        // of the map they were made with. Use them if they plausibly still do.
+        match abstutil::read_json::<MapEdits>(&path) {
+            Ok(edits) if edits.map_name == map.get_name() && edits.ids_exist(map) => {
+                timer.warn(format!(
+                    "{} uses old map IDs; save it again to keep it working when the map changes",
+                    path
+                ));
+                Ok(edits)
+            }
+            _ => Err(err.to_string()),
+        }
+    }
+
+    fn ids_exist(&self, map: &Map) -> bool {
+        let lane = |l: &LaneID| l.0 < map.all_lanes().len();
+        let road = |r: &RoadID| r.0 < map.all_roads().len();
+        let intersection = |i: &IntersectionID| i.0 < map.all_intersections().len();
+        self.lane_overrides.keys().all(lane)
+            && self.reversed_lanes.iter().all(lane)
+            && self.speed_limit_overrides.keys().all(road)
+            && self.closed_roads.iter().all(road)
+            && self.intersection_type_overrides.keys().all(intersection)
+            && self.stop_sign_overrides.keys().all(intersection)
+            && self.traffic_signal_overrides.keys().all(intersection)
+            && self
+                .offstreet_parking_overrides
+                .keys()
+                .all(|b| b.0 < map.all_buildings().len())
+            && self
+                .turn_restriction_overrides
+                .keys()
+                .all(|(from, to)| road(from) && road(to))
+    }
+}
+
+fn diff_maps<K: Ord, V: PartialEq, F1: Fn(&K) -> String, F2: Fn(&V) -> String>(
+    before: &BTreeMap<K, V>,
+    after: &BTreeMap<K, V>,
+    describe_key: F1,
+    describe_value: F2,
+    lines: &mut Vec<String>,
+) {
+    for (key, old) in before {
+        match after.get(key) {
+            Some(new) => {
+                if old != new {
+                    lines.push(format!(
+                        "{} changed from {} to {}",
+                        describe_key(key),
+                        describe_value(old),
+                        describe_value(new)
+                    ));
+                }
+            }
+            None => {
+                lines.push(format!(
+                    "{} reverted to the original (was {})",
+                    describe_key(key),
+                    describe_value(old)
+                ));
+            }
+        }
+    }
+    for (key, new) in after {
+        if !before.contains_key(key) {
+            lines.push(format!(
+                "{} is now {}",
+                describe_key(key),
+                describe_value(new)
+            ));
+        }
+    }
+}
@@ -0,0 +1,334 @@
+use crate::raw_data::{OriginalIntersection, OriginalLane, OriginalRoad, RestrictionType};
+use crate::{
+    ControlStopSign, ControlTrafficSignal, Cycle, IntersectionID, IntersectionType, LaneType, Map,
+    MapEdits, RoadWithStopSign, TurnID, TurnPriority,
+};
+use abstutil::Timer;
+use geom::{Duration, Speed};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// MapEdits as they're saved to disk. Everything refers to what it came from in OSM instead of the
+// IDs, which change every time the map is imported. The undo history isn't kept.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PermanentMapEdits {
+    map_name: String,
+    edits_name: String,
+    lane_overrides: Vec<(OriginalLane, LaneType)>,
+    reversed_lanes: Vec<OriginalLane>,
+    speed_limit_overrides: Vec<(OriginalRoad, Speed)>,
+    closed_roads: Vec<OriginalRoad>,
+    intersection_type_overrides: Vec<(OriginalIntersection, IntersectionType)>,
+    stop_sign_overrides: Vec<PermanentStopSign>,
+    traffic_signal_overrides: Vec<PermanentTrafficSignal>,
+    // Buildings are identified by their OSM way. Edits saved before garages existed don't have
+    // this.
+    #[serde(default)]
+    offstreet_parking_overrides: Vec<(i64, usize)>,
+    turn_restriction_overrides: Vec<(OriginalRoad, OriginalRoad, Option<RestrictionType>)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PermanentStopSign {
+    id: OriginalIntersection,
+    turns: Vec<(OriginalTurn, TurnPriority)>,
+    roads: Vec<(OriginalRoad, Vec<OriginalLane>, bool)>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PermanentTrafficSignal {
+    id: OriginalIntersection,
+    cycles: Vec<PermanentCycle>,
+    offset: Duration,
+    actuated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct PermanentCycle {
+    priority_turns: Vec<OriginalTurn>,
+    yield_turns: Vec<OriginalTurn>,
+    duration: Duration,
+    max_extension: Duration,
+}
+
+// Source and destination lane. The intersection is whatever the turn is being used in.
+type OriginalTurn = (OriginalLane, OriginalLane);
+
+impl PermanentMapEdits {
+    // The edits must be the ones currently applied to the map.
+    pub fn new(edits: &MapEdits, map: &Map) -> PermanentMapEdits {
+        PermanentMapEdits {
+            map_name: edits.map_name.clone(),
+            edits_name: edits.edits_name.clone(),
+            lane_overrides: edits
+                .lane_overrides
+                .iter()
+                .map(|(l, lt)| (map.original_lane(*l), *lt))
+                .collect(),
+            reversed_lanes: edits
+                .reversed_lanes
+                .iter()
+                .map(|l| map.original_lane(*l))
+                .collect(),
+            speed_limit_overrides: edits
+                .speed_limit_overrides
+                .iter()
+                .map(|(r, speed)| (map.original_road(*r), *speed))
+                .collect(),
+            closed_roads: edits
+                .closed_roads
+                .iter()
+                .map(|r| map.original_road(*r))
+                .collect(),
+            intersection_type_overrides: edits
+                .intersection_type_overrides
+                .iter()
+                .map(|(i, it)| (map.original_intersection(*i), *it))
+                .collect(),
+            stop_sign_overrides: edits
+                .stop_sign_overrides
+                .values()
+                .map(|ss| PermanentStopSign::new(ss, map))
+                .collect(),
+            traffic_signal_overrides: edits
+                .traffic_signal_overrides
+                .values()
+                .map(|ts| PermanentTrafficSignal::new(ts, map))
+                .collect(),
+            offstreet_parking_overrides: edits
+                .offstreet_parking_overrides
+                .iter()
+                .map(|(b, n)| (map.get_b(*b).osm_way_id, *n))
+                .collect(),
+            turn_restriction_overrides: edits
+                .turn_restriction_overrides
+                .iter()
+                .map(|((from, to), rt)| (map.original_road(*from), map.original_road(*to), *rt))
+                .collect(),
+        }
+    }
+
+    // Anything that can't be found in this version of the map is skipped with a warning.
+    pub fn resolve(self, map: &Map, timer: &mut Timer) -> MapEdits {
+        let mut edits = MapEdits::new(self.map_name);
+        edits.edits_name = self.edits_name;
+        let mut missing: Vec<String> = Vec::new();
+
+        for (orig, lt) in self.lane_overrides {
+            match map.find_original_lane(orig) {
+                Some(l) => {
+                    edits.lane_overrides.insert(l, lt);
+                }
+                None => missing.push(format!("lane type override for {:?}", orig)),
+            }
+        }
+        for orig in self.reversed_lanes {
+            match map.find_original_lane(orig) {
+                Some(l) => {
+                    edits.reversed_lanes.insert(l);
+                }
+                None => missing.push(format!("reversed lane {:?}", orig)),
+            }
+        }
+        for (orig, speed) in self.speed_limit_overrides {
+            match map.find_original_road(orig) {
+                Some(r) => {
+                    edits.speed_limit_overrides.insert(r, speed);
+                }
+                None => missing.push(format!("speed limit override for {:?}", orig)),
+            }
+        }
+        for orig in self.closed_roads {
+            match map.find_original_road(orig) {
+                Some(r) => {
+                    edits.closed_roads.insert(r);
+                }
+                None => missing.push(format!("closed road {:?}", orig)),
+            }
+        }
+        for (orig, it) in self.intersection_type_overrides {
+            match map.find_original_intersection(orig) {
+                Some(i) => {
+                    edits.intersection_type_overrides.insert(i, it);
+                }
+                None => missing.push(format!("intersection type override for {:?}", orig)),
+            }
+        }
+        for ss in self.stop_sign_overrides {
+            match ss.resolve(map) {
+                Some(resolved) => {
+                    edits.stop_sign_overrides.insert(resolved.id, resolved);
+                }
+                None => missing.push(format!("stop sign at {:?}", ss.id)),
+            }
+        }
+        for ts in self.traffic_signal_overrides {
+            match ts.resolve(map) {
+                Some(resolved) => {
+                    edits.traffic_signal_overrides.insert(resolved.id, resolved);
+                }
+                None => missing.push(format!("traffic signal at {:?}", ts.id)),
+            }
+        }
+        for (osm_way_id, n) in self.offstreet_parking_overrides {
+            match map.find_original_building(osm_way_id) {
+                Some(b) => {
+                    edits.offstreet_parking_overrides.insert(b, n);
+                }
+                None => missing.push(format!(
+                    "off-street parking for building with OSM way {}",
+                    osm_way_id
+                )),
+            }
+        }
+        for (orig_from, orig_to, rt) in self.turn_restriction_overrides {
+            match (
+                map.find_original_road(orig_from),
+                map.find_original_road(orig_to),
+            ) {
+                (Some(from), Some(to)) => {
+                    edits.turn_restriction_overrides.insert((from, to), rt);
+                }
+                _ => missing.push(format!(
+                    "turn restriction from {:?} to {:?}",
+                    orig_from, orig_to
+                )),
+            }
+        }
+
+        for thing in missing {
+            timer.warn(format!(
+                "Skipping {} from edits {}; it doesn't match anything in {}",
+                thing,
+                edits.edits_name,
+                map.get_name()
+            ));
+        }
+        edits
+    }
+}
+
+impl PermanentStopSign {
+    fn new(ss: &ControlStopSign, map: &Map) -> PermanentStopSign {
+        PermanentStopSign {
+            id: map.original_intersection(ss.id),
+            turns: ss
+                .turns
+                .iter()
+                .map(|(t, pri)| (original_turn(*t, map), *pri))
+                .collect(),
+            roads: ss
+                .roads
+                .iter()
+                .map(|(r, road)| {
+                    (
+                        map.original_road(*r),
+                        road.travel_lanes
+                            .iter()
+                            .map(|l| map.original_lane(*l))
+                            .collect(),
+                        road.enabled,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    fn resolve(&self, map: &Map) -> Option<ControlStopSign> {
+        let id = map.find_original_intersection(self.id)?;
+        let mut turns = BTreeMap::new();
+        for (t, pri) in &self.turns {
+            turns.insert(find_turn(id, *t, map)?, *pri);
+        }
+        let mut roads = BTreeMap::new();
+        for (r, travel_lanes, enabled) in &self.roads {
+            let travel_lanes = travel_lanes
+                .iter()
+                .map(|l| map.find_original_lane(*l))
+                .collect::<Option<Vec<_>>>()?;
+            roads.insert(
+                map.find_original_road(*r)?,
+                RoadWithStopSign {
+                    travel_lanes,
+                    enabled: *enabled,
+                },
+            );
+        }
+        let ss = ControlStopSign { id, turns, roads };
+        // The intersection might have different turns now.
+        if ss.turns.len() != map.get_i(id).turns.len() {
+            return None;
+        }
+        ss.validate(map).ok()?;
+        Some(ss)
+    }
+}
+
+impl PermanentTrafficSignal {
+    fn new(ts: &ControlTrafficSignal, map: &Map) -> PermanentTrafficSignal {
+        PermanentTrafficSignal {
+            id: map.original_intersection(ts.id),
+            cycles: ts
+                .cycles
+                .iter()
+                .map(|cycle| PermanentCycle {
+                    priority_turns: cycle
+                        .priority_turns
+                        .iter()
+                        .map(|t| original_turn(*t, map))
+                        .collect(),
+                    yield_turns: cycle
+                        .yield_turns
+                        .iter()
+                        .map(|t| original_turn(*t, map))
+                        .collect(),
+                    duration: cycle.duration,
+                    max_extension: cycle.max_extension,
+                })
+                .collect(),
+            offset: ts.offset,
+            actuated: ts.actuated,
+        }
+    }
+
+    fn resolve(&self, map: &Map) -> Option<ControlTrafficSignal> {
+        let id = map.find_original_intersection(self.id)?;
+        let mut cycles = Vec::new();
+        for (idx, orig) in self.cycles.iter().enumerate() {
+            let mut cycle = Cycle::new(id, idx);
+            for t in &orig.priority_turns {
+                cycle.priority_turns.insert(find_turn(id, *t, map)?);
+            }
+            for t in &orig.yield_turns {
+                cycle.yield_turns.insert(find_turn(id, *t, map)?);
+            }
+            cycle.duration = orig.duration;
+            cycle.max_extension = orig.max_extension;
+            cycles.push(cycle);
+        }
+        let ts = ControlTrafficSignal {
+            id,
+            cycles,
+            offset: self.offset,
+            actuated: self.actuated,
+        };
+        // This also checks that the signal covers exactly the intersection's turns.
+        ts.validate(map).ok()?;
+        Some(ts)
+    }
+}
+
+fn original_turn(t: TurnID, map: &Map) -> OriginalTurn {
+    (map.original_lane(t.src), map.original_lane(t.dst))
+}
+
+// The lanes might still exist, but not connect here anymore.
+fn find_turn(parent: IntersectionID, t: OriginalTurn, map: &Map) -> Option<TurnID> {
+    let id = TurnID {
+        parent,
+        src: map.find_original_lane(t.0)?,
+        dst: map.find_original_lane(t.1)?,
+    };
+    map.maybe_get_t(id)?;
+    Some(id)
+}
@@ -1,8 +1,8 @@
 use crate::{raw_data, LaneID, LaneType, Map, Road, RoadID, TurnID};
-use abstutil;
-use geom::{Distance, Polygon, Pt2D};
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, LonLat, Polygon, Pt2D};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 
 // TODO reconsider pub usize. maybe outside world shouldnt know.
@@ -28,6 +28,8 @@ pub struct Intersection {
     // This is the original OSM point where center lines met, in a smoky alley... ehem, it doesn't
     // represent the "center" of the intersection geometrically in many cases.
     pub point: Pt2D,
+    // The same point, for matching the intersection up with other versions of the map.
+    pub gps_point: LonLat,
     // This needs to be in clockwise orientation, or later rendering of sidewalk corners breaks.
     pub polygon: Polygon,
     pub turns: Vec<TurnID>,
@@ -43,6 +45,12 @@ pub struct Intersection {
     pub outgoing_lanes: Vec<LaneID>,
 
     pub roads: BTreeSet<RoadID>,
+    // From OSM. Only used to set up the initial stop sign.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub approach_signs: BTreeMap<RoadID, raw_data::ApproachSign>,
 }
 
 impl Intersection {
@@ -46,6 +46,9 @@ pub struct Lane {
 
     pub src_i: IntersectionID,
     pub dst_i: IntersectionID,
+    // (Distance along the lane, elevation), sorted by distance. Empty if there's no elevation
+    // data.
+    pub elevation_profile: Vec<(Distance, Distance)>,
 
     // Sorted by distance of the front path
     pub building_paths: Vec<BuildingID>,
@@ -106,6 +109,36 @@ impl Lane {
         self.lane_center_pts.length()
     }
 
+    // Interpolates between the nearest two samples.
+    pub fn elevation_at(&self, dist_along: Distance) -> Distance {
+        let profile = &self.elevation_profile;
+        if profile.is_empty() {
+            return Distance::ZERO;
+        }
+        if dist_along <= profile[0].0 {
+            return profile[0].1;
+        }
+        for pair in profile.windows(2) {
+            let ((d1, e1), (d2, e2)) = (pair[0], pair[1]);
+            if dist_along <= d2 {
+                if d1 == d2 {
+                    return e2;
+                }
+                return e1 + (e2 - e1) * ((dist_along - d1) / (d2 - d1));
+            }
+        }
+        profile.last().unwrap().1
+    }
+
+    // Rise over run going from one distance to the other, so positive means uphill. Works in
+    // either direction along the lane.
+    pub fn grade(&self, from: Distance, to: Distance) -> f64 {
+        if from == to {
+            return 0.0;
+        }
+        (self.elevation_at(to) - self.elevation_at(from)) / (to - from).abs()
+    }
+
     pub fn dump_debug(&self) {
         println!(
             "\nlet lane_center_l{}_pts = {}",
@@ -7,6 +7,7 @@ mod lane;
 mod make;
 mod map;
 mod neighborhood;
+mod parking_lot;
 mod pathfind;
 pub mod raw_data;
 mod road;
@@ -16,15 +17,20 @@ mod traversable;
 mod turn;
 
 pub use crate::area::{Area, AreaID, AreaType};
-pub use crate::building::{Building, BuildingID, BuildingType, FrontPath};
-pub use crate::bus_stop::{BusRoute, BusRouteID, BusStop, BusStopID};
-pub use crate::edits::MapEdits;
+pub use crate::building::{Building, BuildingID, BuildingType, FrontPath, OffstreetParking};
+pub use crate::bus_stop::{BusRoute, BusRouteID, BusStop, BusStopID, ScheduledRun};
+pub use crate::edits::{EditCmd, MapEdits};
 pub use crate::intersection::{Intersection, IntersectionID, IntersectionType};
 pub use crate::lane::{Lane, LaneID, LaneType, PARKING_SPOT_LENGTH};
-pub use crate::make::RoadSpec;
+pub use crate::make::{
+    get_lane_types, get_speed_limits, get_turn_arrows, RoadSpec, SpeedLimitDefaults,
+};
 pub use crate::map::Map;
 pub use crate::neighborhood::{FullNeighborhoodInfo, Neighborhood, NeighborhoodBuilder};
-pub use crate::pathfind::{Path, PathRequest, PathStep};
+pub use crate::parking_lot::{ParkingLot, ParkingLotID};
+pub use crate::pathfind::{
+    Path, PathRequest, PathStep, TransitAccess, TransitPlan, TransitRequest,
+};
 pub use crate::road::{DirectedRoadID, Road, RoadID};
 pub use crate::stop_signs::{ControlStopSign, RoadWithStopSign};
 pub use crate::traffic_signals::{ControlTrafficSignal, Cycle};
@@ -40,3 +46,4 @@ impl Cloneable for IntersectionID {}
 impl Cloneable for MapEdits {}
 impl Cloneable for Neighborhood {}
 impl Cloneable for NeighborhoodBuilder {}
+impl Cloneable for RoadID {}
@@ -56,6 +56,8 @@ pub fn make_all_buildings(
                     line,
                 },
                 num_residential_units: input[idx].num_residential_units,
+                // Needs a full Map to find the driving lane; filled out later.
+                parking: None,
             });
         }
     }
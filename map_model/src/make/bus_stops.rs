@@ -1,9 +1,10 @@
 use crate::make::sidewalk_finder::find_sidewalk_points;
 use crate::{
     BusRoute, BusRouteID, BusStop, BusStopID, LaneID, LaneType, Map, PathRequest, Position,
+    ScheduledRun,
 };
 use abstutil::{MultiMap, Timer};
-use geom::{Bounds, Distance, GPSBounds, HashablePt2D, Pt2D};
+use geom::{Bounds, Distance, Duration, GPSBounds, HashablePt2D, Pt2D};
 use gtfs;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter;
@@ -17,13 +18,10 @@ pub fn make_bus_stops(
 ) -> (BTreeMap<BusStopID, BusStop>, Vec<BusRoute>) {
     timer.start("make bus stops");
     let mut bus_stop_pts: HashSet<HashablePt2D> = HashSet::new();
-    let mut route_lookups: MultiMap<String, HashablePt2D> = MultiMap::new();
     for route in bus_routes {
         for gps in &route.stops {
             if let Some(pt) = Pt2D::from_gps(*gps, gps_bounds) {
-                let hash_pt: HashablePt2D = pt.into();
-                bus_stop_pts.insert(hash_pt);
-                route_lookups.insert(route.name.to_string(), hash_pt);
+                bus_stop_pts.insert(pt.into());
             }
         }
     }
@@ -78,12 +76,18 @@ pub fn make_bus_stops(
     let mut routes: Vec<BusRoute> = Vec::new();
     for route in bus_routes {
         let route_name = route.name.to_string();
-        let stops: Vec<BusStopID> = route_lookups
-            .get(route_name.clone())
-            .iter()
-            .filter_map(|pt| point_to_stop_id.get(pt))
-            .cloned()
-            .collect();
+        // Keep track of which of the GTFS stops are in the slice of the map, to match up the
+        // schedule.
+        let mut gtfs_indices: Vec<usize> = Vec::new();
+        let mut stops: Vec<BusStopID> = Vec::new();
+        for (idx, gps) in route.stops.iter().enumerate() {
+            if let Some(stop) = Pt2D::from_gps(*gps, gps_bounds)
+                .and_then(|pt| point_to_stop_id.get(&HashablePt2D::from(pt)))
+            {
+                gtfs_indices.push(idx);
+                stops.push(*stop);
+            }
+        }
         if stops.len() < 2 {
             if !stops.is_empty() {
                 timer.warn(format!(
@@ -94,17 +98,69 @@ pub fn make_bus_stops(
             }
             continue;
         }
+        let schedule: Vec<ScheduledRun> = route
+            .trips
+            .iter()
+            .filter_map(|trip| {
+                let stop_times: Vec<&gtfs::StopTime> = gtfs_indices
+                    .iter()
+                    .map(|idx| &trip.stop_times[*idx])
+                    .collect();
+                let departures = interpolate_times(
+                    stop_times
+                        .iter()
+                        .map(|st| st.departure.or(st.arrival))
+                        .collect(),
+                )?;
+                Some(ScheduledRun {
+                    departures,
+                    timepoints: stop_times.iter().map(|st| st.timepoint).collect(),
+                })
+            })
+            .collect();
+        if schedule.len() != route.trips.len() {
+            timer.warn(format!(
+                "Route {} has {} trips without times at the first and last stop in the map",
+                route_name,
+                route.trips.len() - schedule.len()
+            ));
+        }
+
         let id = BusRouteID(routes.len());
         routes.push(BusRoute {
             id,
             name: route_name.to_string(),
             stops,
+            schedule,
         });
     }
     timer.stop("make bus stops");
     (bus_stops, routes)
 }
 
+// Fill in missing times by assuming the bus goes at a constant pace between the known stops. None
+// if the first or last time is missing.
+fn interpolate_times(times: Vec<Option<Duration>>) -> Option<Vec<Duration>> {
+    let mut result: Vec<Duration> = Vec::new();
+    let mut last_known = (0, (*times.first()?)?);
+    for (idx, time) in times.iter().enumerate() {
+        if let Some(t) = time {
+            let (prev_idx, prev_time) = last_known;
+            for missing in (prev_idx + 1)..idx {
+                let pct = ((missing - prev_idx) as f64) / ((idx - prev_idx) as f64);
+                result.push(prev_time + pct * (*t - prev_time));
+            }
+            result.push(*t);
+            last_known = (idx, *t);
+        }
+    }
+    if result.len() == times.len() {
+        Some(result)
+    } else {
+        None
+    }
+}
+
 pub fn verify_bus_routes(map: &Map, routes: Vec<BusRoute>, timer: &mut Timer) -> Vec<BusRoute> {
     timer.start_iter("verify bus routes are connected", routes.len());
     routes
@@ -1,10 +1,10 @@
 use crate::{
     make, raw_data, Area, AreaID, Building, Intersection, IntersectionID, IntersectionType, Lane,
-    LaneID, Road, RoadID, Turn, TurnID, LANE_THICKNESS,
+    LaneID, ParkingLot, Road, RoadID, Turn, TurnID, LANE_THICKNESS,
 };
 use abstutil::Timer;
-use geom::{Bounds, GPSBounds, Polygon, Pt2D};
-use std::collections::BTreeMap;
+use geom::{Bounds, Distance, GPSBounds, Polygon, Pt2D};
+use std::collections::{BTreeMap, HashMap};
 
 pub struct HalfMap {
     pub roads: Vec<Road>,
@@ -12,6 +12,7 @@ pub struct HalfMap {
     pub intersections: Vec<Intersection>,
     pub turns: BTreeMap<TurnID, Turn>,
     pub buildings: Vec<Building>,
+    pub parking_lots: Vec<ParkingLot>,
     pub areas: Vec<Area>,
 
     pub turn_lookup: Vec<TurnID>,
@@ -30,6 +31,7 @@ pub fn make_half_map(
         intersections: Vec::new(),
         turns: BTreeMap::new(),
         buildings: Vec::new(),
+        parking_lots: Vec::new(),
         areas: Vec::new(),
         turn_lookup: Vec::new(),
     };
@@ -50,6 +52,7 @@ pub fn make_half_map(
         half_map.intersections.push(Intersection {
             id,
             point: pt,
+            gps_point: raw_i.point,
             // IMPORTANT! We're relying on the triangulation algorithm not to mess with the order
             // of the points. Sidewalk corner rendering depends on it later.
             polygon: Polygon::new(&i.polygon),
@@ -62,6 +65,13 @@ pub fn make_half_map(
             incoming_lanes: Vec::new(),
             outgoing_lanes: Vec::new(),
             roads: i.roads.iter().map(|id| road_id_mapping[id]).collect(),
+            // Roads might've been merged away
+            approach_signs: raw_i
+                .approach_signs
+                .iter()
+                .filter(|(r, _)| i.roads.contains(*r))
+                .map(|(r, sign)| (road_id_mapping[r], *sign))
+                .collect(),
         });
         intersection_id_mapping.insert(i.id, id);
     }
@@ -74,6 +84,7 @@ pub fn make_half_map(
         let road_id = road_id_mapping[&r.id];
         let i1 = intersection_id_mapping[&r.src_i];
         let i2 = intersection_id_mapping[&r.dst_i];
+        let speed_limits = make::get_speed_limits(&raw_r.osm_tags, &data.speed_limit_defaults);
 
         let mut road = Road {
             id: road_id,
@@ -88,7 +99,12 @@ pub fn make_half_map(
             dst_i: i2,
             parking_lane_fwd: raw_r.parking_lane_fwd,
             parking_lane_back: raw_r.parking_lane_back,
+            turn_restrictions: Vec::new(),
+            complicated_turn_restrictions: Vec::new(),
+            osm_speed_limits: speed_limits,
+            speed_limits,
         };
+        let elevation_profile = elevation_profile(raw_r, gps_bounds);
 
         for lane in &r.lane_specs {
             let id = LaneID(half_map.lanes.len());
@@ -114,12 +130,29 @@ pub fn make_half_map(
             let lane_center_pts = unshifted_pts
                 .shift_right(width)
                 .with_context(timer, format!("shift for {}", id));
+            // Trimming at intersections only shaves a bit off the ends, so just stretch the
+            // original road's profile over each lane.
+            let lane_len = lane_center_pts.length();
+            let mut lane_elevation: Vec<(Distance, Distance)> = elevation_profile
+                .iter()
+                .map(|(pct, elevation)| {
+                    if lane.reverse_pts {
+                        (lane_len * (1.0 - pct), *elevation)
+                    } else {
+                        (lane_len * *pct, *elevation)
+                    }
+                })
+                .collect();
+            if lane.reverse_pts {
+                lane_elevation.reverse();
+            }
 
             half_map.lanes.push(Lane {
                 id,
                 lane_center_pts,
                 src_i,
                 dst_i,
+                elevation_profile: lane_elevation,
                 lane_type: lane.lane_type,
                 parent: road_id,
                 building_paths: Vec::new(),
@@ -135,6 +168,8 @@ pub fn make_half_map(
         half_map.roads.push(road);
     }
 
+    match_turn_restrictions(&mut half_map, &data.turn_restrictions, gps_bounds, timer);
+
     for i in half_map.intersections.iter_mut() {
         if is_border(i, &half_map.lanes) {
             i.intersection_type = IntersectionType::Border;
@@ -146,7 +181,13 @@ pub fn make_half_map(
             continue;
         }
 
-        for t in make::turns::make_all_turns(i, &half_map.roads, &half_map.lanes, timer) {
+        for t in make::turns::make_all_turns(
+            i,
+            &half_map.roads,
+            &half_map.lanes,
+            &BTreeMap::new(),
+            timer,
+        ) {
             assert!(!half_map.turns.contains_key(&t.id));
             i.turns.push(t.id);
             half_map.turns.insert(t.id, t);
@@ -179,6 +220,15 @@ pub fn make_half_map(
         half_map.lanes[lane.0].building_paths = bldgs;
     }
 
+    make::make_all_parking_lots(
+        &mut half_map.parking_lots,
+        &data.parking_lots,
+        &gps_bounds,
+        &bounds,
+        &half_map.lanes,
+        timer,
+    );
+
     for (idx, a) in data.areas.iter().enumerate() {
         let pts = gps_bounds.must_convert(&a.points);
         if pts[0] != *pts.last().unwrap() {
@@ -199,6 +249,125 @@ pub fn make_half_map(
     half_map
 }
 
+// OSM ways get split into many roads, so find the pieces that actually meet.
+fn match_turn_restrictions(
+    half_map: &mut HalfMap,
+    restrictions: &Vec<raw_data::TurnRestriction>,
+    gps_bounds: &GPSBounds,
+    timer: &mut Timer,
+) {
+    let mut roads_per_way: HashMap<i64, Vec<RoadID>> = HashMap::new();
+    for r in &half_map.roads {
+        roads_per_way
+            .entry(r.osm_way_id)
+            .or_insert_with(Vec::new)
+            .push(r.id);
+    }
+    let no_roads = Vec::new();
+
+    for tr in restrictions {
+        let from_roads = roads_per_way.get(&tr.from_way).unwrap_or(&no_roads);
+        let to_roads = roads_per_way.get(&tr.to_way).unwrap_or(&no_roads);
+
+        match tr.via {
+            raw_data::RestrictionVia::Node(pt) => {
+                let via_pt = match Pt2D::from_gps(pt, gps_bounds) {
+                    Some(pt) => pt,
+                    None => {
+                        continue;
+                    }
+                };
+                // If the ways meet more than once, use the intersection closest to the via node.
+                let mut best: Option<(RoadID, RoadID, Distance)> = None;
+                for from in from_roads {
+                    for to in to_roads {
+                        for i in
+                            shared_intersections(&half_map.roads[from.0], &half_map.roads[to.0])
+                        {
+                            let dist = half_map.intersections[i.0].point.dist_to(via_pt);
+                            if best.map(|(_, _, d)| dist < d).unwrap_or(true) {
+                                best = Some((*from, *to, dist));
+                            }
+                        }
+                    }
+                }
+                if let Some((from, to, _)) = best {
+                    half_map.roads[from.0]
+                        .turn_restrictions
+                        .push((tr.restriction, to));
+                } else {
+                    timer.warn(format!(
+                        "Turn restriction {} doesn't match any roads",
+                        tr.osm_relation_id
+                    ));
+                }
+            }
+            raw_data::RestrictionVia::Way(via_way) => {
+                let via_roads = roads_per_way.get(&via_way).unwrap_or(&no_roads);
+                // TODO The via way might've been split into more than one road.
+                let mut found = None;
+                for from in from_roads {
+                    for via in via_roads {
+                        for to in to_roads {
+                            let from_r = &half_map.roads[from.0];
+                            let via_r = &half_map.roads[via.0];
+                            let to_r = &half_map.roads[to.0];
+                            for i1 in shared_intersections(from_r, via_r) {
+                                for i2 in shared_intersections(via_r, to_r) {
+                                    if i1 != i2 {
+                                        found = Some((*from, *via, *to, i2));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+                let (from, via, to, via_end) = match found {
+                    Some(x) => x,
+                    None => {
+                        timer.warn(format!(
+                            "Turn restriction {} via way {} doesn't match any roads",
+                            tr.osm_relation_id, via_way
+                        ));
+                        continue;
+                    }
+                };
+                match tr.restriction {
+                    raw_data::RestrictionType::BanTurns => {
+                        half_map.roads[from.0]
+                            .complicated_turn_restrictions
+                            .push((via, to, to));
+                    }
+                    raw_data::RestrictionType::OnlyAllowTurns => {
+                        // After coming from the first road onto the via road, every way out of
+                        // the via road besides the goal is banned.
+                        let banned: Vec<(RoadID, RoadID, RoadID)> = half_map.intersections
+                            [via_end.0]
+                            .roads
+                            .iter()
+                            .filter(|r| **r != via && **r != to)
+                            .map(|r| (via, *r, to))
+                            .collect();
+                        half_map.roads[from.0]
+                            .complicated_turn_restrictions
+                            .extend(banned);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn shared_intersections(r1: &Road, r2: &Road) -> Vec<IntersectionID> {
+    let mut result = Vec::new();
+    for i in &[r1.src_i, r1.dst_i] {
+        if (*i == r2.src_i || *i == r2.dst_i) && !result.contains(i) {
+            result.push(*i);
+        }
+    }
+    result
+}
+
 fn is_border(intersection: &Intersection, lanes: &Vec<Lane>) -> bool {
     // Raw data said it is.
     if intersection.intersection_type == IntersectionType::Border {
@@ -221,3 +390,25 @@ fn is_border(intersection: &Intersection, lanes: &Vec<Lane>) -> bool {
         .any(|l| lanes[l.0].is_driving());
     has_driving_in != has_driving_out
 }
+
+// Percent along the road and elevation of every original point
+fn elevation_profile(r: &raw_data::Road, gps_bounds: &GPSBounds) -> Vec<(f64, Distance)> {
+    if r.elevations.len() != r.points.len() {
+        return Vec::new();
+    }
+    let pts = gps_bounds.must_convert(&r.points);
+    let mut dists = vec![Distance::ZERO];
+    for pair in pts.windows(2) {
+        let d = *dists.last().unwrap() + pair[0].dist_to(pair[1]);
+        dists.push(d);
+    }
+    let total = *dists.last().unwrap();
+    if total == Distance::ZERO {
+        return Vec::new();
+    }
+    dists
+        .into_iter()
+        .zip(r.elevations.iter())
+        .map(|(d, elevation)| (d / total, *elevation))
+        .collect()
+}
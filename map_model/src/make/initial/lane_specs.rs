@@ -1,9 +1,10 @@
-use crate::{raw_data, LaneType};
+use crate::{raw_data, LaneType, TurnType};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 use std::iter;
 
-// (original direction, reversed direction)
+// (original direction, reversed direction). Each side goes from the middle of the road to the
+// curb.
 pub fn get_lane_types(
     tags: &BTreeMap<String, String>,
     parking_lane_fwd: bool,
@@ -26,86 +27,249 @@ pub fn get_lane_types(
         return (vec![LaneType::Sidewalk], Vec::new());
     }
 
+    // oneway=-1 ways are drawn against the direction of traffic. Work out the lanes as if the way
+    // pointed the other way, then swap the sides back.
+    if tags.get("oneway") == Some(&"-1".to_string()) {
+        let (fwd, back) = get_lane_types(&flip_tags(tags), parking_lane_back, parking_lane_fwd);
+        return (back, fwd);
+    }
+
     // TODO Reversible roads should be handled differently?
-    let oneway = tags.get("oneway") == Some(&"yes".to_string())
-        || tags.get("oneway") == Some(&"reversible".to_string());
-    let num_driving_lanes_per_side =
-        if let Some(n) = tags.get("lanes").and_then(|num| num.parse::<usize>().ok()) {
-            if oneway {
-                // TODO OSM way 124940792 is I5 express lane, should it be considered oneway?
-                n
-            } else {
-                // TODO How to distribute odd number of lanes?
-                (n / 2).max(1)
-            }
-        } else {
-            // TODO Does https://wiki.openstreetmap.org/wiki/Key:lanes#Assumptions help?
-            1
-        };
-    let mut driving_lanes_per_side: Vec<LaneType> = iter::repeat(LaneType::Driving)
-        .take(num_driving_lanes_per_side)
-        .collect();
-    // TODO Don't even bother trying to parse this yet.
-    let has_bus_lane = tags.contains_key("bus:lanes");
-    // TODO This is circumstantial at best. :)
-    if has_bus_lane && driving_lanes_per_side.len() > 1 {
-        driving_lanes_per_side.pop();
-    }
-
-    let has_bike_lane = tags.get("cycleway") == Some(&"lane".to_string());
-    let has_sidewalk = tags.get("highway") != Some(&"motorway".to_string())
-        && tags.get("highway") != Some(&"motorway_link".to_string());
-    // TODO Bus/bike and parking lanes can coexist, but then we have to make sure cars are fine
-    // with merging in/out of the bus/bike lane to park. ><
-    //let has_parking = has_sidewalk && !has_bus_lane && !has_bike_lane;
+    let oneway = is_oneway(tags);
+    let (num_fwd, num_back) = num_vehicle_lanes(tags, oneway);
+    let mut fwd_side = vehicle_lanes(tags, num_fwd, if oneway { "" } else { ":forward" });
+    let mut back_side = vehicle_lanes(tags, num_back, ":backward");
 
-    let mut fwd_side = driving_lanes_per_side.clone();
-    if has_bus_lane {
-        fwd_side.push(LaneType::Bus);
-    }
-    if has_bike_lane {
+    let (bike_fwd, bike_back) = bike_lanes(tags, oneway);
+    if bike_fwd {
         fwd_side.push(LaneType::Biking);
     }
+    if bike_back {
+        back_side.push(LaneType::Biking);
+    }
+
+    // TODO Bus/bike and parking lanes can coexist, but then we have to make sure cars are fine
+    // with merging in/out of the bus/bike lane to park. ><
     // TODO Should we warn when a link road has parking assigned to it from the blockface?
     let is_link = match tags.get("highway") {
         Some(hwy) => hwy.ends_with("_link"),
         None => false,
     };
-    if parking_lane_fwd && !is_link {
+    // Explicit OSM tags win over blockface.
+    let parking_fwd = has_parking(tags, "right").unwrap_or(parking_lane_fwd);
+    let parking_back = has_parking(tags, "left").unwrap_or(parking_lane_back);
+    if parking_fwd && !is_link {
         fwd_side.push(LaneType::Parking);
     }
-    if has_sidewalk {
+    // Ignore off-side parking on one-ways, since cars don't know how to park on lanes without a
+    // driving lane in that direction too.
+    if parking_back && !is_link && !oneway {
+        back_side.push(LaneType::Parking);
+    }
+
+    let (sidewalk_fwd, sidewalk_back) = sidewalks(tags, oneway);
+    if sidewalk_fwd {
         fwd_side.push(LaneType::Sidewalk);
     }
+    if sidewalk_back {
+        back_side.push(LaneType::Sidewalk);
+    }
+
+    (fwd_side, back_side)
+}
 
+// Turn arrows for each vehicle lane (driving or bus) going one way, closest to the middle of the
+// road first. None means the lane has no arrows. Empty if OSM doesn't say.
+pub fn get_turn_arrows(tags: &BTreeMap<String, String>, fwd: bool) -> Vec<Option<Vec<TurnType>>> {
+    let key = match (tags.get("oneway").map(|x| x.as_str()), fwd) {
+        (Some("-1"), false) => "turn:lanes",
+        (Some("-1"), true) => {
+            return Vec::new();
+        }
+        _ if is_oneway(tags) && fwd => "turn:lanes",
+        (_, true) => "turn:lanes:forward",
+        (_, false) => "turn:lanes:backward",
+    };
+    let value = match tags.get(key) {
+        Some(v) => v,
+        None => {
+            return Vec::new();
+        }
+    };
+    value
+        .split('|')
+        .map(|lane| {
+            let mut arrows = Vec::new();
+            for arrow in lane.split(';') {
+                match arrow.trim() {
+                    "left" | "sharp_left" => arrows.push(TurnType::Left),
+                    "slight_left" => arrows.extend(vec![TurnType::Left, TurnType::Straight]),
+                    "through" | "merge_to_left" | "merge_to_right" => {
+                        arrows.push(TurnType::Straight)
+                    }
+                    "right" | "sharp_right" => arrows.push(TurnType::Right),
+                    "slight_right" => arrows.extend(vec![TurnType::Right, TurnType::Straight]),
+                    // No U-turns yet, and none means no arrows.
+                    _ => {}
+                }
+            }
+            if arrows.is_empty() {
+                None
+            } else {
+                Some(arrows)
+            }
+        })
+        .collect()
+}
+
+fn is_oneway(tags: &BTreeMap<String, String>) -> bool {
+    match tags.get("oneway").map(|x| x.as_str()) {
+        Some("yes") | Some("reversible") | Some("1") | Some("true") => true,
+        _ => false,
+    }
+}
+
+// Swaps everything that depends on which way the way points.
+fn flip_tags(tags: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    tags.iter()
+        .map(|(k, v)| {
+            let k = k
+                .replace(":forward", ":TMP")
+                .replace(":backward", ":forward")
+                .replace(":TMP", ":backward")
+                .replace(":left", ":TMP")
+                .replace(":right", ":left")
+                .replace(":TMP", ":right");
+            let v = if k == "oneway" {
+                "yes".to_string()
+            } else if k == "sidewalk" && v == "left" {
+                "right".to_string()
+            } else if k == "sidewalk" && v == "right" {
+                "left".to_string()
+            } else {
+                v.clone()
+            };
+            (k, v)
+        })
+        .collect()
+}
+
+fn parse_count(tags: &BTreeMap<String, String>, key: &str) -> Option<usize> {
+    tags.get(key).and_then(|n| n.parse::<usize>().ok())
+}
+
+// Driving and bus lanes in each direction
+fn num_vehicle_lanes(tags: &BTreeMap<String, String>, oneway: bool) -> (usize, usize) {
+    let total = parse_count(tags, "lanes");
     if oneway {
-        // Only residential streets have a sidewalk on the other side of a one-way.
-        // Ignore off-side parking, since cars don't know how to park on lanes without a driving
-        // lane in that direction too.
-        let back_side = if has_sidewalk
-            && (tags.get("highway") == Some(&"residential".to_string())
-                || tags.get("sidewalk") == Some(&"both".to_string()))
-        {
-            vec![LaneType::Sidewalk]
-        } else {
-            Vec::new()
-        };
-        (fwd_side, back_side)
-    } else {
-        let mut back_side = driving_lanes_per_side;
-        if has_bus_lane {
-            back_side.push(LaneType::Bus);
+        // TODO OSM way 124940792 is I5 express lane, should it be considered oneway?
+        return (total.unwrap_or(1).max(1), 0);
+    }
+    // We don't model shared center turn lanes.
+    let center = parse_count(tags, "lanes:both_ways").unwrap_or(0);
+    let (fwd, back) = match (
+        parse_count(tags, "lanes:forward"),
+        parse_count(tags, "lanes:backward"),
+    ) {
+        (Some(fwd), Some(back)) => (fwd, back),
+        (Some(fwd), None) => (
+            fwd,
+            total.map(|n| n.saturating_sub(fwd + center)).unwrap_or(1),
+        ),
+        (None, Some(back)) => (
+            total.map(|n| n.saturating_sub(back + center)).unwrap_or(1),
+            back,
+        ),
+        (None, None) => {
+            // TODO Does https://wiki.openstreetmap.org/wiki/Key:lanes#Assumptions help?
+            let n = total.unwrap_or(2).saturating_sub(center);
+            // OSM doesn't say where an odd lane goes, so guess forwards.
+            ((n + 1) / 2, n / 2)
         }
-        if has_bike_lane {
-            back_side.push(LaneType::Biking);
+    };
+    (fwd.max(1), back.max(1))
+}
+
+// Driving lanes, except where bus:lanes or psv:lanes say a lane is for buses. The suffix is
+// ":forward", ":backward", or empty for one-ways.
+fn vehicle_lanes(tags: &BTreeMap<String, String>, num: usize, suffix: &str) -> Vec<LaneType> {
+    let mut lanes: Vec<LaneType> = iter::repeat(LaneType::Driving).take(num).collect();
+    for prefix in &["bus:lanes", "psv:lanes"] {
+        if let Some(value) = tags.get(&format!("{}{}", prefix, suffix)) {
+            let access: Vec<&str> = value.split('|').collect();
+            // If this doesn't match up, the tags are out of date.
+            if access.len() == num {
+                for (idx, a) in access.into_iter().enumerate() {
+                    if a == "designated" {
+                        lanes[idx] = LaneType::Bus;
+                    }
+                }
+            }
         }
-        if parking_lane_back && !is_link {
-            back_side.push(LaneType::Parking);
+    }
+    lanes
+}
+
+// Painted lanes and separated tracks are both treated as bike lanes.
+fn bike_lanes(tags: &BTreeMap<String, String>, oneway: bool) -> (bool, bool) {
+    let lane = &["lane", "track"];
+    let opposite = &["opposite_lane", "opposite_track"];
+
+    let mut fwd = tag_is(tags, "cycleway", lane)
+        || tag_is(tags, "cycleway:both", lane)
+        || tag_is(tags, "cycleway:right", lane);
+    let mut back = tag_is(tags, "cycleway:both", lane) || tag_is(tags, "cycleway:left", lane);
+    if oneway {
+        // On one-ways, a bike lane on the left usually goes with traffic.
+        let contraflow = tag_is(tags, "cycleway", opposite)
+            || tag_is(tags, "cycleway:left", opposite)
+            || tag_is(tags, "cycleway:right", opposite)
+            || tag_is(tags, "cycleway:left:oneway", &["-1", "no"]);
+        if back && !contraflow {
+            back = false;
+            fwd = true;
         }
-        if has_sidewalk {
-            back_side.push(LaneType::Sidewalk);
+        if contraflow {
+            back = true;
+        }
+    } else if tag_is(tags, "cycleway", lane) {
+        back = true;
+    }
+    (fwd, back)
+}
+
+fn tag_is(tags: &BTreeMap<String, String>, key: &str, values: &[&str]) -> bool {
+    match tags.get(key) {
+        Some(v) => values.contains(&v.as_str()),
+        None => false,
+    }
+}
+
+// From parking:lane:*. None if OSM doesn't say. The side is "left" or "right".
+fn has_parking(tags: &BTreeMap<String, String>, side: &str) -> Option<bool> {
+    let value = tags
+        .get(&format!("parking:lane:{}", side))
+        .or_else(|| tags.get("parking:lane:both"))?;
+    Some(match value.as_str() {
+        "parallel" | "diagonal" | "perpendicular" | "marked" | "yes" => true,
+        _ => false,
+    })
+}
+
+fn sidewalks(tags: &BTreeMap<String, String>, oneway: bool) -> (bool, bool) {
+    match tags.get("sidewalk").map(|x| x.as_str()) {
+        Some("both") => (true, true),
+        Some("right") => (true, false),
+        Some("left") => (false, true),
+        Some("none") | Some("no") | Some("separate") => (false, false),
+        _ => {
+            let has_sidewalk = tags.get("highway") != Some(&"motorway".to_string())
+                && tags.get("highway") != Some(&"motorway_link".to_string());
+            // Only residential streets have a sidewalk on the other side of a one-way.
+            let back = has_sidewalk
+                && (!oneway || tags.get("highway") == Some(&"residential".to_string()));
+            (has_sidewalk, back)
         }
-        (fwd_side, back_side)
     }
 }
 
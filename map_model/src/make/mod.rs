@@ -2,12 +2,16 @@ mod buildings;
 mod bus_stops;
 mod half_map;
 mod initial;
+mod parking_lots;
 mod sidewalk_finder;
+mod speed_limits;
 mod turns;
 
 pub use self::buildings::make_all_buildings;
 pub use self::bus_stops::{make_bus_stops, verify_bus_routes};
 pub use self::half_map::make_half_map;
-pub use self::initial::lane_specs::{get_lane_types, RoadSpec};
+pub use self::initial::lane_specs::{get_lane_types, get_turn_arrows, RoadSpec};
 pub use self::initial::InitialMap;
-pub use self::turns::make_all_turns;
+pub use self::parking_lots::make_all_parking_lots;
+pub use self::speed_limits::{get_speed_limits, SpeedLimitDefaults};
+pub use self::turns::{get_turn_restriction, make_all_turns};
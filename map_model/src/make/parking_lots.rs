@@ -0,0 +1,52 @@
+use crate::make::sidewalk_finder::find_sidewalk_points;
+use crate::{raw_data, Lane, ParkingLot, ParkingLotID};
+use abstutil::Timer;
+use geom::{Bounds, Distance, GPSBounds, HashablePt2D, Polygon, Pt2D};
+use std::collections::HashSet;
+
+pub fn make_all_parking_lots(
+    results: &mut Vec<ParkingLot>,
+    input: &Vec<raw_data::ParkingLot>,
+    gps_bounds: &GPSBounds,
+    bounds: &Bounds,
+    lanes: &Vec<Lane>,
+    timer: &mut Timer,
+) {
+    timer.start("convert parking lots");
+    let mut pts_per_lot: Vec<Vec<Pt2D>> = Vec::new();
+    let mut center_per_lot: Vec<HashablePt2D> = Vec::new();
+    let mut query: HashSet<HashablePt2D> = HashSet::new();
+    for lot in input {
+        let pts = Pt2D::approx_dedupe(gps_bounds.must_convert(&lot.points), geom::EPSILON_DIST);
+        let center: HashablePt2D = Pt2D::center(&pts).into();
+        pts_per_lot.push(pts);
+        center_per_lot.push(center);
+        query.insert(center);
+    }
+
+    // People have to walk between the lot and the sidewalk, so skip lots that're too far away.
+    let sidewalk_pts = find_sidewalk_points(bounds, query, lanes, Distance::meters(100.0), timer);
+
+    for (idx, points) in pts_per_lot.into_iter().enumerate() {
+        if let Some(sidewalk_pos) = sidewalk_pts.get(&center_per_lot[idx]) {
+            results.push(ParkingLot {
+                id: ParkingLotID(results.len()),
+                polygon: Polygon::new(&points),
+                osm_tags: input[idx].osm_tags.clone(),
+                osm_way_id: input[idx].osm_way_id,
+                sidewalk_pos: *sidewalk_pos,
+                // Needs a full Map to find the driving lane; filled out later.
+                parking: None,
+            });
+        }
+    }
+
+    let discarded = input.len() - results.len();
+    if discarded > 0 {
+        timer.note(format!(
+            "Discarded {} parking lots that weren't close enough to a sidewalk",
+            discarded
+        ));
+    }
+    timer.stop("convert parking lots");
+}
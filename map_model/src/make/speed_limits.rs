@@ -0,0 +1,148 @@
+use geom::Speed;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// What to assume for roads without a usable maxspeed tag, based on the highway tag
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeedLimitDefaults {
+    pub per_highway: BTreeMap<String, Speed>,
+    // For any other kind of highway
+    pub fallback: Speed,
+}
+
+impl Default for SpeedLimitDefaults {
+    fn default() -> SpeedLimitDefaults {
+        let mut per_highway = BTreeMap::new();
+        for (highway, mph) in vec![
+            ("motorway", 60.0),
+            ("trunk", 50.0),
+            ("primary", 40.0),
+            ("secondary", 40.0),
+            ("tertiary", 30.0),
+            ("residential", 20.0),
+            ("unclassified", 20.0),
+            ("service", 10.0),
+            ("living_street", 10.0),
+        ] {
+            per_highway.insert(highway.to_string(), Speed::miles_per_hour(mph));
+        }
+        SpeedLimitDefaults {
+            per_highway,
+            fallback: Speed::miles_per_hour(20.0),
+        }
+    }
+}
+
+impl SpeedLimitDefaults {
+    fn get(&self, highway: Option<&String>) -> Speed {
+        if let Some(highway) = highway {
+            // primary_link is like primary, unless it's listed separately
+            for key in vec![highway.as_str(), highway.trim_end_matches("_link")] {
+                if let Some(speed) = self.per_highway.get(key) {
+                    return *speed;
+                }
+            }
+        }
+        self.fallback
+    }
+}
+
+// Forwards and backwards, relative to the OSM way
+pub fn get_speed_limits(
+    tags: &BTreeMap<String, String>,
+    defaults: &SpeedLimitDefaults,
+) -> (Speed, Speed) {
+    let both = tags
+        .get("maxspeed")
+        .and_then(|value| parse_maxspeed(value))
+        // Sometimes the implicit limit is only recorded here
+        .or_else(|| {
+            tags.get("maxspeed:type")
+                .and_then(|value| implicit_speed_limit(value))
+        })
+        .or_else(|| {
+            tags.get("source:maxspeed")
+                .and_then(|value| implicit_speed_limit(value))
+        })
+        .unwrap_or_else(|| defaults.get(tags.get("highway")));
+    let fwd = tags
+        .get("maxspeed:forward")
+        .and_then(|value| parse_maxspeed(value))
+        .unwrap_or(both);
+    let back = tags
+        .get("maxspeed:backward")
+        .and_then(|value| parse_maxspeed(value))
+        .unwrap_or(both);
+    (fwd, back)
+}
+
+// Bare numbers are km/h. Values like "none" or "signals" don't say anything useful.
+fn parse_maxspeed(value: &str) -> Option<Speed> {
+    // Some ways list several limits for different lanes or times. Just use the first.
+    let value = value.split(';').next()?.trim();
+    if let Some(speed) = implicit_speed_limit(value) {
+        return Some(speed);
+    }
+
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or_else(|| value.len());
+    let number = value[0..split].trim().parse::<f64>().ok()?;
+    if number <= 0.0 {
+        return None;
+    }
+    match value[split..].trim() {
+        "" | "km/h" | "kmh" | "kph" => Some(Speed::km_per_hour(number)),
+        "mph" => Some(Speed::miles_per_hour(number)),
+        "knots" => Some(Speed::meters_per_second(number * 0.514_444)),
+        _ => None,
+    }
+}
+
+// Country-specific values like DE:urban or GB:nsl_single. See
+// https://wiki.openstreetmap.org/wiki/Speed_limits_by_country
+fn implicit_speed_limit(value: &str) -> Option<Speed> {
+    let mut parts = value.splitn(2, ':');
+    let country = parts.next()?;
+    let kind = parts.next()?;
+    let uses_mph = country == "US" || country == "GB";
+
+    let limit = if kind.starts_with("zone") {
+        // Like DE:zone30 or DE:zone:30
+        kind["zone".len()..]
+            .trim_start_matches(':')
+            .parse::<f64>()
+            .ok()?
+    } else {
+        match (country, kind) {
+            ("US", "urban") => 25.0,
+            ("US", "rural") => 55.0,
+            ("US", "motorway") => 65.0,
+            ("GB", "nsl_restricted") | ("GB", "urban") => 30.0,
+            ("GB", "nsl_single") => 60.0,
+            ("GB", "nsl_dual") | ("GB", "motorway") => 70.0,
+            ("DE", "rural") | ("AT", "rural") => 100.0,
+            ("FR", "rural") | ("CH", "rural") | ("NL", "rural") => 80.0,
+            ("IT", "rural") => 90.0,
+            ("CH", "motorway") => 120.0,
+            ("FR", "motorway") | ("AT", "motorway") | ("IT", "motorway") | ("NL", "motorway") => {
+                130.0
+            }
+            ("DE", "living_street") | ("AT", "living_street") => 7.0,
+            // Almost every other country uses this
+            (_, "urban") => 50.0,
+            // Includes DE:motorway, which has no limit. Fall back to the default for the road.
+            _ => {
+                return None;
+            }
+        }
+    };
+    if limit <= 0.0 {
+        return None;
+    }
+    if uses_mph {
+        Some(Speed::miles_per_hour(limit))
+    } else {
+        Some(Speed::km_per_hour(limit))
+    }
+}
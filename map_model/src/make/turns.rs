@@ -1,11 +1,13 @@
+use crate::make::initial::lane_specs::get_turn_arrows;
+use crate::raw_data::RestrictionType;
 use crate::{
-    Intersection, IntersectionID, IntersectionType, Lane, LaneID, LaneType, Road, Turn, TurnID,
-    TurnType, LANE_THICKNESS,
+    Intersection, IntersectionID, IntersectionType, Lane, LaneID, LaneType, Road, RoadID, Turn,
+    TurnID, TurnType, LANE_THICKNESS,
 };
 use abstutil::{Timer, Warn};
 use geom::{Distance, Line, PolyLine, Pt2D};
 use nbez::{Bez3o, BezCurve, Point2d};
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 // TODO Add proper warnings when the geometry is too small to handle.
 
@@ -13,12 +15,19 @@ pub fn make_all_turns(
     i: &Intersection,
     roads: &Vec<Road>,
     lanes: &Vec<Lane>,
+    turn_restriction_overrides: &BTreeMap<(RoadID, RoadID), Option<RestrictionType>>,
     timer: &mut Timer,
 ) -> Vec<Turn> {
     assert!(i.intersection_type != IntersectionType::Border);
 
     let mut turns: Vec<Turn> = Vec::new();
-    turns.extend(make_vehicle_turns(i, roads, lanes, timer));
+    turns.extend(
+        make_vehicle_turns(i, roads, lanes, timer)
+            .into_iter()
+            .filter(|t| {
+                does_turn_pass_restrictions(t, i, roads, lanes, turn_restriction_overrides)
+            }),
+    );
     turns.extend(make_walking_turns(i, roads, lanes, timer));
     let turns = ensure_unique(turns);
 
@@ -50,6 +59,48 @@ pub fn make_all_turns(
     turns
 }
 
+// Restrictions from OSM, unless the edits say otherwise.
+pub fn get_turn_restriction(
+    from: &Road,
+    to: RoadID,
+    turn_restriction_overrides: &BTreeMap<(RoadID, RoadID), Option<RestrictionType>>,
+) -> Option<RestrictionType> {
+    if let Some(rt) = turn_restriction_overrides.get(&(from.id, to)) {
+        return *rt;
+    }
+    from.turn_restrictions
+        .iter()
+        .find(|(_, r)| *r == to)
+        .map(|(rt, _)| *rt)
+}
+
+fn does_turn_pass_restrictions(
+    turn: &Turn,
+    i: &Intersection,
+    roads: &Vec<Road>,
+    lanes: &Vec<Lane>,
+    turn_restriction_overrides: &BTreeMap<(RoadID, RoadID), Option<RestrictionType>>,
+) -> bool {
+    let from = &roads[lanes[turn.id.src.0].parent.0];
+    let to = lanes[turn.id.dst.0].parent;
+
+    let mut only_allowed = Vec::new();
+    for r in &i.roads {
+        match get_turn_restriction(from, *r, turn_restriction_overrides) {
+            Some(RestrictionType::BanTurns) => {
+                if *r == to {
+                    return false;
+                }
+            }
+            Some(RestrictionType::OnlyAllowTurns) => {
+                only_allowed.push(*r);
+            }
+            None => {}
+        }
+    }
+    only_allowed.is_empty() || only_allowed.contains(&to)
+}
+
 fn ensure_unique(turns: Vec<Turn>) -> Vec<Turn> {
     let mut ids = HashSet::new();
     let mut keep: Vec<Turn> = Vec::new();
@@ -81,12 +132,16 @@ fn make_vehicle_turns(
     lane_types.remove(&LaneType::Parking);
     lane_types.remove(&LaneType::Sidewalk);
 
-    let mut result: Vec<Option<Turn>> = Vec::new();
+    let mut result: Vec<Turn> = Vec::new();
 
     for lane_type in lane_types.into_iter() {
         if i.is_dead_end() {
-            result
-                .extend(make_vehicle_turns_for_dead_end(i, all_roads, lanes, lane_type).get(timer));
+            result.extend(
+                make_vehicle_turns_for_dead_end(i, all_roads, lanes, lane_type)
+                    .get(timer)
+                    .into_iter()
+                    .filter_map(|x| x),
+            );
             continue;
         }
 
@@ -98,6 +153,7 @@ fn make_vehicle_turns(
                 continue;
             }
 
+            let mut definite_turns = Vec::new();
             let mut maybe_add_turns = Vec::new();
             let mut all_incoming_lanes_covered = false;
 
@@ -135,7 +191,7 @@ fn make_vehicle_turns(
                                     } else if idx1 > idx2 {
                                         t.turn_type = TurnType::LaneChangeLeft;
                                     }
-                                    result.push(Some(t));
+                                    definite_turns.push(Some(t));
                                 }
                             }
                         }
@@ -146,7 +202,7 @@ fn make_vehicle_turns(
                             for l2 in &outgoing {
                                 let turn = make_vehicle_turn(lanes, i.id, *l1, *l2);
                                 if idx == incoming.len() - 1 {
-                                    result.push(turn);
+                                    definite_turns.push(turn);
                                 } else {
                                     maybe_add_turns.push(turn);
                                 }
@@ -158,7 +214,7 @@ fn make_vehicle_turns(
                             for l2 in &outgoing {
                                 let turn = make_vehicle_turn(lanes, i.id, *l1, *l2);
                                 if idx == 0 {
-                                    result.push(turn);
+                                    definite_turns.push(turn);
                                 } else {
                                     maybe_add_turns.push(turn);
                                 }
@@ -169,13 +225,65 @@ fn make_vehicle_turns(
                 };
             }
 
-            if !all_incoming_lanes_covered {
-                result.extend(maybe_add_turns);
+            let definite_turns: Vec<Turn> = definite_turns.into_iter().filter_map(|x| x).collect();
+            let maybe_add_turns: Vec<Turn> =
+                maybe_add_turns.into_iter().filter_map(|x| x).collect();
+            let arrows = turn_arrows(r1, i.id);
+            for l1 in &incoming {
+                // Lanes with turn arrows get exactly the turns painted on them, unless the
+                // geometry doesn't agree at all.
+                if let Some(allowed) = arrows.get(l1) {
+                    let marked: Vec<Turn> = definite_turns
+                        .iter()
+                        .chain(maybe_add_turns.iter())
+                        .filter(|t| {
+                            t.id.src == *l1 && allowed.contains(&straight_if_lc(t.turn_type))
+                        })
+                        .cloned()
+                        .collect();
+                    if !marked.is_empty() {
+                        result.extend(marked);
+                        continue;
+                    }
+                }
+                result.extend(definite_turns.iter().filter(|t| t.id.src == *l1).cloned());
+                if !all_incoming_lanes_covered {
+                    result.extend(maybe_add_turns.iter().filter(|t| t.id.src == *l1).cloned());
+                }
             }
         }
     }
 
-    result.into_iter().filter_map(|x| x).collect()
+    result
+}
+
+// Turn arrows from OSM for the incoming driving and bus lanes of a road. They're ignored if the
+// number of lanes doesn't match anymore, like after some edits.
+fn turn_arrows(r: &Road, i: IntersectionID) -> HashMap<LaneID, Vec<TurnType>> {
+    let mut result = HashMap::new();
+    let arrows = get_turn_arrows(&r.osm_tags, r.dst_i == i);
+    let vehicle_lanes: Vec<LaneID> = r
+        .incoming_lanes(i)
+        .iter()
+        .filter(|(_, lt)| *lt == LaneType::Driving || *lt == LaneType::Bus)
+        .map(|(l, _)| *l)
+        .collect();
+    if arrows.len() != vehicle_lanes.len() {
+        return result;
+    }
+    for (l, allowed) in vehicle_lanes.into_iter().zip(arrows.into_iter()) {
+        if let Some(allowed) = allowed {
+            result.insert(l, allowed);
+        }
+    }
+    result
+}
+
+fn straight_if_lc(turn_type: TurnType) -> TurnType {
+    match turn_type {
+        TurnType::LaneChangeLeft | TurnType::LaneChangeRight => TurnType::Straight,
+        _ => turn_type,
+    }
 }
 
 fn make_vehicle_turns_for_dead_end(
@@ -1,19 +1,24 @@
 use crate::make::get_lane_types;
 use crate::pathfind::Pathfinder;
+use crate::raw_data::{OriginalIntersection, OriginalLane, OriginalRoad, RestrictionType};
 use crate::{
     make, raw_data, Area, AreaID, Building, BuildingID, BusRoute, BusRouteID, BusStop, BusStopID,
-    ControlStopSign, ControlTrafficSignal, Intersection, IntersectionID, IntersectionType, Lane,
-    LaneID, LaneType, MapEdits, Path, PathRequest, Position, Road, RoadID, Turn, TurnID,
-    TurnPriority,
+    ControlStopSign, ControlTrafficSignal, DirectedRoadID, Intersection, IntersectionID,
+    IntersectionType, Lane, LaneID, LaneType, MapEdits, OffstreetParking, ParkingLot, ParkingLotID,
+    Path, PathRequest, Position, Road, RoadID, TransitPlan, TransitRequest, Turn, TurnID,
+    TurnPriority, LANE_THICKNESS,
 };
 use abstutil;
 use abstutil::{deserialize_btreemap, serialize_btreemap, Error, Timer};
-use geom::{Bounds, GPSBounds, Polygon};
+use geom::{Bounds, Distance, Duration, GPSBounds, LonLat, Polygon, Speed};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::io;
 use std::path;
 
+// How far an intersection can move between versions of the map and still be matched up.
+const ORIGINAL_MATCH_THRESHOLD: Distance = Distance::const_meters(2.0);
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Map {
     roads: Vec<Road>,
@@ -25,6 +30,9 @@ pub struct Map {
     )]
     turns: BTreeMap<TurnID, Turn>,
     buildings: Vec<Building>,
+    // Older maps don't have these
+    #[serde(default)]
+    parking_lots: Vec<ParkingLot>,
     #[serde(
         serialize_with = "serialize_btreemap",
         deserialize_with = "deserialize_btreemap"
@@ -42,8 +50,24 @@ pub struct Map {
     bounds: Bounds,
 
     turn_lookup: Vec<TurnID>,
+    // For matching up things that were saved by where they came from in OSM. Sorted, so the map
+    // always serializes the same way.
+    roads_per_osm_way: BTreeMap<i64, Vec<RoadID>>,
+    bldgs_per_osm_way: BTreeMap<i64, BuildingID>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    intersections_per_cell: BTreeMap<(i64, i64), Vec<IntersectionID>>,
     // TODO Argh, hack, initialization order is hard!
     pathfinder: Option<Pathfinder>,
+    // How long it took to cross each road (including waiting at the end) in a previous
+    // simulation. Empty means pathfinding assumes free-flow.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    observed_travel_times: BTreeMap<DirectedRoadID, Duration>,
 
     name: String,
     edits: MapEdits,
@@ -82,6 +106,7 @@ impl Map {
             intersections: half_map.intersections,
             turns: half_map.turns,
             buildings: half_map.buildings,
+            parking_lots: half_map.parking_lots,
             bus_stops: BTreeMap::new(),
             bus_routes: Vec::new(),
             areas: half_map.areas,
@@ -91,11 +116,31 @@ impl Map {
             gps_bounds,
             bounds,
             turn_lookup: half_map.turn_lookup,
+            roads_per_osm_way: BTreeMap::new(),
+            bldgs_per_osm_way: BTreeMap::new(),
+            intersections_per_cell: BTreeMap::new(),
             pathfinder: None,
+            observed_travel_times: BTreeMap::new(),
             name: name.clone(),
             edits: MapEdits::new(name),
         };
 
+        for r in &m.roads {
+            m.roads_per_osm_way
+                .entry(r.osm_way_id)
+                .or_insert_with(Vec::new)
+                .push(r.id);
+        }
+        for b in &m.buildings {
+            m.bldgs_per_osm_way.entry(b.osm_way_id).or_insert(b.id);
+        }
+        for i in &m.intersections {
+            m.intersections_per_cell
+                .entry(original_cell(i.gps_point))
+                .or_insert_with(Vec::new)
+                .push(i.id);
+        }
+
         // Extra setup that's annoying to do as HalfMap, since we want to pass around a Map.
         {
             let mut stop_signs: BTreeMap<IntersectionID, ControlStopSign> = BTreeMap::new();
@@ -126,6 +171,7 @@ impl Map {
 
             m.bus_routes = make::verify_bus_routes(&m, routes, timer);
         }
+        m.update_offstreet_parking();
         m.pathfinder = Some(Pathfinder::new(&m));
 
         timer.stop("finalize Map");
@@ -152,6 +198,10 @@ impl Map {
         &self.buildings
     }
 
+    pub fn all_parking_lots(&self) -> &Vec<ParkingLot> {
+        &self.parking_lots
+    }
+
     pub fn all_areas(&self) -> &Vec<Area> {
         &self.areas
     }
@@ -212,6 +262,10 @@ impl Map {
         &self.buildings[id.0]
     }
 
+    pub fn get_pl(&self, id: ParkingLotID) -> &ParkingLot {
+        &self.parking_lots[id.0]
+    }
+
     pub fn get_a(&self, id: AreaID) -> &Area {
         &self.areas[id.0]
     }
@@ -399,6 +453,80 @@ impl Map {
         self.find_closest_lane(from, types)
     }
 
+    pub fn original_intersection(&self, i: IntersectionID) -> OriginalIntersection {
+        OriginalIntersection {
+            point: self.get_i(i).gps_point,
+        }
+    }
+
+    pub fn original_road(&self, r: RoadID) -> OriginalRoad {
+        let road = self.get_r(r);
+        OriginalRoad {
+            osm_way_id: road.osm_way_id,
+            i1: self.original_intersection(road.src_i),
+            i2: self.original_intersection(road.dst_i),
+        }
+    }
+
+    pub fn original_lane(&self, l: LaneID) -> OriginalLane {
+        let r = self.get_l(l).parent;
+        let (fwds, back) = self.get_original_children(r);
+        let (fwd, idx) = match fwds.iter().position(|x| *x == l) {
+            Some(idx) => (true, idx),
+            None => (false, back.iter().position(|x| *x == l).unwrap()),
+        };
+        OriginalLane {
+            parent: self.original_road(r),
+            fwd,
+            idx,
+        }
+    }
+
+    // The closest intersection, if there's one close enough to plausibly be the same. Cells are
+    // bigger than the threshold, so only the neighboring cells have to be checked.
+    pub fn find_original_intersection(&self, orig: OriginalIntersection) -> Option<IntersectionID> {
+        let (x, y) = original_cell(orig.point);
+        let mut candidates = Vec::new();
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                if let Some(list) = self.intersections_per_cell.get(&(x + dx, y + dy)) {
+                    candidates.extend(list);
+                }
+            }
+        }
+        candidates
+            .into_iter()
+            .map(|i| (self.get_i(*i).gps_point.gps_dist_meters(orig.point), *i))
+            .filter(|(dist, _)| *dist <= ORIGINAL_MATCH_THRESHOLD)
+            .min()
+            .map(|(_, id)| id)
+    }
+
+    pub fn find_original_road(&self, orig: OriginalRoad) -> Option<RoadID> {
+        let i1 = self.find_original_intersection(orig.i1)?;
+        let i2 = self.find_original_intersection(orig.i2)?;
+        self.roads_per_osm_way
+            .get(&orig.osm_way_id)?
+            .iter()
+            .find(|r| self.get_r(**r).src_i == i1 && self.get_r(**r).dst_i == i2)
+            .cloned()
+    }
+
+    // Buildings are only split from one OSM way.
+    pub fn find_original_building(&self, osm_way_id: i64) -> Option<BuildingID> {
+        self.bldgs_per_osm_way.get(&osm_way_id).cloned()
+    }
+
+    pub fn find_original_lane(&self, orig: OriginalLane) -> Option<LaneID> {
+        let r = self.find_original_road(orig.parent)?;
+        let (fwds, back) = self.get_original_children(r);
+        if orig.fwd {
+            fwds.get(orig.idx).cloned()
+        } else {
+            back.get(orig.idx).cloned()
+        }
+    }
+
     // TODO reconsider names, or put somewhere else?
     pub fn intersection(&self, label: &str) -> &Intersection {
         for i in &self.intersections {
@@ -458,6 +586,9 @@ impl Map {
     }
 
     pub fn is_turn_allowed(&self, t: TurnID) -> bool {
+        if self.is_closed(t.src) || self.is_closed(t.dst) {
+            return false;
+        }
         if let Some(ss) = self.stop_signs.get(&t.parent) {
             ss.get_priority(t) != TurnPriority::Banned
         } else if let Some(ts) = self.traffic_signals.get(&t.parent) {
@@ -470,6 +601,33 @@ impl Map {
         }
     }
 
+    // Driving lanes on closed roads can't be used by anybody.
+    pub fn is_closed(&self, l: LaneID) -> bool {
+        let lane = self.get_l(l);
+        lane.lane_type == LaneType::Driving && self.edits.closed_roads.contains(&lane.parent)
+    }
+
+    // From OSM, with edits applied
+    pub fn get_turn_restriction(&self, from: RoadID, to: RoadID) -> Option<RestrictionType> {
+        make::get_turn_restriction(self.get_r(from), to, &self.edits.turn_restriction_overrides)
+    }
+
+    // (via, banned) pairs. Edits to turning from this road onto the road named by the OSM
+    // restriction lift these.
+    pub fn get_complicated_turn_restrictions(&self, from: RoadID) -> Vec<(RoadID, RoadID)> {
+        self.get_r(from)
+            .complicated_turn_restrictions
+            .iter()
+            .filter(|(_, _, to)| {
+                !self
+                    .edits
+                    .turn_restriction_overrides
+                    .contains_key(&(from, *to))
+            })
+            .map(|(via, banned, _)| (*via, *banned))
+            .collect()
+    }
+
     // When driving towards some goal building, there may not be a driving lane directly outside the
     // building. So BFS out in a deterministic way and find one.
     pub fn find_driving_lane_near_building(&self, b: BuildingID) -> LaneID {
@@ -523,19 +681,53 @@ impl Map {
         self.pathfinder.as_ref().unwrap().pathfind(req, self)
     }
 
+    // Contraction hierarchies make vehicle pathfinding much faster, but take a while to prepare.
+    // They're saved with the map.
+    pub fn use_contraction_hierarchies(&mut self, enabled: bool, timer: &mut Timer) {
+        let mut pathfinder = self.pathfinder.take().unwrap();
+        if !enabled {
+            pathfinder.drop_contraction_hierarchies();
+        } else if !pathfinder.has_contraction_hierarchies() {
+            pathfinder.prepare_contraction_hierarchies(self, timer);
+        }
+        self.pathfinder = Some(pathfinder);
+    }
+
     pub fn pathfind_slow(&self, req: PathRequest) -> Option<Path> {
         crate::pathfind::slow::shortest_distance(self, req)
     }
 
-    pub fn should_use_transit(
-        &self,
-        start: Position,
-        end: Position,
-    ) -> Option<(BusStopID, BusStopID, BusRouteID)> {
+    // Roughly how long a vehicle takes to make the trip, without building the path.
+    pub fn estimate_travel_time(&self, req: PathRequest) -> Option<Duration> {
         self.pathfinder
             .as_ref()
             .unwrap()
-            .should_use_transit(self, start, end)
+            .estimate_travel_time(&req, self)
+    }
+
+    pub fn plan_transit(&self, req: TransitRequest) -> Option<TransitPlan> {
+        self.pathfinder.as_ref().unwrap().plan_transit(&req, self)
+    }
+
+    pub fn get_observed_travel_times(&self) -> &BTreeMap<DirectedRoadID, Duration> {
+        &self.observed_travel_times
+    }
+
+    // Vehicle pathfinding will use these instead of free-flow times. Roads missing from this
+    // still use free-flow.
+    pub fn set_observed_travel_times(
+        &mut self,
+        times: BTreeMap<DirectedRoadID, Duration>,
+        timer: &mut Timer,
+    ) {
+        self.observed_travel_times = times;
+        // Every edge cost might change, but the structure of the graphs doesn't.
+        timer.start("update pathfinder");
+        let all_turns: BTreeSet<TurnID> = self.turns.keys().cloned().collect();
+        let mut pathfinder = self.pathfinder.take().unwrap();
+        pathfinder.apply_edits(&all_turns, &all_turns, self);
+        self.pathfinder = Some(pathfinder);
+        timer.stop("update pathfinder");
     }
 }
 
@@ -570,6 +762,40 @@ impl Map {
                 all_traffic_signals.insert(*id, ts.clone());
             }
         }
+        let mut all_reversal_edits: BTreeSet<RoadID> = BTreeSet::new();
+        for l in self
+            .edits
+            .reversed_lanes
+            .symmetric_difference(&new_edits.reversed_lanes)
+        {
+            all_reversal_edits.insert(self.lanes[l.0].parent);
+        }
+        let mut all_speed_limit_edits: BTreeMap<RoadID, (Speed, Speed)> = BTreeMap::new();
+        for (id, speed) in &new_edits.speed_limit_overrides {
+            if self.edits.speed_limit_overrides.get(id) != Some(speed) {
+                // Overrides apply to both directions
+                all_speed_limit_edits.insert(*id, (*speed, *speed));
+            }
+        }
+        let all_closed_road_edits: BTreeSet<RoadID> = self
+            .edits
+            .closed_roads
+            .symmetric_difference(&new_edits.closed_roads)
+            .cloned()
+            .collect();
+        let mut all_intersection_type_edits: BTreeMap<IntersectionID, IntersectionType> =
+            BTreeMap::new();
+        for (id, it) in &new_edits.intersection_type_overrides {
+            if self.edits.intersection_type_overrides.get(id) != Some(it) {
+                all_intersection_type_edits.insert(*id, *it);
+            }
+        }
+        let mut all_turn_restriction_edits: BTreeSet<(RoadID, RoadID)> = BTreeSet::new();
+        for (pair, rt) in &new_edits.turn_restriction_overrides {
+            if self.edits.turn_restriction_overrides.get(pair) != Some(rt) {
+                all_turn_restriction_edits.insert(*pair);
+            }
+        }
 
         // May need to revert some previous changes
         for id in self.edits.lane_overrides.keys() {
@@ -587,16 +813,65 @@ impl Map {
                 all_traffic_signals.insert(*id, ControlTrafficSignal::new(self, *id, timer));
             }
         }
+        for pair in self.edits.turn_restriction_overrides.keys() {
+            if !new_edits.turn_restriction_overrides.contains_key(pair) {
+                all_turn_restriction_edits.insert(*pair);
+            }
+        }
+        for id in self.edits.speed_limit_overrides.keys() {
+            if !new_edits.speed_limit_overrides.contains_key(id) {
+                all_speed_limit_edits.insert(*id, self.roads[id.0].osm_speed_limits);
+            }
+        }
+        for id in self.edits.intersection_type_overrides.keys() {
+            if !new_edits.intersection_type_overrides.contains_key(id) {
+                all_intersection_type_edits.insert(*id, self.get_original_intersection_type(*id));
+            }
+        }
 
         timer.note(format!(
-            "Total diff: {} lanes, {} stop signs, {} traffic signals",
+            "Total diff: {} lanes, {} reversed roads, {} speed limits, {} closed roads, {} \
+             intersection types, {} stop signs, {} traffic signals, {} turn restrictions",
             all_lane_edits.len(),
+            all_reversal_edits.len(),
+            all_speed_limit_edits.len(),
+            all_closed_road_edits.len(),
+            all_intersection_type_edits.len(),
             all_stop_sign_edits.len(),
-            all_traffic_signals.len()
+            all_traffic_signals.len(),
+            all_turn_restriction_edits.len()
         ));
 
         let mut changed_lanes = BTreeSet::new();
         let mut changed_intersections = BTreeSet::new();
+        // Do this before changing lane types, since that looks up the lane's position in the
+        // road.
+        for r in all_reversal_edits {
+            for l in self.reverse_lanes(r, &new_edits.reversed_lanes, timer) {
+                changed_lanes.insert(l);
+            }
+            changed_intersections.insert(self.roads[r.0].src_i);
+            changed_intersections.insert(self.roads[r.0].dst_i);
+        }
+        // Both only affect the cost of turns (or whether they're allowed), but it's simplest to
+        // remake them.
+        for (id, speeds) in all_speed_limit_edits {
+            let r = &mut self.roads[id.0];
+            r.speed_limits = speeds;
+            changed_intersections.insert(r.src_i);
+            changed_intersections.insert(r.dst_i);
+        }
+        for id in all_closed_road_edits {
+            let r = &self.roads[id.0];
+            changed_intersections.insert(r.src_i);
+            changed_intersections.insert(r.dst_i);
+        }
+        for (id, it) in all_intersection_type_edits {
+            self.intersections[id.0].intersection_type = it;
+            self.stop_signs.remove(&id);
+            self.traffic_signals.remove(&id);
+            changed_intersections.insert(id);
+        }
         for (id, lt) in all_lane_edits {
             changed_lanes.insert(id);
 
@@ -614,13 +889,26 @@ impl Map {
             changed_intersections.insert(l.src_i);
             changed_intersections.insert(l.dst_i);
         }
+        // Simple restrictions live at either end of the from road. Complicated ones only live in
+        // the pathfinder, which always recalculates them.
+        for (from, _) in all_turn_restriction_edits {
+            let r = &self.roads[from.0];
+            for i in vec![r.src_i, r.dst_i] {
+                if self.intersections[i.0].intersection_type != IntersectionType::Border {
+                    changed_intersections.insert(i);
+                }
+            }
+        }
 
         // Recompute turns and intersection policy
         let mut delete_turns = BTreeSet::new();
         let mut add_turns = BTreeSet::new();
         for id in changed_intersections {
-            // TODO Did something affect a border intersection?
             let i = &mut self.intersections[id.0];
+            // Borders don't have any turns.
+            if i.intersection_type == IntersectionType::Border {
+                continue;
+            }
 
             let mut old_turns = Vec::new();
             for id in i.turns.drain(..) {
@@ -628,7 +916,13 @@ impl Map {
                 delete_turns.insert(id);
             }
 
-            for t in make::make_all_turns(i, &self.roads, &self.lanes, timer) {
+            for t in make::make_all_turns(
+                i,
+                &self.roads,
+                &self.lanes,
+                &new_edits.turn_restriction_overrides,
+                timer,
+            ) {
                 add_turns.insert(t.id);
                 i.turns.push(t.id);
                 if let Some(_existing_t) = old_turns.iter().find(|turn| turn.id == t.id) {
@@ -640,25 +934,38 @@ impl Map {
 
             // TODO Deal with turn_lookup
 
-            // Do this before applying intersection policy edits.
+            // Do this before applying intersection policy edits. Overrides that didn't change
+            // aren't in the diff, so keep them here.
             match i.intersection_type {
                 IntersectionType::StopSign => {
-                    self.stop_signs
-                        .insert(id, ControlStopSign::new(self, id, timer));
+                    let ss = match new_edits.stop_sign_overrides.get(&id) {
+                        Some(ss) => ss.clone(),
+                        None => ControlStopSign::new(self, id, timer),
+                    };
+                    self.stop_signs.insert(id, ss);
                 }
                 IntersectionType::TrafficSignal => {
-                    self.traffic_signals
-                        .insert(id, ControlTrafficSignal::new(self, id, timer));
+                    let ts = match new_edits.traffic_signal_overrides.get(&id) {
+                        Some(ts) => ts.clone(),
+                        None => ControlTrafficSignal::new(self, id, timer),
+                    };
+                    self.traffic_signals.insert(id, ts);
                 }
                 IntersectionType::Border => {}
             }
         }
 
-        // Make sure all of the turns of modified intersections are re-added in the pathfinder;
-        // they might've become banned. Lane markings may also change based on turn priorities.
+        // Make sure all of the turns of modified intersections are removed and re-added in the
+        // pathfinder; they might've become banned, or their delay might be longer. Lane markings
+        // may also change based on turn priorities.
         for (id, ss) in all_stop_sign_edits {
+            // The intersection type might've been changed too.
+            if self.get_i(id).intersection_type != IntersectionType::StopSign {
+                continue;
+            }
             self.stop_signs.insert(id, ss);
             for t in &self.get_i(id).turns {
+                delete_turns.insert(*t);
                 add_turns.insert(*t);
             }
             for l in &self.get_i(id).incoming_lanes {
@@ -666,8 +973,12 @@ impl Map {
             }
         }
         for (id, ts) in all_traffic_signals {
+            if self.get_i(id).intersection_type != IntersectionType::TrafficSignal {
+                continue;
+            }
             self.traffic_signals.insert(id, ts);
             for t in &self.get_i(id).turns {
+                delete_turns.insert(*t);
                 add_turns.insert(*t);
             }
             for l in &self.get_i(id).incoming_lanes {
@@ -675,14 +986,57 @@ impl Map {
             }
         }
 
+        // The pathfinder needs to see the new turn restrictions.
+        self.edits = new_edits;
+
         let mut pathfinder = self.pathfinder.take().unwrap();
         pathfinder.apply_edits(&delete_turns, &add_turns, self);
         self.pathfinder = Some(pathfinder);
 
-        self.edits = new_edits;
+        self.update_offstreet_parking();
         (changed_lanes, delete_turns, add_turns)
     }
 
+    // Off-street parking needs a driving lane to enter and exit from. Edits can change the number
+    // of stalls or the lanes nearby.
+    fn update_offstreet_parking(&mut self) {
+        for idx in 0..self.buildings.len() {
+            let b = &self.buildings[idx];
+            let num_stalls = self
+                .edits
+                .offstreet_parking_overrides
+                .get(&b.id)
+                .cloned()
+                .or_else(|| b.osm_num_parking_stalls())
+                .unwrap_or(0);
+            let parking = self.connect_offstreet_parking(b.front_path.sidewalk, num_stalls);
+            self.buildings[idx].parking = parking;
+        }
+        for idx in 0..self.parking_lots.len() {
+            let lot = &self.parking_lots[idx];
+            let parking =
+                self.connect_offstreet_parking(lot.sidewalk_pos, lot.osm_num_parking_stalls());
+            self.parking_lots[idx].parking = parking;
+        }
+    }
+
+    fn connect_offstreet_parking(
+        &self,
+        sidewalk_pos: Position,
+        num_stalls: usize,
+    ) -> Option<OffstreetParking> {
+        if num_stalls == 0 {
+            return None;
+        }
+        let l = self
+            .find_closest_lane(sidewalk_pos.lane(), vec![LaneType::Driving])
+            .ok()?;
+        Some(OffstreetParking {
+            num_stalls,
+            driving_pos: sidewalk_pos.equiv_pos(l, self),
+        })
+    }
+
     fn get_original_lt(&self, id: LaneID) -> LaneType {
         let parent = self.get_parent(id);
         let (side1, side2) = get_lane_types(
@@ -690,11 +1044,179 @@ impl Map {
             parent.parking_lane_fwd,
             parent.parking_lane_back,
         );
-        let (fwds, idx) = parent.dir_and_offset(id);
-        if fwds {
+        let (fwds, back) = self.get_original_children(parent.id);
+        if let Some(idx) = fwds.iter().position(|l| *l == id) {
             side1[idx]
         } else {
-            side2[idx]
+            side2[back.iter().position(|l| *l == id).unwrap()]
         }
     }
+
+    // The lanes of a road without any reversals from the current edits, closest to the middle of
+    // the road first.
+    fn get_original_children(&self, r: RoadID) -> (Vec<LaneID>, Vec<LaneID>) {
+        let road = &self.roads[r.0];
+        let fwds: Vec<LaneID> = road.children_forwards.iter().map(|(l, _)| *l).collect();
+        let back: Vec<LaneID> = road.children_backwards.iter().map(|(l, _)| *l).collect();
+        // Reversed lanes are always the innermost ones, in the opposite order.
+        let num_from_back = fwds
+            .iter()
+            .take_while(|l| self.edits.reversed_lanes.contains(l))
+            .count();
+        let num_from_fwds = back
+            .iter()
+            .take_while(|l| self.edits.reversed_lanes.contains(l))
+            .count();
+        (
+            back[0..num_from_fwds]
+                .iter()
+                .rev()
+                .chain(fwds[num_from_back..].iter())
+                .cloned()
+                .collect(),
+            fwds[0..num_from_back]
+                .iter()
+                .rev()
+                .chain(back[num_from_fwds..].iter())
+                .cloned()
+                .collect(),
+        )
+    }
+
+    // Points the innermost lanes of a road the right way for the new edits. Returns the lanes
+    // that flipped.
+    fn reverse_lanes(
+        &mut self,
+        r: RoadID,
+        reversed_lanes: &BTreeSet<LaneID>,
+        timer: &mut Timer,
+    ) -> Vec<LaneID> {
+        let (orig_fwds, orig_back) = self.get_original_children(r);
+        let num_fwds = orig_fwds
+            .iter()
+            .take_while(|l| reversed_lanes.contains(l))
+            .count();
+        let num_back = orig_back
+            .iter()
+            .take_while(|l| reversed_lanes.contains(l))
+            .count();
+        let new_fwds: Vec<LaneID> = orig_back[0..num_back]
+            .iter()
+            .rev()
+            .chain(orig_fwds[num_fwds..].iter())
+            .cloned()
+            .collect();
+        let new_back: Vec<LaneID> = orig_fwds[0..num_fwds]
+            .iter()
+            .rev()
+            .chain(orig_back[num_back..].iter())
+            .cloned()
+            .collect();
+
+        let mut flipped = Vec::new();
+        for l in &new_fwds {
+            if !self.roads[r.0].is_forwards(*l) {
+                flipped.push(*l);
+            }
+        }
+        for l in &new_back {
+            if self.roads[r.0].is_forwards(*l) {
+                flipped.push(*l);
+            }
+        }
+
+        // The lanes stay in the same physical place, just pointing the other way.
+        for id in &flipped {
+            let l = &mut self.lanes[id.0];
+            l.lane_center_pts = l.lane_center_pts.reversed();
+            let len = l.length();
+            l.elevation_profile = l
+                .elevation_profile
+                .iter()
+                .rev()
+                .map(|(dist, elevation)| (len - *dist, *elevation))
+                .collect();
+            std::mem::swap(&mut l.src_i, &mut l.dst_i);
+            let (src_i, dst_i) = (l.src_i, l.dst_i);
+
+            let src = &mut self.intersections[src_i.0];
+            src.incoming_lanes.retain(|x| x != id);
+            src.outgoing_lanes.push(*id);
+            let dst = &mut self.intersections[dst_i.0];
+            dst.outgoing_lanes.retain(|x| x != id);
+            dst.incoming_lanes.push(*id);
+        }
+
+        // The center line divides the two directions, so it moves over by however many lanes
+        // switched sides.
+        let lanes = &self.lanes;
+        let road = &mut self.roads[r.0];
+        let old_num_fwds = road.children_forwards.len();
+        if new_fwds.len() < old_num_fwds {
+            let shift = LANE_THICKNESS * ((old_num_fwds - new_fwds.len()) as f64);
+            road.center_pts = road
+                .center_pts
+                .shift_right(shift)
+                .with_context(timer, format!("moving the center line of {}", r));
+        } else if new_fwds.len() > old_num_fwds {
+            let shift = LANE_THICKNESS * ((new_fwds.len() - old_num_fwds) as f64);
+            road.center_pts = road
+                .center_pts
+                .shift_left(shift)
+                .with_context(timer, format!("moving the center line of {}", r));
+        }
+        road.children_forwards = new_fwds
+            .into_iter()
+            .map(|l| (l, lanes[l.0].lane_type))
+            .collect();
+        road.children_backwards = new_back
+            .into_iter()
+            .map(|l| (l, lanes[l.0].lane_type))
+            .collect();
+
+        flipped
+    }
+
+    // Only the innermost lane on either side can be reversed, and both sides have to keep at
+    // least one lane. Bus stops refer to driving lanes by distance, so leave those alone.
+    pub fn can_reverse_lane(&self, id: LaneID) -> bool {
+        let l = self.get_l(id);
+        if l.is_sidewalk() {
+            return false;
+        }
+        let r = self.get_parent(id);
+        let (fwds, idx) = r.dir_and_offset(id);
+        if idx != 0 {
+            return false;
+        }
+        let (this_side, other_side) = if fwds {
+            (&r.children_forwards, &r.children_backwards)
+        } else {
+            (&r.children_backwards, &r.children_forwards)
+        };
+        if this_side.len() == 1 || other_side.is_empty() {
+            return false;
+        }
+        !self
+            .bus_stops
+            .values()
+            .any(|bs| bs.driving_pos.lane() == id)
+    }
+
+    // Only valid for intersections with a type override in the current edits
+    fn get_original_intersection_type(&self, id: IntersectionID) -> IntersectionType {
+        match self.intersections[id.0].intersection_type {
+            IntersectionType::StopSign => IntersectionType::TrafficSignal,
+            IntersectionType::TrafficSignal => IntersectionType::StopSign,
+            IntersectionType::Border => unreachable!(),
+        }
+    }
+}
+
+// Roughly 10m on a side, depending on the latitude
+fn original_cell(pt: LonLat) -> (i64, i64) {
+    (
+        (pt.longitude * 10_000.0).floor() as i64,
+        (pt.latitude * 10_000.0).floor() as i64,
+    )
 }
@@ -0,0 +1,38 @@
+use crate::building::guess_num_parking_stalls;
+use crate::{OffstreetParking, Position};
+use abstutil;
+use geom::Polygon;
+use serde_derive::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ParkingLotID(pub usize);
+
+impl fmt::Display for ParkingLotID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ParkingLotID({0})", self.0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ParkingLot {
+    pub id: ParkingLotID,
+    pub polygon: Polygon,
+    pub osm_tags: BTreeMap<String, String>,
+    pub osm_way_id: i64,
+    // Where people walk to and from their cars
+    pub sidewalk_pos: Position,
+    // None if there's no driving lane nearby
+    pub parking: Option<OffstreetParking>,
+}
+
+impl ParkingLot {
+    pub fn dump_debug(&self) {
+        println!("{}", abstutil::to_json(self));
+    }
+
+    pub fn osm_num_parking_stalls(&self) -> usize {
+        guess_num_parking_stalls(&self.osm_tags, &self.polygon)
+    }
+}
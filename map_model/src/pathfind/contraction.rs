@@ -0,0 +1,513 @@
+use crate::pathfind::driving::{cost, end_of, path_from_roads, turn_roads, Outcome};
+use crate::{DirectedRoadID, IntersectionID, LaneType, Map, PathRequest, TurnID};
+use abstutil::{deserialize_btreemap, serialize_btreemap, Timer};
+use geom::Duration;
+use serde_derive::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap};
+
+// A contraction hierarchy over directed roads. Much faster to query than A*, but slow to build.
+//
+// Which nodes get contracted in what order and which shortcuts exist only depend on which roads
+// meet at each intersection, not on turns or costs. So after edits, only the weights have to be
+// recalculated ("customized"), which is cheap compared to contracting again.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ContractionHierarchy {
+    lane_types: Vec<LaneType>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    nodes: BTreeMap<DirectedRoadID, usize>,
+    roads: Vec<DirectedRoadID>,
+    // Lower ranks were contracted first.
+    rank: Vec<usize>,
+    edges: Vec<Edge>,
+    // Per node, the edges leaving it towards a higher rank
+    up: Vec<Vec<usize>>,
+    // Per node, the edges arriving at it from a higher rank
+    down: Vec<Vec<usize>>,
+    // (u->v, v->w, u->w), in the order v was contracted
+    triangles: Vec<(usize, usize, usize)>,
+    // Per edge, the triangles making it a shortcut, in order
+    made_from: Vec<Vec<usize>>,
+    // Per edge, the triangles using it to make a shortcut
+    used_in: Vec<Vec<usize>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Edge {
+    from: usize,
+    to: usize,
+    // Crossing the from road and turning directly onto the to road. None if no turn is usable or
+    // the roads don't even meet.
+    direct: Option<Duration>,
+    // The best of direct and any shortcut
+    weight: Option<Duration>,
+    // If the weight comes from a shortcut, the two edges it's made of
+    shortcut: Option<(usize, usize)>,
+}
+
+impl ContractionHierarchy {
+    pub fn new(map: &Map, lane_types: Vec<LaneType>, timer: &mut Timer) -> ContractionHierarchy {
+        let mut ch = ContractionHierarchy {
+            lane_types,
+            nodes: BTreeMap::new(),
+            roads: Vec::new(),
+            rank: Vec::new(),
+            edges: Vec::new(),
+            up: Vec::new(),
+            down: Vec::new(),
+            triangles: Vec::new(),
+            made_from: Vec::new(),
+            used_in: Vec::new(),
+        };
+        // Same nodes as VehiclePathfinder, so lane type edits don't change the structure.
+        for r in map.all_roads() {
+            if !r.children_forwards.is_empty() {
+                ch.add_node(r.id.forwards());
+            }
+            if !r.children_backwards.is_empty() {
+                ch.add_node(r.id.backwards());
+            }
+        }
+        let num_nodes = ch.roads.len();
+
+        // The graph of nodes not contracted yet, mapping neighbors to the edge.
+        let mut out_adj: Vec<BTreeMap<usize, usize>> = vec![BTreeMap::new(); num_nodes];
+        let mut in_adj: Vec<BTreeMap<usize, usize>> = vec![BTreeMap::new(); num_nodes];
+
+        // Connect every pair of roads meeting at an intersection, even if there's no turn between
+        // them yet.
+        for i in map.all_intersections() {
+            let mut incoming = Vec::new();
+            let mut outgoing = Vec::new();
+            for r in &i.roads {
+                for dr in vec![r.forwards(), r.backwards()] {
+                    if let Some(node) = ch.nodes.get(&dr) {
+                        if end_of(dr, map) == i.id {
+                            incoming.push(*node);
+                        }
+                        if start_of(dr, map) == i.id {
+                            outgoing.push(*node);
+                        }
+                    }
+                }
+            }
+            for from in &incoming {
+                for to in &outgoing {
+                    if from != to && !out_adj[*from].contains_key(to) {
+                        let e = ch.add_edge(*from, *to);
+                        out_adj[*from].insert(*to, e);
+                        in_adj[*to].insert(*from, e);
+                    }
+                }
+            }
+        }
+
+        // Greedily contract whatever adds the fewest shortcuts. Priorities go stale as neighbors
+        // are contracted, so recheck before committing.
+        let mut queue: BinaryHeap<Reverse<(usize, usize)>> = (0..num_nodes)
+            .map(|v| Reverse((fill_in(v, &out_adj, &in_adj), v)))
+            .collect();
+        let mut contracted = vec![false; num_nodes];
+        ch.rank = vec![0; num_nodes];
+        let mut next_rank = 0;
+        timer.start_iter("contract nodes", num_nodes);
+        while let Some(Reverse((priority, v))) = queue.pop() {
+            if contracted[v] {
+                continue;
+            }
+            let current = fill_in(v, &out_adj, &in_adj);
+            if current > priority {
+                queue.push(Reverse((current, v)));
+                continue;
+            }
+            timer.next();
+            contracted[v] = true;
+            ch.rank[v] = next_rank;
+            next_rank += 1;
+
+            let preds: Vec<(usize, usize)> = in_adj[v].iter().map(|(u, e)| (*u, *e)).collect();
+            let succs: Vec<(usize, usize)> = out_adj[v].iter().map(|(w, e)| (*w, *e)).collect();
+            for (u, uv) in &preds {
+                for (w, vw) in &succs {
+                    if u == w {
+                        continue;
+                    }
+                    let uw = if let Some(e) = out_adj[*u].get(w) {
+                        *e
+                    } else {
+                        let e = ch.add_edge(*u, *w);
+                        out_adj[*u].insert(*w, e);
+                        in_adj[*w].insert(*u, e);
+                        e
+                    };
+                    ch.triangles.push((*uv, *vw, uw));
+                }
+            }
+            for (u, _) in preds {
+                out_adj[u].remove(&v);
+            }
+            for (w, _) in succs {
+                in_adj[w].remove(&v);
+            }
+            out_adj[v].clear();
+            in_adj[v].clear();
+        }
+
+        ch.up = vec![Vec::new(); num_nodes];
+        ch.down = vec![Vec::new(); num_nodes];
+        for (idx, e) in ch.edges.iter().enumerate() {
+            if ch.rank[e.to] > ch.rank[e.from] {
+                ch.up[e.from].push(idx);
+            } else {
+                ch.down[e.to].push(idx);
+            }
+        }
+        ch.made_from = vec![Vec::new(); ch.edges.len()];
+        ch.used_in = vec![Vec::new(); ch.edges.len()];
+        for (idx, (uv, vw, uw)) in ch.triangles.iter().enumerate() {
+            ch.made_from[*uw].push(idx);
+            ch.used_in[*uv].push(idx);
+            ch.used_in[*vw].push(idx);
+        }
+        timer.note(format!(
+            "{} nodes, {} edges including shortcuts",
+            num_nodes,
+            ch.edges.len()
+        ));
+
+        timer.start("customize contraction hierarchy");
+        for idx in 0..ch.edges.len() {
+            ch.edges[idx].direct = ch.direct_cost(ch.edges[idx].from, ch.edges[idx].to, map);
+        }
+        ch.customize();
+        timer.stop("customize contraction hierarchy");
+
+        ch
+    }
+
+    fn add_node(&mut self, dr: DirectedRoadID) {
+        self.nodes.insert(dr, self.roads.len());
+        self.roads.push(dr);
+    }
+
+    fn add_edge(&mut self, from: usize, to: usize) -> usize {
+        self.edges.push(Edge {
+            from,
+            to,
+            direct: None,
+            weight: None,
+            shortcut: None,
+        });
+        self.edges.len() - 1
+    }
+
+    fn find_edge(&self, from: usize, to: usize) -> Option<usize> {
+        self.up[from]
+            .iter()
+            .chain(self.down[to].iter())
+            .find(|e| self.edges[**e].from == from && self.edges[**e].to == to)
+            .cloned()
+    }
+
+    // Matches the edge costs of VehiclePathfinder.
+    fn direct_cost(&self, from: usize, to: usize, map: &Map) -> Option<Duration> {
+        let (from, to) = (self.roads[from], self.roads[to]);
+        let i = end_of(from, map);
+        if start_of(to, map) != i {
+            return None;
+        }
+        map.get_i(i)
+            .turns
+            .iter()
+            .filter_map(|t| {
+                let src_l = map.get_l(t.src);
+                let dst_l = map.get_l(t.dst);
+                if map.is_turn_allowed(*t)
+                    && self.lane_types.contains(&src_l.lane_type)
+                    && self.lane_types.contains(&dst_l.lane_type)
+                    && src_l.get_directed_parent(map) == from
+                    && dst_l.get_directed_parent(map) == to
+                {
+                    Some(cost(src_l, map.get_t(*t), &self.lane_types, map))
+                } else {
+                    None
+                }
+            })
+            .min()
+    }
+
+    // Bottom-up, every shortcut u->w through v is the best of the direct edge and u->v->w.
+    fn customize(&mut self) {
+        for e in &mut self.edges {
+            e.weight = e.direct;
+            e.shortcut = None;
+        }
+        let edges = &mut self.edges;
+        for (uv, vw, uw) in &self.triangles {
+            if let (Some(w1), Some(w2)) = (edges[*uv].weight, edges[*vw].weight) {
+                let candidate = w1 + w2;
+                if edges[*uw].weight.map(|w| candidate < w).unwrap_or(true) {
+                    edges[*uw].weight = Some(candidate);
+                    edges[*uw].shortcut = Some((*uv, *vw));
+                }
+            }
+        }
+    }
+
+    // After the direct cost of some edges changes, only redo the shortcuts depending on them.
+    // Shortcuts are only made from edges finished earlier, so going in order of the last
+    // triangle making each edge works bottom-up.
+    fn recustomize(&mut self, changed: Vec<usize>) {
+        let mut queue: BTreeSet<(usize, usize)> =
+            changed.into_iter().map(|e| (self.level(e), e)).collect();
+        while let Some((level, e)) = queue.iter().next().cloned() {
+            queue.remove(&(level, e));
+            let old_weight = self.edges[e].weight;
+            let (weight, shortcut) = self.best_weight(e);
+            self.edges[e].weight = weight;
+            self.edges[e].shortcut = shortcut;
+            if weight != old_weight {
+                for t in &self.used_in[e] {
+                    let uw = self.triangles[*t].2;
+                    queue.insert((self.level(uw), uw));
+                }
+            }
+        }
+    }
+
+    fn level(&self, e: usize) -> usize {
+        self.made_from[e].last().map(|t| t + 1).unwrap_or(0)
+    }
+
+    // The same as customize() figures out, assuming everything this edge is made from is done
+    fn best_weight(&self, e: usize) -> (Option<Duration>, Option<(usize, usize)>) {
+        let mut weight = self.edges[e].direct;
+        let mut shortcut = None;
+        for t in &self.made_from[e] {
+            let (uv, vw, _) = self.triangles[*t];
+            if let (Some(w1), Some(w2)) = (self.edges[uv].weight, self.edges[vw].weight) {
+                let candidate = w1 + w2;
+                if weight.map(|w| candidate < w).unwrap_or(true) {
+                    weight = Some(candidate);
+                    shortcut = Some((uv, vw));
+                }
+            }
+        }
+        (weight, shortcut)
+    }
+
+    pub fn pathfind(&self, req: &PathRequest, map: &Map) -> Outcome {
+        assert!(!map.get_l(req.start.lane()).is_sidewalk());
+
+        let start = self.nodes[&map.get_l(req.start.lane()).get_directed_parent(map)];
+        let end = self.nodes[&map.get_l(req.end.lane()).get_directed_parent(map)];
+
+        let mut forwards = Search::new(start);
+        let mut backwards = Search::new(end);
+        // The cheapest meeting point so far
+        let mut best: Option<(Duration, usize)> = None;
+        loop {
+            // Both searches only go up the hierarchy, so the cheapest path might only be
+            // reachable from one side at its top. Each side has to keep going until nothing left
+            // on it could beat the best, which also means the two frontiers add up to more than
+            // the best.
+            let fwd_done = forwards.done(best);
+            let back_done = backwards.done(best);
+            if fwd_done && back_done {
+                break;
+            }
+            let go_forwards = if fwd_done {
+                false
+            } else if back_done {
+                true
+            } else {
+                forwards.min_cost() <= backwards.min_cost()
+            };
+            let (search, other) = if go_forwards {
+                (&mut forwards, &backwards)
+            } else {
+                (&mut backwards, &forwards)
+            };
+            if let Some((node, cost)) = self.step(search, go_forwards) {
+                if let Some((other_cost, _)) = other.best.get(&node) {
+                    let total = cost + *other_cost;
+                    if best.map(|(c, _)| total < c).unwrap_or(true) {
+                        best = Some((total, node));
+                    }
+                }
+            }
+        }
+        let meet = match best {
+            Some((_, n)) => n,
+            None => {
+                return Outcome::Failure;
+            }
+        };
+        let (forwards, backwards) = (forwards.best, backwards.best);
+
+        let mut path_edges = Vec::new();
+        let mut current = meet;
+        while let Some(e) = forwards[&current].1 {
+            path_edges.push(e);
+            current = self.edges[e].from;
+        }
+        path_edges.reverse();
+        current = meet;
+        while let Some(e) = backwards[&current].1 {
+            path_edges.push(e);
+            current = self.edges[e].to;
+        }
+
+        let mut roads = vec![self.roads[start]];
+        for e in path_edges {
+            self.unpack(e, &mut roads);
+        }
+
+        // The hierarchy doesn't know about turn restrictions spanning two intersections.
+        if roads.windows(3).any(|triple| {
+            map.get_complicated_turn_restrictions(triple[0].id)
+                .contains(&(triple[1].id, triple[2].id))
+        }) {
+            return Outcome::RetrySlow;
+        }
+
+        path_from_roads(roads, req, map)
+    }
+
+    // One step of Dijkstra that only goes up the hierarchy. Returns the node reached and its
+    // cost, unless there's nothing left to do.
+    fn step(&self, search: &mut Search, forwards: bool) -> Option<(usize, Duration)> {
+        while let Some(Reverse((cost, node))) = search.queue.pop() {
+            if cost > search.best[&node].0 {
+                continue;
+            }
+            let edges = if forwards {
+                &self.up[node]
+            } else {
+                &self.down[node]
+            };
+            for e in edges {
+                let edge = &self.edges[*e];
+                let weight = match edge.weight {
+                    Some(w) => w,
+                    None => {
+                        continue;
+                    }
+                };
+                let next = if forwards { edge.to } else { edge.from };
+                let total = cost + weight;
+                if search
+                    .best
+                    .get(&next)
+                    .map(|(c, _)| total < *c)
+                    .unwrap_or(true)
+                {
+                    search.best.insert(next, (total, Some(*e)));
+                    search.queue.push(Reverse((total, next)));
+                }
+            }
+            return Some((node, cost));
+        }
+        None
+    }
+
+    // Appends the roads after the edge's from node.
+    fn unpack(&self, e: usize, roads: &mut Vec<DirectedRoadID>) {
+        if let Some((e1, e2)) = self.edges[e].shortcut {
+            self.unpack(e1, roads);
+            self.unpack(e2, roads);
+        } else {
+            roads.push(self.roads[self.edges[e].to]);
+        }
+    }
+
+    pub fn apply_edits(
+        &mut self,
+        delete_turns: &BTreeSet<TurnID>,
+        add_turns: &BTreeSet<TurnID>,
+        map: &Map,
+    ) {
+        let mut changed: BTreeSet<(usize, usize)> = BTreeSet::new();
+        for t in delete_turns.iter().chain(add_turns.iter()) {
+            let (from, to) = turn_roads(*t, map);
+            if let (Some(u), Some(w)) = (self.nodes.get(&from), self.nodes.get(&to)) {
+                changed.insert((*u, *w));
+            }
+        }
+        if changed.is_empty() {
+            return;
+        }
+
+        let mut changed_edges = Vec::new();
+        for (u, w) in changed {
+            if let Some(e) = self.find_edge(u, w) {
+                let direct = self.direct_cost(u, w, map);
+                if direct != self.edges[e].direct {
+                    self.edges[e].direct = direct;
+                    changed_edges.push(e);
+                }
+            }
+        }
+        self.recustomize(changed_edges);
+    }
+}
+
+// One direction of a query. For each reached node, the cost and the edge used to get there.
+struct Search {
+    best: BTreeMap<usize, (Duration, Option<usize>)>,
+    queue: BinaryHeap<Reverse<(Duration, usize)>>,
+}
+
+impl Search {
+    fn new(start: usize) -> Search {
+        let mut best = BTreeMap::new();
+        best.insert(start, (Duration::ZERO, None));
+        let mut queue = BinaryHeap::new();
+        queue.push(Reverse((Duration::ZERO, start)));
+        Search { best, queue }
+    }
+
+    // A lower bound on anything this search finds from now on. Stale queue entries only make it
+    // lower.
+    fn min_cost(&self) -> Option<Duration> {
+        self.queue.peek().map(|Reverse((cost, _))| *cost)
+    }
+
+    fn done(&self, best: Option<(Duration, usize)>) -> bool {
+        match (self.min_cost(), best) {
+            (None, _) => true,
+            (Some(cost), Some((best, _))) => cost >= best,
+            (Some(_), None) => false,
+        }
+    }
+}
+
+// How many shortcuts contracting this node would add
+fn fill_in(
+    v: usize,
+    out_adj: &Vec<BTreeMap<usize, usize>>,
+    in_adj: &Vec<BTreeMap<usize, usize>>,
+) -> usize {
+    let mut count = 0;
+    for u in in_adj[v].keys() {
+        for w in out_adj[v].keys() {
+            if u != w && !out_adj[*u].contains_key(w) {
+                count += 1;
+            }
+        }
+    }
+    count
+}
+
+// The intersection at the start of a directed road
+fn start_of(dr: DirectedRoadID, map: &Map) -> IntersectionID {
+    let r = map.get_r(dr.id);
+    if dr.forwards {
+        r.src_i
+    } else {
+        r.dst_i
+    }
+}
@@ -1,22 +1,66 @@
-use crate::{DirectedRoadID, LaneID, LaneType, Map, Path, PathRequest, PathStep, Turn, TurnID};
+use crate::{
+    DirectedRoadID, IntersectionID, Lane, LaneID, LaneType, Map, Path, PathRequest, PathStep,
+    Position, RoadID, Turn, TurnID, TurnPriority,
+};
 use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::Distance;
+use geom::{Distance, Duration, Speed};
 use petgraph::graph::NodeIndex;
 use petgraph::stable_graph::StableGraph;
+use petgraph::visit::EdgeRef;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, VecDeque};
+use std::ops;
+
+// Rough time lost at a stop sign, including slowing down and speeding back up
+const STOP_SIGN_DELAY: Duration = Duration::const_seconds(5.0);
+// Cyclists avoid hills even when it takes longer. A 5% climb feels twice as long.
+const CLIMB_PENALTY: f64 = 20.0;
 
 // TODO Make the graph smaller by considering RoadID, or even (directed?) bundles of roads based on
 // OSM way.
+// Edges are weighted by the time to cross the source road and the turn.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct VehiclePathfinder {
-    graph: StableGraph<DirectedRoadID, Distance>,
+    graph: StableGraph<DirectedRoadID, Duration>,
     #[serde(
         serialize_with = "serialize_btreemap",
         deserialize_with = "deserialize_btreemap"
     )]
     nodes: BTreeMap<DirectedRoadID, NodeIndex<u32>>,
     lane_types: Vec<LaneType>,
+    // For the A* heuristic to never overestimate
+    max_speed: Speed,
+    // Complicated turn restrictions span two intersections, so they can't be expressed by
+    // removing one edge. Instead, arriving at the via road from the restricted road leads to a
+    // copy of the via road's node, missing the banned edges.
+    restricted_copies: Vec<RestrictedCopy>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct RestrictedCopy {
+    node: NodeIndex<u32>,
+    from: DirectedRoadID,
+    via: DirectedRoadID,
+}
+
+// petgraph's search needs costs with a Default. That doesn't make sense for a Duration in
+// general, so wrap it.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+struct Cost(Duration);
+
+impl Default for Cost {
+    fn default() -> Cost {
+        Cost(Duration::ZERO)
+    }
+}
+
+impl ops::Add for Cost {
+    type Output = Cost;
+
+    fn add(self, other: Cost) -> Cost {
+        Cost(self.0 + other.0)
+    }
 }
 
 pub enum Outcome {
@@ -31,6 +75,8 @@ impl VehiclePathfinder {
             graph: StableGraph::new(),
             nodes: BTreeMap::new(),
             lane_types,
+            max_speed: max_speed(map),
+            restricted_copies: Vec::new(),
         };
 
         for r in map.all_roads() {
@@ -49,6 +95,7 @@ impl VehiclePathfinder {
         for t in map.all_turns().values() {
             g.add_turn(t, map);
         }
+        g.add_complicated_restrictions(map);
 
         /*println!(
             "{} nodes, {} edges",
@@ -69,10 +116,86 @@ impl VehiclePathfinder {
         {
             let src = self.get_node(t.id.src, map);
             let dst = self.get_node(t.id.dst, map);
-            // First length arbitrarily wins.
-            if self.graph.find_edge(src, dst).is_none() {
-                self.graph
-                    .add_edge(src, dst, src_l.length() + t.geom.length());
+            // The cheapest turn between the two roads wins, so buses prefer bus lanes.
+            let cost = cost(src_l, t, &self.lane_types, map);
+            if let Some(e) = self.graph.find_edge(src, dst) {
+                if cost < self.graph[e] {
+                    self.graph[e] = cost;
+                }
+            } else {
+                self.graph.add_edge(src, dst, cost);
+            }
+        }
+    }
+
+    fn add_complicated_restrictions(&mut self, map: &Map) {
+        let mut banned: BTreeMap<(DirectedRoadID, DirectedRoadID), BTreeSet<RoadID>> =
+            BTreeMap::new();
+        for r in map.all_roads() {
+            for (via, to) in map.get_complicated_turn_restrictions(r.id) {
+                let via_r = map.get_r(via);
+                for from in vec![r.id.forwards(), r.id.backwards()] {
+                    let i = end_of(from, map);
+                    let via_dir = if via_r.src_i == i {
+                        via.forwards()
+                    } else if via_r.dst_i == i {
+                        via.backwards()
+                    } else {
+                        continue;
+                    };
+                    banned
+                        .entry((from, via_dir))
+                        .or_insert_with(BTreeSet::new)
+                        .insert(to);
+                }
+            }
+        }
+
+        for ((from, via), to) in banned {
+            let (from_node, via_node) = match (self.nodes.get(&from), self.nodes.get(&via)) {
+                (Some(n1), Some(n2)) => (*n1, *n2),
+                _ => {
+                    continue;
+                }
+            };
+            let edge = match self.graph.find_edge(from_node, via_node) {
+                Some(e) => e,
+                None => {
+                    continue;
+                }
+            };
+            let weight = self.graph[edge];
+            let outgoing: Vec<(NodeIndex<u32>, Duration)> = self
+                .graph
+                .edges(via_node)
+                .filter(|e| !to.contains(&self.graph[e.target()].id))
+                .map(|e| (e.target(), *e.weight()))
+                .collect();
+
+            let copy = self.graph.add_node(via);
+            self.graph.remove_edge(edge);
+            self.graph.add_edge(from_node, copy, weight);
+            for (next, weight) in outgoing {
+                self.graph.add_edge(copy, next, weight);
+            }
+            self.restricted_copies.push(RestrictedCopy {
+                node: copy,
+                from,
+                via,
+            });
+        }
+    }
+
+    // Puts back the direct edges that the copies replaced, if some turn still allows them.
+    fn remove_complicated_restrictions(&mut self, map: &Map) {
+        for copy in std::mem::replace(&mut self.restricted_copies, Vec::new()) {
+            self.graph.remove_node(copy.node);
+            for t in &map.get_i(end_of(copy.from, map)).turns {
+                if map.get_l(t.src).get_directed_parent(map) == copy.from
+                    && map.get_l(t.dst).get_directed_parent(map) == copy.via
+                {
+                    self.add_turn(map.get_t(*t), map);
+                }
             }
         }
     }
@@ -84,77 +207,102 @@ impl VehiclePathfinder {
     pub fn pathfind(&self, req: &PathRequest, map: &Map) -> Outcome {
         assert!(!map.get_l(req.start.lane()).is_sidewalk());
 
+        let raw_nodes = match self.astar(req, map) {
+            Some((_, nodes)) => nodes,
+            None => {
+                return Outcome::Failure;
+            }
+        };
+
+        path_from_roads(
+            raw_nodes.into_iter().map(|n| self.graph[n]).collect(),
+            req,
+            map,
+        )
+    }
+
+    // Roughly how long the best path takes, without building it. The whole first lane counts.
+    pub fn estimate_time(&self, req: &PathRequest, map: &Map) -> Option<Duration> {
+        let (cost, _) = self.astar(req, map)?;
+        let end_speed = map
+            .get_parent(req.end.lane())
+            .get_lane_speed_limit(req.end.lane());
+        Some(cost + req.end.dist_along() / end_speed)
+    }
+
+    // Roughly how long it takes to reach the start of every directed road from here, going no
+    // faster than max_speed. Like estimate_time, the whole first lane counts.
+    pub fn times_from(
+        &self,
+        start: Position,
+        max_speed: Option<Speed>,
+        map: &Map,
+    ) -> HashMap<DirectedRoadID, Duration> {
+        let start_node = self.get_node(start.lane(), map);
+        let mut best: HashMap<NodeIndex<u32>, Duration> = HashMap::new();
+        let mut queue: BinaryHeap<Reverse<(Duration, NodeIndex<u32>)>> = BinaryHeap::new();
+        best.insert(start_node, Duration::ZERO);
+        queue.push(Reverse((Duration::ZERO, start_node)));
+
+        while let Some(Reverse((cost, node))) = queue.pop() {
+            if cost > best[&node] {
+                continue;
+            }
+            // The edge weights assume the speed limit. Slower vehicles can't do better than
+            // crossing the road at their own top speed.
+            let min_time =
+                max_speed.map(|s| map.get_r(self.graph[node].id).center_pts.length() / s);
+            for e in self.graph.edges(node) {
+                let mut dt = *e.weight();
+                if let Some(t) = min_time {
+                    dt = dt.max(t);
+                }
+                let next_cost = cost + dt;
+                if best
+                    .get(&e.target())
+                    .map(|c| next_cost < *c)
+                    .unwrap_or(true)
+                {
+                    best.insert(e.target(), next_cost);
+                    queue.push(Reverse((next_cost, e.target())));
+                }
+            }
+        }
+
+        // Restricted copies of a road might have been reached separately; keep the fastest.
+        let mut times: HashMap<DirectedRoadID, Duration> = HashMap::new();
+        for (node, cost) in best {
+            let dr = self.graph[node];
+            if times.get(&dr).map(|c| cost < *c).unwrap_or(true) {
+                times.insert(dr, cost);
+            }
+        }
+        times
+    }
+
+    fn astar(&self, req: &PathRequest, map: &Map) -> Option<(Duration, Vec<NodeIndex<u32>>)> {
         let start_node = self.get_node(req.start.lane(), map);
-        let end_node = self.get_node(req.end.lane(), map);
+        // There might be copies of the end node, so don't compare node indices.
+        let end_road = map.get_l(req.end.lane()).get_directed_parent(map);
         let end_pt = map.get_l(req.end.lane()).first_pt();
 
-        let raw_nodes = match petgraph::algo::astar(
+        let (cost, nodes) = petgraph::algo::astar(
             &self.graph,
             start_node,
-            |n| n == end_node,
-            |e| *e.weight(),
+            |n| self.graph[n] == end_road,
+            |e| Cost(*e.weight()),
             |n| {
                 let dr = self.graph[n];
                 let r = map.get_r(dr.id);
-                if dr.forwards {
+                let dist = if dr.forwards {
                     end_pt.dist_to(r.center_pts.last_pt())
                 } else {
                     end_pt.dist_to(r.center_pts.first_pt())
-                }
-            },
-        ) {
-            Some((_, nodes)) => nodes,
-            None => {
-                return Outcome::Failure;
-            }
-        };
-
-        // TODO windows(2) would be fine for peeking, except it drops the last element for odd
-        // cardinality
-        let mut nodes = VecDeque::from(raw_nodes);
-
-        let mut steps: Vec<PathStep> = Vec::new();
-        while !nodes.is_empty() {
-            let n = nodes.pop_front().unwrap();
-            let dr = self.graph[n];
-            if steps.is_empty() {
-                steps.push(PathStep::Lane(req.start.lane()));
-            } else {
-                let from_lane = match steps.last() {
-                    Some(PathStep::Lane(l)) => *l,
-                    _ => unreachable!(),
                 };
-                if let Some(turn) = map.get_turns_from_lane(from_lane).into_iter().find(|t| {
-                    // Special case the last step
-                    if nodes.is_empty() {
-                        t.id.dst == req.end.lane()
-                    } else {
-                        let l = map.get_l(t.id.dst);
-                        if l.get_directed_parent(map) == dr {
-                            // TODO different case when nodes.len() == 1.
-                            map.get_turns_from_lane(l.id).into_iter().any(|t2| {
-                                map.get_l(t2.id.dst).get_directed_parent(map)
-                                    == self.graph[nodes[0]]
-                            })
-                        } else {
-                            false
-                        }
-                    }
-                }) {
-                    steps.push(PathStep::Turn(turn.id));
-                    steps.push(PathStep::Lane(turn.id.dst));
-                } else {
-                    if steps.len() == 1 {
-                        // Started in the wrong lane
-                        return Outcome::RetrySlow;
-                    } else {
-                        // Need more lookahead to stitch together the right path
-                        return Outcome::RetrySlow;
-                    }
-                }
-            }
-        }
-        Outcome::Success(Path::new(map, steps, req.end.dist_along()))
+                Cost(dist / self.max_speed)
+            },
+        )?;
+        Some((cost.0, nodes))
     }
 
     pub fn apply_edits(
@@ -163,19 +311,164 @@ impl VehiclePathfinder {
         add_turns: &BTreeSet<TurnID>,
         map: &Map,
     ) {
+        self.remove_complicated_restrictions(map);
+        // Speed limits might've been raised.
+        self.max_speed = max_speed(map);
+
         // Most turns will be in both lists. That's fine -- we want to re-add the same turn and
         // check if the lane type is different.
         for t in delete_turns {
-            if let Some(e) = self
-                .graph
-                .find_edge(self.get_node(t.src, map), self.get_node(t.dst, map))
-            {
-                self.graph.remove_edge(e);
+            let (from, to) = turn_roads(*t, map);
+            if let (Some(src), Some(dst)) = (self.nodes.get(&from), self.nodes.get(&to)) {
+                if let Some(e) = self.graph.find_edge(*src, *dst) {
+                    self.graph.remove_edge(e);
+                }
             }
         }
 
         for t in add_turns {
             self.add_turn(map.get_t(*t), map);
         }
+
+        self.add_complicated_restrictions(map);
+    }
+}
+
+// Turns a sequence of directed roads into lanes and turns. If the specific lanes don't line up,
+// the caller has to fall back to something slower.
+pub fn path_from_roads(roads: Vec<DirectedRoadID>, req: &PathRequest, map: &Map) -> Outcome {
+    // TODO windows(2) would be fine for peeking, except it drops the last element for odd
+    // cardinality
+    let mut nodes = VecDeque::from(roads);
+
+    let mut steps: Vec<PathStep> = Vec::new();
+    while !nodes.is_empty() {
+        let dr = nodes.pop_front().unwrap();
+        if steps.is_empty() {
+            steps.push(PathStep::Lane(req.start.lane()));
+        } else {
+            let from_lane = match steps.last() {
+                Some(PathStep::Lane(l)) => *l,
+                _ => unreachable!(),
+            };
+            if let Some(turn) = map.get_turns_from_lane(from_lane).into_iter().find(|t| {
+                // Special case the last step
+                if nodes.is_empty() {
+                    t.id.dst == req.end.lane()
+                } else {
+                    let l = map.get_l(t.id.dst);
+                    if l.get_directed_parent(map) == dr {
+                        // TODO different case when nodes.len() == 1.
+                        map.get_turns_from_lane(l.id)
+                            .into_iter()
+                            .any(|t2| map.get_l(t2.id.dst).get_directed_parent(map) == nodes[0])
+                    } else {
+                        false
+                    }
+                }
+            }) {
+                steps.push(PathStep::Turn(turn.id));
+                steps.push(PathStep::Lane(turn.id.dst));
+            } else {
+                if steps.len() == 1 {
+                    // Started in the wrong lane
+                    return Outcome::RetrySlow;
+                } else {
+                    // Need more lookahead to stitch together the right path
+                    return Outcome::RetrySlow;
+                }
+            }
+        }
+    }
+    Outcome::Success(Path::new(map, steps, req.end.dist_along()))
+}
+
+// If a previous simulation observed how long it takes to cross the road, use that. Otherwise
+// assume free-flow at the speed limit, plus some delay from the intersection. Buses in a bus lane
+// skip the traffic that was observed. Bikes also pay for climbing.
+pub fn cost(src_l: &Lane, t: &Turn, lane_types: &[LaneType], map: &Map) -> Duration {
+    let speed = map.get_parent(src_l.id).get_lane_speed_limit(src_l.id);
+    let turn_time = t.geom.length() / speed;
+    let free_flow = src_l.length() / speed;
+    let mut total = free_flow + control_delay(t.id, map);
+    if src_l.lane_type != LaneType::Bus {
+        if let Some(dt) = map
+            .get_observed_travel_times()
+            .get(&src_l.get_directed_parent(map))
+        {
+            // This already includes waiting at the intersection.
+            total = *dt;
+        }
+    }
+    if lane_types.contains(&LaneType::Biking) {
+        let grade = src_l.grade(Distance::ZERO, src_l.length());
+        if grade > 0.0 {
+            total += free_flow * (CLIMB_PENALTY * grade);
+        }
+    }
+    total + turn_time
+}
+
+fn max_speed(map: &Map) -> Speed {
+    map.all_roads()
+        .iter()
+        .map(|r| r.get_speed_limit())
+        .fold(Speed::ZERO, |a, b| if b > a { b } else { a })
+}
+
+// On average, how long is spent waiting to do this turn?
+fn control_delay(t: TurnID, map: &Map) -> Duration {
+    if let Some(ss) = map.maybe_get_stop_sign(t.parent) {
+        if ss.get_priority(t) == TurnPriority::Stop {
+            STOP_SIGN_DELAY
+        } else {
+            Duration::ZERO
+        }
+    } else if let Some(ts) = map.maybe_get_traffic_signal(t.parent) {
+        // Arriving at a random time, the expected wait is red^2 / (2 * cycle length), treating
+        // all of the red time as one block.
+        let mut total = Duration::ZERO;
+        let mut red = Duration::ZERO;
+        for cycle in &ts.cycles {
+            total += cycle.duration;
+            if cycle.get_priority(t) == TurnPriority::Banned {
+                red += cycle.duration;
+            }
+        }
+        if total == Duration::ZERO {
+            return Duration::ZERO;
+        }
+        Duration::seconds(red.inner_seconds().powi(2) / (2.0 * total.inner_seconds()))
+    } else {
+        Duration::ZERO
+    }
+}
+
+// The directed roads a turn connects. Lanes might've been reversed since the turn was made, so go
+// by the intersection instead of the lanes' current direction.
+pub fn turn_roads(t: TurnID, map: &Map) -> (DirectedRoadID, DirectedRoadID) {
+    let src = map.get_r(map.get_l(t.src).parent);
+    let dst = map.get_r(map.get_l(t.dst).parent);
+    (
+        if src.dst_i == t.parent {
+            src.id.forwards()
+        } else {
+            src.id.backwards()
+        },
+        if dst.src_i == t.parent {
+            dst.id.forwards()
+        } else {
+            dst.id.backwards()
+        },
+    )
+}
+
+// The intersection at the end of a directed road
+pub fn end_of(dr: DirectedRoadID, map: &Map) -> IntersectionID {
+    let r = map.get_r(dr.id);
+    if dr.forwards {
+        r.dst_i
+    } else {
+        r.src_i
     }
 }
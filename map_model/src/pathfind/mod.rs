@@ -1,11 +1,14 @@
+mod contraction;
 mod driving;
 pub mod slow;
 mod walking;
 
+use self::contraction::ContractionHierarchy;
 use self::driving::{Outcome, VehiclePathfinder};
 use self::walking::SidewalkPathfinder;
 use crate::{BusRouteID, BusStopID, LaneID, LaneType, Map, Position, Traversable, TurnID};
-use geom::{Distance, PolyLine};
+use abstutil::Timer;
+use geom::{Distance, Duration, PolyLine, Speed};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeSet, VecDeque};
 use std::fmt;
@@ -127,10 +130,44 @@ impl Path {
         self.steps.push_back(step);
     }
 
+    // Swap the current lane for a parallel one, along with the turn leaving it. The turn must
+    // lead to the same next lane as before.
+    pub fn change_lanes(&mut self, new_lane: LaneID, new_turn: TurnID) {
+        match self.steps[0] {
+            PathStep::Lane(_) => {}
+            x => panic!("Can't change lanes from {:?}", x),
+        }
+        let old_turn = self.steps[1].as_turn();
+        assert_eq!(old_turn.dst, new_turn.dst);
+        assert_eq!(new_turn.src, new_lane);
+        self.steps[0] = PathStep::Lane(new_lane);
+        self.steps[1] = PathStep::Turn(new_turn);
+    }
+
     pub fn current_step(&self) -> PathStep {
         self.steps[0]
     }
 
+    // From start_dist along the first step to end_dist along the last step.
+    pub fn total_length(&self, start_dist: Distance, map: &Map) -> Distance {
+        let mut total = Distance::ZERO;
+        for (idx, step) in self.steps.iter().enumerate() {
+            let len = step.as_traversable().length(map);
+            let (mut from, mut to) = match step {
+                PathStep::ContraflowLane(_) => (len, Distance::ZERO),
+                _ => (Distance::ZERO, len),
+            };
+            if idx == 0 {
+                from = start_dist;
+            }
+            if idx == self.steps.len() - 1 {
+                to = self.end_dist;
+            }
+            total += if to > from { to - from } else { from - to };
+        }
+        total
+    }
+
     pub fn next_step(&self) -> PathStep {
         self.steps[1]
     }
@@ -271,6 +308,45 @@ impl fmt::Display for PathRequest {
     }
 }
 
+// Both positions are on sidewalks.
+#[derive(Clone)]
+pub struct TransitRequest {
+    pub start: Position,
+    pub end: Position,
+    pub walk_speed: Speed,
+    pub access: TransitAccess,
+}
+
+// How to get to the first bus stop
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum TransitAccess {
+    Walk,
+    // Bike or drive there from this driving position, going no faster than the speed limit or
+    // max_speed
+    Vehicle {
+        start: Position,
+        can_use_bike_lanes: bool,
+        max_speed: Option<Speed>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct TransitPlan {
+    // In order. Transfers might involve walking to another stop, or just waiting at the same one.
+    pub rides: Vec<(BusStopID, BusStopID, BusRouteID)>,
+    // Getting to the first stop by vehicle. Zero when the access is walking.
+    pub access_time: Duration,
+    pub walking_time: Duration,
+    // Waiting for buses and riding them
+    pub transit_time: Duration,
+}
+
+impl TransitPlan {
+    pub fn total_time(&self) -> Duration {
+        self.access_time + self.walking_time + self.transit_time
+    }
+}
+
 fn validate(map: &Map, steps: &Vec<PathStep>) {
     if steps.is_empty() {
         panic!("Empty Path");
@@ -321,19 +397,58 @@ pub struct Pathfinder {
     bus_graph: VehiclePathfinder,
     walking_graph: SidewalkPathfinder,
     walking_with_transit_graph: SidewalkPathfinder,
+    // Optional, since they're slow to prepare. When present, vehicles use them before falling
+    // back to the graphs above.
+    car_ch: Option<ContractionHierarchy>,
+    bike_ch: Option<ContractionHierarchy>,
+    bus_ch: Option<ContractionHierarchy>,
 }
 
 impl Pathfinder {
     pub fn new(map: &Map) -> Pathfinder {
+        let bus_graph = VehiclePathfinder::new(map, vec![LaneType::Driving, LaneType::Bus]);
         Pathfinder {
             car_graph: VehiclePathfinder::new(map, vec![LaneType::Driving]),
             bike_graph: VehiclePathfinder::new(map, vec![LaneType::Driving, LaneType::Biking]),
-            bus_graph: VehiclePathfinder::new(map, vec![LaneType::Driving, LaneType::Bus]),
-            walking_graph: SidewalkPathfinder::new(map, false),
-            walking_with_transit_graph: SidewalkPathfinder::new(map, true),
+            walking_graph: SidewalkPathfinder::new(map, None),
+            walking_with_transit_graph: SidewalkPathfinder::new(map, Some(&bus_graph)),
+            bus_graph,
+            car_ch: None,
+            bike_ch: None,
+            bus_ch: None,
         }
     }
 
+    pub fn has_contraction_hierarchies(&self) -> bool {
+        self.car_ch.is_some()
+    }
+
+    pub fn prepare_contraction_hierarchies(&mut self, map: &Map, timer: &mut Timer) {
+        timer.start("prepare contraction hierarchies");
+        self.car_ch = Some(ContractionHierarchy::new(
+            map,
+            vec![LaneType::Driving],
+            timer,
+        ));
+        self.bike_ch = Some(ContractionHierarchy::new(
+            map,
+            vec![LaneType::Driving, LaneType::Biking],
+            timer,
+        ));
+        self.bus_ch = Some(ContractionHierarchy::new(
+            map,
+            vec![LaneType::Driving, LaneType::Bus],
+            timer,
+        ));
+        timer.stop("prepare contraction hierarchies");
+    }
+
+    pub fn drop_contraction_hierarchies(&mut self) {
+        self.car_ch = None;
+        self.bike_ch = None;
+        self.bus_ch = None;
+    }
+
     pub fn pathfind(&self, req: PathRequest, map: &Map) -> Option<Path> {
         // Weird case, but it can happen for walking from a building path to a bus stop that're
         // actually at the same spot.
@@ -351,11 +466,11 @@ impl Pathfinder {
                 None => Outcome::Failure,
             }
         } else if req.can_use_bus_lanes {
-            self.bus_graph.pathfind(&req, map)
+            vehicle_pathfind(&self.bus_ch, &self.bus_graph, &req, map)
         } else if req.can_use_bike_lanes {
-            self.bike_graph.pathfind(&req, map)
+            vehicle_pathfind(&self.bike_ch, &self.bike_graph, &req, map)
         } else {
-            self.car_graph.pathfind(&req, map)
+            vehicle_pathfind(&self.car_ch, &self.car_graph, &req, map)
         };
         match outcome {
             Outcome::Success(path) => Some(path),
@@ -364,14 +479,28 @@ impl Pathfinder {
         }
     }
 
-    pub fn should_use_transit(
-        &self,
-        map: &Map,
-        start: Position,
-        end: Position,
-    ) -> Option<(BusStopID, BusStopID, BusRouteID)> {
+    // Only for vehicles. Doesn't use contraction hierarchies, which don't keep costs around.
+    pub fn estimate_travel_time(&self, req: &PathRequest, map: &Map) -> Option<Duration> {
+        if req.can_use_bus_lanes {
+            self.bus_graph.estimate_time(req, map)
+        } else if req.can_use_bike_lanes {
+            self.bike_graph.estimate_time(req, map)
+        } else {
+            self.car_graph.estimate_time(req, map)
+        }
+    }
+
+    pub fn plan_transit(&self, req: &TransitRequest, map: &Map) -> Option<TransitPlan> {
+        let access_graph = match req.access {
+            TransitAccess::Walk => None,
+            TransitAccess::Vehicle {
+                can_use_bike_lanes: true,
+                ..
+            } => Some(&self.bike_graph),
+            TransitAccess::Vehicle { .. } => Some(&self.car_graph),
+        };
         self.walking_with_transit_graph
-            .should_use_transit(map, start, end)
+            .plan_transit(req, access_graph, map)
     }
 
     pub fn apply_edits(
@@ -383,5 +512,35 @@ impl Pathfinder {
         self.car_graph.apply_edits(delete_turns, add_turns, map);
         self.bike_graph.apply_edits(delete_turns, add_turns, map);
         self.bus_graph.apply_edits(delete_turns, add_turns, map);
+        for ch in vec![&mut self.car_ch, &mut self.bike_ch, &mut self.bus_ch] {
+            if let Some(ch) = ch {
+                ch.apply_edits(delete_turns, add_turns, map);
+            }
+        }
+        self.walking_graph
+            .apply_edits(delete_turns, add_turns, None, map);
+        self.walking_with_transit_graph.apply_edits(
+            delete_turns,
+            add_turns,
+            Some(&self.bus_graph),
+            map,
+        );
+    }
+}
+
+fn vehicle_pathfind(
+    ch: &Option<ContractionHierarchy>,
+    graph: &VehiclePathfinder,
+    req: &PathRequest,
+    map: &Map,
+) -> Outcome {
+    if let Some(ch) = ch {
+        match ch.pathfind(req, map) {
+            Outcome::RetrySlow => {}
+            outcome => {
+                return outcome;
+            }
+        }
     }
+    graph.pathfind(req, map)
 }
@@ -1,4 +1,4 @@
-use crate::{LaneType, Map, Path, PathRequest, PathStep, Position, Traversable};
+use crate::{LaneType, Map, Path, PathRequest, PathStep, Position, RoadID, Traversable};
 use geom::{Distance, Pt2D};
 use ordered_float::NotNan;
 use std::collections::{BinaryHeap, HashMap};
@@ -32,16 +32,30 @@ struct SlowPathfinder {
 }
 
 impl SlowPathfinder {
-    fn expand(&self, map: &Map, current: PathStep) -> Vec<PathStep> {
+    // prev is the step leading to current, if any.
+    fn expand(&self, map: &Map, current: PathStep, prev: Option<PathStep>) -> Vec<PathStep> {
         let mut results: Vec<PathStep> = Vec::new();
         match current {
             PathStep::Lane(l) => {
+                // Only the best way of reaching this lane is remembered, so this could miss a
+                // path that enters the via road some other way.
+                let banned: Vec<(RoadID, RoadID)> = match prev {
+                    Some(PathStep::Turn(t)) => map
+                        .get_complicated_turn_restrictions(map.get_l(t.src).parent)
+                        .into_iter()
+                        .filter(|(via, _)| *via == map.get_l(l).parent)
+                        .collect(),
+                    _ => Vec::new(),
+                };
+
                 for (turn, next) in map
                     .get_next_turns_and_lanes(l, map.get_l(l).dst_i)
                     .into_iter()
                 {
                     if !map.is_turn_allowed(turn.id) {
                         // Skip
+                    } else if banned.iter().any(|(_, to)| *to == next.parent) {
+                        // Skip
                     } else if !self.can_use_bike_lanes && next.lane_type == LaneType::Biking {
                         // Skip
                     } else if !self.can_use_bus_lanes && next.lane_type == LaneType::Bus {
@@ -89,7 +103,10 @@ impl SlowPathfinder {
             }
 
             // Expand
-            for next in self.expand(map, current).into_iter() {
+            for next in self
+                .expand(map, current, backrefs.get(&current).cloned())
+                .into_iter()
+            {
                 backrefs.entry(next).or_insert_with(|| {
                     let cost = cost(&next, map);
                     let heuristic = heuristic(&next, self.goal_pt, map);
@@ -1,12 +1,20 @@
+use crate::pathfind::driving::VehiclePathfinder;
 use crate::{
-    BusRouteID, BusStopID, DirectedRoadID, IntersectionID, LaneID, LaneType, Map, Path,
-    PathRequest, PathStep, Position,
+    BusRoute, BusRouteID, BusStopID, DirectedRoadID, IntersectionID, LaneID, LaneType, Map, Path,
+    PathRequest, PathStep, TransitAccess, TransitPlan, TransitRequest, Turn, TurnID,
 };
 use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::Distance;
-use petgraph::graph::{Graph, NodeIndex};
+use geom::{Distance, Duration};
+use petgraph::graph::{EdgeIndex, Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
+
+// Roughly how long a bus waits at each stop
+const BUS_DWELL_TIME: Duration = Duration::const_seconds(10.0);
+// For routes that only run once, there's no headway to go by.
+const DEFAULT_HEADWAY: Duration = Duration::const_seconds(3600.0);
 
 // TODO Make the graph smaller by considering RoadID, or even (directed?) bundles of roads based on
 // OSM way.
@@ -23,11 +31,17 @@ pub struct SidewalkPathfinder {
 #[derive(Serialize, Deserialize, Debug)]
 enum Edge {
     Cross(Distance),
-    RideBus(BusStopID, BusStopID, BusRouteID),
+    // Includes the expected wait for the bus at the first stop
+    RideBus(BusStopID, BusStopID, BusRouteID, Duration),
 }
 
+// Search state for planning transit: the node, and if a bus has been ridden yet
+type TransitState = (NodeIndex<u32>, bool);
+
 impl SidewalkPathfinder {
-    pub fn new(map: &Map, use_transit: bool) -> SidewalkPathfinder {
+    // Bus rides are only added if the bus graph is passed in, since it's used to figure out how
+    // long they take.
+    pub fn new(map: &Map, bus_graph: Option<&VehiclePathfinder>) -> SidewalkPathfinder {
         let mut g = SidewalkPathfinder {
             graph: Graph::new(),
             nodes: BTreeMap::new(),
@@ -46,29 +60,12 @@ impl SidewalkPathfinder {
         }
 
         for t in map.all_turns().values() {
-            if !t.between_sidewalks() || !map.is_turn_allowed(t.id) {
-                continue;
-            }
-            let src_l = map.get_l(t.id.src);
-            let src = g.get_node(t.id.src, map);
-            let dst = g.get_node(t.id.dst, map);
-            // First length arbitrarily wins.
-            if !g.graph.contains_edge(src, dst) {
-                g.graph
-                    .add_edge(src, dst, Edge::Cross(src_l.length() + t.geom.length()));
-            }
+            g.add_turn(t, map);
         }
 
-        // Add edges for all the bus rides. No transfers.
-        if use_transit {
-            for stop1 in map.all_bus_stops().values() {
-                let src = g.get_node(stop1.sidewalk_pos.lane(), map);
-                for (stop2, route) in map.get_connected_bus_stops(stop1.id).into_iter() {
-                    let dst = g.get_node(map.get_bs(stop2).sidewalk_pos.lane(), map);
-                    g.graph
-                        .add_edge(src, dst, Edge::RideBus(stop1.id, stop2, route));
-                }
-            }
+        // Add edges for all the bus rides. Transfers happen by walking between stops.
+        if let Some(bus_graph) = bus_graph {
+            g.add_bus_rides(bus_graph, map);
         }
 
         /*println!(
@@ -80,6 +77,77 @@ impl SidewalkPathfinder {
         g
     }
 
+    fn add_turn(&mut self, t: &Turn, map: &Map) {
+        if !t.between_sidewalks() || !map.is_turn_allowed(t.id) {
+            return;
+        }
+        let src_l = map.get_l(t.id.src);
+        let src = self.get_node(t.id.src, map);
+        let dst = self.get_node(t.id.dst, map);
+        // First length arbitrarily wins.
+        if self.find_cross_edge(src, dst).is_none() {
+            self.graph
+                .add_edge(src, dst, Edge::Cross(src_l.length() + t.geom.length()));
+        }
+    }
+
+    fn add_bus_rides(&mut self, bus_graph: &VehiclePathfinder, map: &Map) {
+        for route in map.get_all_bus_routes() {
+            for (stop1, stop2, time) in bus_rides(route, bus_graph, map) {
+                let src = self.get_node(map.get_bs(stop1).sidewalk_pos.lane(), map);
+                let dst = self.get_node(map.get_bs(stop2).sidewalk_pos.lane(), map);
+                self.graph
+                    .add_edge(src, dst, Edge::RideBus(stop1, stop2, route.id, time));
+            }
+        }
+    }
+
+    // There might also be bus rides between the same two nodes.
+    fn find_cross_edge(&self, src: NodeIndex<u32>, dst: NodeIndex<u32>) -> Option<EdgeIndex<u32>> {
+        self.graph
+            .edges(src)
+            .find(|e| {
+                e.target() == dst
+                    && match e.weight() {
+                        Edge::Cross(_) => true,
+                        Edge::RideBus(_, _, _, _) => false,
+                    }
+            })
+            .map(|e| e.id())
+    }
+
+    // Only the sidewalk edges for the changed turns are touched. Bus rides all get recalculated,
+    // since any road a bus uses might be faster or slower now.
+    pub fn apply_edits(
+        &mut self,
+        delete_turns: &BTreeSet<TurnID>,
+        add_turns: &BTreeSet<TurnID>,
+        bus_graph: Option<&VehiclePathfinder>,
+        map: &Map,
+    ) {
+        // The deleted turns might not exist anymore, and the lanes they connected might not be
+        // sidewalks anymore, but the lanes are still there. Removing an edge that a turn in
+        // add_turns still needs is fine; it'll come back below.
+        for t in delete_turns {
+            let src = self.get_node(t.src, map);
+            let dst = self.get_node(t.dst, map);
+            if let Some(e) = self.find_cross_edge(src, dst) {
+                self.graph.remove_edge(e);
+            }
+        }
+        for t in add_turns {
+            self.add_turn(map.get_t(*t), map);
+        }
+
+        if let Some(bus_graph) = bus_graph {
+            self.graph.retain_edges(|g, e| match g[e] {
+                Edge::Cross(_) => true,
+                Edge::RideBus(_, _, _, _) => false,
+            });
+            self.add_bus_rides(bus_graph, map);
+        }
+    }
+
     fn get_node(&self, lane: LaneID, map: &Map) -> NodeIndex<u32> {
         self.nodes[&map.get_l(lane).get_directed_parent(map)]
     }
@@ -128,8 +196,8 @@ impl SidewalkPathfinder {
             |n| n == end_node,
             |e| match e.weight() {
                 Edge::Cross(dist) => *dist,
-                // Free for now
-                Edge::RideBus(_, _, _) => Distance::ZERO,
+                // Only the graph for planning transit has these.
+                Edge::RideBus(_, _, _, _) => Distance::ZERO,
             },
             |n| {
                 let dr = self.graph[n];
@@ -183,44 +251,177 @@ impl SidewalkPathfinder {
         Some(Path::new(map, steps, req.end.dist_along()))
     }
 
-    // Attempt the pathfinding and see if riding a bus is a step.
-    pub fn should_use_transit(
+    // Finds the quickest combination of walking and riding buses, maybe starting with a bike or
+    // car ride to the first stop. Riding buses is optional if the whole trip is on foot. The
+    // vehicle graph is only needed for that first ride.
+    pub fn plan_transit(
         &self,
+        req: &TransitRequest,
+        vehicle_graph: Option<&VehiclePathfinder>,
         map: &Map,
-        start: Position,
-        end: Position,
-    ) -> Option<(BusStopID, BusStopID, BusRouteID)> {
-        let start_node = self.get_node(start.lane(), map);
-        let end_node = self.get_node(end.lane(), map);
-        let end_pt = map.get_l(end.lane()).first_pt();
+    ) -> Option<TransitPlan> {
+        let end_node = self.get_node(req.end.lane(), map);
 
-        let (_, raw_nodes) = petgraph::algo::astar(
-            &self.graph,
-            start_node,
-            |n| n == end_node,
-            |e| match e.weight() {
-                Edge::Cross(dist) => *dist,
-                // Free for now
-                Edge::RideBus(_, _, _) => Distance::ZERO,
-            },
-            |n| {
-                let dr = self.graph[n];
-                let r = map.get_r(dr.id);
-                if dr.forwards {
-                    end_pt.dist_to(r.center_pts.last_pt())
-                } else {
-                    end_pt.dist_to(r.center_pts.first_pt())
+        // Plain Dijkstra. Buses are faster than walking, so the usual heuristic doesn't work.
+        let mut best: HashMap<TransitState, Duration> = HashMap::new();
+        let mut prev: HashMap<TransitState, (TransitState, EdgeIndex<u32>, Duration)> =
+            HashMap::new();
+        let mut queue: BinaryHeap<Reverse<(Duration, NodeIndex<u32>, bool)>> = BinaryHeap::new();
+        match req.access {
+            TransitAccess::Walk => {
+                let start_node = self.get_node(req.start.lane(), map);
+                best.insert((start_node, false), Duration::ZERO);
+                queue.push(Reverse((Duration::ZERO, start_node, false)));
+            }
+            TransitAccess::Vehicle {
+                start, max_speed, ..
+            } => {
+                // Start at every bus stop, after driving or biking to it.
+                let times = vehicle_graph
+                    .expect("vehicle access needs a vehicle graph")
+                    .times_from(start, max_speed, map);
+                for stop in map.all_bus_stops().values() {
+                    let pos = stop.driving_pos;
+                    let time = match times.get(&map.get_l(pos.lane()).get_directed_parent(map)) {
+                        Some(t) => *t,
+                        None => {
+                            continue;
+                        }
+                    };
+                    let mut speed = map.get_parent(pos.lane()).get_lane_speed_limit(pos.lane());
+                    if let Some(s) = max_speed {
+                        speed = speed.min(s);
+                    }
+                    let cost = time + pos.dist_along() / speed;
+                    let node = (self.get_node(stop.sidewalk_pos.lane(), map), false);
+                    if best.get(&node).map(|c| cost < *c).unwrap_or(true) {
+                        best.insert(node, cost);
+                        queue.push(Reverse((cost, node.0, node.1)));
+                    }
                 }
-            },
-        )?;
+            }
+        }
 
-        for pair in raw_nodes.windows(2) {
-            if let Edge::RideBus(stop1, stop2, route) =
-                self.graph[self.graph.find_edge(pair[0], pair[1]).unwrap()]
-            {
-                return Some((stop1, stop2, route));
+        let mut found: Option<TransitState> = None;
+        while let Some(Reverse((cost, node, rode_bus))) = queue.pop() {
+            if cost > best[&(node, rode_bus)] {
+                continue;
+            }
+            if node == end_node && (rode_bus || req.access == TransitAccess::Walk) {
+                found = Some((node, rode_bus));
+                break;
+            }
+            for e in self.graph.edges(node) {
+                let (next_rode_bus, dt) = match e.weight() {
+                    Edge::Cross(dist) => (rode_bus, *dist / req.walk_speed),
+                    Edge::RideBus(_, _, _, time) => (true, *time),
+                };
+                let next = (e.target(), next_rode_bus);
+                let next_cost = cost + dt;
+                if best.get(&next).map(|c| next_cost < *c).unwrap_or(true) {
+                    best.insert(next, next_cost);
+                    prev.insert(next, ((node, rode_bus), e.id(), dt));
+                    queue.push(Reverse((next_cost, next.0, next.1)));
+                }
+            }
+        }
+
+        let mut plan = TransitPlan {
+            rides: Vec::new(),
+            access_time: Duration::ZERO,
+            walking_time: Duration::ZERO,
+            transit_time: Duration::ZERO,
+        };
+        let mut current = found?;
+        while let Some((from, e, dt)) = prev.get(&current) {
+            match self.graph[*e] {
+                Edge::Cross(_) => {
+                    plan.walking_time += *dt;
+                }
+                Edge::RideBus(stop1, stop2, route, _) => {
+                    plan.rides.push((stop1, stop2, route));
+                    plan.transit_time += *dt;
+                }
+            }
+            current = *from;
+        }
+        // Whatever the search started with was spent getting to the first stop.
+        plan.access_time = best[&current];
+        plan.rides.reverse();
+        Some(plan)
+    }
+}
+
+// Every ride possible on a route, including half of the headway as the expected wait at the first
+// stop.
+fn bus_rides(
+    route: &BusRoute,
+    bus_graph: &VehiclePathfinder,
+    map: &Map,
+) -> Vec<(BusStopID, BusStopID, Duration)> {
+    let n = route.stops.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    // Buses on unscheduled routes loop back to the first stop. Scheduled runs end at the last.
+    let looping = route.schedule.is_empty();
+    let segments: Vec<Option<Duration>> = (0..n)
+        .map(|idx| {
+            if idx == n - 1 && !looping {
+                return None;
+            }
+            bus_graph.estimate_time(
+                &PathRequest {
+                    start: map.get_bs(route.stops[idx]).driving_pos,
+                    end: map.get_bs(route.stops[(idx + 1) % n]).driving_pos,
+                    can_use_bike_lanes: false,
+                    can_use_bus_lanes: true,
+                },
+                map,
+            )
+        })
+        .collect();
+
+    let headway = if looping {
+        // There's one bus per stop, evenly spaced around the loop.
+        let mut loop_time = Some(Duration::ZERO);
+        for dt in &segments {
+            loop_time = match (loop_time, dt) {
+                (Some(t), Some(dt)) => Some(t + *dt + BUS_DWELL_TIME),
+                _ => None,
+            };
+        }
+        loop_time.map(|t| t / (n as f64)).unwrap_or(DEFAULT_HEADWAY)
+    } else if route.schedule.len() >= 2 {
+        let first = route.schedule[0].departures[0];
+        let last = route.schedule.last().unwrap().departures[0];
+        (last - first) / ((route.schedule.len() - 1) as f64)
+    } else {
+        DEFAULT_HEADWAY
+    };
+
+    let mut rides = Vec::new();
+    for idx1 in 0..n {
+        let mut time = headway / 2.0;
+        let mut idx2 = idx1;
+        loop {
+            match segments[idx2] {
+                Some(dt) => {
+                    time += dt;
+                }
+                None => {
+                    break;
+                }
+            }
+            idx2 = (idx2 + 1) % n;
+            if idx2 == idx1 {
+                break;
+            }
+            if route.stops[idx1] != route.stops[idx2] {
+                rides.push((route.stops[idx1], route.stops[idx2], time));
             }
+            time += BUS_DWELL_TIME;
         }
-        None
     }
+    rides
 }
@@ -1,6 +1,6 @@
 use crate::make::get_lane_types;
 pub use crate::make::InitialMap;
-use crate::{AreaType, IntersectionType, RoadSpec};
+use crate::{AreaType, IntersectionType, RoadSpec, SpeedLimitDefaults};
 use geom::{Distance, GPSBounds, LonLat};
 use gtfs::Route;
 use serde_derive::{Deserialize, Serialize};
@@ -25,16 +25,46 @@ impl fmt::Display for StableIntersectionID {
     }
 }
 
+// These refer to things by where they came from in OSM, so they still make sense after the map is
+// imported again. IDs like RoadID are just indices that change whenever the map does.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct OriginalRoad {
+    pub osm_way_id: i64,
+    // One OSM way may be split into many roads, so also remember the endpoints.
+    pub i1: OriginalIntersection,
+    pub i2: OriginalIntersection,
+}
+
+// OSM node IDs don't survive splitting ways, so match on position instead.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct OriginalIntersection {
+    pub point: LonLat,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub struct OriginalLane {
+    pub parent: OriginalRoad,
+    pub fwd: bool,
+    // Counting from the middle of the road, in the order OSM describes, ignoring reversed lanes.
+    pub idx: usize,
+}
+
 #[derive(PartialEq, Debug, Serialize, Deserialize)]
 pub struct Map {
     pub roads: BTreeMap<StableRoadID, Road>,
     pub intersections: BTreeMap<StableIntersectionID, Intersection>,
     pub buildings: Vec<Building>,
+    // Older maps don't have these
+    #[serde(default)]
+    pub parking_lots: Vec<ParkingLot>,
     pub bus_routes: Vec<Route>,
     pub areas: Vec<Area>,
+    pub turn_restrictions: Vec<TurnRestriction>,
 
     pub boundary_polygon: Vec<LonLat>,
     pub coordinates_in_world_space: bool,
+    // For roads without a maxspeed tag
+    pub speed_limit_defaults: SpeedLimitDefaults,
 }
 
 impl Map {
@@ -43,10 +73,13 @@ impl Map {
             roads: BTreeMap::new(),
             intersections: BTreeMap::new(),
             buildings: Vec::new(),
+            parking_lots: Vec::new(),
             bus_routes: Vec::new(),
             areas: Vec::new(),
+            turn_restrictions: Vec::new(),
             boundary_polygon: Vec::new(),
             coordinates_in_world_space: false,
+            speed_limit_defaults: SpeedLimitDefaults::default(),
         }
     }
 
@@ -66,6 +99,11 @@ impl Map {
                 bounds.update(*pt);
             }
         }
+        for p in &self.parking_lots {
+            for pt in &p.points {
+                bounds.update(*pt);
+            }
+        }
         for a in &self.areas {
             for pt in &a.points {
                 bounds.update(*pt);
@@ -87,6 +125,8 @@ pub struct Road {
     pub i1: StableIntersectionID,
     pub i2: StableIntersectionID,
     pub points: Vec<LonLat>,
+    // One per point, or empty if unknown
+    pub elevations: Vec<Distance>,
     pub osm_tags: BTreeMap<String, String>,
     pub osm_way_id: i64,
     pub parking_lane_fwd: bool,
@@ -110,6 +150,15 @@ pub struct Intersection {
     pub elevation: Distance,
     pub intersection_type: IntersectionType,
     pub label: Option<String>,
+    // From OSM stop and give way signs on the roads approaching this intersection. Only used for
+    // stop sign intersections; if it's empty, the signs are guessed from the types of roads.
+    pub approach_signs: BTreeMap<StableRoadID, ApproachSign>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum ApproachSign {
+    Stop,
+    GiveWay,
 }
 
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
@@ -121,6 +170,14 @@ pub struct Building {
     pub num_residential_units: Option<usize>,
 }
 
+// A surface lot, from amenity=parking. Multi-story garages are buildings.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ParkingLot {
+    pub points: Vec<LonLat>,
+    pub osm_tags: BTreeMap<String, String>,
+    pub osm_way_id: i64,
+}
+
 #[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct Area {
     pub area_type: AreaType,
@@ -129,3 +186,40 @@ pub struct Area {
     pub osm_tags: BTreeMap<String, String>,
     pub osm_id: i64,
 }
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize, PartialOrd, Ord)]
+pub enum RestrictionType {
+    BanTurns,
+    OnlyAllowTurns,
+}
+
+impl RestrictionType {
+    // From the OSM restriction=* tag, like no_left_turn or only_straight_on
+    pub fn new(restriction: &str) -> Option<RestrictionType> {
+        if restriction.starts_with("no_") {
+            Some(RestrictionType::BanTurns)
+        } else if restriction.starts_with("only_") {
+            Some(RestrictionType::OnlyAllowTurns)
+        } else {
+            None
+        }
+    }
+}
+
+// Refers to OSM ways, which may get split into many roads.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct TurnRestriction {
+    pub osm_relation_id: i64,
+    pub restriction: RestrictionType,
+    pub from_way: i64,
+    pub via: RestrictionVia,
+    pub to_way: i64,
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub enum RestrictionVia {
+    // Where the from and to ways meet
+    Node(LonLat),
+    // Spans two intersections
+    Way(i64),
+}
@@ -77,6 +77,19 @@ pub struct Road {
     // Need to retain for map editing.
     pub parking_lane_fwd: bool,
     pub parking_lane_back: bool,
+
+    // From OSM, without edits applied. Turning from this road onto the other, at the
+    // intersection they share.
+    pub turn_restrictions: Vec<(raw_data::RestrictionType, RoadID)>,
+    // (via, banned, to): Can't go from this road through the via road onto the banned road. The
+    // last road is the one OSM's restriction names, which edits refer to. For only_* restrictions,
+    // that's the one road that isn't banned.
+    pub complicated_turn_restrictions: Vec<(RoadID, RoadID, RoadID)>,
+
+    // Forwards and backwards, from OSM or the defaults for this kind of road
+    pub osm_speed_limits: (Speed, Speed),
+    // The same, unless edited
+    pub speed_limits: (Speed, Speed),
 }
 
 impl Road {
@@ -180,23 +193,27 @@ impl Road {
         lane == self.children_backwards[0].0
     }
 
+    // The faster of the two directions
     pub fn get_speed_limit(&self) -> Speed {
-        // TODO Should probably cache this
-        if let Some(limit) = self.osm_tags.get("maxspeed") {
-            // TODO handle other units
-            if limit.ends_with(" mph") {
-                if let Ok(mph) = limit[0..limit.len() - 4].parse::<f64>() {
-                    return Speed::miles_per_hour(mph);
-                }
-            }
-        }
+        self.speed_limits.0.max(self.speed_limits.1)
+    }
 
-        if self.osm_tags.get("highway") == Some(&"primary".to_string())
-            || self.osm_tags.get("highway") == Some(&"secondary".to_string())
-        {
-            return Speed::miles_per_hour(40.0);
+    pub fn get_dir_speed_limit(&self, fwds: bool) -> Speed {
+        if fwds {
+            self.speed_limits.0
+        } else {
+            self.speed_limits.1
         }
-        Speed::miles_per_hour(20.0)
+    }
+
+    // lane must belong to this road
+    pub fn get_lane_speed_limit(&self, lane: LaneID) -> Speed {
+        self.get_dir_speed_limit(self.is_forwards(lane))
+    }
+
+    // The faster of the two directions, without edits
+    pub fn speed_limit_from_osm(&self) -> Speed {
+        self.osm_speed_limits.0.max(self.osm_speed_limits.1)
     }
 
     pub fn get_zorder(&self) -> isize {
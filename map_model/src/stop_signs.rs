@@ -1,3 +1,4 @@
+use crate::raw_data::ApproachSign;
 use crate::{IntersectionID, LaneID, Map, RoadID, TurnID, TurnPriority, TurnType};
 use abstutil::{deserialize_btreemap, serialize_btreemap, Error, Timer, Warn};
 use serde_derive::{Deserialize, Serialize};
@@ -49,7 +50,11 @@ pub struct RoadWithStopSign {
 
 impl ControlStopSign {
     pub fn new(map: &Map, id: IntersectionID, timer: &mut Timer) -> ControlStopSign {
-        let mut ss = smart_assignment(map, id).get(timer);
+        let mut ss = if map.get_i(id).approach_signs.is_empty() {
+            smart_assignment(map, id).get(timer)
+        } else {
+            from_osm_signs(map, id)
+        };
         ss.validate(map).unwrap().get(timer);
 
         for r in &map.get_i(id).roads {
@@ -103,7 +108,7 @@ impl ControlStopSign {
     }
 
     // Returns both errors and warnings.
-    fn validate(&self, map: &Map) -> Result<Warn<()>, Error> {
+    pub(crate) fn validate(&self, map: &Map) -> Result<Warn<()>, Error> {
         let mut warnings = Vec::new();
 
         // Does the assignment cover the correct set of turns?
@@ -267,6 +272,50 @@ fn smart_assignment(map: &Map, id: IntersectionID) -> Warn<ControlStopSign> {
     Warn::ok(ss)
 }
 
+// OSM says which roads have to stop or yield. Turns from the other roads get priority when they
+// can.
+fn from_osm_signs(map: &Map, id: IntersectionID) -> ControlStopSign {
+    let signs = &map.get_i(id).approach_signs;
+    let mut ss = ControlStopSign {
+        id,
+        turns: BTreeMap::new(),
+        roads: BTreeMap::new(),
+    };
+    for t in &map.get_i(id).turns {
+        let turn_type = map.get_t(*t).turn_type;
+        if turn_type == TurnType::SharedSidewalkCorner {
+            ss.turns.insert(*t, TurnPriority::Priority);
+            continue;
+        }
+        // Signs are for vehicles. Pedestrians crossing the road aren't bound by them.
+        let sign = if turn_type == TurnType::Crosswalk {
+            None
+        } else {
+            signs.get(&map.get_l(t.src).parent)
+        };
+        match sign {
+            Some(ApproachSign::Stop) => {
+                ss.turns.insert(*t, TurnPriority::Stop);
+            }
+            Some(ApproachSign::GiveWay) => {
+                ss.turns.insert(*t, TurnPriority::Yield);
+            }
+            None => {
+                ss.turns.insert(*t, TurnPriority::Yield);
+                if ss.could_be_priority_turn(*t, map) {
+                    match turn_type {
+                        TurnType::Straight | TurnType::Right | TurnType::Crosswalk => {
+                            ss.turns.insert(*t, TurnPriority::Priority);
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    ss
+}
+
 fn all_way_stop(map: &Map, id: IntersectionID) -> ControlStopSign {
     let mut ss = ControlStopSign {
         id,
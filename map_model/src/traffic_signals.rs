@@ -10,6 +10,14 @@ const CYCLE_DURATION: Duration = Duration::const_seconds(30.0);
 pub struct ControlTrafficSignal {
     pub id: IntersectionID,
     pub cycles: Vec<Cycle>,
+    // The first cycle starts this long after midnight. Adjacent signals can be coordinated into a
+    // green wave by staggering offsets.
+    #[serde(default = "zero_duration")]
+    pub offset: Duration,
+    // If true, the sim extends a cycle while agents are waiting for its turns (up to the cycle's
+    // max_extension) and skips cycles that nobody is waiting for. Otherwise, fixed timing.
+    #[serde(default)]
+    pub actuated: bool,
 }
 
 impl ControlTrafficSignal {
@@ -48,15 +56,32 @@ impl ControlTrafficSignal {
         results
     }
 
-    pub fn current_cycle_and_remaining_time(&self, time: Duration) -> (&Cycle, Duration) {
-        let cycle_idx = (time / CYCLE_DURATION).floor() as usize;
-        let cycle = &self.cycles[cycle_idx % self.cycles.len()];
-        let next_cycle_time = CYCLE_DURATION * (cycle_idx + 1) as f64;
-        let remaining_cycle_time = next_cycle_time - time;
-        (cycle, remaining_cycle_time)
+    // The fixed schedule, if every cycle ran its normal duration. Actuated signals depend on what
+    // the sim is doing, so ask it instead.
+    pub fn current_cycle_idx_and_remaining_time(&self, time: Duration) -> (usize, Duration) {
+        let total = self.total_cycle_duration().inner_seconds();
+        // The offset could be after the current time; wrap around.
+        let mut into_cycle = ((time - self.offset).inner_seconds() % total + total) % total;
+        for (idx, cycle) in self.cycles.iter().enumerate() {
+            let length = cycle.duration.inner_seconds();
+            if into_cycle < length {
+                return (idx, Duration::seconds(length - into_cycle));
+            }
+            into_cycle -= length;
+        }
+        // Floating point imprecision
+        (self.cycles.len() - 1, Duration::EPSILON)
     }
 
-    fn validate(&self, map: &Map) -> Result<(), Error> {
+    pub fn total_cycle_duration(&self) -> Duration {
+        let mut total = Duration::ZERO;
+        for cycle in &self.cycles {
+            total += cycle.duration;
+        }
+        total
+    }
+
+    pub(crate) fn validate(&self, map: &Map) -> Result<(), Error> {
         // TODO Reuse assertions from edit_turn.
 
         // Does the assignment cover the correct set of turns?
@@ -70,6 +95,15 @@ impl ControlTrafficSignal {
             return Err(Error::new(format!("Traffic signal assignment for {} broken. Missing turns {:?}, contains irrelevant turns {:?}", self.id, expected_turns.difference(&actual_turns).cloned().collect::<Vec<TurnID>>(), actual_turns.difference(&expected_turns).cloned().collect::<Vec<TurnID>>())));
         }
 
+        for cycle in &self.cycles {
+            if cycle.duration <= Duration::ZERO || cycle.max_extension < Duration::ZERO {
+                return Err(Error::new(format!(
+                    "Traffic signal {} has cycle {} with bad timing: {} + up to {}",
+                    self.id, cycle.idx, cycle.duration, cycle.max_extension
+                )));
+            }
+        }
+
         for cycle in &self.cycles {
             // Do any of the priority turns in one cycle conflict?
             for t1 in cycle.priority_turns.iter().map(|t| map.get_t(*t)) {
@@ -139,6 +173,8 @@ impl ControlTrafficSignal {
         let ts = ControlTrafficSignal {
             id: intersection,
             cycles,
+            offset: Duration::ZERO,
+            actuated: false,
         };
         // This must succeed
         ts.validate(map).unwrap();
@@ -167,7 +203,12 @@ impl ControlTrafficSignal {
 
         let cycles = make_cycles(map, i, phases);
 
-        let ts = ControlTrafficSignal { id: i, cycles };
+        let ts = ControlTrafficSignal {
+            id: i,
+            cycles,
+            offset: Duration::ZERO,
+            actuated: false,
+        };
         if ts.validate(map).is_ok() {
             Some(ts)
         } else {
@@ -220,7 +261,12 @@ impl ControlTrafficSignal {
             ],
         );
 
-        let ts = ControlTrafficSignal { id: i, cycles };
+        let ts = ControlTrafficSignal {
+            id: i,
+            cycles,
+            offset: Duration::ZERO,
+            actuated: false,
+        };
         if ts.validate(map).is_ok() {
             Some(ts)
         } else {
@@ -266,7 +312,12 @@ impl ControlTrafficSignal {
             ],
         );
 
-        let ts = ControlTrafficSignal { id: i, cycles };
+        let ts = ControlTrafficSignal {
+            id: i,
+            cycles,
+            offset: Duration::ZERO,
+            actuated: false,
+        };
         if ts.validate(map).is_ok() {
             Some(ts)
         } else {
@@ -311,7 +362,12 @@ impl ControlTrafficSignal {
             ],
         );
 
-        let ts = ControlTrafficSignal { id: i, cycles };
+        let ts = ControlTrafficSignal {
+            id: i,
+            cycles,
+            offset: Duration::ZERO,
+            actuated: false,
+        };
         if ts.validate(map).is_ok() {
             Some(ts)
         } else {
@@ -367,7 +423,12 @@ impl ControlTrafficSignal {
             ],
         );
 
-        let ts = ControlTrafficSignal { id: i, cycles };
+        let ts = ControlTrafficSignal {
+            id: i,
+            cycles,
+            offset: Duration::ZERO,
+            actuated: false,
+        };
         if ts.validate(map).is_ok() {
             Some(ts)
         } else {
@@ -383,6 +444,14 @@ pub struct Cycle {
     pub priority_turns: BTreeSet<TurnID>,
     pub yield_turns: BTreeSet<TurnID>,
     pub duration: Duration,
+    // Only used by actuated signals
+    #[serde(default = "zero_duration")]
+    pub max_extension: Duration,
+}
+
+// Signals saved before timing could be tuned don't have these fields.
+fn zero_duration() -> Duration {
+    Duration::ZERO
 }
 
 impl Cycle {
@@ -393,6 +462,7 @@ impl Cycle {
             priority_turns: BTreeSet::new(),
             yield_turns: BTreeSet::new(),
             duration: CYCLE_DURATION,
+            max_extension: Duration::ZERO,
         }
     }
 
@@ -145,8 +145,8 @@ impl Traversable {
 
     pub fn speed_limit(&self, map: &Map) -> Speed {
         match *self {
-            Traversable::Lane(id) => map.get_parent(id).get_speed_limit(),
-            Traversable::Turn(id) => map.get_parent(id.dst).get_speed_limit(),
+            Traversable::Lane(id) => map.get_parent(id).get_lane_speed_limit(id),
+            Traversable::Turn(id) => map.get_parent(id.dst).get_lane_speed_limit(id.dst),
         }
     }
 
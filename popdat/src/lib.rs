@@ -1,6 +1,7 @@
 pub mod psrc;
 
 use abstutil::Timer;
+use city_config::CensusSource;
 use geom::{GPSBounds, LonLat};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
@@ -31,37 +32,37 @@ pub struct Estimate {
 }
 
 impl PopDat {
-    pub fn import_all(timer: &mut Timer) -> PopDat {
+    pub fn import_all(census: &CensusSource, bounds: &GPSBounds, timer: &mut Timer) -> PopDat {
         let mut dat = PopDat {
             tracts: BTreeMap::new(),
             trips: Vec::new(),
         };
         let fields: Vec<(&str, Box<Fn(&mut TractData, BTreeMap<String, Estimate>)>)> = vec![
             (
-                "../data/input/household_vehicles.kml",
+                &census.household_vehicles,
                 Box::new(|tract, map| {
                     tract.household_vehicles = map;
                 }),
             ),
             (
-                "../data/input/commute_time.kml",
+                &census.commute_times,
                 Box::new(|tract, map| {
                     tract.commute_times = map;
                 }),
             ),
             (
-                "../data/input/commute_mode.kml",
+                &census.commute_modes,
                 Box::new(|tract, map| {
                     tract.commute_modes = map;
                 }),
             ),
         ];
         for (path, setter) in fields {
-            for mut shape in kml::load(path, &GPSBounds::seattle_bounds(), timer)
+            for mut shape in kml::load(path, bounds, timer)
                 .expect(&format!("couldn't load {}", path))
                 .shapes
             {
-                let name = shape.attributes.remove("TRACT_LBL").unwrap();
+                let name = shape.attributes.remove(&census.label).unwrap();
 
                 if let Some(ref tract) = dat.tracts.get(&name) {
                     assert_eq!(shape.points, tract.pts);
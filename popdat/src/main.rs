@@ -1,12 +1,34 @@
+use city_config::CityConfig;
+use structopt::StructOpt;
+
+#[derive(StructOpt)]
+#[structopt(name = "popdat")]
+struct Flags {
+    /// City config JSON, with the census tracts to import
+    #[structopt(long = "city")]
+    pub city: String,
+}
+
 fn main() {
+    let flags = Flags::from_args();
+    let city = CityConfig::load(&flags.city).unwrap();
+    let bounds = city.gps_bounds();
+
     let mut timer = abstutil::Timer::new("creating popdat");
-    let mut popdat = popdat::PopDat::import_all(&mut timer);
+    let mut popdat = popdat::PopDat::import_all(
+        city.census_tracts
+            .as_ref()
+            .expect("The city config doesn't have census tracts"),
+        &bounds,
+        &mut timer,
+    );
 
     // TODO Productionize this.
     // https://file.ac/cLdO7Hp_OB0/ has trips_2014.csv. https://file.ac/Xdjmi8lb2dA/ has the 2014
     // inputs.
     let parcels = popdat::psrc::import_parcels(
         "/home/dabreegster/Downloads/psrc/2014/landuse/parcels_urbansim.txt",
+        &bounds,
         &mut timer,
     )
     .unwrap();
@@ -86,6 +86,7 @@ pub fn import_trips(
 // TODO Do we also need the zone ID, or is parcel ID globally unique?
 pub fn import_parcels(
     path: &str,
+    bounds: &GPSBounds,
     timer: &mut Timer,
 ) -> Result<HashMap<String, LonLat>, failure::Error> {
     let mut coords = BufWriter::new(File::create("/tmp/parcels")?);
@@ -128,7 +129,6 @@ pub fn import_parcels(
         prettyprint_usize(parcel_ids.len())
     ));
 
-    let bounds = GPSBounds::seattle_bounds();
     let reader = BufReader::new(output.stdout.as_slice());
     let mut result = HashMap::new();
     timer.start_iter("read cs2cs output", parcel_ids.len());
@@ -8,6 +8,10 @@ struct Flags {
     /// Map
     #[structopt(name = "load")]
     load: String,
+
+    /// Skip preparing contraction hierarchies for faster vehicle pathfinding
+    #[structopt(long = "no_ch")]
+    no_ch: bool,
 }
 
 fn main() {
@@ -22,7 +26,10 @@ fn main() {
         flags.load
     };
 
-    let map = Map::new(&raw_map_path, &mut timer).unwrap();
+    let mut map = Map::new(&raw_map_path, &mut timer).unwrap();
+    if !flags.no_ch {
+        map.use_contraction_hierarchies(true, &mut timer);
+    }
     timer.start("save map");
     map.save();
     timer.stop("save map");
@@ -6,9 +6,13 @@ use serde_derive::{Deserialize, Serialize};
 pub enum Event {
     CarReachedParkingSpot(CarID, ParkingSpot),
     CarOrBikeReachedBorder(CarID, IntersectionID),
+    // Nothing free within reach, so the car vanished and its trip was aborted.
+    CarGaveUpOnParking(CarID),
 
     BusArrivedAtStop(CarID, BusStopID),
     BusDepartedFromStop(CarID, BusStopID),
+    // The bus was full, so this many people waiting for it couldn't board.
+    BusLeftPedsBehind(CarID, BusStopID, usize),
 
     PedReachedParkingSpot(PedestrianID, ParkingSpot),
     PedReachedBuilding(PedestrianID, BuildingID),
@@ -11,22 +11,30 @@ mod trips;
 
 pub use self::events::Event;
 pub use self::make::{
-    ABTest, ABTestResults, BorderSpawnOverTime, OriginDestination, Scenario, SeedParkedCars,
-    SimFlags, SpawnOverTime, TripSpawner, TripSpec,
+    choice_probabilities, ABTest, ABTestResults, BorderRef, BorderSpawnOverTime, Itinerary,
+    OriginDestination, PlannedLeg, Scenario, SeedParkedCars, SimFlags, SpawnOverTime, TripEndpoint,
+    TripPlanRequest, TripSpawner, TripSpec,
 };
+pub(crate) use self::make::{choose_itinerary, plan_trip};
+pub use self::mechanics::SpeedProfile;
 pub(crate) use self::mechanics::{
-    DrivingSimState, IntersectionSimState, ParkingSimState, WalkingSimState,
+    DrivingSimState, IntersectionSimState, ParkingSimState, WalkingSimState, TIME_TO_PARK,
+    TIME_TO_UNPARK,
 };
 pub use self::query::{Benchmark, ScoreSummary, SimStats, Summary};
 pub(crate) use self::router::{ActionAtEnd, Router};
-pub(crate) use self::scheduler::{Command, Scheduler};
+pub use self::scheduler::{Command, Scheduler};
 pub use self::sim::Sim;
 pub(crate) use self::transit::TransitSimState;
+pub use self::transit::{BusStopVisit, TIME_TO_ALIGHT, TIME_TO_BOARD, TIME_TO_WAIT_AT_STOP};
+pub use self::trips::{FinishedTrip, TripMode};
 pub(crate) use self::trips::{TripLeg, TripManager};
 pub use crate::render::{CarStatus, DrawCarInput, DrawPedestrianInput, GetDrawAgents};
 use abstutil::Cloneable;
-use geom::{Distance, Duration, Speed};
-use map_model::{BuildingID, BusStopID, IntersectionID, LaneID, LaneType, Map, Path, Position};
+use geom::{Acceleration, Distance, Duration, Speed};
+use map_model::{
+    BuildingID, BusStopID, IntersectionID, LaneID, LaneType, Map, ParkingLotID, Path, Position,
+};
 use serde_derive::{Deserialize, Serialize};
 use std::fmt;
 
@@ -37,10 +45,45 @@ pub const MIN_CAR_LENGTH: Distance = Distance::const_meters(4.5);
 pub const MAX_CAR_LENGTH: Distance = Distance::const_meters(6.5);
 // Note this is more than MAX_CAR_LENGTH
 pub const BUS_LENGTH: Distance = Distance::const_meters(12.5);
+// Seated and standing
+pub const BUS_CAPACITY: usize = 60;
+
+// Comfortable (not maximum) rates for starting up and braking.
+pub const CAR_ACCEL: Acceleration = Acceleration::const_meters_per_second_squared(2.5);
+pub const CAR_DECEL: Acceleration = Acceleration::const_meters_per_second_squared(3.0);
+pub const BUS_ACCEL: Acceleration = Acceleration::const_meters_per_second_squared(1.2);
+pub const BUS_DECEL: Acceleration = Acceleration::const_meters_per_second_squared(1.5);
+pub const BIKE_ACCEL: Acceleration = Acceleration::const_meters_per_second_squared(1.0);
+pub const BIKE_DECEL: Acceleration = Acceleration::const_meters_per_second_squared(2.0);
+// Nobody brakes harder than this, even when something suddenly stops in front of them.
+pub const MAX_DECEL: Acceleration = Acceleration::const_meters_per_second_squared(7.0);
 
 // At all speeds (including at rest), cars must be at least this far apart, measured from front of
 // one car to the back of the other.
 pub const FOLLOWING_DISTANCE: Distance = Distance::const_meters(1.0);
+// When changing lanes mid-block in front of a moving car, leave at least this much room between
+// our back and their front.
+pub const MIN_LANE_CHANGE_GAP: Distance = Distance::const_meters(5.0);
+
+// Steeper than this is probably noise in the elevation data.
+const MAX_GRADE: f64 = 0.3;
+
+// Tobler's hiking function, relative to flat ground. Walking slightly downhill is fastest.
+pub fn walking_speed_on_grade(speed: Speed, grade: f64) -> Speed {
+    let grade = grade.max(-MAX_GRADE).min(MAX_GRADE);
+    speed * (-3.5 * ((grade + 0.05).abs() - 0.05)).exp()
+}
+
+// Cyclists slow down a lot going uphill, and coast a bit faster going downhill. The speed limit
+// still applies.
+pub fn biking_speed_on_grade(speed: Speed, grade: f64) -> Speed {
+    let grade = grade.max(-MAX_GRADE).min(MAX_GRADE);
+    if grade > 0.0 {
+        speed * (1.0 / (1.0 + 10.0 * grade))
+    } else {
+        speed * (1.0 - 5.0 * grade).min(1.5)
+    }
+}
 
 // The VehicleType is only used for convenient debugging. The numeric ID itself must be sufficient.
 // TODO Implement Eq, Hash, Ord manually to guarantee this.
@@ -118,6 +161,8 @@ pub struct Vehicle {
     pub vehicle_type: VehicleType,
     pub length: Distance,
     pub max_speed: Option<Speed>,
+    pub accel: Acceleration,
+    pub decel: Acceleration,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -125,6 +170,9 @@ pub struct VehicleSpec {
     pub vehicle_type: VehicleType,
     pub length: Distance,
     pub max_speed: Option<Speed>,
+    // Both positive
+    pub accel: Acceleration,
+    pub decel: Acceleration,
 }
 
 impl VehicleSpec {
@@ -135,19 +183,25 @@ impl VehicleSpec {
             vehicle_type: self.vehicle_type,
             length: self.length,
             max_speed: self.max_speed,
+            accel: self.accel,
+            decel: self.decel,
         }
     }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
-pub struct ParkingSpot {
-    pub lane: LaneID,
-    pub idx: usize,
+pub enum ParkingSpot {
+    // Parking lane and index of the spot along it
+    Onstreet(LaneID, usize),
+    // A garage attached to a building, and the stall in it
+    Offstreet(BuildingID, usize),
+    // A surface lot, and the stall in it
+    Lot(ParkingLotID, usize),
 }
 
 impl ParkingSpot {
     pub fn new(lane: LaneID, idx: usize) -> ParkingSpot {
-        ParkingSpot { lane, idx }
+        ParkingSpot::Onstreet(lane, idx)
     }
 }
 
@@ -225,13 +279,9 @@ impl SidewalkSpot {
         map: &Map,
         parking_sim: &ParkingSimState,
     ) -> SidewalkSpot {
-        // TODO Consider precomputing this.
-        let sidewalk = map
-            .find_closest_lane(spot.lane, vec![LaneType::Sidewalk])
-            .unwrap();
         SidewalkSpot {
             connection: SidewalkPOI::ParkingSpot(spot),
-            sidewalk_pos: parking_sim.spot_to_sidewalk_pos(spot, sidewalk, map),
+            sidewalk_pos: parking_sim.spot_to_sidewalk_pos(spot, map),
         }
     }
 
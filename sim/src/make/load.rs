@@ -135,11 +135,9 @@ fn apply_edits(map: &mut Map, edits_name: &str, timer: &mut Timer) {
     if edits_name == "no_edits" {
         return;
     }
-    let edits: MapEdits = abstutil::read_json(&format!(
-        "../data/edits/{}/{}.json",
-        map.get_name(),
-        edits_name
-    ))
-    .unwrap();
+    let edits = match MapEdits::load(map, edits_name, timer) {
+        Ok(edits) => edits,
+        Err(err) => panic!("Couldn't load edits {}: {}", edits_name, err),
+    };
     map.apply_edits(edits, timer);
 }
@@ -1,11 +1,16 @@
 mod a_b_test;
 mod load;
+mod planner;
 mod scenario;
 mod spawner;
 
 pub use self::a_b_test::{ABTest, ABTestResults};
 pub use self::load::SimFlags;
+pub use self::planner::{
+    choice_probabilities, Itinerary, PlannedLeg, TripEndpoint, TripPlanRequest,
+};
+pub(crate) use self::planner::{choose_itinerary, plan_trip};
 pub use self::scenario::{
-    BorderSpawnOverTime, OriginDestination, Scenario, SeedParkedCars, SpawnOverTime,
+    BorderRef, BorderSpawnOverTime, OriginDestination, Scenario, SeedParkedCars, SpawnOverTime,
 };
 pub use self::spawner::{TripSpawner, TripSpec};
@@ -0,0 +1,498 @@
+use crate::{
+    biking_speed_on_grade, DrivingGoal, ParkingSimState, ParkingSpot, SidewalkPOI, SidewalkSpot,
+    TripMode, VehicleSpec, VehicleType, TIME_TO_PARK, TIME_TO_UNPARK,
+};
+use geom::{Distance, Duration, Speed};
+use map_model::{
+    BuildingID, BusRouteID, BusStopID, IntersectionID, LaneType, Map, PathRequest, PathStep,
+    Position, TransitAccess, TransitPlan, TransitRequest,
+};
+use rand::Rng;
+use rand_xorshift::XorShiftRng;
+use serde_derive::{Deserialize, Serialize};
+
+// Mode choice is a logit model over the generalized cost of each itinerary. An option costing this
+// much more than another is e times less likely to be picked.
+const LOGIT_SCALE: Duration = Duration::const_seconds(5.0 * 60.0);
+// Every transfer between buses feels like this much extra time.
+const TRANSFER_PENALTY: Duration = Duration::const_seconds(3.0 * 60.0);
+// Biking next to cars feels this much longer than biking in a bike lane.
+const MIXED_TRAFFIC_WEIGHT: f64 = 1.5;
+// If there's no free parking near the destination, expect to circle around for this long.
+const PARKING_SEARCH_PENALTY: Duration = Duration::const_seconds(5.0 * 60.0);
+
+// One way to make a trip, decided before it starts.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Itinerary {
+    pub legs: Vec<PlannedLeg>,
+    // From the pathfinders, ignoring everybody else on the map
+    pub estimated_time: Duration,
+    // How much worse the unpleasant parts of the trip feel, like transfers, biking in traffic,
+    // and hunting for parking
+    pub penalty: Duration,
+}
+
+// Everything except walking starts where the previous walk ended.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum PlannedLeg {
+    Walk(SidewalkSpot),
+    // Drive the car parked here
+    Drive(ParkingSpot, DrivingGoal),
+    Bike(VehicleSpec, DrivingGoal),
+    RideBus(BusRouteID, BusStopID, BusStopID),
+}
+
+// Where a trip can end
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TripEndpoint {
+    Building(BuildingID),
+    Border(IntersectionID),
+}
+
+impl Itinerary {
+    pub fn modes(&self) -> Vec<TripMode> {
+        self.legs
+            .iter()
+            .map(|leg| match leg {
+                PlannedLeg::Walk(_) => TripMode::Walk,
+                PlannedLeg::Drive(_, _) => TripMode::Drive,
+                PlannedLeg::Bike(_, _) => TripMode::Bike,
+                PlannedLeg::RideBus(_, _, _) => TripMode::Transit,
+            })
+            .collect()
+    }
+
+    pub fn generalized_cost(&self) -> Duration {
+        self.estimated_time + self.penalty
+    }
+}
+
+// Everything about one person that matters for planning their trip. Requests don't depend on each
+// other, so many can be planned at once.
+#[derive(Clone, Debug)]
+pub struct TripPlanRequest {
+    pub start: SidewalkSpot,
+    pub goal: TripEndpoint,
+    pub parked_car: Option<ParkingSpot>,
+    pub bike: Option<VehicleSpec>,
+    pub ped_speed: Speed,
+    // If not, bus rides aren't even considered.
+    pub use_transit: bool,
+}
+
+// Quickest first. Options that can't be spawned, like biking from a sidewalk without a bike rack,
+// are left out.
+pub(crate) fn plan_trip(
+    req: &TripPlanRequest,
+    map: &Map,
+    parking: &ParkingSimState,
+) -> Vec<Itinerary> {
+    let start = &req.start;
+    let goal = req.goal;
+    let ped_speed = req.ped_speed;
+    let mut results = Vec::new();
+    // Borders without sidewalks can only be reached by vehicle.
+    let walk_goal = match goal {
+        TripEndpoint::Building(b) => Some(SidewalkSpot::building(b, map)),
+        TripEndpoint::Border(i) => SidewalkSpot::end_at_border(i, map),
+    };
+
+    if let Some(ref walk_goal) = walk_goal {
+        if start != walk_goal {
+            if let Some(time) =
+                walking_time(start.sidewalk_pos, walk_goal.sidewalk_pos, ped_speed, map)
+            {
+                results.push(Itinerary {
+                    legs: vec![PlannedLeg::Walk(walk_goal.clone())],
+                    estimated_time: time,
+                    penalty: Duration::ZERO,
+                });
+            }
+        }
+
+        if req.use_transit {
+            if let Some(plan) = plan_transit(start, walk_goal, TransitAccess::Walk, ped_speed, map)
+            {
+                // Otherwise it's the same as just walking.
+                if !plan.rides.is_empty() {
+                    results.push(Itinerary {
+                        legs: transit_legs(Vec::new(), &plan, walk_goal, map),
+                        estimated_time: plan.total_time(),
+                        penalty: transfer_penalty(&plan),
+                    });
+                }
+            }
+        }
+    }
+
+    // Park-and-ride only happens with a goal that can be walked to.
+    let transit_goal = if req.use_transit { walk_goal } else { None };
+    if let Some(spot) = req.parked_car {
+        results.extend(plan_driving(
+            start,
+            goal,
+            &transit_goal,
+            spot,
+            ped_speed,
+            map,
+            parking,
+        ));
+    }
+    if let Some(ref vehicle) = req.bike {
+        results.extend(plan_biking(
+            start,
+            goal,
+            &transit_goal,
+            vehicle,
+            ped_speed,
+            map,
+        ));
+    }
+
+    results.sort_by_key(|i| i.estimated_time);
+    results
+}
+
+// How likely each itinerary is to be picked. Cheaper ones are more likely.
+pub fn choice_probabilities(itineraries: &[Itinerary]) -> Vec<f64> {
+    let best = match itineraries.iter().map(|i| i.generalized_cost()).min() {
+        Some(cost) => cost,
+        None => {
+            return Vec::new();
+        }
+    };
+    // Relative to the best option, so nothing underflows
+    let weights: Vec<f64> = itineraries
+        .iter()
+        .map(|i| ((best - i.generalized_cost()) / LOGIT_SCALE).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+    weights.into_iter().map(|w| w / total).collect()
+}
+
+pub(crate) fn choose_itinerary(
+    mut itineraries: Vec<Itinerary>,
+    rng: &mut XorShiftRng,
+) -> Option<Itinerary> {
+    let probabilities = choice_probabilities(&itineraries);
+    if probabilities.is_empty() {
+        return None;
+    }
+    let mut x = rng.gen_range(0.0, 1.0);
+    for (idx, p) in probabilities.iter().enumerate() {
+        if x < *p {
+            return Some(itineraries.remove(idx));
+        }
+        x -= *p;
+    }
+    // Floating point error
+    itineraries.pop()
+}
+
+// All the way there, and park-and-ride
+fn plan_driving(
+    start: &SidewalkSpot,
+    goal: TripEndpoint,
+    transit_goal: &Option<SidewalkSpot>,
+    spot: ParkingSpot,
+    ped_speed: Speed,
+    map: &Map,
+    parking: &ParkingSimState,
+) -> Vec<Itinerary> {
+    let mut results = Vec::new();
+    let car_spot = SidewalkSpot::parking_spot(spot, map, parking);
+    let walk_to_car = match walking_time(start.sidewalk_pos, car_spot.sidewalk_pos, ped_speed, map)
+    {
+        Some(t) => t,
+        None => {
+            return results;
+        }
+    };
+    let start_driving = parking
+        .get_car_at_spot(spot)
+        .unwrap()
+        .get_driving_pos(parking, map);
+
+    if let Some(drive_to) = driving_goal(goal, vec![LaneType::Driving], map) {
+        if let Some(drive_time) = map.estimate_travel_time(PathRequest {
+            start: start_driving,
+            end: drive_to.goal_pos(map),
+            can_use_bike_lanes: false,
+            can_use_bus_lanes: false,
+        }) {
+            // Where the car winds up parking isn't known yet, so the last walk isn't counted.
+            let mut itinerary = Itinerary {
+                legs: vec![
+                    PlannedLeg::Walk(car_spot.clone()),
+                    PlannedLeg::Drive(spot, drive_to.clone()),
+                ],
+                estimated_time: walk_to_car + TIME_TO_UNPARK + drive_time,
+                penalty: Duration::ZERO,
+            };
+            if let DrivingGoal::ParkNear(b) = drive_to {
+                itinerary.estimated_time += TIME_TO_PARK;
+                itinerary.penalty += parking_penalty(b, map, parking);
+                itinerary
+                    .legs
+                    .push(PlannedLeg::Walk(SidewalkSpot::building(b, map)));
+            }
+            results.push(itinerary);
+        }
+    }
+
+    if let Some(ref walk_goal) = transit_goal {
+        if let Some(plan) = plan_transit(
+            start,
+            walk_goal,
+            TransitAccess::Vehicle {
+                start: start_driving,
+                can_use_bike_lanes: false,
+                max_speed: None,
+            },
+            ped_speed,
+            map,
+        ) {
+            if let Some(b) = building_near_stop(plan.rides[0].0, map) {
+                results.push(Itinerary {
+                    legs: transit_legs(
+                        vec![
+                            PlannedLeg::Walk(car_spot),
+                            PlannedLeg::Drive(spot, DrivingGoal::ParkNear(b)),
+                        ],
+                        &plan,
+                        walk_goal,
+                        map,
+                    ),
+                    estimated_time: walk_to_car + TIME_TO_UNPARK + TIME_TO_PARK + plan.total_time(),
+                    penalty: transfer_penalty(&plan) + parking_penalty(b, map, parking),
+                });
+            }
+        }
+    }
+
+    results
+}
+
+// All the way there, and biking to a bus stop
+fn plan_biking(
+    start: &SidewalkSpot,
+    goal: TripEndpoint,
+    transit_goal: &Option<SidewalkSpot>,
+    vehicle: &VehicleSpec,
+    ped_speed: Speed,
+    map: &Map,
+) -> Vec<Itinerary> {
+    assert_eq!(vehicle.vehicle_type, VehicleType::Bike);
+    let mut results = Vec::new();
+    // TODO Just start biking on the other side of the street if the sidewalk is on a one-way.
+    let rack = match SidewalkSpot::bike_rack(start.sidewalk_pos.lane(), map) {
+        Some(spot) => spot,
+        None => {
+            return results;
+        }
+    };
+    let start_biking = match rack.connection {
+        SidewalkPOI::BikeRack(pos) => pos,
+        _ => unreachable!(),
+    };
+    let walk_to_bike = match walking_time(start.sidewalk_pos, rack.sidewalk_pos, ped_speed, map) {
+        Some(t) => t,
+        None => {
+            return results;
+        }
+    };
+
+    if let Some(bike_to) = driving_goal(goal, vec![LaneType::Driving, LaneType::Biking], map) {
+        if can_bike_to(start, &bike_to, map) {
+            if let Some((bike_time, penalty)) =
+                biking_cost(start_biking, bike_to.goal_pos(map), vehicle, map)
+            {
+                let mut legs = vec![
+                    PlannedLeg::Walk(rack.clone()),
+                    PlannedLeg::Bike(vehicle.clone(), bike_to.clone()),
+                ];
+                if let DrivingGoal::ParkNear(b) = bike_to {
+                    legs.push(PlannedLeg::Walk(SidewalkSpot::building(b, map)));
+                }
+                results.push(Itinerary {
+                    legs,
+                    estimated_time: walk_to_bike + bike_time,
+                    penalty,
+                });
+            }
+        }
+    }
+
+    if let Some(ref walk_goal) = transit_goal {
+        if let Some(plan) = plan_transit(
+            start,
+            walk_goal,
+            TransitAccess::Vehicle {
+                start: start_biking,
+                can_use_bike_lanes: true,
+                max_speed: vehicle.max_speed,
+            },
+            ped_speed,
+            map,
+        ) {
+            if let Some(b) = building_near_stop(plan.rides[0].0, map) {
+                let bike_to = DrivingGoal::ParkNear(b);
+                if can_bike_to(start, &bike_to, map) {
+                    results.push(Itinerary {
+                        legs: transit_legs(
+                            vec![
+                                PlannedLeg::Walk(rack),
+                                PlannedLeg::Bike(vehicle.clone(), bike_to),
+                            ],
+                            &plan,
+                            walk_goal,
+                            map,
+                        ),
+                        estimated_time: walk_to_bike + plan.total_time(),
+                        penalty: transfer_penalty(&plan),
+                    });
+                }
+            }
+        }
+    }
+
+    results
+}
+
+fn plan_transit(
+    start: &SidewalkSpot,
+    goal: &SidewalkSpot,
+    access: TransitAccess,
+    ped_speed: Speed,
+    map: &Map,
+) -> Option<TransitPlan> {
+    map.plan_transit(TransitRequest {
+        start: start.sidewalk_pos,
+        end: goal.sidewalk_pos,
+        walk_speed: ped_speed,
+        access,
+    })
+}
+
+// Appends a walk to each bus stop, the rides, and the final walk to the goal. A transfer at the
+// same stop is a walk to where the pedestrian already is.
+fn transit_legs(
+    mut legs: Vec<PlannedLeg>,
+    plan: &TransitPlan,
+    goal: &SidewalkSpot,
+    map: &Map,
+) -> Vec<PlannedLeg> {
+    for (stop1, stop2, route) in &plan.rides {
+        legs.push(PlannedLeg::Walk(SidewalkSpot::bus_stop(*stop1, map)));
+        legs.push(PlannedLeg::RideBus(*route, *stop1, *stop2));
+    }
+    legs.push(PlannedLeg::Walk(goal.clone()));
+    legs
+}
+
+fn transfer_penalty(plan: &TransitPlan) -> Duration {
+    TRANSFER_PENALTY * (plan.rides.len().saturating_sub(1) as f64)
+}
+
+// Only looks at what's free right now, in the building or along its road.
+fn parking_penalty(b: BuildingID, map: &Map, parking: &ParkingSimState) -> Duration {
+    if !parking.get_free_offstreet_spots(b).is_empty() {
+        return Duration::ZERO;
+    }
+    let road = map.building_to_road(b);
+    for (l, lt) in road
+        .children_forwards
+        .iter()
+        .chain(road.children_backwards.iter())
+    {
+        if *lt == LaneType::Parking && !parking.get_free_spots(*l).is_empty() {
+            return Duration::ZERO;
+        }
+    }
+    PARKING_SEARCH_PENALTY
+}
+
+fn walking_time(start: Position, end: Position, speed: Speed, map: &Map) -> Option<Duration> {
+    if start == end {
+        return Some(Duration::ZERO);
+    }
+    let path = map.pathfind(PathRequest {
+        start,
+        end,
+        can_use_bike_lanes: false,
+        can_use_bus_lanes: false,
+    })?;
+    Some(path.total_length(start.dist_along(), map) / speed)
+}
+
+// Returns the time, and the penalty for biking without a bike lane. Whole lanes are counted,
+// including their hills.
+fn biking_cost(
+    start: Position,
+    end: Position,
+    vehicle: &VehicleSpec,
+    map: &Map,
+) -> Option<(Duration, Duration)> {
+    let path = map.pathfind(PathRequest {
+        start,
+        end,
+        can_use_bike_lanes: true,
+        can_use_bus_lanes: false,
+    })?;
+    let mut time = Duration::ZERO;
+    let mut penalty = Duration::ZERO;
+    for step in path.get_steps() {
+        let lane = match step {
+            PathStep::Lane(l) | PathStep::ContraflowLane(l) => *l,
+            PathStep::Turn(t) => t.dst,
+        };
+        let mut speed = map.get_parent(lane).get_lane_speed_limit(lane);
+        if let Some(mut s) = vehicle.max_speed {
+            if let PathStep::Lane(l) = step {
+                let l = map.get_l(*l);
+                s = biking_speed_on_grade(s, l.grade(Distance::ZERO, l.length()));
+            }
+            speed = speed.min(s);
+        }
+        let dt = step.as_traversable().length(map) / speed;
+        time += dt;
+        if let PathStep::Lane(l) = step {
+            if map.get_l(*l).lane_type != LaneType::Biking {
+                penalty += dt * (MIXED_TRAFFIC_WEIGHT - 1.0);
+            }
+        }
+    }
+    Some((time, penalty))
+}
+
+fn driving_goal(goal: TripEndpoint, lane_types: Vec<LaneType>, map: &Map) -> Option<DrivingGoal> {
+    match goal {
+        TripEndpoint::Building(b) => Some(DrivingGoal::ParkNear(b)),
+        TripEndpoint::Border(i) => DrivingGoal::end_at_border(i, lane_types, map),
+    }
+}
+
+// The bike has to be able to stop near a sidewalk, and it's silly to bike to the same sidewalk.
+fn can_bike_to(start: &SidewalkSpot, goal: &DrivingGoal, map: &Map) -> bool {
+    match goal {
+        DrivingGoal::ParkNear(b) => {
+            let end_at = map.get_b(*b).sidewalk();
+            let last_lane = goal.goal_pos(map).lane();
+            map.get_parent(last_lane)
+                .bike_to_sidewalk(last_lane)
+                .is_some()
+                && start.sidewalk_pos.lane() != end_at
+        }
+        DrivingGoal::Border(_, _) => true,
+    }
+}
+
+// Cars and bikes going to a bus stop aim for the closest building on the same sidewalk.
+fn building_near_stop(stop: BusStopID, map: &Map) -> Option<BuildingID> {
+    let pos = map.get_bs(stop).sidewalk_pos;
+    map.get_l(pos.lane())
+        .building_paths
+        .iter()
+        .min_by_key(|b| (map.get_b(**b).front_path.sidewalk.dist_along() - pos.dist_along()).abs())
+        .cloned()
+}
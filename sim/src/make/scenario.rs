@@ -1,18 +1,21 @@
 use crate::{
-    CarID, DrivingGoal, ParkingSpot, SidewalkSpot, Sim, TripSpec, VehicleSpec, VehicleType,
-    BIKE_LENGTH, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
+    choose_itinerary, CarID, DrivingGoal, ParkingSpot, SidewalkSpot, Sim, TripEndpoint,
+    TripPlanRequest, TripSpec, VehicleSpec, VehicleType, BIKE_ACCEL, BIKE_DECEL, BIKE_LENGTH,
+    CAR_ACCEL, CAR_DECEL, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
 };
 use abstutil;
 use abstutil::{fork_rng, Timer, WeightedUsizeChoice};
 use geom::{Distance, Duration, Speed};
+use map_model::raw_data::OriginalIntersection;
 use map_model::{
-    BuildingID, FullNeighborhoodInfo, IntersectionID, LaneType, Map, Position, RoadID,
+    BuildingID, FullNeighborhoodInfo, IntersectionID, IntersectionType, LaneType, Map, Position,
+    RoadID,
 };
 use rand::seq::SliceRandom;
 use rand::Rng;
 use rand_xorshift::XorShiftRng;
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::collections::{BTreeSet, HashMap, HashSet};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct Scenario {
@@ -35,6 +38,7 @@ pub struct SpawnOverTime {
     pub stop_time: Duration,
     pub start_from_neighborhood: String,
     pub goal: OriginDestination,
+    // Everybody might drive if they have a car, but not everybody has a bike.
     pub percent_biking: f64,
     pub percent_use_transit: f64,
 }
@@ -47,9 +51,9 @@ pub struct BorderSpawnOverTime {
     // TODO use https://docs.rs/rand/0.5.5/rand/distributions/struct.Normal.html
     pub start_time: Duration,
     pub stop_time: Duration,
-    // TODO A serialized Scenario won't last well as the map changes...
-    pub start_from_border: IntersectionID,
+    pub start_from_border: BorderRef,
     pub goal: OriginDestination,
+    // Only for the pedestrians
     pub percent_use_transit: f64,
 }
 
@@ -95,6 +99,9 @@ impl Scenario {
             );
         }
 
+        // Everything random about the people choosing their mode is decided first. Then their
+        // trips, which are the expensive part, are all planned at once.
+        let mut requests: Vec<(Duration, TripPlanRequest)> = Vec::new();
         // Don't let two pedestrians starting from one building use the same car.
         let mut reserved_cars: HashSet<CarID> = HashSet::new();
 
@@ -106,16 +113,43 @@ impl Scenario {
             timer.start_iter("SpawnOverTime each agent", s.num_agents);
             for _ in 0..s.num_agents {
                 timer.next();
-                s.spawn_agent(rng, sim, &mut reserved_cars, &neighborhoods, map, timer);
+                if let Some(req) =
+                    s.make_request(rng, sim, &mut reserved_cars, &neighborhoods, map, timer)
+                {
+                    requests.push(req);
+                }
             }
         }
 
         timer.start_iter("BorderSpawnOverTime", self.border_spawn_over_time.len());
         for s in &self.border_spawn_over_time {
             timer.next();
-            s.spawn_peds(rng, sim, &neighborhoods, map, timer);
-            s.spawn_cars(rng, sim, &neighborhoods, map, timer);
-            s.spawn_bikes(rng, sim, &neighborhoods, map, timer);
+            if let Some(start) = find_border(s.start_from_border, map, timer) {
+                requests.extend(s.ped_requests(start, rng, &neighborhoods, map, timer));
+                s.spawn_cars(start, rng, sim, &neighborhoods, map, timer);
+                s.spawn_bikes(start, rng, sim, &neighborhoods, map, timer);
+            }
+        }
+
+        let all_itineraries = sim.plan_trips(
+            requests.iter().map(|(_, req)| req.clone()).collect(),
+            map,
+            timer,
+        );
+        for ((spawn_time, req), itineraries) in requests.into_iter().zip(all_itineraries) {
+            if let Some(itinerary) = choose_itinerary(itineraries, rng) {
+                sim.schedule_trip(
+                    spawn_time,
+                    TripSpec::UsingItinerary {
+                        start: req.start,
+                        itinerary,
+                        ped_speed: req.ped_speed,
+                    },
+                    map,
+                );
+            } else {
+                timer.warn(format!("Couldn't find any way to make {:?}", req));
+            }
         }
 
         sim.spawn_all_trips(map, timer, true);
@@ -156,7 +190,7 @@ impl Scenario {
                     num_bikes: 10,
                     start_time: Duration::ZERO,
                     stop_time: Duration::seconds(5.0),
-                    start_from_border: i.id,
+                    start_from_border: BorderRef::Original(map.original_intersection(i.id)),
                     goal: OriginDestination::Neighborhood("_everywhere_".to_string()),
                     percent_use_transit: 0.5,
                 })
@@ -168,7 +202,9 @@ impl Scenario {
                 start_time: Duration::ZERO,
                 stop_time: Duration::seconds(5.0),
                 start_from_neighborhood: "_everywhere_".to_string(),
-                goal: OriginDestination::Border(i.id),
+                goal: OriginDestination::Border(BorderRef::Original(
+                    map.original_intersection(i.id),
+                )),
                 percent_biking: 0.5,
                 percent_use_transit: 0.5,
             });
@@ -206,6 +242,8 @@ impl Scenario {
             vehicle_type: VehicleType::Car,
             length,
             max_speed: None,
+            accel: CAR_ACCEL,
+            decel: CAR_DECEL,
         }
     }
 
@@ -219,6 +257,8 @@ impl Scenario {
             vehicle_type: VehicleType::Bike,
             length: BIKE_LENGTH,
             max_speed,
+            accel: BIKE_ACCEL,
+            decel: BIKE_DECEL,
         }
     }
 
@@ -246,15 +286,15 @@ impl Scenario {
 }
 
 impl SpawnOverTime {
-    fn spawn_agent(
+    fn make_request(
         &self,
         rng: &mut XorShiftRng,
-        sim: &mut Sim,
+        sim: &Sim,
         reserved_cars: &mut HashSet<CarID>,
         neighborhoods: &HashMap<String, FullNeighborhoodInfo>,
         map: &Map,
         timer: &mut Timer,
-    ) {
+    ) -> Option<(Duration, TripPlanRequest)> {
         let spawn_time = rand_time(rng, self.start_time, self.stop_time);
         // Note that it's fine for agents to start/end at the same building. Later we might
         // want a better assignment of people per household, or workers per office building.
@@ -263,182 +303,91 @@ impl SpawnOverTime {
             .choose(rng)
             .unwrap();
 
-        // What mode?
-        if let Some(parked_car) = sim
+        let goal = match self.goal.pick_endpoint(map, &neighborhoods, rng, timer) {
+            Some(g) => g,
+            None => {
+                timer.warn(format!("Couldn't fulfill {:?} at all", self));
+                return None;
+            }
+        };
+        // The car is offered to this agent only, even if they wind up not driving, so that
+        // everybody's trip can be planned independently.
+        let parked_car = sim
             .get_parked_cars_by_owner(from_bldg)
             .into_iter()
             .find(|p| !reserved_cars.contains(&p.vehicle.id))
-        {
-            if let Some(goal) = self.goal.pick_driving_goal(
-                vec![LaneType::Driving],
-                map,
-                &neighborhoods,
-                rng,
-                timer,
-            ) {
-                reserved_cars.insert(parked_car.vehicle.id);
-                sim.schedule_trip(
-                    spawn_time,
-                    TripSpec::UsingParkedCar {
-                        start: SidewalkSpot::building(from_bldg, map),
-                        spot: parked_car.spot,
-                        goal,
-                        ped_speed: Scenario::rand_ped_speed(rng),
-                    },
-                    map,
-                );
-                return;
-            }
-        }
-
-        if rng.gen_bool(self.percent_biking) {
-            if let Some(goal) = self.goal.pick_driving_goal(
-                vec![LaneType::Driving, LaneType::Biking],
-                map,
-                &neighborhoods,
-                rng,
-                timer,
-            ) {
-                let start_at = map.get_b(from_bldg).sidewalk();
-                // TODO Just start biking on the other side of the street if the sidewalk
-                // is on a one-way. Or at least warn.
-                if map
-                    .get_parent(start_at)
-                    .sidewalk_to_bike(start_at)
-                    .is_some()
-                {
-                    let ok = if let DrivingGoal::ParkNear(to_bldg) = goal {
-                        let end_at = map.get_b(to_bldg).sidewalk();
-                        map.get_parent(end_at).sidewalk_to_bike(end_at).is_some()
-                            && start_at != end_at
-                    } else {
-                        true
-                    };
-                    if ok {
-                        sim.schedule_trip(
-                            spawn_time,
-                            TripSpec::UsingBike {
-                                start: SidewalkSpot::building(from_bldg, map),
-                                vehicle: Scenario::rand_bike(rng),
-                                goal,
-                                ped_speed: Scenario::rand_ped_speed(rng),
-                            },
-                            map,
-                        );
-                        return;
-                    }
-                }
-            }
-        }
-
-        if let Some(goal) = self.goal.pick_walking_goal(map, &neighborhoods, rng, timer) {
-            let start_spot = SidewalkSpot::building(from_bldg, map);
-            if start_spot == goal {
-                timer.warn("Skipping walking trip between same two buildings".to_string());
-                return;
-            }
-
-            if rng.gen_bool(self.percent_use_transit) {
-                // TODO This throws away some work. It also sequentially does expensive
-                // work right here.
-                if let Some((stop1, stop2, route)) =
-                    map.should_use_transit(start_spot.sidewalk_pos, goal.sidewalk_pos)
-                {
-                    sim.schedule_trip(
-                        spawn_time,
-                        TripSpec::UsingTransit {
-                            start: start_spot,
-                            route,
-                            stop1,
-                            stop2,
-                            goal,
-                            ped_speed: Scenario::rand_ped_speed(rng),
-                        },
-                        map,
-                    );
-                    return;
-                }
-            }
-
-            sim.schedule_trip(
-                spawn_time,
-                TripSpec::JustWalking {
-                    start: start_spot,
-                    goal,
-                    ped_speed: Scenario::rand_ped_speed(rng),
-                },
-                map,
-            );
-            return;
-        }
-
-        timer.warn(format!("Couldn't fulfill {:?} at all", self));
+            .map(|p| {
+                reserved_cars.insert(p.vehicle.id);
+                p.spot
+            });
+        let bike = if rng.gen_bool(self.percent_biking) {
+            Some(Scenario::rand_bike(rng))
+        } else {
+            None
+        };
+        let use_transit = rng.gen_bool(self.percent_use_transit);
+
+        Some((
+            spawn_time,
+            TripPlanRequest {
+                start: SidewalkSpot::building(from_bldg, map),
+                goal,
+                parked_car,
+                bike,
+                ped_speed: Scenario::rand_ped_speed(rng),
+                use_transit,
+            },
+        ))
     }
 }
 
 impl BorderSpawnOverTime {
-    fn spawn_peds(
+    fn ped_requests(
         &self,
+        start_from_border: IntersectionID,
         rng: &mut XorShiftRng,
-        sim: &mut Sim,
         neighborhoods: &HashMap<String, FullNeighborhoodInfo>,
         map: &Map,
         timer: &mut Timer,
-    ) {
+    ) -> Vec<(Duration, TripPlanRequest)> {
+        let mut requests = Vec::new();
         if self.num_peds == 0 {
-            return;
+            return requests;
         }
 
-        let start = if let Some(s) = SidewalkSpot::start_at_border(self.start_from_border, map) {
+        let start = if let Some(s) = SidewalkSpot::start_at_border(start_from_border, map) {
             s
         } else {
             timer.warn(format!(
                 "Can't start_at_border for {} without sidewalk",
-                self.start_from_border
+                start_from_border
             ));
-            return;
+            return requests;
         };
 
         for _ in 0..self.num_peds {
             let spawn_time = rand_time(rng, self.start_time, self.stop_time);
-            if let Some(goal) = self.goal.pick_walking_goal(map, &neighborhoods, rng, timer) {
-                if rng.gen_bool(self.percent_use_transit) {
-                    // TODO This throws away some work. It also sequentially does expensive
-                    // work right here.
-                    if let Some((stop1, stop2, route)) =
-                        map.should_use_transit(start.sidewalk_pos, goal.sidewalk_pos)
-                    {
-                        sim.schedule_trip(
-                            spawn_time,
-                            TripSpec::UsingTransit {
-                                start: start.clone(),
-                                route,
-                                stop1,
-                                stop2,
-                                goal,
-                                ped_speed: Scenario::rand_ped_speed(rng),
-                            },
-                            map,
-                        );
-                        continue;
-                    }
-                }
-
-                sim.schedule_trip(
+            if let Some(goal) = self.goal.pick_endpoint(map, &neighborhoods, rng, timer) {
+                // Pedestrians coming from a border don't have a car or bike with them.
+                requests.push((
                     spawn_time,
-                    TripSpec::JustWalking {
+                    TripPlanRequest {
                         start: start.clone(),
                         goal,
+                        parked_car: None,
+                        bike: None,
                         ped_speed: Scenario::rand_ped_speed(rng),
+                        use_transit: rng.gen_bool(self.percent_use_transit),
                     },
-                    map,
-                );
+                ));
             }
         }
+        requests
     }
 
     fn spawn_cars(
         &self,
+        start_from_border: IntersectionID,
         rng: &mut XorShiftRng,
         sim: &mut Sim,
         neighborhoods: &HashMap<String, FullNeighborhoodInfo>,
@@ -449,12 +398,12 @@ impl BorderSpawnOverTime {
             return;
         }
         let starting_driving_lanes = map
-            .get_i(self.start_from_border)
+            .get_i(start_from_border)
             .get_outgoing_lanes(map, LaneType::Driving);
         if starting_driving_lanes.is_empty() {
             timer.warn(format!(
                 "Can't start car at border for {}",
-                self.start_from_border
+                start_from_border
             ));
             return;
         }
@@ -494,6 +443,7 @@ impl BorderSpawnOverTime {
 
     fn spawn_bikes(
         &self,
+        start_from_border: IntersectionID,
         rng: &mut XorShiftRng,
         sim: &mut Sim,
         neighborhoods: &HashMap<String, FullNeighborhoodInfo>,
@@ -504,10 +454,10 @@ impl BorderSpawnOverTime {
             return;
         }
         let mut starting_biking_lanes = map
-            .get_i(self.start_from_border)
+            .get_i(start_from_border)
             .get_outgoing_lanes(map, LaneType::Biking);
         for l in map
-            .get_i(self.start_from_border)
+            .get_i(start_from_border)
             .get_outgoing_lanes(map, LaneType::Driving)
         {
             if map.get_parent(l).supports_bikes() {
@@ -519,7 +469,7 @@ impl BorderSpawnOverTime {
         {
             timer.warn(format!(
                 "Can't start bike at border for {}",
-                self.start_from_border
+                start_from_border
             ));
             return;
         }
@@ -552,8 +502,34 @@ impl BorderSpawnOverTime {
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub enum OriginDestination {
     Neighborhood(String),
-    // TODO A serialized Scenario won't last well as the map changes...
-    Border(IntersectionID),
+    Border(BorderRef),
+}
+
+// Scenarios refer to borders by where they are, so they still work after the map is imported
+// again. Older scenarios used IntersectionIDs, which only match the version of the map they were
+// made with, but they're still loaded.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(untagged)]
+pub enum BorderRef {
+    Original(OriginalIntersection),
+    Legacy(IntersectionID),
+}
+
+impl BorderRef {
+    pub fn resolve(self, map: &Map) -> Option<IntersectionID> {
+        match self {
+            BorderRef::Original(orig) => map.find_original_intersection(orig),
+            BorderRef::Legacy(i) => {
+                if i.0 < map.all_intersections().len()
+                    && map.get_i(i).intersection_type == IntersectionType::Border
+                {
+                    Some(i)
+                } else {
+                    None
+                }
+            }
+        }
+    }
 }
 
 impl OriginDestination {
@@ -569,8 +545,9 @@ impl OriginDestination {
             OriginDestination::Neighborhood(ref n) => Some(DrivingGoal::ParkNear(
                 *neighborhoods[n].buildings.choose(rng).unwrap(),
             )),
-            OriginDestination::Border(i) => {
-                let goal = DrivingGoal::end_at_border(*i, lane_types, map);
+            OriginDestination::Border(orig) => {
+                let i = find_border(*orig, map, timer)?;
+                let goal = DrivingGoal::end_at_border(i, lane_types, map);
                 if goal.is_none() {
                     timer.warn(format!(
                         "Can't spawn a car ending at border {}; no appropriate lanes there",
@@ -582,29 +559,32 @@ impl OriginDestination {
         }
     }
 
-    fn pick_walking_goal(
+    fn pick_endpoint(
         &self,
         map: &Map,
         neighborhoods: &HashMap<String, FullNeighborhoodInfo>,
         rng: &mut XorShiftRng,
         timer: &mut Timer,
-    ) -> Option<SidewalkSpot> {
+    ) -> Option<TripEndpoint> {
         match self {
-            OriginDestination::Neighborhood(ref n) => Some(SidewalkSpot::building(
+            OriginDestination::Neighborhood(ref n) => Some(TripEndpoint::Building(
                 *neighborhoods[n].buildings.choose(rng).unwrap(),
-                map,
             )),
-            OriginDestination::Border(i) => {
-                let goal = SidewalkSpot::end_at_border(*i, map);
-                if goal.is_none() {
-                    timer.warn(format!("Can't end_at_border for {} without a sidewalk", i));
-                }
-                goal
+            OriginDestination::Border(orig) => {
+                Some(TripEndpoint::Border(find_border(*orig, map, timer)?))
             }
         }
     }
 }
 
+fn find_border(border: BorderRef, map: &Map, timer: &mut Timer) -> Option<IntersectionID> {
+    let i = border.resolve(map);
+    if i.is_none() {
+        timer.warn(format!("No border {:?} in {}", border, map.get_name()));
+    }
+    i
+}
+
 fn seed_parked_cars(
     sim: &mut Sim,
     cars_per_building: &WeightedUsizeChoice,
@@ -614,25 +594,33 @@ fn seed_parked_cars(
     map: &Map,
     timer: &mut Timer,
 ) {
-    // Track the available parking spots per road, only for the roads in the appropriate
-    // neighborhood.
+    // Count the available parking spots, only for the roads in the appropriate neighborhood.
     let mut total_spots = 0;
-    let mut open_spots_per_road: HashMap<RoadID, Vec<ParkingSpot>> = HashMap::new();
     for id in neighborhoods_roads {
         let r = map.get_r(*id);
-        let mut spots: Vec<ParkingSpot> = Vec::new();
         for (lane, lane_type) in r
             .children_forwards
             .iter()
             .chain(r.children_backwards.iter())
         {
             if *lane_type == LaneType::Parking {
-                spots.extend(sim.get_free_spots(*lane));
+                total_spots += sim.get_free_spots(*lane).len();
+            }
+        }
+    }
+    // Garages count towards the road their building is on, and lots towards the road they're
+    // entered from.
+    for b in map.all_buildings() {
+        if b.parking.is_some() && neighborhoods_roads.contains(&map.building_to_road(b.id).id) {
+            total_spots += sim.get_free_offstreet_spots(b.id).len();
+        }
+    }
+    for lot in map.all_parking_lots() {
+        if let Some(ref p) = lot.parking {
+            if neighborhoods_roads.contains(&map.get_l(p.driving_pos.lane()).parent) {
+                total_spots += sim.get_free_lot_spots(lot.id).len();
             }
         }
-        total_spots += spots.len();
-        spots.shuffle(&mut fork_rng(base_rng));
-        open_spots_per_road.insert(r.id, spots);
     }
 
     let mut new_cars = 0;
@@ -641,14 +629,10 @@ fn seed_parked_cars(
         timer.next();
         for _ in 0..cars_per_building.sample(base_rng) {
             let mut forked_rng = fork_rng(base_rng);
-            if let Some(spot) = find_spot_near_building(
-                *b,
-                &mut open_spots_per_road,
-                neighborhoods_roads,
-                map,
-                timer,
-            ) {
-                sim.seed_parked_car(Scenario::rand_car(&mut forked_rng), spot, Some(*b));
+            let vehicle = Scenario::rand_car(&mut forked_rng);
+            if let Some(spot) = find_spot_near_building(*b, &vehicle, sim, neighborhoods_roads, map)
+            {
+                sim.seed_parked_car(vehicle, spot, Some(*b));
                 new_cars += 1;
             } else {
                 // TODO This should be more critical, but neighborhoods can currently contain a
@@ -673,48 +657,29 @@ fn seed_parked_cars(
     ));
 }
 
-// Pick a parking spot for this building. If the building's road has a free spot, use it. If not,
-// start BFSing out from the road in a deterministic way until finding a nearby road with an open
-// spot.
+// Pick a parking spot for this building the same way its driver would when arriving: the closest
+// free spot by driving distance, cruising outwards if the building's own lane is full. The spot
+// has to be in the neighborhood.
 fn find_spot_near_building(
     b: BuildingID,
-    open_spots_per_road: &mut HashMap<RoadID, Vec<ParkingSpot>>,
+    vehicle: &VehicleSpec,
+    sim: &Sim,
     neighborhoods_roads: &BTreeSet<RoadID>,
     map: &Map,
-    timer: &mut Timer,
 ) -> Option<ParkingSpot> {
-    let mut roads_queue: VecDeque<RoadID> = VecDeque::new();
-    let mut visited: HashSet<RoadID> = HashSet::new();
-    {
-        let start = map.building_to_road(b).id;
-        roads_queue.push_back(start);
-        visited.insert(start);
-    }
-
-    loop {
-        if roads_queue.is_empty() {
-            timer.warn(format!(
-                "Giving up looking for a free parking spot, searched {} roads of {}: {:?}",
-                visited.len(),
-                open_spots_per_road.len(),
-                visited
-            ));
-        }
-        let r = roads_queue.pop_front()?;
-        if let Some(spots) = open_spots_per_road.get_mut(&r) {
-            // TODO With some probability, skip this available spot and park farther away
-            if !spots.is_empty() {
-                return spots.pop();
-            }
-        }
-
-        for next_r in map.get_next_roads(r).into_iter() {
-            // Don't floodfill out of the neighborhood
-            if !visited.contains(&next_r) && neighborhoods_roads.contains(&next_r) {
-                roads_queue.push_back(next_r);
-                visited.insert(next_r);
-            }
+    let spot = sim.find_parking_spot_near_building(b, vehicle, map)?;
+    let road = match spot {
+        ParkingSpot::Onstreet(l, _) => map.get_l(l).parent,
+        ParkingSpot::Offstreet(bldg, _) => map.building_to_road(bldg).id,
+        ParkingSpot::Lot(lot, _) => {
+            map.get_l(map.get_pl(lot).parking.as_ref()?.driving_pos.lane())
+                .parent
         }
+    };
+    if neighborhoods_roads.contains(&road) {
+        Some(spot)
+    } else {
+        None
     }
 }
 
@@ -1,7 +1,7 @@
 use crate::{
-    CarID, Command, CreateCar, CreatePedestrian, DrivingGoal, ParkingSimState, ParkingSpot,
-    PedestrianID, Scheduler, SidewalkPOI, SidewalkSpot, TripLeg, TripManager, VehicleSpec,
-    VehicleType, MAX_CAR_LENGTH,
+    CarID, Command, CreateCar, CreatePedestrian, DrivingGoal, Itinerary, ParkingSimState,
+    ParkingSpot, PedestrianID, PlannedLeg, Scheduler, SidewalkPOI, SidewalkSpot, TripLeg,
+    TripManager, VehicleSpec, VehicleType, MAX_CAR_LENGTH,
 };
 use abstutil::Timer;
 use geom::{Duration, Speed, EPSILON_DIST};
@@ -43,6 +43,12 @@ pub enum TripSpec {
         stop2: BusStopID,
         ped_speed: Speed,
     },
+    // Whatever combination of modes the planner came up with
+    UsingItinerary {
+        start: SidewalkSpot,
+        itinerary: Itinerary,
+        ped_speed: Speed,
+    },
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -141,6 +147,70 @@ impl TripSpawner {
                 }
             }
             TripSpec::UsingTransit { .. } => {}
+            TripSpec::UsingItinerary { itinerary, .. } => {
+                match itinerary.legs.get(0) {
+                    Some(PlannedLeg::Walk(_)) => {}
+                    x => panic!("An itinerary has to start by walking, not {:?}", x),
+                }
+                match itinerary.legs.last() {
+                    Some(PlannedLeg::RideBus(_, _, _))
+                    | Some(PlannedLeg::Drive(_, DrivingGoal::ParkNear(_)))
+                    | Some(PlannedLeg::Bike(_, DrivingGoal::ParkNear(_))) => {
+                        panic!(
+                            "An itinerary has to end by walking or at a border: {:?}",
+                            itinerary
+                        );
+                    }
+                    _ => {}
+                }
+                // Every other leg starts where the pedestrian walked to.
+                for pair in itinerary.legs.windows(2) {
+                    let ok = match (&pair[0], &pair[1]) {
+                        (PlannedLeg::Walk(spot), PlannedLeg::Drive(parking_spot, _)) => {
+                            *spot == SidewalkSpot::parking_spot(*parking_spot, map, parking)
+                        }
+                        (PlannedLeg::Walk(spot), PlannedLeg::Bike(_, _)) => match spot.connection {
+                            SidewalkPOI::BikeRack(_) => true,
+                            _ => false,
+                        },
+                        (PlannedLeg::Walk(spot), PlannedLeg::RideBus(_, stop1, _)) => {
+                            *spot == SidewalkSpot::bus_stop(*stop1, map)
+                        }
+                        (PlannedLeg::Walk(_), PlannedLeg::Walk(_)) => false,
+                        (PlannedLeg::Drive(_, DrivingGoal::Border(_, _)), _)
+                        | (PlannedLeg::Bike(_, DrivingGoal::Border(_, _)), _) => false,
+                        (_, PlannedLeg::Walk(_)) => true,
+                        _ => false,
+                    };
+                    if !ok {
+                        panic!("{:?} can't follow {:?} in an itinerary", pair[1], pair[0]);
+                    }
+                }
+                // Only one vehicle is allocated for the trip.
+                let mut vehicles = 0;
+                for leg in &itinerary.legs {
+                    match leg {
+                        PlannedLeg::Drive(spot, _) => {
+                            vehicles += 1;
+                            let car_id = parking.get_car_at_spot(*spot).unwrap().vehicle.id;
+                            if self.parked_cars_claimed.contains(&car_id) {
+                                panic!(
+                                    "A TripSpec wants to use {}, which is already claimed",
+                                    car_id
+                                );
+                            }
+                            self.parked_cars_claimed.insert(car_id);
+                        }
+                        PlannedLeg::Bike(_, _) => {
+                            vehicles += 1;
+                        }
+                        PlannedLeg::Walk(_) | PlannedLeg::RideBus(_, _, _) => {}
+                    }
+                }
+                if vehicles > 1 {
+                    panic!("An itinerary can only use one car or bike: {:?}", itinerary);
+                }
+            }
         };
 
         self.trips.push((start_time, ped_id, car_id, spec));
@@ -326,6 +396,47 @@ impl TripSpawner {
                         }),
                     );
                 }
+                TripSpec::UsingItinerary {
+                    start,
+                    itinerary,
+                    ped_speed,
+                } => {
+                    let ped = ped_id.unwrap();
+                    let legs: Vec<TripLeg> = itinerary
+                        .legs
+                        .into_iter()
+                        .map(|leg| match leg {
+                            PlannedLeg::Walk(spot) => TripLeg::Walk(ped, ped_speed, spot),
+                            PlannedLeg::Drive(spot, goal) => TripLeg::Drive(
+                                parking.get_car_at_spot(spot).unwrap().vehicle.clone(),
+                                goal,
+                            ),
+                            PlannedLeg::Bike(vehicle, goal) => {
+                                TripLeg::Drive(vehicle.make(car_id.unwrap(), None), goal)
+                            }
+                            PlannedLeg::RideBus(route, _, stop2) => {
+                                TripLeg::RideBus(ped, route, stop2)
+                            }
+                        })
+                        .collect();
+                    let walk_to = match legs[0] {
+                        TripLeg::Walk(_, _, ref spot) => spot.clone(),
+                        _ => unreachable!(),
+                    };
+                    let trip = trips.new_trip(start_time, legs);
+
+                    scheduler.push(
+                        start_time,
+                        Command::SpawnPed(CreatePedestrian {
+                            id: ped,
+                            speed: ped_speed,
+                            start,
+                            goal: walk_to,
+                            path,
+                            trip,
+                        }),
+                    );
+                }
             }
         }
     }
@@ -392,6 +503,17 @@ impl TripSpec {
                 can_use_bike_lanes: false,
                 can_use_bus_lanes: false,
             },
+            TripSpec::UsingItinerary {
+                start, itinerary, ..
+            } => PathRequest {
+                start: start.sidewalk_pos,
+                end: match itinerary.legs[0] {
+                    PlannedLeg::Walk(ref spot) => spot.sidewalk_pos,
+                    _ => unreachable!(),
+                },
+                can_use_bike_lanes: false,
+                can_use_bus_lanes: false,
+            },
         }
     }
 }
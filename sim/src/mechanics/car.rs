@@ -1,7 +1,8 @@
 use crate::{
-    CarStatus, DistanceInterval, DrawCarInput, ParkingSpot, Router, TimeInterval, Vehicle,
+    biking_speed_on_grade, CarStatus, DistanceInterval, DrawCarInput, ParkingSpot, Router,
+    TimeInterval, Vehicle, VehicleType, MAX_DECEL,
 };
-use geom::{Distance, Duration, PolyLine};
+use geom::{Acceleration, Distance, Duration, PolyLine, Speed, EPSILON_DIST};
 use map_model::{Map, Traversable, LANE_THICKNESS};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::VecDeque;
@@ -15,25 +16,62 @@ pub struct Car {
     // In reverse order -- most recently left is first. The sum length of these must be >=
     // vehicle.length.
     pub last_steps: VecDeque<Traversable>,
+
+    // When the car last finished Crossing, and how fast it was going. If it starts Crossing again
+    // at the same time, it keeps that speed instead of starting from rest.
+    pub finished_crossing: Option<(Duration, Speed)>,
+    // When the car entered the start of its current lane. None if it started partway along.
+    pub entered_lane: Option<Duration>,
+    // The length of every step already left behind, minus how far along the first one the car
+    // started.
+    pub dist_crossed: Distance,
 }
 
 impl Car {
-    // Assumes the current head of the path is the thing to cross.
+    // Assumes the current head of the path is the thing to cross. If the car already knows it has
+    // to stop somewhere before the end -- behind a stopped leader, or at the stop line -- it only
+    // crosses up to there and brakes on the way.
     pub fn crossing_state(
         &self,
         start_dist: Distance,
         start_time: Duration,
+        stop_at: Option<Distance>,
         map: &Map,
     ) -> CarState {
-        let dist_int = DistanceInterval::new_driving(
-            start_dist,
-            if self.router.last_step() {
-                self.router.get_end_dist()
-            } else {
-                self.router.head().length(map)
-            },
-        );
-        self.crossing_state_with_end_dist(dist_int, start_time, map)
+        let end_dist = self.crossing_end(map);
+        let (dist_int, stop_at_end) = match stop_at {
+            Some(dist) if dist <= end_dist => (
+                DistanceInterval::new_driving(start_dist, dist.max(start_dist)),
+                true,
+            ),
+            _ => (
+                DistanceInterval::new_driving(start_dist, end_dist),
+                self.router.last_step() && self.router.stops_at_end(),
+            ),
+        };
+        self.crossing_state_with_profile(dist_int, start_time, stop_at_end, map)
+    }
+
+    // How far the car has actually gone, including any cruising for parking, when its front is
+    // this far along the current step.
+    pub fn dist_travelled(&self, front: Distance) -> Distance {
+        self.dist_crossed + front
+    }
+
+    // Where Crossing the current head of the path ends, if nothing's in the way.
+    pub fn crossing_end(&self, map: &Map) -> Distance {
+        if self.router.last_step() {
+            self.router.get_end_dist()
+        } else {
+            self.router.head().length(map)
+        }
+    }
+
+    pub fn is_crossing(&self) -> bool {
+        match self.state {
+            CarState::Crossing(_, _, _) => true,
+            _ => false,
+        }
     }
 
     pub fn crossing_state_with_end_dist(
@@ -41,14 +79,64 @@ impl Car {
         dist_int: DistanceInterval,
         start_time: Duration,
         map: &Map,
+    ) -> CarState {
+        self.crossing_state_with_profile(dist_int, start_time, false, map)
+    }
+
+    fn crossing_state_with_profile(
+        &self,
+        dist_int: DistanceInterval,
+        start_time: Duration,
+        stop_at_end: bool,
+        map: &Map,
     ) -> CarState {
         let on = self.router.head();
         let mut speed = on.speed_limit(map);
-        if let Some(s) = self.vehicle.max_speed {
+        if let Some(mut s) = self.vehicle.max_speed {
+            if self.vehicle.vehicle_type == VehicleType::Bike {
+                if let Traversable::Lane(l) = on {
+                    s = biking_speed_on_grade(s, map.get_l(l).grade(dist_int.start, dist_int.end));
+                }
+            }
             speed = speed.min(s);
         }
-        let dt = (dist_int.end - dist_int.start) / speed;
-        CarState::Crossing(TimeInterval::new(start_time, start_time + dt), dist_int)
+        let profile = SpeedProfile::new(
+            dist_int.end - dist_int.start,
+            self.current_speed(dist_int.start, start_time),
+            speed,
+            self.vehicle.accel,
+            self.vehicle.decel,
+            stop_at_end,
+        );
+        let dt = profile.total_time();
+        CarState::Crossing(
+            TimeInterval::new(start_time, start_time + dt),
+            dist_int,
+            profile,
+        )
+    }
+
+    // How fast is the car going right now, at the front position? Anything not Crossing is at
+    // rest, unless it finished Crossing at this exact moment and is about to continue.
+    fn current_speed(&self, front: Distance, time: Duration) -> Speed {
+        match self.state {
+            CarState::Crossing(ref time_int, ref dist_int, ref profile) => {
+                let dt = (time - time_int.start).min(time_int.end - time_int.start);
+                // If the car's actually stuck behind a leader, it's not really moving.
+                if front + EPSILON_DIST < dist_int.start + profile.dist_after(dt) {
+                    Speed::ZERO
+                } else {
+                    profile.speed_after(dt)
+                }
+            }
+            CarState::Queued | CarState::WaitingToAdvance => match self.finished_crossing {
+                Some((t, speed)) if t == time => speed,
+                _ => Speed::ZERO,
+            },
+            CarState::Unparking(_, _) | CarState::Parking(_, _, _) | CarState::Idling(_, _) => {
+                Speed::ZERO
+            }
+        }
     }
 
     pub fn get_draw_car(&self, front: Distance, time: Duration, map: &Map) -> DrawCarInput {
@@ -113,7 +201,7 @@ impl Car {
                 // TODO Cars can be Queued behind a slow Crossing. Looks kind of weird.
                 CarState::Queued => CarStatus::Stuck,
                 CarState::WaitingToAdvance => CarStatus::Stuck,
-                CarState::Crossing(_, _) => CarStatus::Moving,
+                CarState::Crossing(_, _, _) => CarStatus::Moving,
                 // Eh they're technically moving, but this is a bit easier to spot
                 CarState::Unparking(_, _) => CarStatus::Parked,
                 CarState::Parking(_, _, _) => CarStatus::Parked,
@@ -129,7 +217,7 @@ impl Car {
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
 pub enum CarState {
-    Crossing(TimeInterval, DistanceInterval),
+    Crossing(TimeInterval, DistanceInterval, SpeedProfile),
     Queued,
     WaitingToAdvance,
     // Where's the front of the car while this is happening?
@@ -141,7 +229,7 @@ pub enum CarState {
 impl CarState {
     pub fn get_end_time(&self) -> Duration {
         match self {
-            CarState::Crossing(ref time_int, _) => time_int.end,
+            CarState::Crossing(ref time_int, _, _) => time_int.end,
             CarState::Queued => unreachable!(),
             CarState::WaitingToAdvance => unreachable!(),
             CarState::Unparking(_, ref time_int) => time_int.end,
@@ -150,3 +238,112 @@ impl CarState {
         }
     }
 }
+
+// How a car covers a DistanceInterval: speed up from the starting speed to the cruising speed,
+// then optionally brake to a stop exactly at the end. Times are relative to the start. Braking
+// never exceeds MAX_DECEL; if that's not enough to stop in time, the car reaches the end still
+// moving.
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct SpeedProfile {
+    start_speed: Speed,
+    // The cruising speed, unless the interval is too short to reach it.
+    peak_speed: Speed,
+    accel: Acceleration,
+    decel: Acceleration,
+    accel_time: Duration,
+    cruise_time: Duration,
+    brake_time: Duration,
+}
+
+impl SpeedProfile {
+    pub fn new(
+        dist: Distance,
+        start_speed: Speed,
+        cruise_speed: Speed,
+        accel: Acceleration,
+        decel: Acceleration,
+        stop_at_end: bool,
+    ) -> SpeedProfile {
+        let d = dist.inner_meters();
+        // If we're coming from somewhere faster, assume we already slowed down.
+        let v0 = start_speed.min(cruise_speed).inner_meters_per_second();
+        let vmax = cruise_speed.inner_meters_per_second();
+        let a = accel.inner_meters_per_second_squared();
+        let b_max = MAX_DECEL.inner_meters_per_second_squared();
+        let mut b = decel.inner_meters_per_second_squared().min(b_max);
+
+        let (vp, t1, t2, t3) = if d <= 0.0 {
+            (v0, 0.0, 0.0, 0.0)
+        } else if !stop_at_end {
+            let d1 = (vmax * vmax - v0 * v0) / (2.0 * a);
+            if d1 >= d {
+                let vp = (v0 * v0 + 2.0 * a * d).sqrt();
+                (vp, (vp - v0) / a, 0.0, 0.0)
+            } else {
+                (vmax, (vmax - v0) / a, (d - d1) / vmax, 0.0)
+            }
+        } else if v0 * v0 / (2.0 * b) >= d {
+            // Too close to stop comfortably; brake harder, up to a point.
+            b = (v0 * v0 / (2.0 * d)).min(b_max);
+            let v_end = (v0 * v0 - 2.0 * b * d).max(0.0).sqrt();
+            (v0, 0.0, 0.0, (v0 - v_end) / b)
+        } else {
+            let d1 = (vmax * vmax - v0 * v0) / (2.0 * a);
+            let d3 = vmax * vmax / (2.0 * b);
+            if d1 + d3 <= d {
+                (vmax, (vmax - v0) / a, (d - d1 - d3) / vmax, vmax / b)
+            } else {
+                let vp = ((d + v0 * v0 / (2.0 * a)) / (1.0 / (2.0 * a) + 1.0 / (2.0 * b))).sqrt();
+                (vp, (vp - v0) / a, 0.0, vp / b)
+            }
+        };
+
+        SpeedProfile {
+            start_speed: Speed::meters_per_second(v0),
+            peak_speed: Speed::meters_per_second(vp),
+            accel,
+            decel: Acceleration::meters_per_second_squared(b),
+            accel_time: Duration::seconds(t1),
+            cruise_time: Duration::seconds(t2),
+            brake_time: Duration::seconds(t3),
+        }
+    }
+
+    pub fn total_time(&self) -> Duration {
+        self.accel_time + self.cruise_time + self.brake_time
+    }
+
+    // Distance covered after dt, clamped to the end of the profile.
+    pub fn dist_after(&self, dt: Duration) -> Distance {
+        let v0 = self.start_speed.inner_meters_per_second();
+        let vp = self.peak_speed.inner_meters_per_second();
+        let a = self.accel.inner_meters_per_second_squared();
+        let b = self.decel.inner_meters_per_second_squared();
+        let t = dt.inner_seconds().max(0.0);
+        let t1 = self.accel_time.inner_seconds();
+        let t2 = self.cruise_time.inner_seconds();
+        let t3 = self.brake_time.inner_seconds();
+
+        let d1 = v0 * t1.min(t) + 0.5 * a * t1.min(t).powi(2);
+        if t <= t1 {
+            return Distance::meters(d1);
+        }
+        let d2 = vp * (t - t1).min(t2);
+        if t <= t1 + t2 {
+            return Distance::meters(d1 + d2);
+        }
+        let tb = (t - t1 - t2).min(t3);
+        Distance::meters(d1 + d2 + vp * tb - 0.5 * b * tb * tb)
+    }
+
+    pub fn speed_after(&self, dt: Duration) -> Speed {
+        if dt <= self.accel_time {
+            self.start_speed + self.accel * dt
+        } else if dt <= self.accel_time + self.cruise_time {
+            self.peak_speed
+        } else {
+            let tb = (dt - self.accel_time - self.cruise_time).min(self.brake_time);
+            (self.peak_speed - self.decel * tb).max(Speed::ZERO)
+        }
+    }
+}
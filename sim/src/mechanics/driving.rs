@@ -3,22 +3,37 @@ use crate::mechanics::queue::Queue;
 use crate::{
     ActionAtEnd, AgentID, CarID, Command, CreateCar, DistanceInterval, DrawCarInput,
     IntersectionSimState, ParkedCar, ParkingSimState, Scheduler, TimeInterval, TransitSimState,
-    TripManager, WalkingSimState, FOLLOWING_DISTANCE,
+    TripManager, VehicleType, WalkingSimState, FOLLOWING_DISTANCE,
 };
 use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::{Distance, Duration, PolyLine, Polygon};
-use map_model::{BuildingID, DirectedRoadID, IntersectionID, LaneID, Map, Path, Traversable};
+use geom::{Distance, Duration, PolyLine, Polygon, EPSILON_DIST};
+use map_model::{
+    BuildingID, DirectedRoadID, IntersectionID, LaneID, Map, Path, Traversable, TurnID,
+};
 use petgraph::graph::{Graph, NodeIndex};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, VecDeque};
 
-const TIME_TO_UNPARK: Duration = Duration::const_seconds(10.0);
-const TIME_TO_PARK: Duration = Duration::const_seconds(15.0);
-const TIME_TO_WAIT_AT_STOP: Duration = Duration::const_seconds(10.0);
+pub(crate) const TIME_TO_UNPARK: Duration = Duration::const_seconds(10.0);
+pub(crate) const TIME_TO_PARK: Duration = Duration::const_seconds(15.0);
 
 // TODO Do something else.
 pub(crate) const BLIND_RETRY_TO_CREEP_FORWARDS: Duration = Duration::const_seconds(0.1);
 pub(crate) const BLIND_RETRY_TO_REACH_END_DIST: Duration = Duration::const_seconds(5.0);
+const RETRY_LANE_CHANGE: Duration = Duration::const_seconds(2.0);
+
+// A car moving over to an adjacent lane of the same road, mid-block
+struct LaneChange {
+    car: CarID,
+    // Where the car is in the old lane's queue
+    old_idx: usize,
+    to: LaneID,
+    // Where the car merges into the new lane's queue, and how far along that lane
+    new_idx: usize,
+    new_dist: Distance,
+    // The turn the car takes from the new lane, to stay on its path
+    new_turn: TurnID,
+}
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct DrivingSimState {
@@ -32,6 +47,13 @@ pub struct DrivingSimState {
         deserialize_with = "deserialize_btreemap"
     )]
     queues: BTreeMap<Traversable, Queue>,
+    // Total time cars spent crossing each road (including waiting to turn at the end), and how
+    // many cars did it. Only counts cars that crossed the whole road.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    travel_times: BTreeMap<DirectedRoadID, (Duration, usize)>,
 }
 
 impl DrivingSimState {
@@ -39,6 +61,7 @@ impl DrivingSimState {
         let mut sim = DrivingSimState {
             cars: BTreeMap::new(),
             queues: BTreeMap::new(),
+            travel_times: BTreeMap::new(),
         };
 
         for l in map.all_lanes() {
@@ -64,7 +87,7 @@ impl DrivingSimState {
         params: CreateCar,
         map: &Map,
         intersections: &IntersectionSimState,
-        parking: &ParkingSimState,
+        parking: &mut ParkingSimState,
         scheduler: &mut Scheduler,
     ) -> bool {
         let first_lane = params.router.head().as_lane();
@@ -85,7 +108,17 @@ impl DrivingSimState {
                 // Temporary
                 state: CarState::Queued,
                 last_steps: VecDeque::new(),
+                finished_crossing: None,
+                entered_lane: None,
+                dist_crossed: -params.start_dist,
             };
+            car.router.reserve_target_spot(&car.vehicle, parking, map);
+            // Join the queue first, so planning sees who's ahead.
+            self.queues
+                .get_mut(&Traversable::Lane(first_lane))
+                .unwrap()
+                .cars
+                .insert(idx, car.vehicle.id);
             if params.maybe_parked_car.is_some() {
                 car.state = CarState::Unparking(
                     params.start_dist,
@@ -94,10 +127,13 @@ impl DrivingSimState {
             } else {
                 // Have to do this early
                 if car.router.last_step() {
-                    match car
-                        .router
-                        .maybe_handle_end(params.start_dist, &car.vehicle, parking, map)
-                    {
+                    match car.router.maybe_handle_end(
+                        time,
+                        params.start_dist,
+                        &car.vehicle,
+                        parking,
+                        map,
+                    ) {
                         None | Some(ActionAtEnd::GotoLaneEnd) => {}
                         x => {
                             panic!("Car with one-step route {:?} had unexpected result from maybe_handle_end: {:?}", car.router, x);
@@ -105,14 +141,9 @@ impl DrivingSimState {
                     }
                 }
 
-                car.state = car.crossing_state(params.start_dist, time, map);
+                car.state = self.crossing_state(&car, params.start_dist, time, map, intersections);
             }
             scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
-            self.queues
-                .get_mut(&Traversable::Lane(first_lane))
-                .unwrap()
-                .cars
-                .insert(idx, car.vehicle.id);
             self.cars.insert(car.vehicle.id, car);
             return true;
         }
@@ -164,6 +195,7 @@ impl DrivingSimState {
             // We need to mutate two different cars in one case. To avoid fighting the borrow
             // checker, temporarily move one of them out of the BTreeMap.
             let mut car = self.cars.remove(&id).unwrap();
+            let was_stopped = !car.is_crossing();
             // Responsibility of update_car to manage scheduling stuff!
             need_distances = self.update_car_without_distances(
                 &mut car,
@@ -171,10 +203,19 @@ impl DrivingSimState {
                 map,
                 parking,
                 intersections,
+                trips,
                 transit,
                 scheduler,
             );
+            let queued = car.state == CarState::Queued;
+            let started_moving = was_stopped && car.is_crossing();
             self.cars.insert(id, car);
+            if started_moving {
+                self.wake_followers(id, time, map, intersections, scheduler);
+            }
+            if queued && !need_distances {
+                self.maybe_change_lanes(id, time, map, intersections, scheduler);
+            }
         }
 
         if need_distances {
@@ -188,6 +229,7 @@ impl DrivingSimState {
             // We need to mutate two different cars in some cases. To avoid fighting the borrow
             // checker, temporarily move one of them out of the BTreeMap.
             let mut car = self.cars.remove(&id).unwrap();
+            let was_stopped = !car.is_crossing();
             // Responsibility of update_car_with_distances to manage scheduling stuff!
             if self.update_car_with_distances(
                 &mut car,
@@ -201,7 +243,11 @@ impl DrivingSimState {
                 walking,
                 intersections,
             ) {
+                let started_moving = was_stopped && car.is_crossing();
                 self.cars.insert(id, car);
+                if started_moving {
+                    self.wake_followers(id, time, map, intersections, scheduler);
+                }
             }
         }
     }
@@ -216,13 +262,19 @@ impl DrivingSimState {
         map: &Map,
         parking: &mut ParkingSimState,
         intersections: &mut IntersectionSimState,
+        trips: &mut TripManager,
         transit: &mut TransitSimState,
         scheduler: &mut Scheduler,
     ) -> bool {
         match car.state {
-            CarState::Crossing(_, _) => {
+            CarState::Crossing(ref time_int, ref dist_int, ref profile) => {
+                car.finished_crossing =
+                    Some((time, profile.speed_after(time_int.end - time_int.start)));
+                let stopped_short = dist_int.end < car.crossing_end(map);
                 car.state = CarState::Queued;
-                if car.router.last_step() {
+                // If we planned to stop behind somebody, they might've moved on since. Or this is
+                // the last step.
+                if stopped_short || car.router.last_step() {
                     // Immediately run update_car_with_distances.
                     return true;
                 }
@@ -234,20 +286,29 @@ impl DrivingSimState {
                 }
             }
             CarState::Unparking(front, _) => {
+                trips.car_finished_unparking(time, car.vehicle.id);
                 if car.router.last_step() {
                     // Actually, we need to do this first. Ignore the answer -- if we're
                     // doing something weird like vanishing or re-parking immediately
                     // (quite unlikely), the next loop will pick that up. Just trigger the
                     // side effect of choosing an end_dist.
                     car.router
-                        .maybe_handle_end(front, &car.vehicle, parking, map);
+                        .maybe_handle_end(time, front, &car.vehicle, parking, map);
                 }
-                car.state = car.crossing_state(front, time, map);
+                car.state = self.crossing_state(car, front, time, map, intersections);
                 scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
             }
-            CarState::Idling(dist, _) => {
-                car.router = transit.bus_departed_from_stop(car.vehicle.id);
-                car.state = car.crossing_state(dist, time, map);
+            CarState::Idling(dist, ref time_int) => {
+                // People boarding late might hold the bus up.
+                let depart_at = transit.bus_departure_time(car.vehicle.id);
+                if depart_at > time {
+                    car.state =
+                        CarState::Idling(dist, TimeInterval::new(time_int.start, depart_at));
+                    scheduler.push(depart_at, Command::UpdateCar(car.vehicle.id));
+                    return false;
+                }
+                car.router = transit.bus_departed_from_stop(time, car.vehicle.id);
+                car.state = self.crossing_state(car, dist, time, map, intersections);
                 scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
 
                 // Update our follower, so they know we stopped idling.
@@ -258,28 +319,30 @@ impl DrivingSimState {
                     .position(|c| *c == car.vehicle.id)
                     .unwrap();
                 if idx != queue.cars.len() - 1 {
-                    let mut follower = self.cars.get_mut(&queue.cars[idx + 1]).unwrap();
+                    let follower_id = queue.cars[idx + 1];
+                    let follower = &self.cars[&follower_id];
                     match follower.state {
                         CarState::Queued => {
                             // If they're on their last step, they might be ending early and not
                             // right behind us.
                             if !follower.router.last_step() {
-                                follower.state = follower.crossing_state(
+                                let state = self.crossing_state(
+                                    follower,
                                     // Since the follower was Queued, this must be where they are.
                                     dist - car.vehicle.length - FOLLOWING_DISTANCE,
                                     time,
                                     map,
+                                    intersections,
                                 );
-                                scheduler.update(
-                                    Command::UpdateCar(follower.vehicle.id),
-                                    follower.state.get_end_time(),
-                                );
+                                scheduler
+                                    .update(Command::UpdateCar(follower_id), state.get_end_time());
+                                self.cars.get_mut(&follower_id).unwrap().state = state;
                             }
                         }
                         CarState::WaitingToAdvance => unreachable!(),
                         // They weren't blocked. Note that there's no way the Crossing state could jump
                         // forwards here; the leader is still in front of them.
-                        CarState::Crossing(_, _)
+                        CarState::Crossing(_, _, _)
                         | CarState::Unparking(_, _)
                         | CarState::Parking(_, _, _)
                         | CarState::Idling(_, _) => {}
@@ -300,6 +363,7 @@ impl DrivingSimState {
                         time,
                         map,
                         scheduler,
+                        trips,
                     ) {
                         // Don't schedule a retry here.
                         return false;
@@ -315,8 +379,33 @@ impl DrivingSimState {
                 // We do NOT need to update the follower. If they were Queued, they'll remain that
                 // way, until laggy_head is None.
 
-                let last_step = car.router.advance(&car.vehicle, parking, map);
-                car.state = car.crossing_state(Distance::ZERO, time, map);
+                match (from, car.entered_lane) {
+                    (Traversable::Lane(l), Some(t0)) => {
+                        // Buses stopping and slow bikes would skew things.
+                        if car.vehicle.vehicle_type == VehicleType::Car {
+                            let entry = self
+                                .travel_times
+                                .entry(map.get_l(l).get_directed_parent(map))
+                                .or_insert((Duration::ZERO, 0));
+                            entry.0 += time - t0;
+                            entry.1 += 1;
+                        }
+                        car.entered_lane = None;
+                    }
+                    (Traversable::Turn(_), _) => {
+                        car.entered_lane = Some(time);
+                    }
+                    _ => {}
+                }
+
+                car.dist_crossed += from.length(map);
+                let last_step = car.router.advance(time, &car.vehicle, parking, map);
+                self.queues
+                    .get_mut(&goto)
+                    .unwrap()
+                    .cars
+                    .push_back(car.vehicle.id);
+                car.state = self.crossing_state(car, Distance::ZERO, time, map, intersections);
                 scheduler.push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
 
                 car.last_steps.push_front(last_step);
@@ -339,12 +428,6 @@ impl DrivingSimState {
                 // the way into a lane later.
 
                 // Don't mark turn_finished until our back is out of the turn.
-
-                self.queues
-                    .get_mut(&goto)
-                    .unwrap()
-                    .cars
-                    .push_back(car.vehicle.id);
             }
             CarState::Parking(_, _, _) => unreachable!(),
         }
@@ -373,57 +456,80 @@ impl DrivingSimState {
 
         // Just two cases here.
         match car.state {
-            CarState::Crossing(_, _)
+            CarState::Crossing(_, _, _)
             | CarState::Unparking(_, _)
             | CarState::Idling(_, _)
             | CarState::WaitingToAdvance => unreachable!(),
             CarState::Queued => {
+                if our_dist + EPSILON_DIST < car.crossing_end(map) {
+                    // Not there yet. If nothing stopped is in the way anymore, keep going.
+                    let state = self.crossing_state(car, our_dist, time, map, intersections);
+                    if let CarState::Crossing(_, ref dist_int, _) = state {
+                        if dist_int.length() > EPSILON_DIST {
+                            car.state = state;
+                            scheduler
+                                .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                            return true;
+                        }
+                    }
+                    // Whoever's in front of us will wake us up when they start moving again or
+                    // leave the queue; see wake_followers.
+                    if !car.router.last_step() {
+                        return true;
+                    }
+                }
+
                 match car
                     .router
-                    .maybe_handle_end(our_dist, &car.vehicle, parking, map)
+                    .maybe_handle_end(time, our_dist, &car.vehicle, parking, map)
                 {
                     Some(ActionAtEnd::VanishAtBorder(i)) => {
+                        trips.agent_travelled(
+                            AgentID::Car(car.vehicle.id),
+                            car.dist_travelled(our_dist),
+                        );
                         trips.car_or_bike_reached_border(time, car.vehicle.id, i);
                     }
                     Some(ActionAtEnd::StartParking(spot)) => {
+                        trips.car_started_parking(time, car.vehicle.id);
                         car.state = CarState::Parking(
                             our_dist,
                             spot,
                             TimeInterval::new(time, time + TIME_TO_PARK),
                         );
-                        // If we don't do this, then we might have another car creep up
-                        // behind, see the spot free, and start parking too. This can
-                        // happen with multiple lanes and certain vehicle lengths.
-                        parking.reserve_spot(spot);
                         scheduler
                             .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
                         return true;
                     }
                     Some(ActionAtEnd::GotoLaneEnd) => {
-                        car.state = car.crossing_state(our_dist, time, map);
+                        car.state = self.crossing_state(car, our_dist, time, map, intersections);
                         scheduler
                             .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
                         return true;
                     }
                     Some(ActionAtEnd::StopBiking(bike_rack)) => {
+                        trips.agent_travelled(
+                            AgentID::Car(car.vehicle.id),
+                            car.dist_travelled(our_dist),
+                        );
                         trips.bike_reached_end(time, car.vehicle.id, bike_rack, map, scheduler);
                     }
                     Some(ActionAtEnd::BusAtStop) => {
-                        transit.bus_arrived_at_stop(
+                        if let Some(depart_at) = transit.bus_arrived_at_stop(
                             time,
                             car.vehicle.id,
                             trips,
                             walking,
                             scheduler,
                             map,
-                        );
-                        car.state = CarState::Idling(
-                            our_dist,
-                            TimeInterval::new(time, time + TIME_TO_WAIT_AT_STOP),
-                        );
-                        scheduler
-                            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
-                        return true;
+                        ) {
+                            car.state =
+                                CarState::Idling(our_dist, TimeInterval::new(time, depart_at));
+                            scheduler
+                                .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                            return true;
+                        }
+                        // Otherwise the bus finished its run and vanishes.
                     }
                     None => {
                         scheduler.push(
@@ -436,7 +542,7 @@ impl DrivingSimState {
                         /*
                         // If this car wasn't blocked at all, when would it reach its goal?
                         let ideal_end_time = match car.crossing_state(our_dist, time, map) {
-                            CarState::Crossing(time_int, _) => time_int.end,
+                            CarState::Crossing(time_int, _, _) => time_int.end,
                             _ => unreachable!(),
                         };
                         if ideal_end_time == time {
@@ -454,12 +560,21 @@ impl DrivingSimState {
                     }
                 }
             }
-            CarState::Parking(_, spot, _) => {
+            CarState::Parking(front, spot, ref time_int) => {
+                trips.agent_travelled(AgentID::Car(car.vehicle.id), car.dist_travelled(front));
                 parking.add_parked_car(ParkedCar {
                     vehicle: car.vehicle.clone(),
                     spot,
                 });
-                trips.car_reached_parking_spot(time, car.vehicle.id, spot, map, parking, scheduler);
+                trips.car_reached_parking_spot(
+                    time,
+                    car.vehicle.id,
+                    spot,
+                    car.router.time_spent_cruising(time_int.start),
+                    map,
+                    parking,
+                    scheduler,
+                );
             }
         }
 
@@ -479,21 +594,22 @@ impl DrivingSimState {
         // Update the follower so that they don't suddenly jump forwards.
         if idx != dists.len() - 1 {
             let (follower_id, follower_dist) = dists[idx + 1];
-            let mut follower = self.cars.get_mut(&follower_id).unwrap();
+            let follower = &self.cars[&follower_id];
             // TODO If the leader vanished at a border node, this still jumps a bit -- the
             // lead car's back is still sticking out. Need to still be bound by them, even
             // though they don't exist! If the leader just parked, then we're fine.
             match follower.state {
-                CarState::Queued | CarState::Crossing(_, _) => {
+                CarState::Queued | CarState::Crossing(_, _, _) => {
                     // If the follower was still Crossing, they might not've been blocked
                     // by leader yet. In that case, recalculating their Crossing state is a
                     // no-op. But if they were blocked, then this will prevent them from
                     // jumping forwards.
-                    follower.state = follower.crossing_state(follower_dist, time, map);
-                    scheduler.update(
-                        Command::UpdateCar(follower_id),
-                        follower.state.get_end_time(),
-                    );
+                    let state =
+                        self.crossing_state(follower, follower_dist, time, map, intersections);
+                    scheduler.update(Command::UpdateCar(follower_id), state.get_end_time());
+                    self.cars.get_mut(&follower_id).unwrap().state = state;
+                    // Anybody Queued behind the follower was waiting on us too.
+                    self.wake_followers(follower_id, time, map, intersections, scheduler);
                 }
                 // They weren't blocked
                 CarState::Unparking(_, _) | CarState::Parking(_, _, _) | CarState::Idling(_, _) => {
@@ -505,6 +621,280 @@ impl DrivingSimState {
         false
     }
 
+    // A Queued car that isn't on its last step might be stuck behind something that won't clear
+    // soon -- a car parking or unparking, an idling bus, or somebody waiting to make a different
+    // turn. If an adjacent lane of the same road also leads to the next lane in our path, move
+    // over when there's a big enough gap.
+    pub fn maybe_change_lanes(
+        &mut self,
+        id: CarID,
+        time: Duration,
+        map: &Map,
+        intersections: &IntersectionSimState,
+        scheduler: &mut Scheduler,
+    ) {
+        let car = match self.cars.get(&id) {
+            Some(car) => car,
+            // The retry is stale.
+            None => {
+                return;
+            }
+        };
+        if car.state != CarState::Queued || car.router.last_step() || !car.last_steps.is_empty() {
+            return;
+        }
+        let from = match car.router.head() {
+            Traversable::Lane(l) => l,
+            Traversable::Turn(_) => {
+                return;
+            }
+        };
+        let next_turn = car.router.next().as_turn();
+        let vehicle_len = car.vehicle.length;
+
+        let dists =
+            self.queues[&Traversable::Lane(from)].get_car_positions(time, &self.cars, &self.queues);
+        let idx = dists.iter().position(|(c, _)| *c == id).unwrap();
+        let our_dist = dists[idx].1;
+        if our_dist < vehicle_len {
+            return;
+        }
+        let blocked = dists[0..idx]
+            .iter()
+            .any(|(leader, _)| self.blocks_lane(&self.cars[leader], next_turn));
+        if !blocked {
+            return;
+        }
+
+        let lane = map.get_l(from);
+        let road = map.get_r(lane.parent);
+        let (fwds, offset) = road.dir_and_offset(from);
+        let siblings = if fwds {
+            &road.children_forwards
+        } else {
+            &road.children_backwards
+        };
+        // Prefer moving left, then right. The order just has to be deterministic.
+        let mut candidates = Vec::new();
+        if offset > 0 {
+            candidates.push(siblings[offset - 1]);
+        }
+        if offset + 1 < siblings.len() {
+            candidates.push(siblings[offset + 1]);
+        }
+
+        for (to, lt) in candidates {
+            if lt != lane.lane_type {
+                continue;
+            }
+            let turn = match map
+                .get_turns_from_lane(to)
+                .into_iter()
+                .find(|t| t.id.dst == next_turn.dst && map.is_turn_allowed(t.id))
+            {
+                Some(t) => t.id,
+                None => continue,
+            };
+            // Parallel lanes aren't quite the same length.
+            let to_len = map.get_l(to).length();
+            let new_dist = our_dist.min(to_len);
+            if new_dist < vehicle_len {
+                continue;
+            }
+            if let Some(new_idx) = self.queues[&Traversable::Lane(to)].get_idx_to_merge_car(
+                new_dist,
+                vehicle_len,
+                time,
+                &self.cars,
+                &self.queues,
+            ) {
+                self.change_lanes(
+                    LaneChange {
+                        car: id,
+                        old_idx: idx,
+                        to,
+                        new_idx,
+                        new_dist,
+                        new_turn: turn,
+                    },
+                    &dists,
+                    time,
+                    map,
+                    intersections,
+                    scheduler,
+                );
+                return;
+            }
+        }
+
+        // No gap right now; check again soon. Use update so there's only ever one retry pending.
+        scheduler.update(Command::RetryLaneChange(id), time + RETRY_LANE_CHANGE);
+    }
+
+    fn blocks_lane(&self, leader: &Car, our_turn: TurnID) -> bool {
+        match leader.state {
+            CarState::Parking(_, _, _) | CarState::Unparking(_, _) | CarState::Idling(_, _) => true,
+            // Cars on their last step might be about to park or stop.
+            CarState::Queued | CarState::WaitingToAdvance => match leader.router.maybe_next() {
+                Some(Traversable::Turn(t)) => t != our_turn,
+                _ => true,
+            },
+            CarState::Crossing(_, _, _) => false,
+        }
+    }
+
+    // old_dists are the positions of everybody on the lane the car is leaving.
+    fn change_lanes(
+        &mut self,
+        change: LaneChange,
+        old_dists: &Vec<(CarID, Distance)>,
+        time: Duration,
+        map: &Map,
+        intersections: &IntersectionSimState,
+        scheduler: &mut Scheduler,
+    ) {
+        let LaneChange {
+            car: id,
+            old_idx,
+            to,
+            new_idx,
+            new_dist,
+            new_turn,
+        } = change;
+        let mut car = self.cars.remove(&id).unwrap();
+        let from = car.router.head();
+        assert_eq!(
+            self.queues
+                .get_mut(&from)
+                .unwrap()
+                .cars
+                .remove(old_idx)
+                .unwrap(),
+            id
+        );
+        self.queues
+            .get_mut(&Traversable::Lane(to))
+            .unwrap()
+            .cars
+            .insert(new_idx, id);
+
+        // TODO The car instantly jumps sideways.
+        car.router.change_lanes(to, new_turn);
+        car.state = self.crossing_state(&car, new_dist, time, map, intersections);
+        scheduler.update(Command::UpdateCar(id), car.state.get_end_time());
+        self.cars.insert(id, car);
+
+        // Update the old follower so that they don't suddenly jump forwards.
+        if old_idx != old_dists.len() - 1 {
+            let (follower_id, follower_dist) = old_dists[old_idx + 1];
+            let follower = &self.cars[&follower_id];
+            match follower.state {
+                CarState::Queued | CarState::Crossing(_, _, _) => {
+                    let state =
+                        self.crossing_state(follower, follower_dist, time, map, intersections);
+                    scheduler.update(Command::UpdateCar(follower_id), state.get_end_time());
+                    self.cars.get_mut(&follower_id).unwrap().state = state;
+                    // Anybody Queued behind the follower was waiting on us too.
+                    self.wake_followers(follower_id, time, map, intersections, scheduler);
+                }
+                CarState::Unparking(_, _) | CarState::Parking(_, _, _) | CarState::Idling(_, _) => {
+                }
+                CarState::WaitingToAdvance => unreachable!(),
+            }
+        }
+    }
+
+    // Plans how the car crosses the current head of its path, braking for anything it already
+    // knows it has to stop for.
+    fn crossing_state(
+        &self,
+        car: &Car,
+        start_dist: Distance,
+        time: Duration,
+        map: &Map,
+        intersections: &IntersectionSimState,
+    ) -> CarState {
+        let mut stop_at = self.stopped_leader_back(car);
+        if !car.router.last_step() {
+            if let (Traversable::Lane(_), Traversable::Turn(t)) =
+                (car.router.head(), car.router.next())
+            {
+                // If a signal changes before they get there, they still stop briefly.
+                if intersections.must_stop_before(t, time, map) {
+                    let stop_line = car.router.head().length(map);
+                    stop_at = Some(stop_at.map_or(stop_line, |d| d.min(stop_line)));
+                }
+            }
+        }
+        car.crossing_state(start_dist, time, stop_at, map)
+    }
+
+    // If the car's stuck behind somebody who isn't moving, where does it have to stop? Leaders
+    // that are moving don't count; Queue keeps followers behind them.
+    fn stopped_leader_back(&self, car: &Car) -> Option<Distance> {
+        let queue = &self.queues[&car.router.head()];
+        let idx = queue.cars.iter().position(|id| *id == car.vehicle.id)?;
+        // Queued leaders are packed behind whoever's in front of them, so keep looking.
+        let mut gap = Distance::ZERO;
+        for leader_id in queue.cars.iter().take(idx).rev() {
+            // The leader might be temporarily removed while they're updated, in which case
+            // they're starting to move.
+            let leader = self.cars.get(leader_id)?;
+            gap += leader.vehicle.length + FOLLOWING_DISTANCE;
+            match leader.state {
+                CarState::WaitingToAdvance => {
+                    return Some(queue.geom_len - gap);
+                }
+                CarState::Unparking(front, _)
+                | CarState::Parking(front, _, _)
+                | CarState::Idling(front, _) => {
+                    return Some(front - gap);
+                }
+                CarState::Queued if !leader.router.last_step() => {}
+                // Cars on their last step might be ending early.
+                CarState::Queued | CarState::Crossing(_, _, _) => {
+                    return None;
+                }
+            }
+        }
+        None
+    }
+
+    // Cars Queued behind a leader that wasn't moving don't have anything scheduled; they wait to
+    // be woken up. When the leader starts moving again or leaves the queue, start everybody stuck
+    // behind them, down the line until somebody's waiting for something else.
+    fn wake_followers(
+        &mut self,
+        leader: CarID,
+        time: Duration,
+        map: &Map,
+        intersections: &IntersectionSimState,
+        scheduler: &mut Scheduler,
+    ) {
+        let queue = &self.queues[&self.cars[&leader].router.head()];
+        let idx = queue.cars.iter().position(|c| *c == leader).unwrap();
+        let dists = queue.get_car_positions(time, &self.cars, &self.queues);
+        for (follower_id, follower_dist) in dists.into_iter().skip(idx + 1) {
+            let follower = &self.cars[&follower_id];
+            if follower.is_crossing() {
+                // Already moving, but maybe their followers aren't.
+                continue;
+            }
+            // Cars on their last step retry on their own.
+            if follower.state != CarState::Queued || follower.router.last_step() {
+                break;
+            }
+            let state = self.crossing_state(follower, follower_dist, time, map, intersections);
+            match state {
+                CarState::Crossing(_, ref dist_int, _) if dist_int.length() > EPSILON_DIST => {}
+                // Still stuck behind somebody else
+                _ => break,
+            }
+            scheduler.update(Command::UpdateCar(follower_id), state.get_end_time());
+            self.cars.get_mut(&follower_id).unwrap().state = state;
+        }
+    }
+
     pub fn update_laggy_head(
         &mut self,
         id: CarID,
@@ -598,7 +988,7 @@ impl DrivingSimState {
                         CarState::WaitingToAdvance => unreachable!(),
                         // They weren't blocked. Note that there's no way the Crossing state could jump
                         // forwards here; the leader vanished from the end of the traversable.
-                        CarState::Crossing(_, _)
+                        CarState::Crossing(_, _, _)
                         | CarState::Unparking(_, _)
                         | CarState::Parking(_, _, _)
                         | CarState::Idling(_, _) => {}
@@ -627,7 +1017,7 @@ impl DrivingSimState {
             if let Traversable::Turn(t) = queue.id {
                 let polygon = map.get_i(t.parent).polygon.clone();
                 match self.cars[&queue.cars[0]].state {
-                    CarState::Crossing(_, _)
+                    CarState::Crossing(_, _, _)
                     | CarState::Unparking(_, _)
                     | CarState::Parking(_, _, _)
                     | CarState::Idling(_, _) => {
@@ -652,7 +1042,7 @@ impl DrivingSimState {
                     moving_len += car.vehicle.length;
                 } else {
                     match car.state {
-                        CarState::Crossing(_, _)
+                        CarState::Crossing(_, _, _)
                         | CarState::Unparking(_, _)
                         | CarState::Parking(_, _, _)
                         | CarState::Idling(_, _) => {
@@ -761,6 +1151,14 @@ impl DrivingSimState {
         ])
     }
 
+    // Average time to cross each road
+    pub fn get_observed_travel_times(&self) -> BTreeMap<DirectedRoadID, Duration> {
+        self.travel_times
+            .iter()
+            .map(|(dr, (total, count))| (*dr, *total / (*count as f64)))
+            .collect()
+    }
+
     pub fn get_path(&self, id: CarID) -> Option<&Path> {
         let car = self.cars.get(&id)?;
         Some(car.router.get_path())
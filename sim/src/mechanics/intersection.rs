@@ -1,18 +1,36 @@
-use crate::{AgentID, Command, Scheduler};
+use crate::{AgentID, Command, Scheduler, TripManager};
 use abstutil::{deserialize_btreemap, serialize_btreemap};
 use geom::Duration;
 use map_model::{
-    ControlStopSign, ControlTrafficSignal, IntersectionID, IntersectionType, LaneID, Map, TurnID,
-    TurnPriority,
+    ControlStopSign, Cycle, IntersectionID, IntersectionType, LaneID, Map, TurnID, TurnPriority,
+    TurnType, LANE_THICKNESS,
 };
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, BTreeSet, HashSet};
 
 const WAIT_AT_STOP_SIGN: Duration = Duration::const_seconds(0.5);
+// Actuated signals extend the current cycle by this much at a time while there's demand.
+const ACTUATED_EXTENSION: Duration = Duration::const_seconds(2.0);
+// How many people fit on a crosswalk at once, per square meter. Past this, people queue up on the
+// sidewalk.
+const CROSSWALK_PEDS_PER_SQ_METER: f64 = 1.0;
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct IntersectionSimState {
     state: BTreeMap<IntersectionID, State>,
+    // Only for actuated traffic signals. Fixed-time signals are a pure function of time.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    actuated: BTreeMap<IntersectionID, ActuatedState>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+struct ActuatedState {
+    current_cycle: usize,
+    cycle_start: Duration,
+    cycle_end: Duration,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -31,6 +49,7 @@ impl IntersectionSimState {
     pub fn new(map: &Map, scheduler: &mut Scheduler) -> IntersectionSimState {
         let mut sim = IntersectionSimState {
             state: BTreeMap::new(),
+            actuated: BTreeMap::new(),
         };
         for i in map.all_intersections() {
             sim.state.insert(
@@ -42,7 +61,23 @@ impl IntersectionSimState {
                 },
             );
             if i.intersection_type == IntersectionType::TrafficSignal {
-                sim.update_intersection(Duration::ZERO, i.id, map, scheduler);
+                let signal = map.get_traffic_signal(i.id);
+                if signal.actuated {
+                    // Start off following the normal schedule.
+                    let (idx, remaining) =
+                        signal.current_cycle_idx_and_remaining_time(Duration::ZERO);
+                    sim.actuated.insert(
+                        i.id,
+                        ActuatedState {
+                            current_cycle: idx,
+                            cycle_start: remaining - signal.cycles[idx].duration,
+                            cycle_end: remaining,
+                        },
+                    );
+                    scheduler.push(remaining, Command::UpdateIntersection(i.id));
+                } else {
+                    sim.update_intersection(Duration::ZERO, i.id, map, scheduler);
+                }
             }
         }
         sim
@@ -79,16 +114,39 @@ impl IntersectionSimState {
 
     // This is only triggered for traffic signals.
     pub fn update_intersection(
-        &self,
+        &mut self,
         now: Duration,
         id: IntersectionID,
         map: &Map,
         scheduler: &mut Scheduler,
     ) {
+        let signal = map.get_traffic_signal(id);
         let state = &self.state[&id];
-        let (_, remaining) = map
-            .get_traffic_signal(id)
-            .current_cycle_and_remaining_time(now);
+        let remaining = if let Some(actuated) = self.actuated.get_mut(&id) {
+            // Edits might've removed cycles.
+            let current = actuated.current_cycle % signal.cycles.len();
+            let cycle = &signal.cycles[current];
+            if state.has_demand(cycle, map)
+                && (now - actuated.cycle_start) + ACTUATED_EXTENSION
+                    <= cycle.duration + cycle.max_extension
+            {
+                actuated.cycle_end = now + ACTUATED_EXTENSION;
+            } else {
+                // Skip over cycles that nobody's waiting for. If nobody's waiting at all, just go
+                // in order.
+                let n = signal.cycles.len();
+                let next = (1..=n)
+                    .map(|offset| (current + offset) % n)
+                    .find(|idx| state.has_demand(&signal.cycles[*idx], map))
+                    .unwrap_or((current + 1) % n);
+                actuated.current_cycle = next;
+                actuated.cycle_start = now;
+                actuated.cycle_end = now + signal.cycles[next].duration;
+            }
+            actuated.cycle_end - now
+        } else {
+            signal.current_cycle_idx_and_remaining_time(now).1
+        };
 
         // TODO Wake up everyone, for now.
         // TODO Use update in case turn_finished scheduled an event for them already.
@@ -99,6 +157,37 @@ impl IntersectionSimState {
         scheduler.push(now + remaining, Command::UpdateIntersection(id));
     }
 
+    // Returns the index of the current cycle and the time left in it.
+    pub fn current_signal_cycle(
+        &self,
+        now: Duration,
+        id: IntersectionID,
+        map: &Map,
+    ) -> (usize, Duration) {
+        let signal = map.get_traffic_signal(id);
+        if let Some(actuated) = self.actuated.get(&id) {
+            (
+                actuated.current_cycle % signal.cycles.len(),
+                actuated.cycle_end - now,
+            )
+        } else {
+            signal.current_cycle_idx_and_remaining_time(now)
+        }
+    }
+
+    // Can a car approaching this turn already tell that it'll have to stop first? At a stop sign
+    // it always does; at a signal, only if the turn isn't allowed right now.
+    pub fn must_stop_before(&self, turn: TurnID, now: Duration, map: &Map) -> bool {
+        if let Some(signal) = map.maybe_get_traffic_signal(turn.parent) {
+            let (idx, _) = self.current_signal_cycle(now, turn.parent, map);
+            signal.cycles[idx].get_priority(turn) == TurnPriority::Banned
+        } else if let Some(sign) = map.maybe_get_stop_sign(turn.parent) {
+            sign.get_priority(turn) == TurnPriority::Stop
+        } else {
+            false
+        }
+    }
+
     // For cars: The head car calls this when they're at the end of the lane WaitingToAdvance. If
     // this returns true, then the head car MUST actually start this turn.
     // For peds: Likewise -- only called when the ped is at the start of the turn. They must
@@ -113,13 +202,22 @@ impl IntersectionSimState {
         now: Duration,
         map: &Map,
         scheduler: &mut Scheduler,
+        trips: &mut TripManager,
     ) -> bool {
         let req = Request { agent, turn };
         let state = self.state.get_mut(&turn.parent).unwrap();
         state.waiting.entry(req.clone()).or_insert(now);
 
-        let allowed = if let Some(ref signal) = map.maybe_get_traffic_signal(state.id) {
-            state.traffic_signal_policy(signal, &req, now, map)
+        let allowed = if state.crosswalk_full(turn, map) {
+            // turn_finished will wake us up when somebody gets across.
+            false
+        } else if let Some(ref signal) = map.maybe_get_traffic_signal(state.id) {
+            let idx = match self.actuated.get(&state.id) {
+                Some(actuated) => actuated.current_cycle % signal.cycles.len(),
+                None => signal.current_cycle_idx_and_remaining_time(now).0,
+            };
+            let cycle = &signal.cycles[idx];
+            state.traffic_signal_policy(cycle, &req, map)
         } else if let Some(ref sign) = map.maybe_get_stop_sign(state.id) {
             state.stop_sign_policy(sign, &req, now, map, scheduler)
         } else {
@@ -129,7 +227,10 @@ impl IntersectionSimState {
 
         if allowed {
             assert!(!state.any_accepted_conflict_with(turn, map));
-            state.waiting.remove(&req).unwrap();
+            let waiting_since = state.waiting.remove(&req).unwrap();
+            if now > waiting_since {
+                trips.agent_waited_at_intersection(agent, now - waiting_since);
+            }
             state.accepted.insert(req);
             true
         } else {
@@ -158,7 +259,8 @@ impl IntersectionSimState {
 
     pub fn is_in_overtime(&self, time: Duration, id: IntersectionID, map: &Map) -> bool {
         if let Some(ref signal) = map.maybe_get_traffic_signal(id) {
-            let (cycle, _) = signal.current_cycle_and_remaining_time(time);
+            let (idx, _) = self.current_signal_cycle(time, id, map);
+            let cycle = &signal.cycles[idx];
             self.state[&id]
                 .accepted
                 .iter()
@@ -177,6 +279,22 @@ impl State {
             .any(|req| map.get_t(req.turn).conflicts_with(turn))
     }
 
+    // Counts people going both ways.
+    fn crosswalk_full(&self, t: TurnID, map: &Map) -> bool {
+        let turn = map.get_t(t);
+        if turn.turn_type != TurnType::Crosswalk {
+            return false;
+        }
+        let area = turn.geom.length().inner_meters() * LANE_THICKNESS.inner_meters();
+        let capacity = ((area * CROSSWALK_PEDS_PER_SQ_METER) as usize).max(1);
+        let crossing = self
+            .accepted
+            .iter()
+            .filter(|req| req.turn == t || (req.turn.src == t.dst && req.turn.dst == t.src))
+            .count();
+        crossing >= capacity
+    }
+
     fn freeform_policy(&self, req: &Request, map: &Map) -> bool {
         // Allow concurrent turns that don't conflict, don't prevent target lane from spilling
         // over.
@@ -228,15 +346,16 @@ impl State {
         true
     }
 
-    fn traffic_signal_policy(
-        &self,
-        signal: &ControlTrafficSignal,
-        new_req: &Request,
-        time: Duration,
-        map: &Map,
-    ) -> bool {
-        let (cycle, _remaining_cycle_time) = signal.current_cycle_and_remaining_time(time);
+    // Is anybody waiting for a turn that this cycle allows? Turns allowed in every cycle don't
+    // count.
+    fn has_demand(&self, cycle: &Cycle, map: &Map) -> bool {
+        self.waiting.keys().any(|req| {
+            cycle.get_priority(req.turn) != TurnPriority::Banned
+                && map.get_t(req.turn).turn_type != TurnType::SharedSidewalkCorner
+        })
+    }
 
+    fn traffic_signal_policy(&self, cycle: &Cycle, new_req: &Request, map: &Map) -> bool {
         // For now, just maintain safety when agents over-run.
         for req in &self.accepted {
             if cycle.get_priority(req.turn) == TurnPriority::Banned {
@@ -5,7 +5,9 @@ mod parking;
 mod queue;
 mod walking;
 
+pub use self::car::SpeedProfile;
 pub use self::driving::DrivingSimState;
+pub(crate) use self::driving::{TIME_TO_PARK, TIME_TO_UNPARK};
 pub use self::intersection::IntersectionSimState;
 pub use self::parking::ParkingSimState;
 pub use self::walking::WalkingSimState;
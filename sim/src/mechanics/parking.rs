@@ -4,11 +4,18 @@ use abstutil::{
 };
 use geom::Distance;
 use map_model;
-use map_model::{BuildingID, Lane, LaneID, LaneType, Map, Position, Traversable};
+use map_model::{
+    BuildingID, Lane, LaneID, LaneType, Map, OffstreetParking, ParkingLotID, PathStep, Position,
+    Traversable, TurnID,
+};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{BTreeMap, BTreeSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap};
 use std::iter;
 
+// Nobody drives around looking for parking farther than this.
+const MAX_PARKING_SEARCH_DIST: Distance = Distance::const_meters(3000.0);
+
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct ParkingSimState {
     #[serde(
@@ -25,6 +32,19 @@ pub struct ParkingSimState {
         deserialize_with = "deserialize_multimap"
     )]
     cars_per_building: MultiMap<BuildingID, CarID>,
+
+    garages: BTreeMap<BuildingID, ParkingGarage>,
+    #[serde(
+        serialize_with = "serialize_multimap",
+        deserialize_with = "deserialize_multimap"
+    )]
+    driving_to_garages: MultiMap<LaneID, BuildingID>,
+    lots: BTreeMap<ParkingLotID, ParkingGarage>,
+    #[serde(
+        serialize_with = "serialize_multimap",
+        deserialize_with = "deserialize_multimap"
+    )]
+    driving_to_lots: MultiMap<LaneID, ParkingLotID>,
 }
 
 impl ParkingSimState {
@@ -35,6 +55,10 @@ impl ParkingSimState {
             reserved_spots: BTreeSet::new(),
             driving_to_parking_lane: BTreeMap::new(),
             cars_per_building: MultiMap::new(),
+            garages: BTreeMap::new(),
+            driving_to_garages: MultiMap::new(),
+            lots: BTreeMap::new(),
+            driving_to_lots: MultiMap::new(),
         };
         for l in map.all_lanes() {
             if let Some(lane) = ParkingLane::new(l, map) {
@@ -43,6 +67,18 @@ impl ParkingSimState {
                 sim.lanes.insert(lane.id, lane);
             }
         }
+        for b in map.all_buildings() {
+            if let Some(ref p) = b.parking {
+                sim.driving_to_garages.insert(p.driving_pos.lane(), b.id);
+                sim.garages.insert(b.id, ParkingGarage::new(p));
+            }
+        }
+        for lot in map.all_parking_lots() {
+            if let Some(ref p) = lot.parking {
+                sim.driving_to_lots.insert(p.driving_pos.lane(), lot.id);
+                sim.lots.insert(lot.id, ParkingGarage::new(p));
+            }
+        }
         sim
     }
 
@@ -57,19 +93,39 @@ impl ParkingSimState {
         spots
     }
 
+    // Empty if the building doesn't have a garage
+    pub fn get_free_offstreet_spots(
+        &self,
+        b: BuildingID,
+    ) -> impl Iterator<Item = ParkingSpot> + '_ {
+        self.garages.get(&b).into_iter().flat_map(move |garage| {
+            garage
+                .free_stalls()
+                .map(move |idx| ParkingSpot::Offstreet(b, idx))
+        })
+    }
+
+    // Empty if the lot isn't connected to a driving lane
+    pub fn get_free_lot_spots(&self, lot: ParkingLotID) -> impl Iterator<Item = ParkingSpot> + '_ {
+        self.lots.get(&lot).into_iter().flat_map(move |garage| {
+            garage
+                .free_stalls()
+                .map(move |idx| ParkingSpot::Lot(lot, idx))
+        })
+    }
+
     pub fn remove_parked_car(&mut self, p: ParkedCar) {
         self.cars.remove(&p.vehicle.id);
-        self.lanes
-            .get_mut(&p.spot.lane)
-            .unwrap()
-            .remove_parked_car(p.vehicle.id);
+        let occupant = self.occupant_mut(p.spot);
+        assert_eq!(*occupant, Some(p.vehicle.id));
+        *occupant = None;
     }
 
     pub fn add_parked_car(&mut self, p: ParkedCar) {
-        let spot = p.spot;
         assert!(self.reserved_spots.remove(&p.spot));
-        assert_eq!(self.lanes[&spot.lane].occupants[spot.idx], None);
-        self.lanes.get_mut(&spot.lane).unwrap().occupants[spot.idx] = Some(p.vehicle.id);
+        let occupant = self.occupant_mut(p.spot);
+        assert_eq!(*occupant, None);
+        *occupant = Some(p.vehicle.id);
         if let Some(b) = p.vehicle.owner {
             self.cars_per_building.insert(b, p.vehicle.id);
         }
@@ -97,11 +153,17 @@ impl ParkingSimState {
         }
     }
 
+    // Cars in garages and lots aren't drawn.
     pub fn get_draw_car(&self, id: CarID, map: &Map) -> Option<DrawCarInput> {
         let p = self.cars.get(&id)?;
-        let lane = p.spot.lane;
+        let (lane, idx) = match p.spot {
+            ParkingSpot::Onstreet(l, idx) => (l, idx),
+            ParkingSpot::Offstreet(_, _) | ParkingSpot::Lot(_, _) => {
+                return None;
+            }
+        };
 
-        let front_dist = self.lanes[&lane].dist_along_for_car(p.spot.idx, &p.vehicle);
+        let front_dist = self.lanes[&lane].dist_along_for_car(idx, &p.vehicle);
         Some(DrawCarInput {
             id: p.vehicle.id,
             waiting_for_turn: None,
@@ -119,54 +181,195 @@ impl ParkingSimState {
     pub fn get_all_draw_cars(&self, map: &Map) -> Vec<DrawCarInput> {
         self.cars
             .keys()
-            .map(|id| self.get_draw_car(*id, map).unwrap())
+            .filter_map(|id| self.get_draw_car(*id, map))
             .collect()
     }
 
     pub fn is_free(&self, spot: ParkingSpot) -> bool {
-        self.lanes[&spot.lane].occupants[spot.idx].is_none() && !self.reserved_spots.contains(&spot)
+        self.occupant(spot).is_none() && !self.reserved_spots.contains(&spot)
     }
 
     pub fn get_car_at_spot(&self, spot: ParkingSpot) -> Option<ParkedCar> {
-        let car = self.lanes[&spot.lane].occupants[spot.idx]?;
+        let car = self.occupant(spot)?;
         Some(self.cars[&car].clone())
     }
 
-    // And the driving position
+    fn occupant(&self, spot: ParkingSpot) -> Option<CarID> {
+        match spot {
+            ParkingSpot::Onstreet(l, idx) => self.lanes[&l].occupants[idx],
+            ParkingSpot::Offstreet(b, idx) => self.garages[&b].occupants[idx],
+            ParkingSpot::Lot(lot, idx) => self.lots[&lot].occupants[idx],
+        }
+    }
+
+    fn occupant_mut(&mut self, spot: ParkingSpot) -> &mut Option<CarID> {
+        match spot {
+            ParkingSpot::Onstreet(l, idx) => &mut self.lanes.get_mut(&l).unwrap().occupants[idx],
+            ParkingSpot::Offstreet(b, idx) => &mut self.garages.get_mut(&b).unwrap().occupants[idx],
+            ParkingSpot::Lot(lot, idx) => &mut self.lots.get_mut(&lot).unwrap().occupants[idx],
+        }
+    }
+
+    // The closest free spot ahead of the driving position, either along the parking lane or in a
+    // garage or lot entered from this lane. And the driving position
     pub fn get_first_free_spot(
         &self,
         driving_pos: Position,
         vehicle: &Vehicle,
         map: &Map,
     ) -> Option<(ParkingSpot, Position)> {
-        let l = *self.driving_to_parking_lane.get(&driving_pos.lane())?;
-        let parking_dist = driving_pos.equiv_pos(l, map).dist_along();
-        let lane = &self.lanes[&l];
-        let idx = lane.occupants.iter().enumerate().position(|(idx, x)| {
-            x.is_none()
-                && !self.reserved_spots.contains(&ParkingSpot::new(l, idx))
-                && parking_dist <= lane.dist_along_for_car(idx, vehicle)
-        })?;
-        let spot = ParkingSpot::new(l, idx);
-        Some((spot, self.spot_to_driving_pos(spot, vehicle, map)))
+        let mut candidates: Vec<(ParkingSpot, Position)> = Vec::new();
+
+        if let Some(l) = self.driving_to_parking_lane.get(&driving_pos.lane()) {
+            let parking_dist = driving_pos.equiv_pos(*l, map).dist_along();
+            let lane = &self.lanes[l];
+            if let Some(idx) = lane.occupants.iter().enumerate().position(|(idx, x)| {
+                x.is_none()
+                    && !self.reserved_spots.contains(&ParkingSpot::new(*l, idx))
+                    && parking_dist <= lane.dist_along_for_car(idx, vehicle)
+            }) {
+                let spot = ParkingSpot::new(*l, idx);
+                candidates.push((spot, self.spot_to_driving_pos(spot, vehicle, map)));
+            }
+        }
+
+        let offstreet = self
+            .driving_to_garages
+            .get(driving_pos.lane())
+            .iter()
+            .filter_map(|b| self.get_free_offstreet_spots(*b).find(|s| self.is_free(*s)))
+            .chain(
+                self.driving_to_lots
+                    .get(driving_pos.lane())
+                    .iter()
+                    .filter_map(|lot| self.get_free_lot_spots(*lot).find(|s| self.is_free(*s))),
+            );
+        for spot in offstreet {
+            let pos = self.spot_to_driving_pos(spot, vehicle, map);
+            if driving_pos.dist_along() <= pos.dist_along() {
+                candidates.push((spot, pos));
+            }
+        }
+
+        candidates
+            .into_iter()
+            .min_by_key(|(_, pos)| pos.dist_along())
+    }
+
+    // Search outwards from the end of a driving lane for the closest lane (by driving distance)
+    // with a free spot. Might circle back to the start lane. Returns the turns and lanes to
+    // follow after the start lane.
+    pub fn path_to_free_parking_spot(
+        &self,
+        start: LaneID,
+        vehicle: &Vehicle,
+        map: &Map,
+    ) -> Option<Vec<PathStep>> {
+        // Set when a lane is popped, so it's always the shortest way there.
+        let mut backrefs: HashMap<LaneID, TurnID> = HashMap::new();
+        // This should be deterministic, since cost ties would be broken by LaneID.
+        let mut queue: BinaryHeap<(Reverse<Distance>, LaneID, Option<TurnID>)> = BinaryHeap::new();
+        queue.push((Reverse(Distance::ZERO), start, None));
+
+        while let Some((Reverse(dist_so_far), current, via)) = queue.pop() {
+            if backrefs.contains_key(&current) {
+                continue;
+            }
+            // The start lane doesn't count, unless we've circled back to it.
+            if let Some(turn) = via {
+                backrefs.insert(current, turn);
+                if self
+                    .get_first_free_spot(Position::new(current, Distance::ZERO), vehicle, map)
+                    .is_some()
+                {
+                    let mut steps = vec![PathStep::Lane(current)];
+                    let mut lookup = current;
+                    loop {
+                        let turn = backrefs[&lookup];
+                        steps.push(PathStep::Turn(turn));
+                        if turn.src == start {
+                            steps.reverse();
+                            return Some(steps);
+                        }
+                        steps.push(PathStep::Lane(turn.src));
+                        lookup = turn.src;
+                    }
+                }
+            }
+
+            for (turn, next) in map
+                .get_next_turns_and_lanes(current, map.get_l(current).dst_i)
+                .into_iter()
+            {
+                if next.lane_type != LaneType::Driving
+                    || !map.is_turn_allowed(turn.id)
+                    || backrefs.contains_key(&next.id)
+                {
+                    continue;
+                }
+                let dist = dist_so_far + next.length();
+                if dist > MAX_PARKING_SEARCH_DIST {
+                    continue;
+                }
+                queue.push((Reverse(dist), next.id, Some(turn.id)));
+            }
+        }
+        None
+    }
+
+    // Where somebody driving to this building would wind up parking right now: the building's own
+    // garage, the closest free spot along the lane they'd aim for, or wherever cruising from
+    // there would lead.
+    pub fn find_spot_near_building(
+        &self,
+        b: BuildingID,
+        vehicle: &Vehicle,
+        map: &Map,
+    ) -> Option<ParkingSpot> {
+        if let Some(spot) = self.get_free_offstreet_spots(b).find(|s| self.is_free(*s)) {
+            return Some(spot);
+        }
+        let lane = map.find_driving_lane_near_building(b);
+        if let Some((spot, _)) =
+            self.get_first_free_spot(Position::new(lane, Distance::ZERO), vehicle, map)
+        {
+            return Some(spot);
+        }
+        let last_lane = self
+            .path_to_free_parking_spot(lane, vehicle, map)?
+            .pop()?
+            .as_lane();
+        self.get_first_free_spot(Position::new(last_lane, Distance::ZERO), vehicle, map)
+            .map(|(spot, _)| spot)
     }
 
     pub fn spot_to_driving_pos(&self, spot: ParkingSpot, vehicle: &Vehicle, map: &Map) -> Position {
-        Position::new(
-            spot.lane,
-            self.lanes[&spot.lane].dist_along_for_car(spot.idx, vehicle),
-        )
-        .equiv_pos(self.lanes[&spot.lane].driving_lane, map)
+        match spot {
+            ParkingSpot::Onstreet(l, idx) => {
+                Position::new(l, self.lanes[&l].dist_along_for_car(idx, vehicle))
+                    .equiv_pos(self.lanes[&l].driving_lane, map)
+            }
+            ParkingSpot::Offstreet(b, _) => self.garages[&b].driving_pos(vehicle, map),
+            ParkingSpot::Lot(lot, _) => self.lots[&lot].driving_pos(vehicle, map),
+        }
     }
 
-    pub fn spot_to_sidewalk_pos(&self, spot: ParkingSpot, sidewalk: LaneID, map: &Map) -> Position {
-        // Always centered in the entire parking spot
-        Position::new(
-            spot.lane,
-            self.lanes[&spot.lane].spot_dist_along[spot.idx]
-                - (map_model::PARKING_SPOT_LENGTH / 2.0),
-        )
-        .equiv_pos(sidewalk, map)
+    pub fn spot_to_sidewalk_pos(&self, spot: ParkingSpot, map: &Map) -> Position {
+        match spot {
+            ParkingSpot::Onstreet(l, idx) => {
+                // TODO Consider precomputing this.
+                let sidewalk = map.find_closest_lane(l, vec![LaneType::Sidewalk]).unwrap();
+                // Always centered in the entire parking spot
+                Position::new(
+                    l,
+                    self.lanes[&l].spot_dist_along[idx] - (map_model::PARKING_SPOT_LENGTH / 2.0),
+                )
+                .equiv_pos(sidewalk, map)
+            }
+            // Walk through the building to get to the car.
+            ParkingSpot::Offstreet(b, _) => map.get_b(b).front_path.sidewalk,
+            ParkingSpot::Lot(lot, _) => map.get_pl(lot).sidewalk_pos,
+        }
     }
 
     pub fn tooltip_lines(&self, id: CarID) -> Option<Vec<String>> {
@@ -190,6 +393,40 @@ impl ParkingSimState {
     }
 }
 
+// A garage or a surface lot
+#[derive(Serialize, Deserialize, PartialEq)]
+struct ParkingGarage {
+    // Where cars enter and exit
+    driving_pos: Position,
+    occupants: Vec<Option<CarID>>,
+}
+
+impl ParkingGarage {
+    fn new(p: &OffstreetParking) -> ParkingGarage {
+        ParkingGarage {
+            driving_pos: p.driving_pos,
+            occupants: iter::repeat(None).take(p.num_stalls).collect(),
+        }
+    }
+
+    fn free_stalls(&self) -> impl Iterator<Item = usize> + '_ {
+        self.occupants
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.is_none())
+            .map(|(idx, _)| idx)
+    }
+
+    fn driving_pos(&self, vehicle: &Vehicle, map: &Map) -> Position {
+        // The whole car has to fit on the lane while it pulls in or out.
+        let len = map.get_l(self.driving_pos.lane()).length();
+        Position::new(
+            self.driving_pos.lane(),
+            self.driving_pos.dist_along().max(vehicle.length).min(len),
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq)]
 struct ParkingLane {
     id: LaneID,
@@ -223,11 +460,6 @@ impl ParkingLane {
         })
     }
 
-    fn remove_parked_car(&mut self, car: CarID) {
-        let idx = self.occupants.iter().position(|x| *x == Some(car)).unwrap();
-        self.occupants[idx] = None;
-    }
-
     fn dist_along_for_car(&self, spot_idx: usize, vehicle: &Vehicle) -> Distance {
         // Find the offset to center this particular car in the parking spot
         self.spot_dist_along[spot_idx] - (map_model::PARKING_SPOT_LENGTH - vehicle.length) / 2.0
@@ -1,5 +1,5 @@
 use crate::mechanics::car::{Car, CarState};
-use crate::{CarID, FOLLOWING_DISTANCE};
+use crate::{CarID, FOLLOWING_DISTANCE, MIN_LANE_CHANGE_GAP};
 use geom::{Distance, Duration};
 use map_model::{Map, Traversable};
 use serde_derive::{Deserialize, Serialize};
@@ -101,10 +101,12 @@ impl Queue {
                     assert_eq!(bound, self.geom_len);
                     self.geom_len
                 }
-                CarState::Crossing(ref time_int, ref dist_int) => {
-                    // TODO Why percent_clamp_end? We process car updates in any order, so we might
-                    // calculate this before moving this car from Crossing to another state.
-                    dist_int.lerp(time_int.percent_clamp_end(time)).min(bound)
+                CarState::Crossing(ref time_int, ref dist_int, ref profile) => {
+                    // The profile clamps at the end. We process car updates in any order, so we
+                    // might calculate this before moving this car from Crossing to another state.
+                    (dist_int.start + profile.dist_after(time - time_int.start))
+                        .min(dist_int.end)
+                        .min(bound)
                 }
                 CarState::Unparking(front, _) => front,
                 CarState::Parking(front, _, _) => front,
@@ -129,6 +131,42 @@ impl Queue {
         }
 
         let dists = self.get_car_positions(time, cars, queues);
+        self.find_gap(&dists, start_dist, vehicle_len, cars)
+    }
+
+    // Like get_idx_to_insert_car, but for a car moving over from an adjacent lane mid-block. A
+    // moving follower needs more room than FOLLOWING_DISTANCE to react.
+    pub fn get_idx_to_merge_car(
+        &self,
+        start_dist: Distance,
+        vehicle_len: Distance,
+        time: Duration,
+        cars: &BTreeMap<CarID, Car>,
+        queues: &BTreeMap<Traversable, Queue>,
+    ) -> Option<usize> {
+        if self.laggy_head.is_none() && self.cars.is_empty() {
+            return Some(0);
+        }
+
+        let dists = self.get_car_positions(time, cars, queues);
+        let idx = self.find_gap(&dists, start_dist, vehicle_len, cars)?;
+        if idx != dists.len() {
+            if let CarState::Crossing(_, _, _) = cars[&dists[idx].0].state {
+                if start_dist - vehicle_len - MIN_LANE_CHANGE_GAP < dists[idx].1 {
+                    return None;
+                }
+            }
+        }
+        Some(idx)
+    }
+
+    fn find_gap(
+        &self,
+        dists: &Vec<(CarID, Distance)>,
+        start_dist: Distance,
+        vehicle_len: Distance,
+        cars: &BTreeMap<CarID, Car>,
+    ) -> Option<usize> {
         // TODO Binary search
         let idx = match dists.iter().position(|(_, dist)| start_dist >= *dist) {
             Some(i) => i,
@@ -185,7 +223,7 @@ fn dump_cars(
         let car = &cars[id];
         println!("- {} @ {} (length {})", id, dist, car.vehicle.length);
         match car.state {
-            CarState::Crossing(ref time_int, ref dist_int) => {
+            CarState::Crossing(ref time_int, ref dist_int, _) => {
                 println!(
                     "  Going {} .. {} during {} .. {}",
                     dist_int.start, dist_int.end, time_int.start, time_int.end
@@ -1,16 +1,26 @@
 use crate::{
-    AgentID, Command, CreatePedestrian, DistanceInterval, DrawPedestrianInput,
-    IntersectionSimState, ParkingSimState, PedestrianID, Scheduler, SidewalkPOI, SidewalkSpot,
-    TimeInterval, TransitSimState, TripManager,
+    walking_speed_on_grade, AgentID, Command, CreatePedestrian, DistanceInterval,
+    DrawPedestrianInput, IntersectionSimState, ParkingSimState, PedestrianID, Scheduler,
+    SidewalkPOI, SidewalkSpot, TimeInterval, TransitSimState, TripManager,
 };
 use abstutil::{deserialize_multimap, serialize_multimap, MultiMap};
 use geom::{Distance, Duration, Line, PolyLine, Speed};
-use map_model::{BuildingID, Map, Path, PathStep, Traversable, LANE_THICKNESS};
+use map_model::{
+    BuildingID, IntersectionID, LaneID, Map, Path, PathStep, Traversable, LANE_THICKNESS,
+};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 const TIME_TO_START_BIKING: Duration = Duration::const_seconds(30.0);
 const TIME_TO_FINISH_BIKING: Duration = Duration::const_seconds(45.0);
+// People per square meter of sidewalk at which nobody can move anymore (Weidmann). Speed drops
+// linearly as a sidewalk fills up, but never below the minimum factor.
+const JAM_DENSITY: f64 = 5.4;
+const MIN_CROWDED_SPEED_FACTOR: f64 = 0.2;
+// Only people this close ahead or behind count towards the crowd.
+const CROWD_RADIUS: Distance = Distance::const_meters(5.0);
+// After walking this far along a sidewalk, check how crowded it is again.
+const CROWD_UPDATE_DIST: Distance = Distance::const_meters(10.0);
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub struct WalkingSimState {
@@ -66,7 +76,13 @@ impl WalkingSimState {
                 Line::new(driving_pos.pt(map), params.start.sidewalk_pos.pt(map)),
                 TimeInterval::new(now, now + TIME_TO_FINISH_BIKING),
             ),
-            _ => ped.crossing_state(params.start.sidewalk_pos.dist_along(), now, map),
+            _ => ped.crossing_state(
+                params.start.sidewalk_pos.dist_along(),
+                now,
+                map,
+                &self.peds,
+                &self.peds_per_traversable,
+            ),
         };
 
         scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
@@ -108,10 +124,22 @@ impl WalkingSimState {
         trips: &mut TripManager,
         transit: &mut TransitSimState,
     ) {
-        let mut ped = self.peds.get_mut(&id).unwrap();
+        // Take the pedestrian out while updating, so everybody else's position can be used to
+        // figure out the crowding.
+        let mut ped = self.peds.remove(&id).unwrap();
         match ped.state {
             PedState::Crossing(ref dist_int, _) => {
-                if ped.path.is_last_step() {
+                if dist_int.end != ped.crossing_end_dist(map) {
+                    // Partway along the sidewalk
+                    ped.state = ped.crossing_state(
+                        dist_int.end,
+                        now,
+                        map,
+                        &self.peds,
+                        &self.peds_per_traversable,
+                    );
+                    scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
+                } else if ped.path.is_last_step() {
                     match ped.goal.connection {
                         SidewalkPOI::ParkingSpot(spot) => {
                             self.peds_per_traversable
@@ -119,7 +147,7 @@ impl WalkingSimState {
                             trips.ped_reached_parking_spot(
                                 now, ped.id, spot, map, parking, scheduler,
                             );
-                            self.peds.remove(&id);
+                            return;
                         }
                         SidewalkPOI::Building(b) => {
                             ped.state = PedState::EnteringBuilding(
@@ -132,10 +160,10 @@ impl WalkingSimState {
                             scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
                         }
                         SidewalkPOI::BusStop(stop) => {
-                            if trips.ped_reached_bus_stop(ped.id, stop, map, transit) {
+                            if trips.ped_reached_bus_stop(now, ped.id, stop, map, transit) {
                                 self.peds_per_traversable
                                     .remove(ped.path.current_step().as_traversable(), ped.id);
-                                self.peds.remove(&id);
+                                return;
                             } else {
                                 ped.state = PedState::WaitingForBus;
                             }
@@ -144,7 +172,7 @@ impl WalkingSimState {
                             self.peds_per_traversable
                                 .remove(ped.path.current_step().as_traversable(), ped.id);
                             trips.ped_reached_border(now, ped.id, i, map);
-                            self.peds.remove(&id);
+                            return;
                         }
                         SidewalkPOI::BikeRack(driving_pos) => {
                             let pt1 = ped.goal.sidewalk_pos.pt(map);
@@ -168,8 +196,10 @@ impl WalkingSimState {
                         now,
                         map,
                         intersections,
+                        &self.peds,
                         &mut self.peds_per_traversable,
                         scheduler,
+                        trips,
                     ) {
                         scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
                     } else {
@@ -183,35 +213,49 @@ impl WalkingSimState {
                     now,
                     map,
                     intersections,
+                    &self.peds,
                     &mut self.peds_per_traversable,
                     scheduler,
+                    trips,
                 ) {
                     scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
                 }
             }
             PedState::LeavingBuilding(b, _) => {
-                ped.state =
-                    ped.crossing_state(map.get_b(b).front_path.sidewalk.dist_along(), now, map);
+                ped.state = ped.crossing_state(
+                    map.get_b(b).front_path.sidewalk.dist_along(),
+                    now,
+                    map,
+                    &self.peds,
+                    &self.peds_per_traversable,
+                );
                 scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
             }
             PedState::EnteringBuilding(bldg, _) => {
                 self.peds_per_traversable
                     .remove(ped.path.current_step().as_traversable(), ped.id);
                 trips.ped_reached_building(now, ped.id, bldg, map);
-                self.peds.remove(&id);
+                return;
             }
             PedState::StartingToBike(ref spot, _, _) => {
                 self.peds_per_traversable
                     .remove(ped.path.current_step().as_traversable(), ped.id);
                 trips.ped_ready_to_bike(now, ped.id, spot.clone(), map, scheduler);
-                self.peds.remove(&id);
+                return;
             }
             PedState::FinishingBiking(ref spot, _, _) => {
-                ped.state = ped.crossing_state(spot.sidewalk_pos.dist_along(), now, map);
+                ped.state = ped.crossing_state(
+                    spot.sidewalk_pos.dist_along(),
+                    now,
+                    map,
+                    &self.peds,
+                    &self.peds_per_traversable,
+                );
                 scheduler.push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
             }
             PedState::WaitingForBus => unreachable!(),
         }
+        self.peds.insert(id, ped);
     }
 
     pub fn ped_boarded_bus(&mut self, id: PedestrianID) {
@@ -260,6 +304,32 @@ impl WalkingSimState {
         let p = self.peds.get(&id)?;
         Some(&p.path)
     }
+
+    // How many people are on each sidewalk, and how many are waiting to cross at each
+    // intersection
+    pub fn get_crowds(
+        &self,
+        map: &Map,
+    ) -> (BTreeMap<LaneID, usize>, BTreeMap<IntersectionID, usize>) {
+        let mut per_sidewalk = BTreeMap::new();
+        let mut waiting = BTreeMap::new();
+        for ped in self.peds.values() {
+            match ped.state {
+                PedState::WaitingToTurn(_) => {
+                    *waiting
+                        .entry(map.get_t(ped.path.next_step().as_turn()).parent)
+                        .or_insert(0) += 1;
+                }
+                PedState::Crossing(_, _) | PedState::WaitingForBus => {
+                    if let Traversable::Lane(l) = ped.path.current_step().as_traversable() {
+                        *per_sidewalk.entry(l).or_insert(0) += 1;
+                    }
+                }
+                _ => {}
+            }
+        }
+        (per_sidewalk, waiting)
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -273,8 +343,35 @@ struct Pedestrian {
 }
 
 impl Pedestrian {
-    fn crossing_state(&self, start_dist: Distance, start_time: Duration, map: &Map) -> PedState {
-        let end_dist = if self.path.is_last_step() {
+    fn crossing_state(
+        &self,
+        start_dist: Distance,
+        start_time: Duration,
+        map: &Map,
+        peds: &BTreeMap<PedestrianID, Pedestrian>,
+        peds_per_traversable: &MultiMap<Traversable, PedestrianID>,
+    ) -> PedState {
+        let mut end_dist = self.crossing_end_dist(map);
+        // Walk sidewalks a bit at a time, so the crowd around us keeps mattering.
+        if let PathStep::Lane(_) | PathStep::ContraflowLane(_) = self.path.current_step() {
+            end_dist = if start_dist <= end_dist {
+                end_dist.min(start_dist + CROWD_UPDATE_DIST)
+            } else {
+                end_dist.max(start_dist - CROWD_UPDATE_DIST)
+            };
+        }
+        let dist_int = DistanceInterval::new_walking(start_dist, end_dist);
+        let mut speed = self.crowded_speed(start_dist, start_time, map, peds, peds_per_traversable);
+        if let Traversable::Lane(l) = self.path.current_step().as_traversable() {
+            speed = walking_speed_on_grade(speed, map.get_l(l).grade(start_dist, end_dist));
+        }
+        let time_int = TimeInterval::new(start_time, start_time + dist_int.length() / speed);
+        PedState::Crossing(dist_int, time_int)
+    }
+
+    // Where walking along the current step ends
+    fn crossing_end_dist(&self, map: &Map) -> Distance {
+        if self.path.is_last_step() {
             self.goal.sidewalk_pos.dist_along()
         } else {
             // TODO PathStep should have a end_dist... or end_pos
@@ -283,15 +380,50 @@ impl Pedestrian {
                 PathStep::ContraflowLane(_) => Distance::ZERO,
                 PathStep::Turn(t) => map.get_t(t).geom.length(),
             }
+        }
+    }
+
+    // Slow down when the sidewalk right around us is crowded.
+    fn crowded_speed(
+        &self,
+        dist: Distance,
+        now: Duration,
+        map: &Map,
+        peds: &BTreeMap<PedestrianID, Pedestrian>,
+        peds_per_traversable: &MultiMap<Traversable, PedestrianID>,
+    ) -> Speed {
+        let on = self.path.current_step().as_traversable();
+        let area = match on {
+            Traversable::Lane(l) => {
+                (CROWD_RADIUS * 2.0)
+                    .min(map.get_l(l).length())
+                    .inner_meters()
+                    * LANE_THICKNESS.inner_meters()
+            }
+            // Crosswalks have a capacity instead.
+            Traversable::Turn(_) => {
+                return self.speed;
+            }
         };
-        let dist_int = DistanceInterval::new_walking(start_dist, end_dist);
-        let time_int = TimeInterval::new(start_time, start_time + dist_int.length() / self.speed);
-        PedState::Crossing(dist_int, time_int)
+        let others = peds_per_traversable
+            .get(on)
+            .iter()
+            .filter(|id| {
+                **id != self.id && (peds[*id].get_dist_along(now, map) - dist).abs() <= CROWD_RADIUS
+            })
+            .count();
+        if others == 0 {
+            return self.speed;
+        }
+        let density = (others as f64) / area;
+        self.speed * (1.0 - density / JAM_DENSITY).max(MIN_CROWDED_SPEED_FACTOR)
     }
 
     fn get_dist_along(&self, time: Duration, map: &Map) -> Distance {
         match self.state {
-            PedState::Crossing(ref dist_int, ref time_int) => dist_int.lerp(time_int.percent(time)),
+            PedState::Crossing(ref dist_int, ref time_int) => {
+                dist_int.lerp(time_int.percent_clamp_end(time))
+            }
             PedState::WaitingToTurn(dist) => dist,
             PedState::LeavingBuilding(b, _) => map.get_b(b).front_path.sidewalk.dist_along(),
             PedState::EnteringBuilding(b, _) => map.get_b(b).front_path.sidewalk.dist_along(),
@@ -395,12 +527,20 @@ impl Pedestrian {
         now: Duration,
         map: &Map,
         intersections: &mut IntersectionSimState,
+        peds: &BTreeMap<PedestrianID, Pedestrian>,
         peds_per_traversable: &mut MultiMap<Traversable, PedestrianID>,
         scheduler: &mut Scheduler,
+        trips: &mut TripManager,
     ) -> bool {
         if let PathStep::Turn(t) = self.path.next_step() {
-            if !intersections.maybe_start_turn(AgentID::Pedestrian(self.id), t, now, map, scheduler)
-            {
+            if !intersections.maybe_start_turn(
+                AgentID::Pedestrian(self.id),
+                t,
+                now,
+                map,
+                scheduler,
+                trips,
+            ) {
                 return false;
             }
         }
@@ -412,7 +552,7 @@ impl Pedestrian {
             PathStep::ContraflowLane(l) => map.get_l(l).length(),
             PathStep::Turn(_) => Distance::ZERO,
         };
-        self.state = self.crossing_state(start_dist, now, map);
+        self.state = self.crossing_state(start_dist, now, map, peds, peds_per_traversable);
         peds_per_traversable.insert(self.path.current_step().as_traversable(), self.id);
         true
     }
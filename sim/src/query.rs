@@ -47,8 +47,8 @@ pub struct Summary {
     pub trips_with_ab_test_divergence: usize,
 }
 
-// TODO This is totally unused, needs to be re-thought
-// As of a moment in time, not necessarily the end of the simulation
+// As of a moment in time, not necessarily the end of the simulation. Walking trips are only
+// walking; driving trips use a car for at least one leg. See Sim::get_finished_trips for details.
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ScoreSummary {
     pub pending_walking_trips: usize,
@@ -1,10 +1,9 @@
 use crate::{ParkingSimState, ParkingSpot, SidewalkSpot, Vehicle};
-use geom::Distance;
+use geom::{Distance, Duration};
 use map_model::{
-    BuildingID, IntersectionID, Map, Path, PathStep, Position, Traversable, Turn, TurnID,
+    BuildingID, IntersectionID, LaneID, Map, Path, PathStep, Position, Traversable, TurnID,
 };
 use serde_derive::{Deserialize, Serialize};
-use std::collections::BTreeSet;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Router {
@@ -24,12 +23,13 @@ pub enum ActionAtEnd {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 enum Goal {
-    // Spot and cached distance along the last driving lane
-    // TODO Right now, the building is ignored.
+    // Spot and cached distance along the last driving lane. The spot is reserved for us. If the
+    // building has its own garage or lot, try that first.
     ParkNearBuilding {
         target: BuildingID,
         spot: Option<(ParkingSpot, Distance)>,
-        turns_attempted_while_roaming: BTreeSet<TurnID>,
+        // When we first reached a lane without a free spot
+        started_cruising: Option<Duration>,
     },
     EndAtBorder {
         end_dist: Distance,
@@ -57,7 +57,7 @@ impl Router {
             goal: Goal::ParkNearBuilding {
                 target: bldg,
                 spot: None,
-                turns_attempted_while_roaming: BTreeSet::new(),
+                started_cruising: None,
             },
         }
     }
@@ -107,21 +107,49 @@ impl Router {
         }
     }
 
+    // Does the vehicle have to come to a stop at the end of the last step?
+    pub fn stops_at_end(&self) -> bool {
+        match self.goal {
+            Goal::EndAtBorder { .. } => false,
+            Goal::ParkNearBuilding { .. }
+            | Goal::BikeThenStop { .. }
+            | Goal::FollowBusRoute { .. } => true,
+        }
+    }
+
     pub fn get_path(&self) -> &Path {
         &self.path
     }
 
+    // Only valid before the last step; the next turn must lead to the same lane as before.
+    pub fn change_lanes(&mut self, new_lane: LaneID, new_turn: TurnID) {
+        assert!(!self.last_step());
+        self.path.change_lanes(new_lane, new_turn);
+    }
+
+    // How long the car has been looking for parking since its first choice of lane was full.
+    pub fn time_spent_cruising(&self, now: Duration) -> Duration {
+        match self.goal {
+            Goal::ParkNearBuilding {
+                started_cruising: Some(t),
+                ..
+            } => now - t,
+            _ => Duration::ZERO,
+        }
+    }
+
     // Returns the step just finished
     pub fn advance(
         &mut self,
+        time: Duration,
         vehicle: &Vehicle,
-        parking: &ParkingSimState,
+        parking: &mut ParkingSimState,
         map: &Map,
     ) -> Traversable {
         let prev = self.path.shift().as_traversable();
         if self.last_step() {
             // Do this to trigger the side-effect of looking for parking.
-            self.maybe_handle_end(Distance::ZERO, vehicle, parking, map);
+            self.maybe_handle_end(time, Distance::ZERO, vehicle, parking, map);
         }
         prev
     }
@@ -130,9 +158,10 @@ impl Router {
     // step.
     pub fn maybe_handle_end(
         &mut self,
+        time: Duration,
         front: Distance,
         vehicle: &Vehicle,
-        parking: &ParkingSimState,
+        parking: &mut ParkingSimState,
         map: &Map,
     ) -> Option<ActionAtEnd> {
         match self.goal {
@@ -143,20 +172,28 @@ impl Router {
                     None
                 }
             }
-            Goal::ParkNearBuilding { ref mut spot, .. } => {
-                let need_new_spot = match spot {
-                    Some((s, _)) => !parking.is_free(*s),
-                    None => true,
-                };
-                if need_new_spot {
-                    if let Some((new_spot, new_pos)) = parking.get_first_free_spot(
-                        Position::new(self.path.current_step().as_lane(), front),
+            Goal::ParkNearBuilding {
+                target,
+                ref mut spot,
+                ref mut started_cruising,
+            } => {
+                // The last lane was full when the trip started, or we've been cruising. Once we
+                // can see a free spot on the lane we're approaching, claim it, so nobody else
+                // takes it first.
+                if spot.is_none() {
+                    let current_lane = self.path.current_step().as_lane();
+                    *spot = claim_spot(
+                        target,
+                        Position::new(current_lane, front),
                         vehicle,
+                        parking,
                         map,
-                    ) {
-                        *spot = Some((new_spot, new_pos.dist_along()));
-                    } else {
-                        self.roam_around_for_parking(vehicle, map);
+                    );
+                    if spot.is_none() {
+                        if started_cruising.is_none() {
+                            *started_cruising = Some(time);
+                        }
+                        self.cruise_for_parking(vehicle, parking, map);
                         return Some(ActionAtEnd::GotoLaneEnd);
                     }
                 }
@@ -193,42 +230,75 @@ impl Router {
         }
     }
 
-    fn roam_around_for_parking(&mut self, vehicle: &Vehicle, map: &Map) {
-        let turns_attempted_while_roaming = match self.goal {
-            Goal::ParkNearBuilding {
-                ref mut turns_attempted_while_roaming,
-                ..
-            } => turns_attempted_while_roaming,
-            _ => unreachable!(),
-        };
+    // Called once the car starts driving. Pick the spot to aim for on the last lane of the path
+    // and reserve it right away, so it's still free when we get there.
+    pub fn reserve_target_spot(
+        &mut self,
+        vehicle: &Vehicle,
+        parking: &mut ParkingSimState,
+        map: &Map,
+    ) {
+        // Starting on the last lane already; maybe_handle_end looks ahead of where we are.
+        if self.last_step() {
+            return;
+        }
+        let last_lane = self.path.last_step().as_lane();
+        if let Goal::ParkNearBuilding {
+            target,
+            ref mut spot,
+            ..
+        } = self.goal
+        {
+            if spot.is_none() {
+                *spot = claim_spot(
+                    target,
+                    Position::new(last_lane, Distance::ZERO),
+                    vehicle,
+                    parking,
+                    map,
+                );
+            }
+        }
+    }
 
+    // Everything on the current lane is taken, so head for the next-closest lane with a free
+    // spot. By the time we get there, it might be full too; then we'll just repeat this.
+    fn cruise_for_parking(&mut self, vehicle: &Vehicle, parking: &ParkingSimState, map: &Map) {
         let current_lane = self.path.current_step().as_lane();
-        let all_choices = map.get_turns_from_lane(current_lane);
-        let new_choices: Vec<&&Turn> = all_choices
-            .iter()
-            .filter(|t| !turns_attempted_while_roaming.contains(&t.id))
-            .collect();
-
-        if all_choices.is_empty() {
-            // TODO Fix properly by picking and pathfinding fully to a nearby parking lane.
-            println!("{} can't find parking on {}, and also it's a dead-end, so they'll be stuck there forever. Vanishing.", vehicle.id, current_lane);
-            self.goal = Goal::EndAtBorder {
-                end_dist: map.get_l(current_lane).length(),
-                i: map.get_l(current_lane).dst_i,
-            };
+        if let Some(steps) = parking.path_to_free_parking_spot(current_lane, vehicle, map) {
+            for step in steps {
+                self.path.add(step);
+            }
             return;
         }
 
-        // TODO Better strategies than this: look for lanes with free spots (if it'd be feasible to
-        // physically see the spots), stay close to the original goal building, avoid lanes we've
-        // visited, prefer easier turns...
-        let turn = if !new_choices.is_empty() {
-            new_choices[0]
-        } else {
-            all_choices[0]
+        // TODO Fix properly by waiting for a spot to open up somewhere. TripManager reports the
+        // aborted trip when the car vanishes.
+        self.goal = Goal::EndAtBorder {
+            end_dist: map.get_l(current_lane).length(),
+            i: map.get_l(current_lane).dst_i,
         };
-        turns_attempted_while_roaming.insert(turn.id);
-        self.path.add(PathStep::Turn(turn.id));
-        self.path.add(PathStep::Lane(turn.id.dst));
     }
 }
+
+// Prefer the building's own garage if it's ahead of us on this lane, then the closest free
+// spot ahead. Reserves whatever it finds.
+fn claim_spot(
+    target: BuildingID,
+    pos: Position,
+    vehicle: &Vehicle,
+    parking: &mut ParkingSimState,
+    map: &Map,
+) -> Option<(ParkingSpot, Distance)> {
+    let own_garage = parking
+        .get_free_offstreet_spots(target)
+        .find(|s| parking.is_free(*s))
+        .map(|s| (s, parking.spot_to_driving_pos(s, vehicle, map)))
+        .filter(|(_, driving_pos)| {
+            driving_pos.lane() == pos.lane() && pos.dist_along() <= driving_pos.dist_along()
+        });
+    let (spot, driving_pos) =
+        own_garage.or_else(|| parking.get_first_free_spot(pos, vehicle, map))?;
+    parking.reserve_spot(spot);
+    Some((spot, driving_pos.dist_along()))
+}
@@ -1,9 +1,11 @@
 use crate::{AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID};
+use abstutil::{deserialize_btreemap, serialize_btreemap};
 use derivative::Derivative;
 use geom::Duration;
 use histogram::Histogram;
 use map_model::IntersectionID;
 use serde_derive::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 
 #[derive(Serialize, Deserialize, PartialEq)]
 pub enum Command {
@@ -13,6 +15,9 @@ pub enum Command {
     UpdateCar(CarID),
     // Distinguish this from UpdateCar to avoid confusing things
     UpdateLaggyHead(CarID),
+    // A Queued car stuck behind something retries moving to an adjacent lane. Stale if the car
+    // isn't Queued anymore.
+    RetryLaneChange(CarID),
     UpdatePed(PedestrianID),
     UpdateIntersection(IntersectionID),
     CheckForGridlock,
@@ -26,17 +31,62 @@ impl Command {
             AgentID::Pedestrian(p) => Command::UpdatePed(p),
         }
     }
+
+    fn to_type(&self) -> CommandType {
+        match self {
+            Command::SpawnCar(ref create, _) => CommandType::SpawnCar(create.vehicle.id),
+            Command::SpawnPed(ref create) => CommandType::SpawnPed(create.id),
+            Command::UpdateCar(id) => CommandType::Car(*id),
+            Command::UpdateLaggyHead(id) => CommandType::CarLaggyHead(*id),
+            Command::RetryLaneChange(id) => CommandType::RetryLaneChange(*id),
+            Command::UpdatePed(id) => CommandType::Ped(*id),
+            Command::UpdateIntersection(id) => CommandType::Intersection(*id),
+            Command::CheckForGridlock => CommandType::CheckForGridlock,
+            Command::Savestate(frequency) => CommandType::Savestate(*frequency),
+        }
+    }
+}
+
+// A cheap, orderable key for finding scheduled Commands again.
+#[derive(Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug)]
+enum CommandType {
+    SpawnCar(CarID),
+    SpawnPed(PedestrianID),
+    Car(CarID),
+    CarLaggyHead(CarID),
+    RetryLaneChange(CarID),
+    Ped(PedestrianID),
+    Intersection(IntersectionID),
+    CheckForGridlock,
+    Savestate(Duration),
 }
 
 #[derive(Serialize, Deserialize, Derivative)]
 #[derivative(PartialEq)]
 pub struct Scheduler {
-    // TODO Implement more efficiently. Last element has earliest time.
-    items: Vec<(Duration, Command)>,
+    // Commands due at each time, by seq. The last seq in each list goes first.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    items: BTreeMap<Duration, Vec<usize>>,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    queued_commands: BTreeMap<usize, (Duration, Command)>,
+    // For update and cancel. Usually there's just one seq per type.
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    cmd_index: BTreeMap<CommandType, BTreeSet<usize>>,
+    next_seq: usize,
 
     latest_time: Duration,
     // TODO Why doesn't the Histogram keep a total count? :(
     num_events: usize,
+    num_events_processed: usize,
     #[derivative(PartialEq = "ignore")]
     #[serde(skip_serializing, skip_deserializing)]
     delta_times: Histogram,
@@ -45,9 +95,13 @@ pub struct Scheduler {
 impl Scheduler {
     pub fn new() -> Scheduler {
         Scheduler {
-            items: Vec::new(),
+            items: BTreeMap::new(),
+            queued_commands: BTreeMap::new(),
+            cmd_index: BTreeMap::new(),
+            next_seq: 0,
             latest_time: Duration::ZERO,
             num_events: 0,
+            num_events_processed: 0,
             delta_times: Histogram::new(),
         }
     }
@@ -64,13 +118,18 @@ impl Scheduler {
             .increment((time - self.latest_time).to_u64())
             .unwrap();
 
-        // TODO Make sure this is deterministic.
-        // Note the order of comparison means times will be descending.
-        let idx = match self.items.binary_search_by(|(at, _)| time.cmp(at)) {
-            Ok(i) => i,
-            Err(i) => i,
-        };
-        self.items.insert(idx, (time, cmd));
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.cmd_index
+            .entry(cmd.to_type())
+            .or_insert_with(BTreeSet::new)
+            .insert(seq);
+        self.queued_commands.insert(seq, (time, cmd));
+        // Ties are ordered by when they were pushed, so the order is deterministic: the new
+        // command goes right before the last one at that time.
+        let bucket = self.items.entry(time).or_insert_with(Vec::new);
+        let idx = bucket.len().saturating_sub(1);
+        bucket.insert(idx, seq);
     }
 
     pub fn update(&mut self, cmd: Command, new_time: Duration) {
@@ -81,22 +140,71 @@ impl Scheduler {
             );
         }
 
-        if let Some(idx) = self.items.iter().position(|(_, i)| *i == cmd) {
-            self.items.remove(idx);
-        }
+        self.cancel(&cmd);
         self.push(new_time, cmd);
     }
 
+    // Removes an equal command, if there is one. Like the old search from the front of the sorted
+    // Vec, that's the latest scheduled one, and the last in line at that time.
+    pub fn cancel(&mut self, cmd: &Command) {
+        let cmd_type = cmd.to_type();
+        let seq = if let Some(seqs) = self.cmd_index.get(&cmd_type) {
+            let queued = &self.queued_commands;
+            seqs.iter()
+                .filter(|seq| queued[*seq].1 == *cmd)
+                .map(|seq| queued[seq].0)
+                .max()
+                .and_then(|time| {
+                    self.items[&time]
+                        .iter()
+                        .find(|seq| queued[*seq].1 == *cmd)
+                        .cloned()
+                })
+        } else {
+            None
+        };
+        if let Some(seq) = seq {
+            self.remove(cmd_type, seq);
+        }
+    }
+
+    fn remove(&mut self, cmd_type: CommandType, seq: usize) -> (Duration, Command) {
+        let seqs = self.cmd_index.get_mut(&cmd_type).unwrap();
+        seqs.remove(&seq);
+        if seqs.is_empty() {
+            self.cmd_index.remove(&cmd_type);
+        }
+        let (time, cmd) = self.queued_commands.remove(&seq).unwrap();
+        let bucket = self.items.get_mut(&time).unwrap();
+        // Usually this is the next command to go, at the end.
+        let idx = bucket.iter().rposition(|s| *s == seq).unwrap();
+        bucket.remove(idx);
+        if bucket.is_empty() {
+            self.items.remove(&time);
+        }
+        (time, cmd)
+    }
+
     // This API is safer than handing out a batch of items at a time, because while processing one
     // item, we might change the priority of other items or add new items. Don't make the caller
     // reconcile those changes -- just keep pulling items from here, one at a time.
     pub fn get_next(&mut self, now: Duration) -> Option<(Command, Duration)> {
-        let next_time = self.items.last().as_ref()?.0;
-        if next_time > now {
-            return None;
-        }
-        self.latest_time = next_time;
-        Some((self.items.pop().unwrap().1, next_time))
+        let seq = {
+            let (time, bucket) = self.items.iter().next()?;
+            if *time > now {
+                return None;
+            }
+            *bucket.last().unwrap()
+        };
+        let cmd_type = self.queued_commands[&seq].1.to_type();
+        let (time, cmd) = self.remove(cmd_type, seq);
+        self.latest_time = time;
+        self.num_events_processed += 1;
+        Some((cmd, time))
+    }
+
+    pub fn num_events_processed(&self) -> usize {
+        self.num_events_processed
     }
 
     pub fn describe_stats(&self) -> String {
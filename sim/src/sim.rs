@@ -1,16 +1,22 @@
 use crate::{
-    AgentID, Benchmark, CarID, Command, CreateCar, DrawCarInput, DrawPedestrianInput, DrivingGoal,
-    DrivingSimState, Event, GetDrawAgents, IntersectionSimState, ParkedCar, ParkingSimState,
-    ParkingSpot, PedestrianID, Router, Scheduler, ScoreSummary, SimStats, Summary, TransitSimState,
-    TripID, TripLeg, TripManager, TripSpawner, TripSpec, VehicleSpec, VehicleType, WalkingSimState,
-    BUS_LENGTH,
+    AgentID, Benchmark, BusStopVisit, CarID, Command, CreateCar, DrawCarInput, DrawPedestrianInput,
+    DrivingGoal, DrivingSimState, Event, FinishedTrip, GetDrawAgents, IntersectionSimState,
+    Itinerary, ParkedCar, ParkingSimState, ParkingSpot, PedestrianID, PlannedLeg, Router,
+    Scheduler, ScoreSummary, SimStats, Summary, TransitSimState, TripID, TripLeg, TripManager,
+    TripPlanRequest, TripSpawner, TripSpec, VehicleSpec, VehicleType, WalkingSimState, BUS_ACCEL,
+    BUS_CAPACITY, BUS_DECEL, BUS_LENGTH,
 };
 use abstutil::Timer;
 use derivative::Derivative;
 use geom::{Distance, Duration, PolyLine, Polygon, Pt2D};
-use map_model::{BuildingID, BusRoute, IntersectionID, LaneID, Map, Path, Traversable};
+use map_model::{
+    BuildingID, BusRoute, DirectedRoadID, IntersectionID, LaneID, Map, ParkingLotID, Path,
+    Traversable,
+};
 use serde_derive::{Deserialize, Serialize};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::Write;
 use std::panic;
 use std::time::Instant;
 
@@ -121,6 +127,22 @@ impl Sim {
                 self.car_id_counter += 1;
                 (Some(ped), Some(car))
             }
+            TripSpec::UsingItinerary { ref itinerary, .. } => {
+                let ped = PedestrianID(self.ped_id_counter);
+                self.ped_id_counter += 1;
+                // Parked cars already have an ID.
+                let bike = if itinerary.legs.iter().any(|leg| match leg {
+                    PlannedLeg::Bike(_, _) => true,
+                    _ => false,
+                }) {
+                    let id = CarID(self.car_id_counter, VehicleType::Bike);
+                    self.car_id_counter += 1;
+                    Some(id)
+                } else {
+                    None
+                };
+                (Some(ped), bike)
+            }
         };
 
         self.spawner
@@ -143,6 +165,27 @@ impl Sim {
         self.parking.get_free_spots(l)
     }
 
+    pub fn get_free_offstreet_spots(&self, b: BuildingID) -> Vec<ParkingSpot> {
+        self.parking.get_free_offstreet_spots(b).collect()
+    }
+
+    pub fn get_free_lot_spots(&self, lot: ParkingLotID) -> Vec<ParkingSpot> {
+        self.parking.get_free_lot_spots(lot).collect()
+    }
+
+    // Where a car of this type driving to the building would park right now
+    pub fn find_parking_spot_near_building(
+        &self,
+        b: BuildingID,
+        vehicle: &VehicleSpec,
+        map: &Map,
+    ) -> Option<ParkingSpot> {
+        let vehicle = vehicle
+            .clone()
+            .make(CarID(self.car_id_counter, vehicle.vehicle_type), Some(b));
+        self.parking.find_spot_near_building(b, &vehicle, map)
+    }
+
     pub fn seed_parked_car(
         &mut self,
         vehicle: VehicleSpec,
@@ -164,20 +207,79 @@ impl Sim {
         self.parking.get_parked_cars_by_owner(bldg)
     }
 
+    // All of the reasonable ways to make a trip, quickest first. The parked car should belong to
+    // whoever's making the trip and not be used by another one.
+    pub fn plan_trip(&self, req: &TripPlanRequest, map: &Map) -> Vec<Itinerary> {
+        crate::plan_trip(req, map, &self.parking)
+    }
+
+    // Like plan_trip, but spreads the searches across threads. The results are in the same order.
+    pub fn plan_trips(
+        &self,
+        requests: Vec<TripPlanRequest>,
+        map: &Map,
+        timer: &mut Timer,
+    ) -> Vec<Vec<Itinerary>> {
+        let parking = &self.parking;
+        timer.parallelize("plan trips", requests, |req| {
+            crate::plan_trip(&req, map, parking)
+        })
+    }
+
     pub fn seed_bus_route(&mut self, route: &BusRoute, map: &Map, timer: &mut Timer) -> Vec<CarID> {
         let mut results: Vec<CarID> = Vec::new();
+        let stops = self.transit.create_empty_route(route, map);
+
+        if !route.schedule.is_empty() {
+            // Each scheduled run appears at the first stop when it's supposed to leave. If there's
+            // no room, it'll just leave late.
+            let (next_stop_idx, start_dist, ref path, end_dist) = stops[0];
+            if start_dist < BUS_LENGTH {
+                timer.warn(format!(
+                    "First stop of {} ({}) is too short to spawn buses there; giving up on the route",
+                    route.name, route.id
+                ));
+                return results;
+            }
+            for (run, scheduled) in route.schedule.iter().enumerate() {
+                let spawn_time = scheduled.departures[0];
+                if spawn_time < self.time {
+                    continue;
+                }
+                let id = CarID(self.car_id_counter, VehicleType::Bus);
+                self.car_id_counter += 1;
+                let trip = self
+                    .trips
+                    .new_trip(spawn_time, vec![TripLeg::ServeBusRoute(id, route.id)]);
+                self.transit
+                    .bus_created(id, route.id, next_stop_idx, Some(run), BUS_CAPACITY);
+                self.scheduler.push(
+                    spawn_time,
+                    Command::SpawnCar(
+                        CreateCar {
+                            vehicle: bus_spec().make(id, None),
+                            router: Router::follow_bus_route(path.clone(), end_dist),
+                            start_dist,
+                            maybe_parked_car: None,
+                            trip,
+                        },
+                        true,
+                    ),
+                );
+                results.push(id);
+            }
+            timer.note(format!(
+                "Scheduled {} buses for route {} ({})",
+                results.len(),
+                route.name,
+                route.id
+            ));
+            return results;
+        }
 
         // Try to spawn a bus at each stop
-        for (next_stop_idx, start_dist, path, end_dist) in
-            self.transit.create_empty_route(route, map).into_iter()
-        {
-            // For now, no desire for randomness. Caller can pass in list of specs if that ever
-            // changes.
-            let vehicle_spec = VehicleSpec {
-                vehicle_type: VehicleType::Bus,
-                length: BUS_LENGTH,
-                max_speed: None,
-            };
+        for (next_stop_idx, start_dist, path, end_dist) in stops.into_iter() {
+            let vehicle_spec = bus_spec();
 
             // TODO Do this validation more up-front in the map layer
             if start_dist < vehicle_spec.length {
@@ -209,11 +311,12 @@ impl Sim {
                 },
                 map,
                 &self.intersections,
-                &self.parking,
+                &mut self.parking,
                 &mut self.scheduler,
             ) {
                 self.trips.agent_starting_trip_leg(AgentID::Car(id), trip);
-                self.transit.bus_created(id, route.id, next_stop_idx);
+                self.transit
+                    .bus_created(id, route.id, next_stop_idx, None, BUS_CAPACITY);
                 timer.note(format!(
                     "Spawned bus {} for route {} ({})",
                     id, route.name, route.id
@@ -311,7 +414,7 @@ impl Sim {
                         create_car.clone(),
                         map,
                         &self.intersections,
-                        &self.parking,
+                        &mut self.parking,
                         &mut self.scheduler,
                     ) {
                         self.trips.agent_starting_trip_leg(
@@ -321,6 +424,14 @@ impl Sim {
                         if let Some(parked_car) = create_car.maybe_parked_car {
                             self.parking.remove_parked_car(parked_car);
                         }
+                        if create_car.vehicle.vehicle_type == VehicleType::Bus {
+                            self.transit.bus_spawned(
+                                self.time,
+                                create_car.vehicle.id,
+                                &mut self.trips,
+                                &mut self.walking,
+                            );
+                        }
                     } else if retry_if_no_room {
                         self.scheduler.push(
                             self.time + BLIND_RETRY_TO_SPAWN,
@@ -341,6 +452,13 @@ impl Sim {
                         AgentID::Pedestrian(create_ped.id),
                         create_ped.trip,
                     );
+                    // Pedestrians never stray from their path, so count all of it now.
+                    self.trips.agent_travelled(
+                        AgentID::Pedestrian(create_ped.id),
+                        create_ped
+                            .path
+                            .total_length(create_ped.start.sidewalk_pos.dist_along(), map),
+                    );
                     self.walking
                         .spawn_ped(self.time, create_ped, map, &mut self.scheduler);
                 }
@@ -366,6 +484,15 @@ impl Sim {
                         &mut self.scheduler,
                     );
                 }
+                Command::RetryLaneChange(car) => {
+                    self.driving.maybe_change_lanes(
+                        car,
+                        self.time,
+                        map,
+                        &self.intersections,
+                        &mut self.scheduler,
+                    );
+                }
                 Command::UpdatePed(ped) => {
                     self.walking.update_ped(
                         ped,
@@ -588,6 +715,10 @@ impl Sim {
             format!("{:.2}x", speed)
         }
     }
+
+    pub fn num_events_processed(&self) -> usize {
+        self.scheduler.num_events_processed()
+    }
 }
 
 // Queries of all sorts
@@ -632,17 +763,32 @@ impl Sim {
         )
     }
 
-    // TODO Rethink this
     pub fn get_score(&self) -> ScoreSummary {
-        ScoreSummary {
-            pending_walking_trips: 0,
-            total_walking_trips: 0,
-            total_walking_trip_time: Duration::ZERO,
-            pending_driving_trips: 0,
-            total_driving_trips: 0,
-            total_driving_trip_time: Duration::ZERO,
-            completion_time: None,
+        self.trips.get_score(self.time)
+    }
+
+    // Not including buses.
+    pub fn get_finished_trips(&self) -> Vec<FinishedTrip> {
+        self.trips.get_finished_trips()
+    }
+
+    // For measuring bunching and schedule adherence
+    pub fn get_bus_stop_visits(&self) -> &Vec<BusStopVisit> {
+        self.transit.get_stop_visits()
+    }
+
+    pub fn save_finished_trips_json(&self, path: &str) -> Result<(), std::io::Error> {
+        abstutil::write_json(path, &self.get_finished_trips())
+    }
+
+    pub fn save_finished_trips_csv(&self, path: &str) -> Result<(), std::io::Error> {
+        std::fs::create_dir_all(std::path::Path::new(path).parent().unwrap())?;
+        let mut file = File::create(path)?;
+        writeln!(file, "{}", FinishedTrip::csv_header())?;
+        for trip in self.get_finished_trips() {
+            writeln!(file, "{}", trip.to_csv_row())?;
         }
+        Ok(())
     }
 
     pub fn debug_ped(&self, id: PedestrianID) {
@@ -665,6 +811,7 @@ impl Sim {
 
     pub fn car_tooltip(&self, car: CarID) -> Vec<String> {
         if let Some(mut lines) = self.driving.tooltip_lines(car) {
+            lines.extend(self.transit.tooltip_lines(car).unwrap_or_else(Vec::new));
             lines.extend(self.trips.tooltip_lines(AgentID::Car(car)));
             lines
         } else {
@@ -676,6 +823,15 @@ impl Sim {
         self.trips.active_agents()
     }
 
+    // How many pedestrians are on each sidewalk, and how many are waiting to cross at each
+    // intersection
+    pub fn get_pedestrian_crowds(
+        &self,
+        map: &Map,
+    ) -> (BTreeMap<LaneID, usize>, BTreeMap<IntersectionID, usize>) {
+        self.walking.get_crowds(map)
+    }
+
     pub fn debug_trip(&self, id: TripID) {
         match self.trips.trip_to_agent(id) {
             Some(AgentID::Car(id)) => self.debug_car(id),
@@ -709,6 +865,11 @@ impl Sim {
         None
     }
 
+    // How long cars took to cross each road, for feeding back into Map::set_observed_travel_times
+    pub fn get_observed_travel_times(&self) -> BTreeMap<DirectedRoadID, Duration> {
+        self.driving.get_observed_travel_times()
+    }
+
     pub fn get_path(&self, id: AgentID) -> Option<&Path> {
         match id {
             AgentID::Car(car) => self.driving.get_path(car),
@@ -785,4 +946,21 @@ impl Sim {
     pub fn is_in_overtime(&self, id: IntersectionID, map: &Map) -> bool {
         self.intersections.is_in_overtime(self.time, id, map)
     }
+
+    // For traffic signals, returns the index of the current cycle and the time left in it.
+    // Actuated signals might extend or skip cycles, so ask the sim, not the map.
+    pub fn current_signal_cycle(&self, id: IntersectionID, map: &Map) -> (usize, Duration) {
+        self.intersections.current_signal_cycle(self.time, id, map)
+    }
+}
+
+// For now, no desire for randomness. Caller can pass in list of specs if that ever changes.
+fn bus_spec() -> VehicleSpec {
+    VehicleSpec {
+        vehicle_type: VehicleType::Bus,
+        length: BUS_LENGTH,
+        max_speed: None,
+        accel: BUS_ACCEL,
+        decel: BUS_DECEL,
+    }
 }
@@ -1,13 +1,18 @@
 use crate::{CarID, Event, PedestrianID, Router, Scheduler, TripManager, WalkingSimState};
 use abstutil::{deserialize_btreemap, serialize_btreemap};
 use geom::{Distance, Duration};
-use map_model::{BusRoute, BusRouteID, BusStopID, Map, Path, PathRequest, Position};
+use map_model::{BusRoute, BusRouteID, BusStopID, Map, Path, PathRequest, Position, ScheduledRun};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::BTreeMap;
 
 // These index stops along a route, not stops along a single sidewalk.
 type StopIdx = usize;
 
+// Buses wait at least this long at every stop, plus time for each passenger getting on or off.
+pub const TIME_TO_WAIT_AT_STOP: Duration = Duration::const_seconds(10.0);
+pub const TIME_TO_BOARD: Duration = Duration::const_seconds(3.0);
+pub const TIME_TO_ALIGHT: Duration = Duration::const_seconds(2.0);
+
 #[derive(Serialize, Deserialize, PartialEq)]
 struct StopForRoute {
     id: BusStopID,
@@ -20,6 +25,7 @@ struct StopForRoute {
 struct Route {
     stops: Vec<StopForRoute>,
     buses: Vec<CarID>,
+    schedule: Vec<ScheduledRun>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
@@ -28,13 +34,41 @@ struct Bus {
     route: BusRouteID,
     // Where does each passenger want to deboard?
     passengers: Vec<(PedestrianID, BusStopID)>,
+    capacity: usize,
     state: BusState,
+    // Which scheduled run of the route this bus serves. If None, the bus loops around forever.
+    run: Option<usize>,
 }
 
 #[derive(Serialize, Deserialize, PartialEq)]
 enum BusState {
     DrivingToStop(StopIdx),
-    AtStop(StopIdx),
+    AtStop {
+        stop: StopIdx,
+        arrived_at: Duration,
+        boarded: usize,
+        alighted: usize,
+        // Pushed back when people board late
+        depart_at: Duration,
+    },
+}
+
+// One bus stopping at one stop.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BusStopVisit {
+    pub bus: CarID,
+    pub route: BusRouteID,
+    pub stop: BusStopID,
+    pub arrived_at: Duration,
+    pub departed_at: Duration,
+    // Positive if the bus left later than scheduled. None for buses that don't follow a schedule.
+    pub delay: Option<Duration>,
+    pub boarded: usize,
+    pub alighted: usize,
+    // Passengers aboard when the bus left
+    pub load: usize,
+    // People waiting for this bus who didn't fit
+    pub left_behind: usize,
 }
 
 // This kind of acts like TripManager, managing transitions... but a bit more statefully.
@@ -52,6 +86,8 @@ pub struct TransitSimState {
     routes: BTreeMap<BusRouteID, Route>,
     // Can organize this more to make querying cheaper
     peds_waiting: Vec<(PedestrianID, BusStopID, BusRouteID, BusStopID)>,
+    // In the order the buses left the stop
+    stop_visits: Vec<BusStopVisit>,
 
     events: Vec<Event>,
 }
@@ -62,6 +98,7 @@ impl TransitSimState {
             buses: BTreeMap::new(),
             routes: BTreeMap::new(),
             peds_waiting: Vec::new(),
+            stop_visits: Vec::new(),
             events: Vec::new(),
         }
     }
@@ -77,6 +114,7 @@ impl TransitSimState {
 
         let route = Route {
             buses: Vec::new(),
+            schedule: bus_route.schedule.clone(),
             stops: bus_route
                 .stops
                 .iter()
@@ -125,7 +163,14 @@ impl TransitSimState {
         stops
     }
 
-    pub fn bus_created(&mut self, bus: CarID, route: BusRouteID, next_stop_idx: StopIdx) {
+    pub fn bus_created(
+        &mut self,
+        bus: CarID,
+        route: BusRouteID,
+        next_stop_idx: StopIdx,
+        run: Option<usize>,
+        capacity: usize,
+    ) {
         self.routes.get_mut(&route).unwrap().buses.push(bus);
         self.buses.insert(
             bus,
@@ -133,11 +178,27 @@ impl TransitSimState {
                 car: bus,
                 route,
                 passengers: Vec::new(),
+                capacity,
                 state: BusState::DrivingToStop(next_stop_idx),
+                run,
             },
         );
     }
 
+    // A scheduled bus just appeared at the first stop of its route, already headed to the next.
+    pub fn bus_spawned(
+        &mut self,
+        time: Duration,
+        id: CarID,
+        trips: &mut TripManager,
+        walking: &mut WalkingSimState,
+    ) {
+        let boarded = self.board_waiting_peds(time, id, 0, trips, walking);
+        self.record_visit(id, 0, time, time, boarded, 0);
+    }
+
+    // Returns when the bus should leave the stop, or None if the bus just finished its scheduled
+    // run and should vanish.
     pub fn bus_arrived_at_stop(
         &mut self,
         time: Duration,
@@ -146,64 +207,92 @@ impl TransitSimState {
         walking: &mut WalkingSimState,
         scheduler: &mut Scheduler,
         map: &Map,
-    ) {
-        let mut bus = self.buses.get_mut(&id).unwrap();
-        match bus.state {
-            BusState::DrivingToStop(stop_idx) => {
-                bus.state = BusState::AtStop(stop_idx);
-                let stop = self.routes[&bus.route].stops[stop_idx].id;
-                self.events.push(Event::BusArrivedAtStop(id, stop));
-
-                // Deboard existing passengers.
-                let mut still_riding = Vec::new();
-                for (ped, stop2) in bus.passengers.drain(..) {
-                    if stop == stop2 {
-                        self.events.push(Event::PedLeavesBus(ped, id));
-                        trips.ped_left_bus(time, ped, map, scheduler);
-                    } else {
-                        still_riding.push((ped, stop2));
-                    }
-                }
-                bus.passengers = still_riding;
-
-                // Board new passengers.
-                let mut still_waiting = Vec::new();
-                for (ped, stop1, route, stop2) in self.peds_waiting.drain(..) {
-                    if stop == stop1 && bus.route == route {
-                        bus.passengers.push((ped, stop2));
-                        self.events.push(Event::PedEntersBus(ped, id));
-                        trips.ped_boarded_bus(ped, walking);
-                    } else {
-                        still_waiting.push((ped, stop1, route, stop2));
-                    }
-                }
-                self.peds_waiting = still_waiting;
+    ) -> Option<Duration> {
+        let bus = self.buses.get_mut(&id).unwrap();
+        let stop_idx = match bus.state {
+            BusState::DrivingToStop(stop_idx) => stop_idx,
+            BusState::AtStop { .. } => unreachable!(),
+        };
+        let route = &self.routes[&bus.route];
+        let stop = route.stops[stop_idx].id;
+        self.events.push(Event::BusArrivedAtStop(id, stop));
+
+        // Deboard existing passengers.
+        let mut alighted = 0;
+        let mut still_riding = Vec::new();
+        for (ped, stop2) in bus.passengers.drain(..) {
+            if stop == stop2 {
+                alighted += 1;
+                self.events.push(Event::PedLeavesBus(ped, id));
+                trips.ped_left_bus(time, ped, map, scheduler);
+            } else {
+                still_riding.push((ped, stop2));
             }
-            BusState::AtStop(_) => unreachable!(),
+        }
+        bus.passengers = still_riding;
+
+        if bus.run.is_some() && stop_idx == route.stops.len() - 1 {
+            // Nobody boards a bus that isn't going to their stop.
+            assert!(bus.passengers.is_empty());
+            self.record_visit(id, stop_idx, time, time, 0, alighted);
+            self.events.push(Event::BusDepartedFromStop(id, stop));
+            let bus = self.buses.remove(&id).unwrap();
+            self.routes
+                .get_mut(&bus.route)
+                .unwrap()
+                .buses
+                .retain(|b| *b != id);
+            trips.bus_finished(time, id);
+            return None;
+        }
+        let boarded = self.board_waiting_peds(time, id, stop_idx, trips, walking);
+        let depart_at = self.departure_time(id, stop_idx, time, boarded, alighted);
+        self.buses.get_mut(&id).unwrap().state = BusState::AtStop {
+            stop: stop_idx,
+            arrived_at: time,
+            boarded,
+            alighted,
+            depart_at,
         };
+        Some(depart_at)
     }
 
-    pub fn bus_departed_from_stop(&mut self, id: CarID) -> Router {
-        let mut bus = self.buses.get_mut(&id).unwrap();
-        match bus.state {
+    // When a bus idling at a stop should leave. People boarding late can push this back.
+    pub fn bus_departure_time(&self, id: CarID) -> Duration {
+        match self.buses[&id].state {
             BusState::DrivingToStop(_) => unreachable!(),
-            BusState::AtStop(stop_idx) => {
-                let route = &self.routes[&bus.route];
-                let stop = &route.stops[stop_idx];
-
-                bus.state = BusState::DrivingToStop(stop.next_stop_idx);
-                self.events.push(Event::BusDepartedFromStop(id, stop.id));
-                Router::follow_bus_route(
-                    stop.path_to_next_stop.clone(),
-                    route.stops[stop.next_stop_idx].driving_pos.dist_along(),
-                )
-            }
+            BusState::AtStop { depart_at, .. } => depart_at,
         }
     }
 
+    pub fn bus_departed_from_stop(&mut self, time: Duration, id: CarID) -> Router {
+        let (stop_idx, arrived_at, boarded, alighted) = match self.buses[&id].state {
+            BusState::DrivingToStop(_) => unreachable!(),
+            BusState::AtStop {
+                stop,
+                arrived_at,
+                boarded,
+                alighted,
+                ..
+            } => (stop, arrived_at, boarded, alighted),
+        };
+        self.record_visit(id, stop_idx, arrived_at, time, boarded, alighted);
+
+        let bus = self.buses.get_mut(&id).unwrap();
+        let route = &self.routes[&bus.route];
+        let stop = &route.stops[stop_idx];
+        bus.state = BusState::DrivingToStop(stop.next_stop_idx);
+        self.events.push(Event::BusDepartedFromStop(id, stop.id));
+        Router::follow_bus_route(
+            stop.path_to_next_stop.clone(),
+            route.stops[stop.next_stop_idx].driving_pos.dist_along(),
+        )
+    }
+
     // If true, the pedestrian boarded a bus immediately.
     pub fn ped_waiting_for_bus(
         &mut self,
+        time: Duration,
         ped: PedestrianID,
         stop1: BusStopID,
         route_id: BusRouteID,
@@ -211,19 +300,49 @@ impl TransitSimState {
     ) -> bool {
         assert!(stop1 != stop2);
         let route = &self.routes[&route_id];
-        for bus in &route.buses {
-            if let BusState::AtStop(idx) = self.buses[bus].state {
-                if route.stops[idx].id == stop1 {
-                    self.buses
-                        .get_mut(bus)
-                        .unwrap()
-                        .passengers
-                        .push((ped, stop2));
-                    // TODO shift trips
-                    self.events.push(Event::PedEntersBus(ped, *bus));
-                    return true;
+        let boarding = route.buses.iter().cloned().find(|id| {
+            let bus = &self.buses[id];
+            match bus.state {
+                BusState::AtStop { stop: idx, .. } => {
+                    route.stops[idx].id == stop1
+                        && bus.passengers.len() < bus.capacity
+                        && will_reach(route, bus.run, idx, stop2)
                 }
+                BusState::DrivingToStop(_) => false,
             }
+        });
+        if let Some(id) = boarding {
+            let (stop_idx, arrived_at, boarded, alighted) = {
+                let bus = self.buses.get_mut(&id).unwrap();
+                bus.passengers.push((ped, stop2));
+                match bus.state {
+                    BusState::AtStop {
+                        stop,
+                        arrived_at,
+                        ref mut boarded,
+                        alighted,
+                        ..
+                    } => {
+                        *boarded += 1;
+                        (stop, arrived_at, *boarded, alighted)
+                    }
+                    BusState::DrivingToStop(_) => unreachable!(),
+                }
+            };
+            // Boarding takes time, even if the bus was about to leave.
+            let depart_at = self
+                .departure_time(id, stop_idx, arrived_at, boarded, alighted)
+                .max(time + TIME_TO_BOARD);
+            if let BusState::AtStop {
+                depart_at: ref mut old,
+                ..
+            } = self.buses.get_mut(&id).unwrap().state
+            {
+                *old = (*old).max(depart_at);
+            }
+            // TODO shift trips
+            self.events.push(Event::PedEntersBus(ped, id));
+            return true;
         }
 
         self.peds_waiting.push((ped, stop1, route_id, stop2));
@@ -233,4 +352,130 @@ impl TransitSimState {
     pub fn collect_events(&mut self) -> Vec<Event> {
         self.events.drain(..).collect()
     }
+
+    pub fn get_stop_visits(&self) -> &Vec<BusStopVisit> {
+        &self.stop_visits
+    }
+
+    // First come, first served, until the bus is full. Returns the number of people boarding.
+    fn board_waiting_peds(
+        &mut self,
+        time: Duration,
+        id: CarID,
+        stop_idx: StopIdx,
+        trips: &mut TripManager,
+        walking: &mut WalkingSimState,
+    ) -> usize {
+        let bus = self.buses.get_mut(&id).unwrap();
+        let route = &self.routes[&bus.route];
+        let stop = route.stops[stop_idx].id;
+        let mut boarded = 0;
+        let mut still_waiting = Vec::new();
+        for (ped, stop1, route_id, stop2) in self.peds_waiting.drain(..) {
+            if stop == stop1
+                && bus.route == route_id
+                && bus.passengers.len() < bus.capacity
+                && will_reach(route, bus.run, stop_idx, stop2)
+            {
+                bus.passengers.push((ped, stop2));
+                boarded += 1;
+                self.events.push(Event::PedEntersBus(ped, id));
+                trips.ped_boarded_bus(time, ped, walking);
+            } else {
+                still_waiting.push((ped, stop1, route_id, stop2));
+            }
+        }
+        self.peds_waiting = still_waiting;
+        boarded
+    }
+
+    // Enough time for everybody to get on and off, but never early from a timepoint.
+    fn departure_time(
+        &self,
+        id: CarID,
+        stop_idx: StopIdx,
+        arrived_at: Duration,
+        boarded: usize,
+        alighted: usize,
+    ) -> Duration {
+        let dwell = arrived_at
+            + TIME_TO_WAIT_AT_STOP
+            + (boarded as f64) * TIME_TO_BOARD
+            + (alighted as f64) * TIME_TO_ALIGHT;
+        match self.scheduled_departure(id, stop_idx) {
+            Some(scheduled) => dwell.max(scheduled),
+            None => dwell,
+        }
+    }
+
+    // Only for timepoints of buses following a schedule
+    fn scheduled_departure(&self, id: CarID, stop_idx: StopIdx) -> Option<Duration> {
+        let bus = &self.buses[&id];
+        let run = &self.routes[&bus.route].schedule[bus.run?];
+        if run.timepoints[stop_idx] {
+            Some(run.departures[stop_idx])
+        } else {
+            None
+        }
+    }
+
+    // Called when the bus leaves a stop. Anybody still waiting for it there got passed up.
+    fn record_visit(
+        &mut self,
+        id: CarID,
+        stop_idx: StopIdx,
+        arrived_at: Duration,
+        departed_at: Duration,
+        boarded: usize,
+        alighted: usize,
+    ) {
+        let bus = &self.buses[&id];
+        let route = &self.routes[&bus.route];
+        let stop = route.stops[stop_idx].id;
+        let left_behind = self
+            .peds_waiting
+            .iter()
+            .filter(|(_, stop1, route_id, stop2)| {
+                *stop1 == stop
+                    && *route_id == bus.route
+                    && will_reach(route, bus.run, stop_idx, *stop2)
+            })
+            .count();
+        if left_behind > 0 {
+            self.events
+                .push(Event::BusLeftPedsBehind(id, stop, left_behind));
+        }
+        self.stop_visits.push(BusStopVisit {
+            bus: id,
+            route: bus.route,
+            stop,
+            arrived_at,
+            departed_at,
+            delay: bus
+                .run
+                .map(|run| departed_at - route.schedule[run].departures[stop_idx]),
+            boarded,
+            alighted,
+            load: bus.passengers.len(),
+            left_behind,
+        });
+    }
+
+    pub fn tooltip_lines(&self, id: CarID) -> Option<Vec<String>> {
+        let bus = self.buses.get(&id)?;
+        Some(vec![format!(
+            "{}/{} passengers",
+            bus.passengers.len(),
+            bus.capacity
+        )])
+    }
+}
+
+// Scheduled buses end at the last stop of the route, so they won't reach stops behind them.
+// TODO Riders wanting to go "backwards" along a scheduled route will wait forever.
+fn will_reach(route: &Route, run: Option<usize>, current: StopIdx, stop: BusStopID) -> bool {
+    if run.is_none() {
+        return true;
+    }
+    route.stops[current + 1..].iter().any(|s| s.id == stop)
 }
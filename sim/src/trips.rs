@@ -1,10 +1,10 @@
 use crate::{
     AgentID, CarID, Command, CreateCar, CreatePedestrian, DrivingGoal, Event, ParkingSimState,
-    ParkingSpot, PedestrianID, Scheduler, SidewalkPOI, SidewalkSpot, TransitSimState, TripID,
-    Vehicle, WalkingSimState,
+    ParkingSpot, PedestrianID, Scheduler, ScoreSummary, SidewalkPOI, SidewalkSpot, TransitSimState,
+    TripID, Vehicle, VehicleType, WalkingSimState,
 };
 use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::{Duration, Speed};
+use geom::{Distance, Duration, Speed};
 use map_model::{BuildingID, BusRouteID, BusStopID, IntersectionID, Map, PathRequest};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::{BTreeMap, VecDeque};
@@ -44,7 +44,15 @@ impl TripManager {
             id,
             spawned_at,
             finished_at: None,
+            modes: legs.iter().filter_map(|leg| leg.mode()).collect(),
             legs: VecDeque::from(legs),
+            total_dist: Distance::ZERO,
+            intersection_delay: Duration::ZERO,
+            parking_time: Duration::ZERO,
+            cruising_time: Duration::ZERO,
+            bus_wait_time: Duration::ZERO,
+            waiting_for_bus_since: None,
+            parking_since: None,
         };
         if !trip.is_bus_trip() {
             self.unfinished_trips += 1;
@@ -63,11 +71,35 @@ impl TripManager {
         }
     }
 
+    // How far an agent went during its current leg. Call this before the leg ends.
+    pub fn agent_travelled(&mut self, agent: AgentID, dist: Distance) {
+        let trip = self.active_trip_mode[&agent];
+        self.trips[trip.0].total_dist += dist;
+    }
+
+    pub fn agent_waited_at_intersection(&mut self, agent: AgentID, delay: Duration) {
+        let trip = self.active_trip_mode[&agent];
+        self.trips[trip.0].intersection_delay += delay;
+    }
+
+    pub fn car_started_parking(&mut self, time: Duration, car: CarID) {
+        let trip = self.active_trip_mode[&AgentID::Car(car)];
+        self.trips[trip.0].parking_since = Some(time);
+    }
+
+    pub fn car_finished_unparking(&mut self, time: Duration, car: CarID) {
+        let trip = &mut self.trips[self.active_trip_mode[&AgentID::Car(car)].0];
+        if let Some(since) = trip.parking_since.take() {
+            trip.parking_time += time - since;
+        }
+    }
+
     pub fn car_reached_parking_spot(
         &mut self,
         time: Duration,
         car: CarID,
         spot: ParkingSpot,
+        time_spent_cruising: Duration,
         map: &Map,
         parking: &ParkingSimState,
         scheduler: &mut Scheduler,
@@ -79,6 +111,10 @@ impl TripManager {
             Some(TripLeg::Drive(vehicle, DrivingGoal::ParkNear(_))) => assert_eq!(car, vehicle.id),
             _ => unreachable!(),
         };
+        if let Some(since) = trip.parking_since.take() {
+            trip.parking_time += time - since;
+        }
+        trip.cruising_time += time_spent_cruising;
 
         if !trip.spawn_ped(
             time,
@@ -133,6 +169,8 @@ impl TripManager {
         };
 
         let router = drive_to.make_router(path, map, parked_car.vehicle.vehicle_type);
+        // The car might have to wait for room on the lane before it can start unparking.
+        trip.parking_since = Some(time);
         scheduler.push(
             time,
             Command::SpawnCar(
@@ -240,6 +278,7 @@ impl TripManager {
     // If true, the pedestrian boarded a bus immediately.
     pub fn ped_reached_bus_stop(
         &mut self,
+        time: Duration,
         ped: PedestrianID,
         stop: BusStopID,
         map: &Map,
@@ -256,10 +295,11 @@ impl TripManager {
         }
         match trip.legs[1] {
             TripLeg::RideBus(_, route, stop2) => {
-                if transit.ped_waiting_for_bus(ped, stop, route, stop2) {
+                if transit.ped_waiting_for_bus(time, ped, stop, route, stop2) {
                     trip.legs.pop_front();
                     true
                 } else {
+                    trip.waiting_for_bus_since = Some(time);
                     false
                 }
             }
@@ -267,10 +307,18 @@ impl TripManager {
         }
     }
 
-    pub fn ped_boarded_bus(&mut self, ped: PedestrianID, walking: &mut WalkingSimState) {
+    pub fn ped_boarded_bus(
+        &mut self,
+        time: Duration,
+        ped: PedestrianID,
+        walking: &mut WalkingSimState,
+    ) {
         // TODO Make sure canonical pt is the bus while the ped is riding it
         let trip = &mut self.trips[self.active_trip_mode[&AgentID::Pedestrian(ped)].0];
         trip.legs.pop_front();
+        if let Some(since) = trip.waiting_for_bus_since.take() {
+            trip.bus_wait_time += time - since;
+        }
         walking.ped_boarded_bus(ped);
     }
 
@@ -317,26 +365,35 @@ impl TripManager {
     }
 
     pub fn car_or_bike_reached_border(&mut self, time: Duration, car: CarID, i: IntersectionID) {
-        self.events.push(Event::CarOrBikeReachedBorder(car, i));
         let trip = &mut self.trips[self.active_trip_mode.remove(&AgentID::Car(car)).unwrap().0];
         match trip.legs.pop_front().unwrap() {
             TripLeg::Drive(_, DrivingGoal::Border(int, _)) => assert_eq!(i, int),
             _ => {
-                // TODO Should be unreachable
-                println!(
-                    "Aborting trip {}, because {} couldn't find parking and got stuck",
-                    trip.id, car
-                );
+                // The car couldn't find any parking nearby, so it vanished. Abort the trip.
+                self.events.push(Event::CarGaveUpOnParking(car));
                 self.unfinished_trips -= 1;
                 return;
             }
         };
+        self.events.push(Event::CarOrBikeReachedBorder(car, i));
         assert!(trip.legs.is_empty());
         assert!(!trip.finished_at.is_some());
         trip.finished_at = Some(time);
         self.unfinished_trips -= 1;
     }
 
+    // A scheduled bus reached the last stop of its run.
+    pub fn bus_finished(&mut self, time: Duration, car: CarID) {
+        let trip = &mut self.trips[self.active_trip_mode.remove(&AgentID::Car(car)).unwrap().0];
+        match trip.legs.pop_front().unwrap() {
+            TripLeg::ServeBusRoute(id, _) => assert_eq!(car, id),
+            x => panic!("{} finished a bus run, but had leg {:?}", car, x),
+        };
+        assert!(trip.legs.is_empty());
+        trip.finished_at = Some(time);
+        self.num_bus_trips -= 1;
+    }
+
     pub fn active_agents(&self) -> Vec<AgentID> {
         self.active_trip_mode.keys().cloned().collect()
     }
@@ -386,6 +443,65 @@ impl TripManager {
     pub fn collect_events(&mut self) -> Vec<Event> {
         self.events.drain(..).collect()
     }
+
+    pub fn get_score(&self, now: Duration) -> ScoreSummary {
+        let mut summary = ScoreSummary {
+            pending_walking_trips: 0,
+            total_walking_trips: 0,
+            total_walking_trip_time: Duration::ZERO,
+            pending_driving_trips: 0,
+            total_driving_trips: 0,
+            total_driving_trip_time: Duration::ZERO,
+            completion_time: None,
+        };
+        for trip in &self.trips {
+            let walking = !trip.modes.is_empty() && trip.modes.iter().all(|m| *m == TripMode::Walk);
+            let driving = trip.modes.contains(&TripMode::Drive);
+            if let Some(t) = trip.finished_at {
+                if walking {
+                    summary.total_walking_trips += 1;
+                    summary.total_walking_trip_time += t - trip.spawned_at;
+                } else if driving {
+                    summary.total_driving_trips += 1;
+                    summary.total_driving_trip_time += t - trip.spawned_at;
+                }
+            }
+        }
+        for trip in self.active_trip_mode.values() {
+            let modes = &self.trips[trip.0].modes;
+            if !modes.is_empty() && modes.iter().all(|m| *m == TripMode::Walk) {
+                summary.pending_walking_trips += 1;
+            } else if modes.contains(&TripMode::Drive) {
+                summary.pending_driving_trips += 1;
+            }
+        }
+        if self.is_done() {
+            summary.completion_time = Some(now);
+        }
+        summary
+    }
+
+    // Not including buses. In order of TripID, not finish time.
+    pub fn get_finished_trips(&self) -> Vec<FinishedTrip> {
+        self.trips
+            .iter()
+            .filter(|trip| !trip.is_bus_trip())
+            .filter_map(|trip| {
+                let finished_at = trip.finished_at?;
+                Some(FinishedTrip {
+                    id: trip.id,
+                    modes: trip.modes.clone(),
+                    spawned_at: trip.spawned_at,
+                    finished_at,
+                    total_dist: trip.total_dist,
+                    intersection_delay: trip.intersection_delay,
+                    parking_time: trip.parking_time,
+                    cruising_time: trip.cruising_time,
+                    bus_wait_time: trip.bus_wait_time,
+                })
+            })
+            .collect()
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
@@ -393,7 +509,18 @@ struct Trip {
     id: TripID,
     spawned_at: Duration,
     finished_at: Option<Duration>,
+    // Remembered from the original legs, which get consumed as the trip happens.
+    modes: Vec<TripMode>,
     legs: VecDeque<TripLeg>,
+
+    total_dist: Distance,
+    intersection_delay: Duration,
+    parking_time: Duration,
+    cruising_time: Duration,
+    bus_wait_time: Duration,
+    waiting_for_bus_since: Option<Duration>,
+    // When the current car started parking, or when the driver reached it to unpark
+    parking_since: Option<Duration>,
 }
 
 impl Trip {
@@ -467,3 +594,75 @@ pub enum TripLeg {
     RideBus(PedestrianID, BusRouteID, BusStopID),
     ServeBusRoute(CarID, BusRouteID),
 }
+
+impl TripLeg {
+    fn mode(&self) -> Option<TripMode> {
+        match self {
+            TripLeg::Walk(_, _, _) => Some(TripMode::Walk),
+            TripLeg::Drive(ref vehicle, _) => {
+                if vehicle.vehicle_type == VehicleType::Bike {
+                    Some(TripMode::Bike)
+                } else {
+                    Some(TripMode::Drive)
+                }
+            }
+            TripLeg::RideBus(_, _, _) => Some(TripMode::Transit),
+            TripLeg::ServeBusRoute(_, _) => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Clone, Copy, Debug)]
+pub enum TripMode {
+    Walk,
+    Bike,
+    Drive,
+    Transit,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct FinishedTrip {
+    pub id: TripID,
+    // One per leg
+    pub modes: Vec<TripMode>,
+    pub spawned_at: Duration,
+    pub finished_at: Duration,
+    // Actually travelled, including cars cruising for parking. Doesn't count getting in and out of
+    // buildings or vehicles.
+    pub total_dist: Distance,
+    pub intersection_delay: Duration,
+    // Unparking and parking, including waiting for room on the lane to unpark
+    pub parking_time: Duration,
+    // Driving around looking for a free spot, after the lane near the goal was full
+    pub cruising_time: Duration,
+    pub bus_wait_time: Duration,
+}
+
+impl FinishedTrip {
+    pub fn duration(&self) -> Duration {
+        self.finished_at - self.spawned_at
+    }
+
+    pub fn csv_header() -> &'static str {
+        "trip,modes,spawned_at_s,finished_at_s,total_dist_m,intersection_delay_s,parking_time_s,cruising_time_s,bus_wait_time_s"
+    }
+
+    pub fn to_csv_row(&self) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{},{}",
+            self.id.0,
+            self.modes
+                .iter()
+                .map(|m| format!("{:?}", m))
+                .collect::<Vec<String>>()
+                .join("+"),
+            self.spawned_at.inner_seconds(),
+            self.finished_at.inner_seconds(),
+            self.total_dist.inner_meters(),
+            self.intersection_delay.inner_seconds(),
+            self.parking_time.inner_seconds(),
+            self.cruising_time.inner_seconds(),
+            self.bus_wait_time.inner_seconds()
+        )
+    }
+}
@@ -336,6 +336,7 @@ impl Model {
                         pt(self.intersections[&r.i1].center),
                         pt(self.intersections[&r.i2].center),
                     ],
+                    elevations: Vec::new(),
                     osm_tags,
                     osm_way_id: id.0 as i64,
                     parking_lane_fwd: r.lanes.fwd.contains(&LaneType::Parking),
@@ -352,6 +353,7 @@ impl Model {
                     elevation: Distance::ZERO,
                     intersection_type: i.intersection_type,
                     label: i.label.clone(),
+                    approach_signs: BTreeMap::new(),
                 },
             );
         }
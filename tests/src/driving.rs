@@ -0,0 +1,326 @@
+use crate::runner::TestRunner;
+use abstutil::Timer;
+use geom::{Distance, Duration, LonLat, Speed};
+use map_model::raw_data::{self, StableIntersectionID, StableRoadID};
+use map_model::{IntersectionID, IntersectionType, LaneID, LaneType, Map, Position, Traversable};
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use sim::{
+    biking_speed_on_grade, walking_speed_on_grade, CarID, CarStatus, DrivingGoal, GetDrawAgents,
+    Scenario, Sim, SpeedProfile, TripSpec, CAR_ACCEL, CAR_DECEL, MAX_DECEL,
+};
+use std::collections::BTreeMap;
+
+pub fn run(t: &mut TestRunner) {
+    t.run_fast("slows_down_before_stop", |_| {
+        let dist = Distance::meters(50.0);
+        let profile = SpeedProfile::new(
+            dist,
+            Speed::meters_per_second(10.0),
+            Speed::meters_per_second(15.0),
+            CAR_ACCEL,
+            CAR_DECEL,
+            true,
+        );
+        let samples = sample(&profile);
+
+        let (_, end_dist, end_speed) = *samples.last().unwrap();
+        assert!((end_dist - dist).inner_meters().abs() < 0.01);
+        assert!(end_speed.inner_meters_per_second() < 0.01);
+        // Right before the stop, the car has to already be going slowly.
+        let before_stop = samples
+            .iter()
+            .find(|(_, d, _)| *d >= dist - Distance::meters(5.0))
+            .unwrap();
+        assert!(before_stop.2 < Speed::meters_per_second(6.0));
+        // And it never brakes harder than it's comfortable with.
+        check_decel(&samples, CAR_DECEL.inner_meters_per_second_squared());
+    });
+
+    t.run_fast("braking_is_bounded", |_| {
+        // Way too close to stop from this speed
+        let dist = Distance::meters(2.0);
+        let profile = SpeedProfile::new(
+            dist,
+            Speed::meters_per_second(20.0),
+            Speed::meters_per_second(20.0),
+            CAR_ACCEL,
+            CAR_DECEL,
+            true,
+        );
+        let samples = sample(&profile);
+
+        let (_, end_dist, end_speed) = *samples.last().unwrap();
+        assert!((end_dist - dist).inner_meters().abs() < 0.01);
+        // Still moving at the end, instead of braking impossibly hard.
+        assert!(end_speed > Speed::ZERO);
+        check_decel(&samples, MAX_DECEL.inner_meters_per_second_squared());
+    });
+
+    t.run_fast("speed_on_grades", |_| {
+        let speed = Speed::meters_per_second(4.0);
+        let walk = |grade: f64| walking_speed_on_grade(speed, grade).inner_meters_per_second();
+        let bike = |grade: f64| biking_speed_on_grade(speed, grade).inner_meters_per_second();
+        let close = |actual: f64, expected: f64| {
+            assert!(
+                (actual - expected).abs() < 0.001,
+                "Got {} m/s, expected {}",
+                actual,
+                expected
+            );
+        };
+
+        // Flat
+        close(walk(0.0), 4.0);
+        close(bike(0.0), 4.0);
+        // Uphill is slower
+        close(walk(0.1), 4.0 * (-0.35_f64).exp());
+        close(bike(0.1), 2.0);
+        assert!(walk(0.2) < walk(0.1));
+        assert!(bike(0.2) < bike(0.1));
+        // A gentle downhill is fastest for walking, but steeper than that slows people down again.
+        close(walk(-0.05), 4.0 * 0.175_f64.exp());
+        assert!(walk(-0.05) > walk(0.0));
+        assert!(walk(-0.2) < walk(-0.05));
+        // Bikes coast faster downhill, up to a point
+        close(bike(-0.05), 5.0);
+        close(bike(-0.2), 6.0);
+        // Anything steeper than 30% is noise
+        close(walk(0.9), walk(0.3));
+        close(walk(-0.9), walk(-0.3));
+        close(bike(0.9), 1.0);
+        close(bike(-0.9), bike(-0.3));
+    });
+
+    t.run_slow("change_lanes_around_parking_car", |_| {
+        let map = parking_road_map("4");
+        let lanes = eastbound_lanes(&map);
+        assert_eq!(lanes.len(), 2);
+        let (left, right) = (lanes[0], lanes[1]);
+        let bldg = map.all_buildings()[0].id;
+        let goal =
+            DrivingGoal::end_at_border(east_border(&map), vec![LaneType::Driving], &map).unwrap();
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let mut sim = Sim::new(&map, "change_lanes_around_parking_car".to_string(), None);
+        // The first car parks, and the second is stuck right behind it
+        let cars: Vec<CarID> = vec![
+            (Distance::meters(30.0), DrivingGoal::ParkNear(bldg)),
+            (Distance::meters(15.0), goal),
+        ]
+        .into_iter()
+        .map(|(dist, goal)| {
+            sim.schedule_trip(
+                Duration::ZERO,
+                TripSpec::CarAppearing {
+                    start_pos: Position::new(right, dist),
+                    goal,
+                    vehicle_spec: Scenario::rand_car(&mut rng),
+                    ped_speed: Scenario::rand_ped_speed(&mut rng),
+                },
+                &map,
+            )
+            .1
+            .unwrap()
+        })
+        .collect();
+        let (parker, stuck) = (cars[0], cars[1]);
+        sim.spawn_all_trips(&map, &mut Timer::throwaway(), false);
+
+        let mut moved_over = false;
+        while !sim.is_done() {
+            assert!(sim.time() < Duration::minutes(2), "Cars never finished");
+            sim.step(&map, Duration::seconds(0.5));
+            if let Some(car) = sim.get_draw_car(stuck, &map) {
+                if car.on == Traversable::Lane(left) {
+                    moved_over = true;
+                }
+            }
+        }
+        assert!(moved_over, "{} waited behind {} parking", stuck, parker);
+    });
+
+    t.run_slow("followers_wake_up_after_parking", |_| {
+        // Only one lane, so nobody can get around the car parking
+        let map = parking_road_map("2");
+        let lanes = eastbound_lanes(&map);
+        assert_eq!(lanes.len(), 1);
+        let lane = lanes[0];
+        let bldg = map.all_buildings()[0].id;
+        let goal =
+            DrivingGoal::end_at_border(east_border(&map), vec![LaneType::Driving], &map).unwrap();
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let mut sim = Sim::new(&map, "followers_wake_up_after_parking".to_string(), None);
+        // The first car parks, and the other two queue up behind it
+        let cars: Vec<CarID> = vec![
+            (Distance::meters(40.0), DrivingGoal::ParkNear(bldg)),
+            (Distance::meters(25.0), goal.clone()),
+            (Distance::meters(10.0), goal),
+        ]
+        .into_iter()
+        .map(|(dist, goal)| {
+            sim.schedule_trip(
+                Duration::ZERO,
+                TripSpec::CarAppearing {
+                    start_pos: Position::new(lane, dist),
+                    goal,
+                    vehicle_spec: Scenario::rand_car(&mut rng),
+                    ped_speed: Scenario::rand_ped_speed(&mut rng),
+                },
+                &map,
+            )
+            .1
+            .unwrap()
+        })
+        .collect();
+        let (parker, first, second) = (cars[0], cars[1], cars[2]);
+        sim.spawn_all_trips(&map, &mut Timer::throwaway(), false);
+
+        let mut waited = false;
+        let mut woke_up = false;
+        while !sim.is_done() {
+            assert!(sim.time() < Duration::minutes(2), "Cars never finished");
+            sim.step(&map, Duration::seconds(0.5));
+            let (first, second) = match (
+                sim.get_draw_car(first, &map),
+                sim.get_draw_car(second, &map),
+            ) {
+                (Some(a), Some(b)) => (a, b),
+                _ => continue,
+            };
+            if first.on != Traversable::Lane(lane) || second.on != Traversable::Lane(lane) {
+                continue;
+            }
+            if first.status == CarStatus::Stuck {
+                waited = true;
+            } else if waited && first.status == CarStatus::Moving {
+                // Once the car in front gets going again, the one behind it shouldn't stay
+                // asleep.
+                woke_up = true;
+                assert!(
+                    second.status == CarStatus::Moving,
+                    "{} is moving again after {} parked, but {} is still stuck at {}",
+                    first.id,
+                    parker,
+                    second.id,
+                    sim.time()
+                );
+            }
+        }
+        assert!(waited, "{} never waited behind {} parking", first, parker);
+        assert!(woke_up, "{} never got going again", first);
+    });
+}
+
+// A road with the given number of lanes, parking, and a building on the side, narrowing to one
+// lane each way past the middle intersection. Both ends are borders.
+fn parking_road_map(lanes: &str) -> Map {
+    let mut raw = raw_data::Map::blank();
+    for (id, lon, intersection_type) in vec![
+        (0, -122.3, IntersectionType::Border),
+        (1, -122.2974, IntersectionType::StopSign),
+        (2, -122.2961, IntersectionType::Border),
+    ] {
+        raw.intersections.insert(
+            StableIntersectionID(id),
+            raw_data::Intersection {
+                point: LonLat::new(lon, 47.65),
+                elevation: Distance::ZERO,
+                intersection_type,
+                label: None,
+                approach_signs: BTreeMap::new(),
+            },
+        );
+    }
+    for (way, i1, i2, tags) in vec![
+        (1, 0, 1, vec![("highway", "primary"), ("lanes", lanes)]),
+        (2, 1, 2, vec![("highway", "residential")]),
+    ] {
+        raw.roads.insert(
+            StableRoadID(raw.roads.len()),
+            raw_data::Road {
+                i1: StableIntersectionID(i1),
+                i2: StableIntersectionID(i2),
+                points: vec![
+                    raw.intersections[&StableIntersectionID(i1)].point,
+                    raw.intersections[&StableIntersectionID(i2)].point,
+                ],
+                elevations: Vec::new(),
+                osm_tags: tags
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                osm_way_id: way,
+                parking_lane_fwd: way == 1,
+                parking_lane_back: way == 1,
+            },
+        );
+    }
+    // South of the middle of the first road, so on the eastbound side
+    let (lon, lat) = (-122.2987, 47.6498);
+    raw.buildings.push(raw_data::Building {
+        points: vec![
+            LonLat::new(lon - 0.00007, lat + 0.000045),
+            LonLat::new(lon + 0.00007, lat + 0.000045),
+            LonLat::new(lon + 0.00007, lat - 0.000045),
+            LonLat::new(lon - 0.00007, lat - 0.000045),
+            LonLat::new(lon - 0.00007, lat + 0.000045),
+        ],
+        osm_tags: BTreeMap::new(),
+        osm_way_id: 3,
+        num_residential_units: None,
+    });
+    raw.boundary_polygon = vec![
+        LonLat::new(-122.301, 47.651),
+        LonLat::new(-122.296, 47.651),
+        LonLat::new(-122.296, 47.649),
+        LonLat::new(-122.301, 47.649),
+        LonLat::new(-122.301, 47.651),
+    ];
+    Map::create_from_raw(format!("{}_lanes", lanes), raw, &mut Timer::throwaway())
+}
+
+fn east_border(map: &Map) -> IntersectionID {
+    map.all_roads()
+        .iter()
+        .find(|r| r.osm_way_id == 2)
+        .unwrap()
+        .dst_i
+}
+
+// The driving lanes of the first road heading east, from the center outwards
+fn eastbound_lanes(map: &Map) -> Vec<LaneID> {
+    let road = map.all_roads().iter().find(|r| r.osm_way_id == 1).unwrap();
+    road.children_forwards
+        .iter()
+        .filter(|(_, lt)| *lt == LaneType::Driving)
+        .map(|(l, _)| *l)
+        .collect()
+}
+
+// (time, distance, speed)
+fn sample(profile: &SpeedProfile) -> Vec<(Duration, Distance, Speed)> {
+    let total = profile.total_time();
+    let steps = 100;
+    (0..=steps)
+        .map(|i| {
+            let dt = total * (i as f64) / (steps as f64);
+            (dt, profile.dist_after(dt), profile.speed_after(dt))
+        })
+        .collect()
+}
+
+fn check_decel(samples: &[(Duration, Distance, Speed)], max: f64) {
+    for pair in samples.windows(2) {
+        let dt = (pair[1].0 - pair[0].0).inner_seconds();
+        if dt == 0.0 {
+            continue;
+        }
+        let dv = pair[1].2.inner_meters_per_second() - pair[0].2.inner_meters_per_second();
+        assert!(
+            -dv / dt <= max + 0.01,
+            "Braking at {} m/s^2, more than {}",
+            -dv / dt,
+            max
+        );
+    }
+}
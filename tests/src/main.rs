@@ -1,7 +1,12 @@
+mod driving;
 mod geom;
 mod map_conversion;
+mod map_edits;
 mod parking;
+mod pathfind;
 mod runner;
+mod scheduler;
+mod signals;
 mod sim_completion;
 mod sim_determinism;
 mod transit;
@@ -12,9 +17,14 @@ use structopt::StructOpt;
 fn main() {
     let mut t = runner::TestRunner::new(runner::Flags::from_args());
 
+    driving::run(t.suite("driving"));
     geom::run(t.suite("geom"));
     map_conversion::run(t.suite("map_conversion"));
+    map_edits::run(t.suite("map_edits"));
     parking::run(t.suite("parking"));
+    pathfind::run(t.suite("pathfind"));
+    scheduler::run(t.suite("scheduler"));
+    signals::run(t.suite("signals"));
     sim_completion::run(t.suite("sim_completion"));
     sim_determinism::run(t.suite("sim_determinism"));
     transit::run(t.suite("transit"));
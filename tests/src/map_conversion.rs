@@ -1,18 +1,21 @@
 use crate::runner::TestRunner;
-use abstutil;
-use convert_osm;
-use map_model;
+use abstutil::{self, Timer};
+use convert_osm::{
+    self, Control, ControlNode, Matcher, MAX_DIST_BTWN_INTERSECTION_AND_CONTROL,
+    MAX_DIST_BTWN_INTERSECTION_AND_CROSSING,
+};
+use geom::{Distance, LonLat, Speed};
+use map_model::raw_data::{ApproachSign, StableIntersectionID, StableRoadID};
+use map_model::{
+    self, raw_data, IntersectionType, RoadSpec, SpeedLimitDefaults, TurnPriority, TurnType,
+};
+use std::collections::BTreeMap;
 
 pub fn run(t: &mut TestRunner) {
     t.run_slow("convert_osm_twice", |_| {
         let flags = convert_osm::Flags {
             osm: "../data/input/montlake.osm".to_string(),
-            elevation: "../data/input/N47W122.hgt".to_string(),
-            traffic_signals: "../data/input/traffic_signals.kml".to_string(),
-            residential_buildings: "../data/input/residential_buildings.kml".to_string(),
-            parking_shapes: "../data/shapes/blockface".to_string(),
-            gtfs: "../data/input/google_transit_2018_18_08".to_string(),
-            neighborhoods: "../data/input/neighborhoods.geojson".to_string(),
+            city: "../data/cities/seattle.json".to_string(),
             clip: "../data/polygons/montlake.poly".to_string(),
             output: "convert_osm_twice".to_string(),
             fast_dev: false,
@@ -49,6 +52,690 @@ pub fn run(t: &mut TestRunner) {
         }
     });
 
+    t.run_slow("original_ids_round_trip", |_| {
+        let map = map_model::Map::new(
+            "../data/raw_maps/montlake.abst",
+            &mut abstutil::Timer::throwaway(),
+        )
+        .unwrap();
+
+        for i in map.all_intersections() {
+            assert_eq!(
+                map.find_original_intersection(map.original_intersection(i.id)),
+                Some(i.id)
+            );
+        }
+        for l in map.all_lanes() {
+            assert_eq!(map.find_original_lane(map.original_lane(l.id)), Some(l.id));
+        }
+    });
+
+    t.run_fast("parse_control_nodes", |_| {
+        let parse = |pairs: Vec<(&str, &str)>| {
+            let tags: BTreeMap<String, String> = pairs
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            ControlNode::parse(LonLat::new(0.0, 0.0), &tags).map(|n| (n.control, n.forwards))
+        };
+
+        assert_eq!(
+            parse(vec![("highway", "traffic_signals")]),
+            Some((Control::TrafficSignal, None))
+        );
+        assert_eq!(
+            parse(vec![("highway", "stop"), ("direction", "forward")]),
+            Some((Control::Stop, Some(true)))
+        );
+        assert_eq!(
+            parse(vec![("highway", "stop"), ("stop", "all")]),
+            Some((Control::AllWayStop, None))
+        );
+        assert_eq!(
+            parse(vec![("highway", "give_way"), ("direction", "backward")]),
+            Some((Control::GiveWay, Some(false)))
+        );
+        assert_eq!(
+            parse(vec![
+                ("highway", "crossing"),
+                ("crossing", "traffic_signals")
+            ]),
+            Some((Control::PedestrianSignal, None))
+        );
+        assert_eq!(
+            parse(vec![("crossing", "traffic_signals")]),
+            Some((Control::PedestrianSignal, None))
+        );
+        assert_eq!(parse(vec![("highway", "crossing")]), None);
+        assert_eq!(
+            parse(vec![("highway", "crossing"), ("crossing", "zebra")]),
+            None
+        );
+    });
+
+    t.run_fast("match_control_nodes", |_| {
+        let raw = one_road_map();
+        let west = raw.intersections[&StableIntersectionID(0)].point;
+        let east = raw.intersections[&StableIntersectionID(1)].point;
+        let matcher = Matcher::new(&raw, &raw.get_gps_bounds());
+        let len = west.gps_dist_meters(east).inner_meters();
+        let find = |meters_from_west: f64, forwards: Option<bool>, max_dist: Distance| {
+            let pt = LonLat::new(
+                west.longitude + (east.longitude - west.longitude) * meters_from_west / len,
+                47.65,
+            );
+            matcher
+                .find(&raw, pt, forwards, Distance::meters(1.0), max_dist)
+                .map(|(i, _)| i)
+        };
+        let control = MAX_DIST_BTWN_INTERSECTION_AND_CONTROL;
+        let west_i = Some(StableIntersectionID(0));
+        let east_i = Some(StableIntersectionID(1));
+
+        // Without a direction, the closer end
+        assert_eq!(find(20.0, None, control), west_i);
+        assert_eq!(find(len - 20.0, None, control), east_i);
+        // The direction picks the end, even if it's the far one
+        assert_eq!(find(20.0, Some(false), control), west_i);
+        assert_eq!(find(len - 20.0, Some(true), control), east_i);
+        assert_eq!(find(20.0, Some(true), control), None);
+        assert_eq!(find(len - 20.0, Some(false), control), None);
+        // The cutoff
+        assert_eq!(find(25.0, None, control), west_i);
+        assert_eq!(find(35.0, None, control), None);
+        assert_eq!(find(len - 35.0, Some(true), control), None);
+        // Crossings have to be closer
+        let crossing = MAX_DIST_BTWN_INTERSECTION_AND_CROSSING;
+        assert_eq!(find(10.0, None, crossing), west_i);
+        assert_eq!(find(25.0, None, crossing), None);
+    });
+
+    t.run_fast("load_maps_without_parking_lots", |_| {
+        let dir = std::env::temp_dir().join("load_maps_without_parking_lots");
+        std::fs::create_dir_all(&dir).unwrap();
+        // Saved before parking lots existed
+        let without_lots = |json: String| -> String {
+            assert!(json.contains("\n  \"parking_lots\": [],\n"));
+            json.replace("\n  \"parking_lots\": [],\n", "\n")
+        };
+
+        let raw = one_road_map();
+        let raw_path = dir.join("raw.json");
+        std::fs::write(&raw_path, without_lots(abstutil::to_json(&raw))).unwrap();
+        let raw_loaded: raw_data::Map = abstutil::read_json(raw_path.to_str().unwrap()).unwrap();
+        assert_eq!(raw_loaded, raw);
+
+        let map =
+            map_model::Map::create_from_raw("one_road".to_string(), raw, &mut Timer::throwaway());
+        let map_path = dir.join("map.json");
+        std::fs::write(&map_path, without_lots(abstutil::to_json(&map))).unwrap();
+        let map_loaded: map_model::Map = abstutil::read_json(map_path.to_str().unwrap()).unwrap();
+        assert!(map_loaded.all_parking_lots().is_empty());
+        assert_eq!(map_loaded.all_roads().len(), map.all_roads().len());
+        assert_eq!(map_loaded.all_lanes().len(), map.all_lanes().len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    });
+
+    t.run_fast("stop_signs_from_osm", |_| {
+        // A 4-way intersection. The road from the west has a stop sign, the road from the east a
+        // give way sign, and north-south has nothing.
+        let center = LonLat::new(-122.3, 47.65);
+        let mut raw = raw_data::Map::blank();
+        for (id, pt) in vec![
+            (0, center),
+            (1, LonLat::new(-122.3013, 47.65)),
+            (2, LonLat::new(-122.2987, 47.65)),
+            (3, LonLat::new(-122.3, 47.6509)),
+            (4, LonLat::new(-122.3, 47.6491)),
+        ] {
+            raw.intersections.insert(
+                StableIntersectionID(id),
+                raw_data::Intersection {
+                    point: pt,
+                    elevation: Distance::ZERO,
+                    intersection_type: IntersectionType::StopSign,
+                    label: None,
+                    approach_signs: BTreeMap::new(),
+                },
+            );
+        }
+        for (way, i1, i2) in vec![(1, 1, 0), (2, 0, 2), (3, 3, 0), (4, 0, 4)] {
+            let mut osm_tags = BTreeMap::new();
+            osm_tags.insert("highway".to_string(), "residential".to_string());
+            raw.roads.insert(
+                StableRoadID(raw.roads.len()),
+                raw_data::Road {
+                    i1: StableIntersectionID(i1),
+                    i2: StableIntersectionID(i2),
+                    points: vec![
+                        raw.intersections[&StableIntersectionID(i1)].point,
+                        raw.intersections[&StableIntersectionID(i2)].point,
+                    ],
+                    elevations: Vec::new(),
+                    osm_tags,
+                    osm_way_id: way,
+                    parking_lane_fwd: false,
+                    parking_lane_back: false,
+                },
+            );
+        }
+        {
+            let signs = &mut raw
+                .intersections
+                .get_mut(&StableIntersectionID(0))
+                .unwrap()
+                .approach_signs;
+            signs.insert(StableRoadID(0), ApproachSign::Stop);
+            signs.insert(StableRoadID(1), ApproachSign::GiveWay);
+        }
+        raw.boundary_polygon = vec![
+            LonLat::new(-122.302, 47.652),
+            LonLat::new(-122.298, 47.652),
+            LonLat::new(-122.298, 47.648),
+            LonLat::new(-122.302, 47.648),
+            LonLat::new(-122.302, 47.652),
+        ];
+        let map =
+            map_model::Map::create_from_raw("four_way".to_string(), raw, &mut Timer::throwaway());
+
+        let i = map
+            .all_intersections()
+            .iter()
+            .find(|i| i.roads.len() == 4)
+            .unwrap();
+        let ss = map.get_stop_sign(i.id);
+        let mut checked_ways = Vec::new();
+        for t in &i.turns {
+            let turn = map.get_t(*t);
+            let priority = ss.get_priority(*t);
+            if turn.between_sidewalks() {
+                assert_ne!(priority, TurnPriority::Stop, "{} has to stop", t);
+                continue;
+            }
+            let way = map.get_r(map.get_l(t.src).parent).osm_way_id;
+            match way {
+                1 => assert_eq!(priority, TurnPriority::Stop, "{}", t),
+                2 => assert_eq!(priority, TurnPriority::Yield, "{}", t),
+                _ => assert_ne!(priority, TurnPriority::Stop, "{}", t),
+            }
+            checked_ways.push(way);
+        }
+        checked_ways.sort();
+        checked_ways.dedup();
+        assert_eq!(checked_ways, vec![1, 2, 3, 4]);
+    });
+
+    t.run_slow("crosswalks_ignore_osm_stop_signs", |_| {
+        let map = map_model::Map::new(
+            "../data/raw_maps/montlake.abst",
+            &mut abstutil::Timer::throwaway(),
+        )
+        .unwrap();
+
+        for i in map.all_intersections() {
+            if i.approach_signs.is_empty() {
+                continue;
+            }
+            let ss = match map.maybe_get_stop_sign(i.id) {
+                Some(ss) => ss,
+                None => {
+                    continue;
+                }
+            };
+            for t in &i.turns {
+                if map.get_t(*t).turn_type == TurnType::Crosswalk {
+                    assert_ne!(ss.get_priority(*t), TurnPriority::Stop, "{} has to stop", t);
+                }
+            }
+        }
+    });
+
+    t.run_fast("lane_tags", |_| {
+        let tags = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+
+        // (tags, expected lanes as a RoadSpec string)
+        for (pairs, expected) in vec![
+            (vec![("highway", "residential")], "ds/ds"),
+            (vec![("highway", "primary"), ("lanes", "4")], "dds/dds"),
+            // Without lanes:forward or lanes:backward, the odd lane goes forwards.
+            (vec![("highway", "primary"), ("lanes", "3")], "dds/ds"),
+            (
+                vec![
+                    ("highway", "primary"),
+                    ("lanes", "3"),
+                    ("lanes:forward", "2"),
+                ],
+                "dds/ds",
+            ),
+            (
+                vec![
+                    ("highway", "primary"),
+                    ("lanes", "3"),
+                    ("lanes:backward", "2"),
+                ],
+                "ds/dds",
+            ),
+            (
+                vec![
+                    ("highway", "primary"),
+                    ("lanes", "5"),
+                    ("lanes:both_ways", "1"),
+                    ("lanes:forward", "2"),
+                    ("lanes:backward", "2"),
+                ],
+                "dds/dds",
+            ),
+            (
+                vec![
+                    ("highway", "primary"),
+                    ("lanes", "3"),
+                    ("lanes:both_ways", "1"),
+                ],
+                "ds/ds",
+            ),
+            (
+                vec![("highway", "primary"), ("oneway", "yes"), ("lanes", "2")],
+                "dds/",
+            ),
+            (
+                vec![("highway", "residential"), ("oneway", "-1"), ("lanes", "2")],
+                "s/dds",
+            ),
+            (
+                vec![
+                    ("highway", "primary"),
+                    ("lanes", "4"),
+                    ("bus:lanes:forward", "|designated"),
+                ],
+                "dus/dds",
+            ),
+        ] {
+            let (fwd, back) = map_model::get_lane_types(&tags(&pairs), false, false);
+            assert_eq!(
+                RoadSpec { fwd, back }.to_string(),
+                expected,
+                "{:?} has the wrong lanes",
+                pairs
+            );
+        }
+
+        // (tags, forwards, expected arrows per lane)
+        for (pairs, fwd, expected) in vec![
+            (
+                vec![("oneway", "yes"), ("turn:lanes", "left|through;right")],
+                true,
+                vec![
+                    Some(vec![TurnType::Left]),
+                    Some(vec![TurnType::Straight, TurnType::Right]),
+                ],
+            ),
+            (
+                vec![("oneway", "yes"), ("turn:lanes", "left|through;right")],
+                false,
+                Vec::new(),
+            ),
+            (
+                vec![("turn:lanes:forward", "left|none")],
+                true,
+                vec![Some(vec![TurnType::Left]), None],
+            ),
+            (
+                vec![("turn:lanes:backward", "through")],
+                false,
+                vec![Some(vec![TurnType::Straight])],
+            ),
+            (
+                vec![("oneway", "-1"), ("turn:lanes", "slight_right")],
+                false,
+                vec![Some(vec![TurnType::Right, TurnType::Straight])],
+            ),
+            (
+                vec![("oneway", "-1"), ("turn:lanes", "slight_right")],
+                true,
+                Vec::new(),
+            ),
+            (vec![("highway", "residential")], true, Vec::new()),
+        ] {
+            assert_eq!(
+                map_model::get_turn_arrows(&tags(&pairs), fwd),
+                expected,
+                "{:?} going {} has the wrong turn arrows",
+                pairs,
+                if fwd { "forwards" } else { "backwards" }
+            );
+        }
+    });
+
+    t.run_fast("speed_limit_tags", |_| {
+        let tags = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let defaults = SpeedLimitDefaults::default();
+        let kmh = Speed::km_per_hour;
+        let mph = Speed::miles_per_hour;
+
+        // (tags, expected forwards and backwards limits)
+        for (pairs, fwd, back) in vec![
+            // Bare numbers are km/h
+            (vec![("maxspeed", "50")], kmh(50.0), kmh(50.0)),
+            (vec![("maxspeed", "50 km/h")], kmh(50.0), kmh(50.0)),
+            (vec![("maxspeed", "25 mph")], mph(25.0), mph(25.0)),
+            (vec![("maxspeed", "25mph")], mph(25.0), mph(25.0)),
+            // Only the first of a list
+            (vec![("maxspeed", "30 mph;20 mph")], mph(30.0), mph(30.0)),
+            (
+                vec![("maxspeed", "60"), ("maxspeed:forward", "40")],
+                kmh(40.0),
+                kmh(60.0),
+            ),
+            (
+                vec![("highway", "residential"), ("maxspeed:backward", "35 mph")],
+                mph(20.0),
+                mph(35.0),
+            ),
+            (vec![("maxspeed", "US:urban")], mph(25.0), mph(25.0)),
+            (vec![("maxspeed", "GB:nsl_single")], mph(60.0), mph(60.0)),
+            (vec![("maxspeed", "FR:urban")], kmh(50.0), kmh(50.0)),
+            (vec![("maxspeed", "DE:zone30")], kmh(30.0), kmh(30.0)),
+            (vec![("maxspeed", "DE:zone:30")], kmh(30.0), kmh(30.0)),
+            (
+                vec![("highway", "primary"), ("maxspeed:type", "DE:rural")],
+                kmh(100.0),
+                kmh(100.0),
+            ),
+            (
+                vec![("highway", "primary"), ("source:maxspeed", "US:rural")],
+                mph(55.0),
+                mph(55.0),
+            ),
+            // Useless values fall back to the default for the highway type
+            (
+                vec![("highway", "motorway"), ("maxspeed", "none")],
+                mph(60.0),
+                mph(60.0),
+            ),
+            (
+                vec![("highway", "motorway"), ("maxspeed", "DE:motorway")],
+                mph(60.0),
+                mph(60.0),
+            ),
+            (
+                vec![("highway", "residential"), ("maxspeed", "signals")],
+                mph(20.0),
+                mph(20.0),
+            ),
+            (
+                vec![("highway", "primary"), ("maxspeed", "0")],
+                mph(40.0),
+                mph(40.0),
+            ),
+            (vec![("highway", "primary_link")], mph(40.0), mph(40.0)),
+            (vec![("highway", "motorway_link")], mph(60.0), mph(60.0)),
+            (vec![("highway", "footway")], mph(20.0), mph(20.0)),
+            (Vec::new(), mph(20.0), mph(20.0)),
+        ] {
+            assert_eq!(
+                map_model::get_speed_limits(&tags(&pairs), &defaults),
+                (fwd, back),
+                "{:?} has the wrong speed limits",
+                pairs
+            );
+        }
+
+        // A _link type listed separately wins over the main type
+        let mut defaults = SpeedLimitDefaults::default();
+        defaults
+            .per_highway
+            .insert("motorway_link".to_string(), mph(35.0));
+        assert_eq!(
+            map_model::get_speed_limits(&tags(&[("highway", "motorway_link")]), &defaults),
+            (mph(35.0), mph(35.0))
+        );
+    });
+
+    t.run_fast("lane_grades", |_| {
+        // A road climbing 10m from west to east, then a flat one continuing east
+        let mut raw = raw_data::Map::blank();
+        for (id, lon, elevation) in
+            vec![(0, -122.3, 0.0), (1, -122.2987, 10.0), (2, -122.2974, 0.0)]
+        {
+            raw.intersections.insert(
+                StableIntersectionID(id),
+                raw_data::Intersection {
+                    point: LonLat::new(lon, 47.65),
+                    elevation: Distance::meters(elevation),
+                    intersection_type: IntersectionType::StopSign,
+                    label: None,
+                    approach_signs: BTreeMap::new(),
+                },
+            );
+        }
+        for (way, i1, i2, elevations) in vec![
+            (1, 0, 1, vec![Distance::ZERO, Distance::meters(10.0)]),
+            (2, 1, 2, Vec::new()),
+        ] {
+            let mut osm_tags = BTreeMap::new();
+            osm_tags.insert("highway".to_string(), "residential".to_string());
+            raw.roads.insert(
+                StableRoadID(raw.roads.len()),
+                raw_data::Road {
+                    i1: StableIntersectionID(i1),
+                    i2: StableIntersectionID(i2),
+                    points: vec![
+                        raw.intersections[&StableIntersectionID(i1)].point,
+                        raw.intersections[&StableIntersectionID(i2)].point,
+                    ],
+                    elevations,
+                    osm_tags,
+                    osm_way_id: way,
+                    parking_lane_fwd: false,
+                    parking_lane_back: false,
+                },
+            );
+        }
+        raw.boundary_polygon = vec![
+            LonLat::new(-122.301, 47.651),
+            LonLat::new(-122.297, 47.651),
+            LonLat::new(-122.297, 47.649),
+            LonLat::new(-122.301, 47.649),
+            LonLat::new(-122.301, 47.651),
+        ];
+        let map =
+            map_model::Map::create_from_raw("slope".to_string(), raw, &mut Timer::throwaway());
+
+        let (mut uphill, mut downhill) = (0, 0);
+        for l in map.all_lanes() {
+            let len = l.length();
+            let grade = l.grade(Distance::ZERO, len);
+            // Either way along a lane works
+            assert!((l.grade(len, Distance::ZERO) + grade).abs() < 0.0001);
+            assert_eq!(l.grade(len / 2.0, len / 2.0), 0.0);
+            if map.get_r(l.parent).osm_way_id == 2 {
+                assert_eq!(grade, 0.0, "{} should be flat", l.id);
+                continue;
+            }
+            let expected = 10.0 / len.inner_meters();
+            if grade > 0.0 {
+                uphill += 1;
+                assert!(
+                    (grade - expected).abs() < 0.0001,
+                    "{} has grade {}",
+                    l.id,
+                    grade
+                );
+            } else {
+                downhill += 1;
+                assert!(
+                    (grade + expected).abs() < 0.0001,
+                    "{} has grade {}",
+                    l.id,
+                    grade
+                );
+            }
+            // Halfway up
+            assert!(
+                (l.elevation_at(len / 2.0) - Distance::meters(5.0))
+                    .inner_meters()
+                    .abs()
+                    < 0.01
+            );
+        }
+        // Each direction has a driving lane and a sidewalk
+        assert_eq!(uphill, 2);
+        assert_eq!(downhill, 2);
+    });
+
+    t.run_fast("elevation_tiles", |_| {
+        let dir = std::env::temp_dir().join("elevation_tiles_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        // A 3-arcsecond tile sloping up to the east, 1m per sample. The northwest corner has no
+        // data.
+        let mut bytes = Vec::new();
+        for _row in 0..1201 {
+            for col in 0..1201 {
+                let value: i16 = if bytes.is_empty() { -32768 } else { col };
+                bytes.extend_from_slice(&value.to_be_bytes());
+            }
+        }
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        std::fs::write(path("N47W122.hgt"), &bytes).unwrap();
+        std::fs::write(path("s01e002.hgt"), &bytes).unwrap();
+        std::fs::write(path("N47W122x.hgt"), &bytes).unwrap();
+        std::fs::write(path("N48W122.hgt"), &bytes[0..100]).unwrap();
+
+        let elevation =
+            convert_osm::Elevation::new(&[path("N47W122.hgt"), path("s01e002.hgt")]).unwrap();
+        let get = |lon: f64, lat: f64| {
+            elevation
+                .get(LonLat::new(lon, lat))
+                .map(|d| d.inner_meters())
+        };
+        let close = |actual: Option<f64>, expected: f64| {
+            let actual = actual.unwrap();
+            assert!(
+                (actual - expected).abs() < 0.001,
+                "Got {}, expected {}",
+                actual,
+                expected
+            );
+        };
+        // Named after the southwest corner, and either case works
+        close(get(-122.0, 47.0), 0.0);
+        close(get(-121.5, 47.25), 600.0);
+        close(get(-121.0, 48.0), 1200.0);
+        close(get(2.5, -0.5), 600.0);
+        assert_eq!(get(-122.5, 47.5), None);
+        assert_eq!(get(-121.5, 48.5), None);
+        // Halfway between samples, interpolate
+        close(get(-122.0 + 10.5 / 1200.0, 47.5), 10.5);
+        // The void is skipped, leaving samples worth 0, 1, and 1 meters.
+        close(get(-122.0 + 0.5 / 1200.0, 48.0 - 0.5 / 1200.0), 2.0 / 3.0);
+
+        let mut bounds = geom::GPSBounds::new();
+        bounds.update(LonLat::new(-121.9, 47.1));
+        bounds.update(LonLat::new(-121.1, 47.9));
+        assert!(elevation.check_coverage(&bounds).is_ok());
+        bounds.update(LonLat::new(-121.1, 48.1));
+        assert!(elevation
+            .check_coverage(&bounds)
+            .unwrap_err()
+            .contains("N48W122.hgt"));
+
+        // Bad names and sizes
+        assert!(convert_osm::Elevation::new(&[path("N47W122x.hgt")]).is_err());
+        assert!(convert_osm::Elevation::new(&[path("N48W122.hgt")]).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    });
+
+    t.run_fast("parse_turn_restrictions", |_| {
+        let path = std::env::temp_dir().join("parse_turn_restrictions_test.osm");
+        std::fs::write(
+            &path,
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="47.65" lon="-122.3"/>
+  <node id="2" lat="47.65" lon="-122.299"/>
+  <node id="3" lat="47.649" lon="-122.299"/>
+  <node id="4" lat="47.649" lon="-122.3"/>
+  <way id="10"><nd ref="1"/><nd ref="2"/><tag k="highway" v="residential"/></way>
+  <way id="11"><nd ref="2"/><nd ref="3"/><tag k="highway" v="residential"/></way>
+  <way id="12"><nd ref="3"/><nd ref="4"/><tag k="highway" v="residential"/></way>
+  <relation id="100">
+    <member type="way" ref="10" role="from"/>
+    <member type="node" ref="2" role="via"/>
+    <member type="way" ref="11" role="to"/>
+    <tag k="type" v="restriction"/>
+    <tag k="restriction" v="no_right_turn"/>
+  </relation>
+  <relation id="101">
+    <member type="way" ref="10" role="from"/>
+    <member type="way" ref="11" role="via"/>
+    <member type="way" ref="12" role="to"/>
+    <tag k="type" v="restriction"/>
+    <tag k="restriction" v="only_right_turn"/>
+  </relation>
+  <relation id="102">
+    <member type="way" ref="10" role="from"/>
+    <member type="node" ref="2" role="via"/>
+    <tag k="type" v="restriction"/>
+    <tag k="restriction" v="no_left_turn"/>
+  </relation>
+  <relation id="103">
+    <member type="way" ref="10" role="from"/>
+    <member type="node" ref="99" role="via"/>
+    <member type="way" ref="11" role="to"/>
+    <tag k="type" v="restriction"/>
+    <tag k="restriction" v="no_left_turn"/>
+  </relation>
+  <relation id="104">
+    <member type="way" ref="10" role="from"/>
+    <member type="node" ref="2" role="via"/>
+    <member type="way" ref="11" role="to"/>
+    <tag k="type" v="restriction"/>
+    <tag k="restriction" v="give_way"/>
+  </relation>
+</osm>"#,
+        )
+        .unwrap();
+        let (_, _, _, _, mut turn_restrictions, _) = convert_osm::osm_to_raw_roads(
+            path.to_str().unwrap(),
+            &mut abstutil::Timer::throwaway(),
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        // Missing members, a via node that isn't in the file, and unknown kinds are skipped.
+        turn_restrictions.sort_by_key(|tr| tr.osm_relation_id);
+        assert_eq!(
+            turn_restrictions,
+            vec![
+                raw_data::TurnRestriction {
+                    osm_relation_id: 100,
+                    restriction: raw_data::RestrictionType::BanTurns,
+                    from_way: 10,
+                    via: raw_data::RestrictionVia::Node(LonLat::new(-122.299, 47.65)),
+                    to_way: 11,
+                },
+                raw_data::TurnRestriction {
+                    osm_relation_id: 101,
+                    restriction: raw_data::RestrictionType::OnlyAllowTurns,
+                    from_way: 10,
+                    via: raw_data::RestrictionVia::Way(11),
+                    to_way: 12,
+                },
+            ]
+        );
+    });
+
     t.run_slow("bigger_map_loads", |_| {
         map_model::Map::new(
             "../data/raw_maps/23rd.abst",
@@ -64,4 +751,141 @@ pub fn run(t: &mut TestRunner) {
         )
         .expect("huge_seattle broke");
     });
+
+    t.run_slow("pbf_matches_xml", |_| {
+        let xml = summarize(convert_osm::osm_to_raw_roads(
+            "../data/input/pbf_test.osm",
+            &mut abstutil::Timer::throwaway(),
+        ));
+        let pbf = summarize(convert_osm::osm_to_raw_roads(
+            "../data/input/pbf_test.osm.pbf",
+            &mut abstutil::Timer::throwaway(),
+        ));
+        assert!(!xml[0].is_empty(), "No roads in the fixture");
+        for (idx, what) in vec![
+            "roads",
+            "buildings",
+            "parking lots",
+            "areas",
+            "turn restrictions",
+            "traffic control nodes",
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            assert_eq!(xml[idx], pbf[idx], "The XML and PBF {} differ", what);
+        }
+    });
+}
+
+// The readers visit things in different orders, and PBF coordinates are fixed-point, so turn
+// everything into sorted strings with rounded coordinates.
+fn summarize(
+    (roads, buildings, parking_lots, areas, turn_restrictions, control_nodes): (
+        Vec<raw_data::Road>,
+        Vec<raw_data::Building>,
+        Vec<raw_data::ParkingLot>,
+        Vec<raw_data::Area>,
+        Vec<raw_data::TurnRestriction>,
+        Vec<ControlNode>,
+    ),
+) -> Vec<Vec<String>> {
+    let pt = |pt: &LonLat| {
+        format!(
+            "({}, {})",
+            (pt.longitude * 1e7).round(),
+            (pt.latitude * 1e7).round()
+        )
+    };
+    let pts = |pts: &Vec<LonLat>| pts.iter().map(pt).collect::<Vec<_>>().join(" ");
+
+    let mut result = vec![
+        roads
+            .iter()
+            .map(|r| format!("{} {:?} {}", r.osm_way_id, r.osm_tags, pts(&r.points)))
+            .collect::<Vec<_>>(),
+        buildings
+            .iter()
+            .map(|b| format!("{} {:?} {}", b.osm_way_id, b.osm_tags, pts(&b.points)))
+            .collect(),
+        parking_lots
+            .iter()
+            .map(|p| format!("{} {:?} {}", p.osm_way_id, p.osm_tags, pts(&p.points)))
+            .collect(),
+        areas
+            .iter()
+            .map(|a| {
+                format!(
+                    "{} {:?} {:?} {}",
+                    a.osm_id,
+                    a.area_type,
+                    a.osm_tags,
+                    pts(&a.points)
+                )
+            })
+            .collect(),
+        turn_restrictions
+            .iter()
+            .map(|tr| {
+                let via = match tr.via {
+                    raw_data::RestrictionVia::Node(ref node) => pt(node),
+                    raw_data::RestrictionVia::Way(id) => id.to_string(),
+                };
+                format!(
+                    "{} {:?} {} {} {}",
+                    tr.osm_relation_id, tr.restriction, tr.from_way, via, tr.to_way
+                )
+            })
+            .collect(),
+        control_nodes
+            .iter()
+            .map(|n| format!("{} {:?} {:?}", pt(&n.pt), n.control, n.forwards))
+            .collect(),
+    ];
+    for list in result.iter_mut() {
+        list.sort();
+    }
+    result
+}
+
+// One road, about 195m long, from StableIntersectionID(0) in the west to 1 in the east
+fn one_road_map() -> raw_data::Map {
+    let mut raw = raw_data::Map::blank();
+    let west = LonLat::new(-122.3, 47.65);
+    let east = LonLat::new(-122.2974, 47.65);
+    for (id, pt) in vec![(0, west), (1, east)] {
+        raw.intersections.insert(
+            StableIntersectionID(id),
+            raw_data::Intersection {
+                point: pt,
+                elevation: Distance::ZERO,
+                intersection_type: IntersectionType::StopSign,
+                label: None,
+                approach_signs: BTreeMap::new(),
+            },
+        );
+    }
+    let mut osm_tags = BTreeMap::new();
+    osm_tags.insert("highway".to_string(), "residential".to_string());
+    raw.roads.insert(
+        StableRoadID(0),
+        raw_data::Road {
+            i1: StableIntersectionID(0),
+            i2: StableIntersectionID(1),
+            points: vec![west, east],
+            elevations: Vec::new(),
+            osm_tags,
+            osm_way_id: 1,
+            parking_lane_fwd: false,
+            parking_lane_back: false,
+        },
+    );
+    raw.boundary_polygon = vec![
+        LonLat::new(-122.301, 47.651),
+        LonLat::new(-122.297, 47.651),
+        LonLat::new(-122.297, 47.649),
+        LonLat::new(-122.301, 47.649),
+        LonLat::new(-122.301, 47.651),
+    ];
+    raw
 }
@@ -0,0 +1,387 @@
+use crate::runner::TestRunner;
+use abstutil::Timer;
+use geom::{Distance, Duration, Speed};
+use map_model::raw_data::RestrictionType;
+use map_model::{
+    Cycle, EditCmd, IntersectionType, LaneType, Map, MapEdits, PathRequest, PathStep, Position,
+    TurnID, TurnPriority, LANE_THICKNESS,
+};
+
+pub fn run(t: &mut TestRunner) {
+    t.run_slow("reversing_lanes_moves_center_line", |_| {
+        let mut map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let (r, l) = map
+            .all_roads()
+            .iter()
+            .find_map(|r| {
+                let (l, _) = *r.children_forwards.get(0)?;
+                if map.can_reverse_lane(l) {
+                    Some((r.id, l))
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+        let orig_center = map.get_r(r).center_pts.clone();
+        let orig_lane = map.get_l(l).lane_center_pts.clone();
+
+        let mut edits = map.get_edits().clone();
+        edits.reversed_lanes.insert(l);
+        map.apply_edits(edits, &mut Timer::throwaway());
+
+        // The lane stays where it was, but now it's the innermost lane going the other way, so
+        // the center line has to be on its other side.
+        let road = map.get_r(r);
+        assert_eq!(road.children_backwards[0].0, l);
+        assert_eq!(
+            map.get_l(l).lane_center_pts.points(),
+            orig_lane.reversed().points(),
+            "{} moved when it was reversed",
+            l
+        );
+        let expected = road
+            .center_pts
+            .shift_left(LANE_THICKNESS / 2.0)
+            .unwrap()
+            .first_pt();
+        let actual = map.get_l(l).lane_center_pts.last_pt();
+        assert!(
+            actual.dist_to(expected) < Distance::meters(0.01),
+            "{} isn't next to the center line of {}: {} vs {}",
+            l,
+            r,
+            actual,
+            expected
+        );
+
+        let mut edits = map.get_edits().clone();
+        edits.reversed_lanes.clear();
+        map.apply_edits(edits, &mut Timer::throwaway());
+        assert_eq!(map.get_r(r).children_forwards[0].0, l);
+        assert_eq!(map.get_l(l).lane_center_pts.points(), orig_lane.points());
+        assert!(
+            map.get_r(r)
+                .center_pts
+                .first_pt()
+                .dist_to(orig_center.first_pt())
+                < Distance::meters(0.01),
+            "Reverting didn't move the center line of {} back",
+            r
+        );
+    });
+
+    t.run_slow("closed_roads_are_avoided", |_| {
+        let mut map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let lanes: Vec<_> = map
+            .all_lanes()
+            .iter()
+            .filter(|l| l.is_driving())
+            .map(|l| l.id)
+            .collect();
+        // Find a route long enough to have a road in the middle to close
+        let (req, orig_path, closed) = lanes
+            .iter()
+            .step_by(7)
+            .find_map(|l1| {
+                let l2 = *lanes.iter().rev().step_by(11).find(|l2| *l2 != l1)?;
+                let req = PathRequest {
+                    start: Position::new(*l1, Distance::ZERO),
+                    end: Position::new(l2, map.get_l(l2).length()),
+                    can_use_bike_lanes: false,
+                    can_use_bus_lanes: false,
+                };
+                let path = map.pathfind(req.clone())?;
+                let ends = vec![map.get_l(*l1).parent, map.get_l(l2).parent];
+                let closed = path.get_steps().iter().find_map(|step| match step {
+                    PathStep::Lane(l) if !ends.contains(&map.get_l(*l).parent) => {
+                        Some(map.get_l(*l).parent)
+                    }
+                    _ => None,
+                })?;
+                Some((req, path, closed))
+            })
+            .unwrap();
+
+        let mut edits = map.get_edits().clone();
+        edits.closed_roads.insert(closed);
+        // Slowing down the road shouldn't matter once it's closed
+        edits
+            .speed_limit_overrides
+            .insert(closed, Speed::miles_per_hour(5.0));
+        map.apply_edits(edits, &mut Timer::throwaway());
+        assert_eq!(
+            map.get_r(closed).get_speed_limit(),
+            Speed::miles_per_hour(5.0)
+        );
+        if let Some(path) = map.pathfind(req.clone()) {
+            for step in path.get_steps() {
+                if let PathStep::Lane(l) = step {
+                    let lane = map.get_l(*l);
+                    assert!(
+                        lane.parent != closed || lane.lane_type != LaneType::Driving,
+                        "{} still goes through closed {}",
+                        req,
+                        closed
+                    );
+                }
+            }
+        }
+
+        let mut edits = map.get_edits().clone();
+        edits.closed_roads.clear();
+        edits.speed_limit_overrides.clear();
+        map.apply_edits(edits, &mut Timer::throwaway());
+        assert_eq!(
+            map.get_r(closed).get_speed_limit(),
+            map.get_r(closed).speed_limit_from_osm()
+        );
+        assert_eq!(
+            map.pathfind(req.clone()),
+            Some(orig_path),
+            "{} changed after reopening {}",
+            req,
+            closed
+        );
+    });
+
+    t.run_slow("intersection_type_changes_are_reversible", |_| {
+        let mut map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let i = map
+            .all_intersections()
+            .iter()
+            .find(|i| i.intersection_type == IntersectionType::StopSign)
+            .unwrap()
+            .id;
+        let mut orig_turns = map.get_i(i).turns.clone();
+        orig_turns.sort();
+
+        let mut edits = map.get_edits().clone();
+        edits
+            .intersection_type_overrides
+            .insert(i, IntersectionType::TrafficSignal);
+        map.apply_edits(edits, &mut Timer::throwaway());
+        assert_eq!(
+            map.get_i(i).intersection_type,
+            IntersectionType::TrafficSignal
+        );
+        assert!(map.maybe_get_traffic_signal(i).is_some());
+        assert!(map.maybe_get_stop_sign(i).is_none());
+
+        let mut edits = map.get_edits().clone();
+        edits.intersection_type_overrides.clear();
+        map.apply_edits(edits, &mut Timer::throwaway());
+        assert_eq!(map.get_i(i).intersection_type, IntersectionType::StopSign);
+        assert!(map.maybe_get_traffic_signal(i).is_none());
+        assert!(map.maybe_get_stop_sign(i).is_some());
+        let mut turns = map.get_i(i).turns.clone();
+        turns.sort();
+        assert_eq!(turns, orig_turns);
+    });
+
+    t.run_slow("undo_and_redo", |_| {
+        let map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let l = map.all_lanes().iter().find(|l| l.is_driving()).unwrap().id;
+        let r = map.get_l(l).parent;
+        let other_r = map.all_roads()[r.0 + 1].id;
+        let ss = map
+            .all_intersections()
+            .iter()
+            .find(|i| i.intersection_type == IntersectionType::StopSign)
+            .unwrap()
+            .id;
+        let ts = map
+            .all_intersections()
+            .iter()
+            .find(|i| i.intersection_type == IntersectionType::TrafficSignal)
+            .unwrap()
+            .id;
+        let b = map.all_buildings()[0].id;
+
+        let original = map.get_edits().clone();
+        let mut edits = original.clone();
+        let cmds = vec![
+            EditCmd::LaneType(l, Some(LaneType::Bus)),
+            EditCmd::LaneType(l, Some(LaneType::Biking)),
+            EditCmd::ReverseLane(l, true),
+            EditCmd::SpeedLimit(r, Some(Speed::miles_per_hour(15.0))),
+            EditCmd::CloseRoad(r, true),
+            EditCmd::IntersectionType(ss, Some(IntersectionType::TrafficSignal)),
+            EditCmd::StopSign(ss, Some(map.get_stop_sign(ss).clone())),
+            EditCmd::TrafficSignal(ts, Some(map.get_traffic_signal(ts).clone())),
+            EditCmd::OffstreetParking(b, Some(0)),
+            EditCmd::TurnRestriction(r, other_r, Some(RestrictionType::BanTurns)),
+            EditCmd::Many(vec![
+                EditCmd::TurnRestriction(other_r, r, None),
+                EditCmd::RevertTurnRestriction(r, other_r),
+                EditCmd::CloseRoad(r, false),
+                EditCmd::CloseRoad(other_r, true),
+            ]),
+        ];
+        let num_cmds = cmds.len();
+        for cmd in cmds {
+            edits.execute(cmd);
+        }
+        let edited = edits.clone();
+        assert!(!original.diff(&edited).is_empty());
+
+        for _ in 0..num_cmds {
+            assert!(edits.can_undo());
+            edits.undo();
+        }
+        assert!(!edits.can_undo());
+        assert_eq!(original.diff(&edits), Vec::<String>::new());
+
+        for _ in 0..num_cmds {
+            assert!(edits.can_redo());
+            edits.redo();
+        }
+        assert!(!edits.can_redo());
+        assert_eq!(edited.diff(&edits), Vec::<String>::new());
+
+        // Doing something new forgets what was undone.
+        edits.undo();
+        edits.execute(EditCmd::OffstreetParking(b, Some(3)));
+        assert!(!edits.can_redo());
+
+        // Edits for another map aren't compared.
+        assert_eq!(original.diff(&MapEdits::new("23rd".to_string())).len(), 1);
+    });
+
+    t.run_slow("longer_turn_delays_change_routes", |_| {
+        let mut map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let lanes: Vec<_> = map
+            .all_lanes()
+            .iter()
+            .filter(|l| l.is_driving())
+            .map(|l| l.id)
+            .collect();
+
+        let mut rerouted = 0;
+        for l1 in lanes.iter().step_by(13) {
+            let l2 = lanes[(l1.0 * 7) % lanes.len()];
+            let req = PathRequest {
+                start: Position::new(*l1, Distance::ZERO),
+                end: Position::new(l2, map.get_l(l2).length()),
+                can_use_bike_lanes: false,
+                can_use_bus_lanes: false,
+            };
+            let path = match map.pathfind(req.clone()) {
+                Some(path) => path,
+                None => {
+                    continue;
+                }
+            };
+            // Hold up every turn at some signal along the way for an extra hour.
+            let i = match path.get_steps().iter().find_map(|step| match step {
+                PathStep::Turn(t) if map.maybe_get_traffic_signal(t.parent).is_some() => {
+                    Some(t.parent)
+                }
+                _ => None,
+            }) {
+                Some(i) => i,
+                None => {
+                    continue;
+                }
+            };
+            let mut signal = map.get_traffic_signal(i).clone();
+            let mut all_red = Cycle::new(i, signal.cycles.len());
+            all_red.duration = Duration::minutes(60);
+            signal.cycles.push(all_red);
+            let mut edits = map.get_edits().clone();
+            edits.traffic_signal_overrides.insert(i, signal);
+            map.apply_edits(edits, &mut Timer::throwaway());
+
+            if let Some(new_path) = map.pathfind(req.clone()) {
+                if !new_path.get_steps().iter().any(|step| match step {
+                    PathStep::Turn(t) => t.parent == i,
+                    _ => false,
+                }) {
+                    rerouted += 1;
+                }
+            }
+
+            let mut edits = map.get_edits().clone();
+            edits.traffic_signal_overrides.clear();
+            map.apply_edits(edits, &mut Timer::throwaway());
+            assert_eq!(
+                map.pathfind(req.clone()),
+                Some(path),
+                "{} changed after reverting the signal at {}",
+                req,
+                i
+            );
+        }
+        assert!(
+            rerouted > 0,
+            "No routes avoided a signal with an hour of red"
+        );
+    });
+
+    t.run_slow("stale_intersection_overrides_are_skipped", |_| {
+        let map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let ss = map
+            .all_intersections()
+            .iter()
+            .find_map(|i| map.maybe_get_stop_sign(i.id))
+            .unwrap()
+            .clone();
+        let ts = map
+            .all_intersections()
+            .iter()
+            .find_map(|i| map.maybe_get_traffic_signal(i.id))
+            .unwrap()
+            .clone();
+
+        // Overrides that still match the map load fine.
+        let mut edits = MapEdits::new(map.get_name().to_string());
+        edits.edits_name = "test_current_overrides".to_string();
+        edits.stop_sign_overrides.insert(ss.id, ss.clone());
+        edits.traffic_signal_overrides.insert(ts.id, ts.clone());
+        let loaded = save_and_load(&edits, &map);
+        assert_eq!(loaded.stop_sign_overrides.get(&ss.id), Some(&ss));
+        assert_eq!(loaded.traffic_signal_overrides.get(&ts.id), Some(&ts));
+
+        // Pretend the stop sign was saved when there was a turn between two lanes that don't
+        // meet here anymore, and the signal before a turn existed.
+        let mut stale_ss = ss.clone();
+        let other_lane = map
+            .all_lanes()
+            .iter()
+            .find(|l| l.src_i != ss.id && l.dst_i != ss.id)
+            .unwrap()
+            .id;
+        stale_ss.turns.insert(
+            TurnID {
+                parent: ss.id,
+                src: other_lane,
+                dst: other_lane,
+            },
+            TurnPriority::Stop,
+        );
+        let mut stale_ts = ts.clone();
+        let dropped = map.get_i(ts.id).turns[0];
+        for cycle in stale_ts.cycles.iter_mut() {
+            cycle.priority_turns.remove(&dropped);
+            cycle.yield_turns.remove(&dropped);
+        }
+        let mut edits = MapEdits::new(map.get_name().to_string());
+        edits.edits_name = "test_stale_overrides".to_string();
+        edits.stop_sign_overrides.insert(ss.id, stale_ss);
+        edits.traffic_signal_overrides.insert(ts.id, stale_ts);
+        let loaded = save_and_load(&edits, &map);
+        assert!(loaded.stop_sign_overrides.is_empty());
+        assert!(loaded.traffic_signal_overrides.is_empty());
+    });
+}
+
+fn save_and_load(edits: &MapEdits, map: &Map) -> MapEdits {
+    edits.save(map);
+    let loaded = MapEdits::load(map, &edits.edits_name, &mut Timer::throwaway()).unwrap();
+    std::fs::remove_file(format!(
+        "../data/edits/{}/{}.json",
+        map.get_name(),
+        edits.edits_name
+    ))
+    .unwrap();
+    loaded
+}
@@ -1,7 +1,11 @@
 use crate::runner::TestRunner;
 use abstutil::Timer;
-use geom::Duration;
-use sim::{DrivingGoal, Event, ParkingSpot, Scenario, SidewalkSpot, SimFlags, TripSpec};
+use geom::{Distance, Duration, Speed};
+use map_model::{Map, PathRequest, Position, PARKING_SPOT_LENGTH};
+use sim::{
+    DrivingGoal, Event, ParkingSpot, PlannedLeg, Scenario, SidewalkPOI, SidewalkSpot, Sim,
+    SimFlags, TripEndpoint, TripPlanRequest, TripSpec,
+};
 
 pub fn run(t: &mut TestRunner) {
     // TODO Lots of boilerplate between these two. Can we do better?
@@ -57,10 +61,13 @@ pub fn run(t: &mut TestRunner) {
         // Fill up all of the north spots, forcing parking to happen on the south lane behind
         // the original spot
         h.seed_parked_cars(&mut sim, &mut rng, north_parking, None, (0..23).collect());
+        let start = SidewalkSpot::building(south_bldg, &map);
+        let to_car = car_spot(&sim, &map, &start, spot);
+        let walk_to_car = walking_dist(&start, &to_car, &map);
         sim.schedule_trip(
             Duration::ZERO,
             TripSpec::UsingParkedCar {
-                start: SidewalkSpot::building(south_bldg, &map),
+                start: start.clone(),
                 spot,
                 goal: DrivingGoal::ParkNear(north_bldg),
                 ped_speed: Scenario::rand_ped_speed(&mut rng),
@@ -79,5 +86,137 @@ pub fn run(t: &mut TestRunner) {
             Duration::minutes(6),
         );
         sim.just_run_until_done(&map, Some(Duration::minutes(1)));
+
+        let trip = sim.get_finished_trips().pop().unwrap();
+        assert!(trip.cruising_time > Duration::ZERO);
+        // 10s to unpark and 15s to park, at least
+        assert!(
+            trip.parking_time >= Duration::seconds(25.0),
+            "Only {} parking",
+            trip.parking_time
+        );
+        // The walks go exactly as planned, so the rest is driving. The car got to the end of the
+        // south lane from somewhere in its spot, then went all the way along the north lane
+        // before giving up on it.
+        let walk_from_car = walking_dist(
+            &car_spot(&sim, &map, &start, ParkingSpot::new(south_parking, 0)),
+            &SidewalkSpot::building(north_bldg, &map),
+            &map,
+        );
+        let south_driving = map
+            .get_parent(south_parking)
+            .parking_to_driving(south_parking)
+            .unwrap();
+        let north_driving = map
+            .get_parent(north_parking)
+            .parking_to_driving(north_parking)
+            .unwrap();
+        let started_at = to_car
+            .sidewalk_pos
+            .equiv_pos(south_driving, &map)
+            .dist_along();
+        let min_drive = map.get_l(south_driving).length() - started_at - PARKING_SPOT_LENGTH
+            + map.get_l(north_driving).length();
+        let drive = trip.total_dist - walk_to_car - walk_from_car;
+        assert!(
+            drive > min_drive,
+            "The trip only counted {} of driving, but it was at least {}",
+            drive,
+            min_drive
+        );
+    });
+
+    t.run_slow("seeded_cars_park_where_drivers_would", |h| {
+        let (map, mut sim, mut rng) =
+            SimFlags::synthetic_test("parking_test", "seeded_cars_park_where_drivers_would")
+                .load(None, &mut Timer::throwaway());
+        let north_bldg = map.bldg("north").id;
+        let north_parking = map.parking_lane("north", 23).id;
+        let south_parking = map.parking_lane("south", 23).id;
+        let vehicle = Scenario::rand_car(&mut rng);
+
+        // The first free spot along the building's lane
+        h.seed_parked_cars(&mut sim, &mut rng, north_parking, None, (0..4).collect());
+        h.seed_parked_cars(&mut sim, &mut rng, north_parking, None, (5..10).collect());
+        assert_eq!(
+            sim.find_parking_spot_near_building(north_bldg, &vehicle, &map),
+            Some(ParkingSpot::new(north_parking, 4))
+        );
+
+        // When that lane's full, the closest lane with room, by driving distance
+        h.seed_parked_cars(&mut sim, &mut rng, north_parking, None, vec![4]);
+        h.seed_parked_cars(&mut sim, &mut rng, north_parking, None, (10..23).collect());
+        h.seed_parked_cars(&mut sim, &mut rng, south_parking, None, vec![0]);
+        let expected = ParkingSpot::new(south_parking, 1);
+        assert_eq!(
+            sim.find_parking_spot_near_building(north_bldg, &vehicle, &map),
+            Some(expected)
+        );
+
+        // And a driver heading there really does cruise around to that spot.
+        let south_driving = map
+            .get_parent(south_parking)
+            .parking_to_driving(south_parking)
+            .unwrap();
+        let car = sim
+            .schedule_trip(
+                Duration::ZERO,
+                TripSpec::CarAppearing {
+                    start_pos: Position::new(south_driving, Distance::meters(10.0)),
+                    goal: DrivingGoal::ParkNear(north_bldg),
+                    vehicle_spec: vehicle,
+                    ped_speed: Scenario::rand_ped_speed(&mut rng),
+                },
+                &map,
+            )
+            .1
+            .unwrap();
+        sim.spawn_all_trips(&map, &mut Timer::throwaway(), false);
+        h.setup_done(&sim);
+
+        sim.run_until_expectations_met(
+            &map,
+            vec![Event::CarReachedParkingSpot(car, expected)],
+            Duration::minutes(6),
+        );
+        sim.just_run_until_done(&map, Some(Duration::minutes(1)));
+        let trip = sim.get_finished_trips().pop().unwrap();
+        assert!(trip.cruising_time > Duration::ZERO);
     });
 }
+
+// Where somebody walks to reach the car parked at this spot
+fn car_spot(sim: &Sim, map: &Map, start: &SidewalkSpot, spot: ParkingSpot) -> SidewalkSpot {
+    let itineraries = sim.plan_trip(
+        &TripPlanRequest {
+            start: start.clone(),
+            goal: TripEndpoint::Building(map.bldg("north").id),
+            parked_car: Some(spot),
+            bike: None,
+            ped_speed: Speed::meters_per_second(1.0),
+            use_transit: false,
+        },
+        map,
+    );
+    itineraries
+        .into_iter()
+        .find_map(|i| match i.legs[0] {
+            PlannedLeg::Walk(ref to) if to.connection == SidewalkPOI::ParkingSpot(spot) => {
+                Some(to.clone())
+            }
+            _ => None,
+        })
+        .unwrap()
+}
+
+// The same path the simulation uses
+fn walking_dist(from: &SidewalkSpot, to: &SidewalkSpot, map: &Map) -> Distance {
+    map.pathfind(PathRequest {
+        start: from.sidewalk_pos,
+        end: to.sidewalk_pos,
+        can_use_bike_lanes: false,
+        can_use_bus_lanes: false,
+    })
+    .unwrap()
+    .total_length(from.sidewalk_pos.dist_along(), map)
+}
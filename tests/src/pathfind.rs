@@ -0,0 +1,341 @@
+use crate::runner::TestRunner;
+use abstutil::Timer;
+use geom::{Distance, Duration, LonLat, Speed};
+use map_model::raw_data::{
+    self, RestrictionType, RestrictionVia, StableIntersectionID, StableRoadID, TurnRestriction,
+};
+use map_model::{IntersectionType, Map, PathRequest, PathStep, Position, RoadID};
+use std::collections::BTreeMap;
+
+pub fn run(t: &mut TestRunner) {
+    t.run_slow("travel_time_estimates", |_| {
+        let map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let max_speed = map
+            .all_roads()
+            .iter()
+            .map(|r| r.get_speed_limit())
+            .fold(Speed::ZERO, |a, b| if b > a { b } else { a });
+        let lanes: Vec<_> = map
+            .all_lanes()
+            .iter()
+            .filter(|l| l.is_driving())
+            .map(|l| l.id)
+            .collect();
+
+        // Not every pair, just enough to cover different parts of the map
+        for l1 in lanes.iter().step_by(17) {
+            for l2 in lanes.iter().step_by(23) {
+                if l1 == l2 {
+                    continue;
+                }
+                let req = PathRequest {
+                    start: Position::new(*l1, map.get_l(*l1).length() / 2.0),
+                    end: Position::new(*l2, map.get_l(*l2).length() / 2.0),
+                    can_use_bike_lanes: false,
+                    can_use_bus_lanes: false,
+                };
+                let estimate = map.estimate_travel_time(req.clone());
+                assert_eq!(
+                    estimate.is_some(),
+                    map.pathfind(req.clone()).is_some(),
+                    "{} has a path or an estimate, but not both",
+                    req
+                );
+                if let Some(time) = estimate {
+                    // Nothing can go faster than a straight line at the top speed.
+                    let best_case = req.start.pt(&map).dist_to(req.end.pt(&map)) / max_speed;
+                    assert!(
+                        time > Duration::ZERO && time >= best_case,
+                        "{} estimated to take {}, but it's at least {}",
+                        req,
+                        time,
+                        best_case
+                    );
+                }
+            }
+        }
+    });
+
+    t.run_slow("contraction_hierarchies_match_graph", |_| {
+        let mut map = Map::new("../data/raw_maps/montlake.abst", &mut Timer::throwaway()).unwrap();
+        let lanes: Vec<_> = map
+            .all_lanes()
+            .iter()
+            .filter(|l| l.is_driving())
+            .map(|l| l.id)
+            .collect();
+        let mut requests = Vec::new();
+        for l1 in lanes.iter().step_by(19) {
+            for l2 in lanes.iter().step_by(29) {
+                if l1 != l2 {
+                    requests.push(PathRequest {
+                        start: Position::new(*l1, map.get_l(*l1).length() / 2.0),
+                        end: Position::new(*l2, map.get_l(*l2).length() / 2.0),
+                        can_use_bike_lanes: false,
+                        can_use_bus_lanes: false,
+                    });
+                }
+            }
+        }
+
+        let expected = roads_per_path(&map, &requests);
+        map.use_contraction_hierarchies(true, &mut Timer::throwaway());
+        check_roads_per_path(&map, &requests, &expected);
+
+        // Closing a road only redoes the shortcuts that depend on it, so make sure that catches
+        // everything.
+        let closed = requests
+            .iter()
+            .find_map(|req| {
+                let path = map.pathfind(req.clone())?;
+                let ends = vec![
+                    map.get_l(req.start.lane()).parent,
+                    map.get_l(req.end.lane()).parent,
+                ];
+                path.get_steps().iter().find_map(|step| match step {
+                    PathStep::Lane(l) if !ends.contains(&map.get_l(*l).parent) => {
+                        Some(map.get_l(*l).parent)
+                    }
+                    _ => None,
+                })
+            })
+            .unwrap();
+        let mut edits = map.get_edits().clone();
+        edits.closed_roads.insert(closed);
+        map.apply_edits(edits, &mut Timer::throwaway());
+        let with_ch = roads_per_path(&map, &requests);
+        map.use_contraction_hierarchies(false, &mut Timer::throwaway());
+        check_roads_per_path(&map, &requests, &with_ch);
+
+        // Start over with the hierarchy built on the original map, then close and reopen the
+        // road. That should go back to the original routes.
+        let mut edits = map.get_edits().clone();
+        edits.closed_roads.clear();
+        map.apply_edits(edits, &mut Timer::throwaway());
+        map.use_contraction_hierarchies(true, &mut Timer::throwaway());
+        let mut edits = map.get_edits().clone();
+        edits.closed_roads.insert(closed);
+        map.apply_edits(edits, &mut Timer::throwaway());
+        let mut edits = map.get_edits().clone();
+        edits.closed_roads.clear();
+        map.apply_edits(edits, &mut Timer::throwaway());
+        check_roads_per_path(&map, &requests, &expected);
+    });
+
+    t.run_fast("turn_restrictions", |_| {
+        // All of these ban going east on 1, south on 6, and then west on 3. The edit key is the
+        // (from, to) pair of ways that OSM names.
+        let cases = vec![
+            (
+                TurnRestriction {
+                    osm_relation_id: 100,
+                    restriction: RestrictionType::new("no_right_turn").unwrap(),
+                    from_way: 1,
+                    via: RestrictionVia::Node(grid_pt(1, 0)),
+                    to_way: 6,
+                },
+                (1, 6),
+            ),
+            (
+                TurnRestriction {
+                    osm_relation_id: 101,
+                    restriction: RestrictionType::new("no_u_turn").unwrap(),
+                    from_way: 1,
+                    via: RestrictionVia::Way(6),
+                    to_way: 3,
+                },
+                (1, 3),
+            ),
+            (
+                TurnRestriction {
+                    osm_relation_id: 102,
+                    restriction: RestrictionType::new("only_left_turn").unwrap(),
+                    from_way: 1,
+                    via: RestrictionVia::Way(6),
+                    to_way: 4,
+                },
+                (1, 4),
+            ),
+        ];
+
+        let unrestricted = grid_map(Vec::new());
+        let req = grid_request(&unrestricted);
+        let direct = vec![1, 6, 3];
+        assert_eq!(ways_per_path(&unrestricted, &req), direct);
+
+        for (tr, (from_way, to_way)) in cases {
+            let id = tr.osm_relation_id;
+            let mut map = grid_map(vec![tr]);
+            let req = grid_request(&map);
+            for ch in vec![false, true] {
+                map.use_contraction_hierarchies(ch, &mut Timer::throwaway());
+                assert_eq!(
+                    ways_per_path(&map, &req),
+                    vec![1, 2, 7, 4, 3],
+                    "Restriction {} doesn't reroute (contraction hierarchies: {})",
+                    id,
+                    ch
+                );
+            }
+
+            // The editor lifts restrictions by the roads that OSM names.
+            let mut edits = map.get_edits().clone();
+            edits
+                .turn_restriction_overrides
+                .insert((grid_road(&map, from_way), grid_road(&map, to_way)), None);
+            map.apply_edits(edits, &mut Timer::throwaway());
+            for ch in vec![false, true] {
+                map.use_contraction_hierarchies(ch, &mut Timer::throwaway());
+                assert_eq!(
+                    ways_per_path(&map, &req),
+                    direct,
+                    "Restriction {} can't be lifted (contraction hierarchies: {})",
+                    id,
+                    ch
+                );
+            }
+        }
+    });
+}
+
+// Intersections 100m apart, with roads labeled by OSM way ID:
+//
+// +--1--+--2--+
+// |     |     |
+// 5     6     7
+// |     |     |
+// +--3--+--4--+
+fn grid_map(turn_restrictions: Vec<TurnRestriction>) -> Map {
+    let mut raw = raw_data::Map::blank();
+    for y in 0..2 {
+        for x in 0..3 {
+            raw.intersections.insert(
+                StableIntersectionID(y * 3 + x),
+                raw_data::Intersection {
+                    point: grid_pt(x, y),
+                    elevation: Distance::ZERO,
+                    intersection_type: IntersectionType::StopSign,
+                    label: None,
+                    approach_signs: BTreeMap::new(),
+                },
+            );
+        }
+    }
+    for (way, (x1, y1), (x2, y2)) in vec![
+        (1, (0, 0), (1, 0)),
+        (2, (1, 0), (2, 0)),
+        (3, (0, 1), (1, 1)),
+        (4, (1, 1), (2, 1)),
+        (5, (0, 0), (0, 1)),
+        (6, (1, 0), (1, 1)),
+        (7, (2, 0), (2, 1)),
+    ] {
+        let mut osm_tags = BTreeMap::new();
+        osm_tags.insert("highway".to_string(), "residential".to_string());
+        raw.roads.insert(
+            StableRoadID(raw.roads.len()),
+            raw_data::Road {
+                i1: StableIntersectionID(y1 * 3 + x1),
+                i2: StableIntersectionID(y2 * 3 + x2),
+                points: vec![grid_pt(x1, y1), grid_pt(x2, y2)],
+                elevations: Vec::new(),
+                osm_tags,
+                osm_way_id: way,
+                parking_lane_fwd: false,
+                parking_lane_back: false,
+            },
+        );
+    }
+    raw.turn_restrictions = turn_restrictions;
+    raw.boundary_polygon = vec![
+        LonLat::new(-122.301, 47.651),
+        LonLat::new(-122.296, 47.651),
+        LonLat::new(-122.296, 47.648),
+        LonLat::new(-122.301, 47.648),
+        LonLat::new(-122.301, 47.651),
+    ];
+    Map::create_from_raw("grid".to_string(), raw, &mut Timer::throwaway())
+}
+
+fn grid_pt(x: usize, y: usize) -> LonLat {
+    LonLat::new(-122.3 + 0.0013 * (x as f64), 47.65 - 0.0009 * (y as f64))
+}
+
+fn grid_road(map: &Map, osm_way_id: i64) -> RoadID {
+    map.all_roads()
+        .iter()
+        .find(|r| r.osm_way_id == osm_way_id)
+        .unwrap()
+        .id
+}
+
+// From the middle of 1 going east to the middle of 3 going west
+fn grid_request(map: &Map) -> PathRequest {
+    let lane = |from_way: i64, onto_way: i64| {
+        let from = map.get_r(grid_road(map, from_way));
+        let onto = map.get_r(grid_road(map, onto_way));
+        from.all_lanes()
+            .into_iter()
+            .map(|l| map.get_l(l))
+            .find(|l| {
+                l.is_driving()
+                    && (l.dst_i == onto.src_i || l.dst_i == onto.dst_i)
+                    && l.src_i != onto.src_i
+                    && l.src_i != onto.dst_i
+            })
+            .unwrap()
+            .id
+    };
+    let start = lane(1, 6);
+    let end = lane(3, 5);
+    PathRequest {
+        start: Position::new(start, map.get_l(start).length() / 2.0),
+        end: Position::new(end, map.get_l(end).length() / 2.0),
+        can_use_bike_lanes: false,
+        can_use_bus_lanes: false,
+    }
+}
+
+fn ways_per_path(map: &Map, req: &PathRequest) -> Vec<i64> {
+    roads_per_path(map, &[req.clone()])
+        .pop()
+        .unwrap()
+        .unwrap_or_else(|| panic!("No path for {}", req))
+        .into_iter()
+        .map(|r| map.get_r(r).osm_way_id)
+        .collect()
+}
+
+// Equally good paths might use different lanes of the same road, so just compare the roads.
+fn roads_per_path(map: &Map, requests: &[PathRequest]) -> Vec<Option<Vec<RoadID>>> {
+    requests
+        .iter()
+        .map(|req| {
+            map.pathfind(req.clone()).map(|path| {
+                let mut roads: Vec<RoadID> = Vec::new();
+                for step in path.get_steps() {
+                    if let PathStep::Lane(l) = step {
+                        let r = map.get_l(*l).parent;
+                        if roads.last() != Some(&r) {
+                            roads.push(r);
+                        }
+                    }
+                }
+                roads
+            })
+        })
+        .collect()
+}
+
+fn check_roads_per_path(map: &Map, requests: &[PathRequest], expected: &[Option<Vec<RoadID>>]) {
+    for (req, (actual, expected)) in requests
+        .iter()
+        .zip(roads_per_path(map, requests).into_iter().zip(expected))
+    {
+        assert_eq!(
+            &actual, expected,
+            "{} goes a different way with and without contraction hierarchies",
+            req
+        );
+    }
+}
@@ -0,0 +1,95 @@
+use crate::runner::TestRunner;
+use geom::Duration;
+use map_model::IntersectionID;
+use rand::{Rng, SeedableRng};
+use rand_xorshift::XorShiftRng;
+use sim::{Command, Scheduler};
+
+pub fn run(t: &mut TestRunner) {
+    t.run_fast("same_order_as_sorted_vec", |_| {
+        let mut rng = XorShiftRng::from_seed([42; 16]);
+        let mut scheduler = Scheduler::new();
+        let mut old = OldScheduler { items: Vec::new() };
+        let mut new_order = Vec::new();
+        let mut old_order = Vec::new();
+
+        for step in 0..500 {
+            let now = Duration::seconds(step as f64);
+            // Only a few distinct times and commands, so there are lots of ties and updates.
+            for _ in 0..rng.gen_range(0, 5) {
+                let time = now + Duration::seconds(rng.gen_range(0, 4) as f64);
+                let id = rng.gen_range(0, 20);
+                if rng.gen_bool(0.3) {
+                    scheduler.update(cmd(id), time);
+                    old.update(id, time);
+                } else {
+                    scheduler.push(time, cmd(id));
+                    old.push(time, id);
+                }
+            }
+            while let Some((c, time)) = scheduler.get_next(now) {
+                match c {
+                    Command::UpdateIntersection(i) => new_order.push((time, i.0)),
+                    _ => unreachable!(),
+                }
+            }
+            while let Some(pair) = old.get_next(now) {
+                old_order.push(pair);
+            }
+        }
+
+        // The old binary search put ties in whatever order it happened to land on, so only
+        // compare the order of commands at different times.
+        for pair in new_order.windows(2) {
+            assert!(
+                pair[0].0 <= pair[1].0,
+                "{:?} went before {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+        assert_eq!(new_order.len(), old_order.len());
+        new_order.sort();
+        old_order.sort();
+        for (idx, (new, old)) in new_order.iter().zip(old_order.iter()).enumerate() {
+            if new != old {
+                panic!("Event {} differs: {:?} vs {:?}", idx, new, old);
+            }
+        }
+    });
+}
+
+fn cmd(id: usize) -> Command {
+    Command::UpdateIntersection(IntersectionID(id))
+}
+
+// How the Scheduler used to work: a Vec sorted by descending time, with the next command last.
+struct OldScheduler {
+    items: Vec<(Duration, usize)>,
+}
+
+impl OldScheduler {
+    fn push(&mut self, time: Duration, id: usize) {
+        // Note the order of comparison means times will be descending.
+        let idx = match self.items.binary_search_by(|(at, _)| time.cmp(at)) {
+            Ok(i) => i,
+            Err(i) => i,
+        };
+        self.items.insert(idx, (time, id));
+    }
+
+    fn update(&mut self, id: usize, time: Duration) {
+        if let Some(idx) = self.items.iter().position(|(_, i)| *i == id) {
+            self.items.remove(idx);
+        }
+        self.push(time, id);
+    }
+
+    fn get_next(&mut self, now: Duration) -> Option<(Duration, usize)> {
+        let next_time = self.items.last()?.0;
+        if next_time > now {
+            return None;
+        }
+        self.items.pop()
+    }
+}
@@ -0,0 +1,102 @@
+use crate::runner::TestRunner;
+use abstutil::Timer;
+use geom::Duration;
+use map_model::{ControlTrafficSignal, Cycle, IntersectionID, IntersectionType};
+use sim::{Scenario, Sim, SimFlags};
+use std::collections::BTreeMap;
+
+pub fn run(t: &mut TestRunner) {
+    t.run_fast("offset_shifts_schedule", |_| {
+        let mut cycles = vec![
+            Cycle::new(IntersectionID(0), 0),
+            Cycle::new(IntersectionID(0), 1),
+        ];
+        cycles[0].duration = Duration::seconds(30.0);
+        cycles[1].duration = Duration::seconds(20.0);
+        let signal = ControlTrafficSignal {
+            id: IntersectionID(0),
+            cycles,
+            offset: Duration::seconds(10.0),
+            actuated: false,
+        };
+
+        // Before the offset, we're still finishing the previous round.
+        assert_eq!(
+            signal.current_cycle_idx_and_remaining_time(Duration::ZERO),
+            (1, Duration::seconds(10.0))
+        );
+        assert_eq!(
+            signal.current_cycle_idx_and_remaining_time(Duration::seconds(10.0)),
+            (0, Duration::seconds(30.0))
+        );
+        assert_eq!(
+            signal.current_cycle_idx_and_remaining_time(Duration::seconds(45.0)),
+            (1, Duration::seconds(15.0))
+        );
+        assert_eq!(
+            signal.current_cycle_idx_and_remaining_time(Duration::seconds(70.0)),
+            (0, Duration::seconds(20.0))
+        );
+    });
+
+    t.run_fast("load_signal_without_timing", |_| {
+        // Saved before signals had offsets or actuation
+        let path = std::env::temp_dir().join("load_signal_without_timing.json");
+        std::fs::write(
+            &path,
+            r#"{"id":3,"cycles":[{"parent":3,"idx":0,"priority_turns":[],"yield_turns":[],"duration":30.0}]}"#,
+        )
+        .unwrap();
+        let result = abstutil::read_json::<ControlTrafficSignal>(path.to_str().unwrap());
+        // Clean up before anything can fail
+        std::fs::remove_file(&path).unwrap();
+        let signal = result.unwrap();
+
+        assert_eq!(signal.offset, Duration::ZERO);
+        assert!(!signal.actuated);
+        assert_eq!(signal.cycles[0].max_extension, Duration::ZERO);
+    });
+
+    t.run_slow("actuated_signals_extend_cycles", |h| {
+        let (mut map, _, mut rng) = SimFlags::for_test("actuated_signals_extend_cycles")
+            .load(None, &mut Timer::throwaway());
+        let mut edits = map.get_edits().clone();
+        for i in map.all_intersections() {
+            if i.intersection_type == IntersectionType::TrafficSignal {
+                let mut signal = map.get_traffic_signal(i.id).clone();
+                signal.actuated = true;
+                for cycle in signal.cycles.iter_mut() {
+                    cycle.max_extension = Duration::seconds(60.0);
+                }
+                edits.traffic_signal_overrides.insert(i.id, signal);
+            }
+        }
+        map.apply_edits(edits, &mut Timer::throwaway());
+
+        let mut sim = Sim::new(&map, "actuated_signals_extend_cycles".to_string(), None);
+        Scenario::small_run(&map).instantiate(&mut sim, &map, &mut rng, &mut Timer::throwaway());
+        h.setup_done(&sim);
+
+        // When did we first see each signal in its current cycle?
+        let mut cycle_seen_since: BTreeMap<IntersectionID, (usize, Duration)> = BTreeMap::new();
+        let mut extended = false;
+        while sim.time() < Duration::minutes(20) && !extended {
+            sim.step(&map, Duration::seconds(1.0));
+            for i in map.all_intersections() {
+                if i.intersection_type != IntersectionType::TrafficSignal {
+                    continue;
+                }
+                let (idx, _) = sim.current_signal_cycle(i.id, &map);
+                let since = match cycle_seen_since.get(&i.id) {
+                    Some((old_idx, since)) if *old_idx == idx => *since,
+                    _ => sim.time(),
+                };
+                cycle_seen_since.insert(i.id, (idx, since));
+                if sim.time() - since > map.get_traffic_signal(i.id).cycles[idx].duration {
+                    extended = true;
+                }
+            }
+        }
+        assert!(extended, "No actuated signal ever extended a cycle");
+    });
+}
@@ -1,13 +1,17 @@
 use crate::runner::TestRunner;
 use abstutil::Timer;
 use geom::Duration;
-use sim::{Event, Scenario, SidewalkSpot, SimFlags, TripSpec};
+use map_model::{BusRoute, ScheduledRun};
+use sim::{
+    Event, Scenario, SidewalkSpot, SimFlags, TripEndpoint, TripMode, TripPlanRequest, TripSpec,
+    BUS_CAPACITY, TIME_TO_ALIGHT, TIME_TO_BOARD, TIME_TO_WAIT_AT_STOP,
+};
 
 pub fn run(t: &mut TestRunner) {
     t.run_slow("bus_reaches_stops", |h| {
         let (map, mut sim, _) = SimFlags::for_test("bus_reaches_stops")
             .load(Some(Duration::seconds(30.0)), &mut Timer::throwaway());
-        let route = map.get_bus_route("49").unwrap();
+        let route = &starting_now(map.get_bus_route("49").unwrap());
         let buses = sim.seed_bus_route(route, &map, &mut Timer::throwaway());
         let bus = buses[0];
         h.setup_done(&sim);
@@ -27,7 +31,7 @@ pub fn run(t: &mut TestRunner) {
     t.run_slow("ped_uses_bus", |h| {
         let (map, mut sim, mut rng) = SimFlags::for_test("ped_uses_bus")
             .load(Some(Duration::seconds(30.0)), &mut Timer::throwaway());
-        let route = map.get_bus_route("49").unwrap();
+        let route = &starting_now(map.get_bus_route("49").unwrap());
         let buses = sim.seed_bus_route(route, &map, &mut Timer::throwaway());
         let bus = buses[0];
         let ped_stop1 = route.stops[1];
@@ -76,5 +80,167 @@ pub fn run(t: &mut TestRunner) {
             ],
             Duration::minutes(9),
         );
+
+        // The pedestrian got to the stop before the bus did.
+        let trip = sim
+            .get_finished_trips()
+            .into_iter()
+            .find(|t| t.modes.contains(&TripMode::Transit))
+            .unwrap();
+        assert!(trip.bus_wait_time > Duration::ZERO);
+        assert!(trip.bus_wait_time < trip.duration());
+    });
+
+    t.run_slow("full_bus_leaves_peds_behind", |h| {
+        let (map, mut sim, mut rng) = SimFlags::for_test("full_bus_leaves_peds_behind")
+            .load(Some(Duration::seconds(30.0)), &mut Timer::throwaway());
+        let route = &starting_now(map.get_bus_route("49").unwrap());
+        let buses = sim.seed_bus_route(route, &map, &mut Timer::throwaway());
+        let bus = buses[0];
+        let ped_stop1 = route.stops[1];
+        let ped_stop2 = route.stops[2];
+        let start_bldg = *map
+            .get_l(map.get_bs(ped_stop1).sidewalk_pos.lane())
+            .building_paths
+            .last()
+            .unwrap();
+        let goal_bldg = map
+            .get_l(map.get_bs(ped_stop2).sidewalk_pos.lane())
+            .building_paths[0];
+        // One more than fits
+        for _ in 0..=BUS_CAPACITY {
+            sim.schedule_trip(
+                Duration::ZERO,
+                TripSpec::UsingTransit {
+                    start: SidewalkSpot::building(start_bldg, &map),
+                    route: route.id,
+                    stop1: ped_stop1,
+                    stop2: ped_stop2,
+                    goal: SidewalkSpot::building(goal_bldg, &map),
+                    ped_speed: Scenario::rand_ped_speed(&mut rng),
+                },
+                &map,
+            )
+            .0
+            .unwrap();
+        }
+        sim.spawn_all_trips(&map, &mut Timer::throwaway(), false);
+        h.setup_done(&sim);
+
+        sim.run_until_expectations_met(
+            &map,
+            vec![
+                Event::BusArrivedAtStop(bus, ped_stop1),
+                Event::BusLeftPedsBehind(bus, ped_stop1, 1),
+                Event::BusDepartedFromStop(bus, ped_stop1),
+                Event::BusArrivedAtStop(bus, ped_stop2),
+                Event::BusDepartedFromStop(bus, ped_stop2),
+            ],
+            Duration::minutes(20),
+        );
+
+        let visit = |stop| {
+            sim.get_bus_stop_visits()
+                .iter()
+                .find(|v| v.bus == bus && v.stop == stop)
+                .unwrap()
+                .clone()
+        };
+        let boarding = visit(ped_stop1);
+        assert_eq!(boarding.boarded, BUS_CAPACITY);
+        assert_eq!(boarding.load, BUS_CAPACITY);
+        assert_eq!(boarding.left_behind, 1);
+        // Everybody takes time to get on and off
+        assert!(
+            boarding.departed_at - boarding.arrived_at
+                >= TIME_TO_WAIT_AT_STOP + (BUS_CAPACITY as f64) * TIME_TO_BOARD
+        );
+        let alighting = visit(ped_stop2);
+        assert_eq!(alighting.alighted, BUS_CAPACITY);
+        assert_eq!(alighting.load, 0);
+        assert!(
+            alighting.departed_at - alighting.arrived_at
+                >= TIME_TO_WAIT_AT_STOP + (BUS_CAPACITY as f64) * TIME_TO_ALIGHT
+        );
+    });
+
+    t.run_slow("planned_transit_trip", |h| {
+        let (map, mut sim, mut rng) = SimFlags::for_test("planned_transit_trip")
+            .load(Some(Duration::seconds(30.0)), &mut Timer::throwaway());
+        let route = &starting_now(map.get_bus_route("49").unwrap());
+        sim.seed_bus_route(route, &map, &mut Timer::throwaway());
+        let start_bldg = *map
+            .get_l(map.get_bs(route.stops[1]).sidewalk_pos.lane())
+            .building_paths
+            .last()
+            .unwrap();
+        let goal_bldg = map
+            .get_l(map.get_bs(route.stops[3]).sidewalk_pos.lane())
+            .building_paths[0];
+        let start = SidewalkSpot::building(start_bldg, &map);
+        let ped_speed = Scenario::rand_ped_speed(&mut rng);
+
+        let itinerary = sim
+            .plan_trip(
+                &TripPlanRequest {
+                    start: start.clone(),
+                    goal: TripEndpoint::Building(goal_bldg),
+                    parked_car: None,
+                    bike: None,
+                    ped_speed,
+                    use_transit: true,
+                },
+                &map,
+            )
+            .into_iter()
+            .find(|i| i.modes().contains(&TripMode::Transit))
+            .unwrap();
+        let ped = sim
+            .schedule_trip(
+                Duration::ZERO,
+                TripSpec::UsingItinerary {
+                    start,
+                    itinerary,
+                    ped_speed,
+                },
+                &map,
+            )
+            .0
+            .unwrap();
+        sim.spawn_all_trips(&map, &mut Timer::throwaway(), false);
+        h.setup_done(&sim);
+
+        sim.run_until_expectations_met(
+            &map,
+            vec![Event::PedReachedBuilding(ped, goal_bldg)],
+            Duration::minutes(20),
+        );
     });
 }
+
+// Scheduled buses first appear whenever GTFS says, which could be hours in. Shift the schedule so
+// the first run leaves right away.
+fn starting_now(route: &BusRoute) -> BusRoute {
+    let first_departure = route
+        .schedule
+        .get(0)
+        .map(|run| run.departures[0])
+        .unwrap_or(Duration::ZERO);
+    BusRoute {
+        id: route.id,
+        name: route.name.clone(),
+        stops: route.stops.clone(),
+        schedule: route
+            .schedule
+            .iter()
+            .map(|run| ScheduledRun {
+                departures: run
+                    .departures
+                    .iter()
+                    .map(|t| *t - first_departure)
+                    .collect(),
+                timepoints: run.timepoints.clone(),
+            })
+            .collect(),
+    }
+}
@@ -1,8 +1,14 @@
 use crate::runner::TestRunner;
 use abstutil::Timer;
-use geom::Duration;
-use map_model::{BuildingID, IntersectionID};
-use sim::{DrivingGoal, Event, Scenario, SidewalkSpot, SimFlags, TripSpec};
+use geom::{Distance, Duration, Speed};
+use map_model::{
+    BuildingID, DirectedRoadID, IntersectionID, LaneType, Map, PathRequest, PathStep, TurnType,
+};
+use sim::{
+    choice_probabilities, DrivingGoal, Event, FinishedTrip, Itinerary, PlannedLeg, Scenario,
+    SidewalkPOI, SidewalkSpot, Sim, SimFlags, TripEndpoint, TripMode, TripPlanRequest, TripSpec,
+};
+use std::collections::{BTreeMap, BTreeSet};
 
 pub fn run(t: &mut TestRunner) {
     t.run_slow("bike_from_border", |h| {
@@ -36,4 +42,233 @@ pub fn run(t: &mut TestRunner) {
         );
         sim.just_run_until_done(&map, Some(Duration::minutes(1)));
     });
+    t.run_slow("mode_share_changes_after_edit", |_| {
+        let (mut map, sim, mut rng) =
+            SimFlags::for_test("mode_share_changes_after_edit").load(None, &mut Timer::throwaway());
+        let bldgs: Vec<BuildingID> = map.all_buildings().iter().map(|b| b.id).collect();
+        let mut requests = Vec::new();
+        for b1 in bldgs.iter().step_by(37) {
+            for b2 in bldgs.iter().step_by(41) {
+                if b1 != b2 {
+                    requests.push(TripPlanRequest {
+                        start: SidewalkSpot::building(*b1, &map),
+                        goal: TripEndpoint::Building(*b2),
+                        parked_car: None,
+                        bike: Some(Scenario::rand_bike(&mut rng)),
+                        ped_speed: Scenario::rand_ped_speed(&mut rng),
+                        use_transit: false,
+                    });
+                }
+            }
+        }
+
+        // Find the stretch of road that the most bike trips share with cars
+        let mut mixed_traffic: BTreeMap<DirectedRoadID, Vec<usize>> = BTreeMap::new();
+        for (idx, itineraries) in sim
+            .plan_trips(requests.clone(), &map, &mut Timer::throwaway())
+            .into_iter()
+            .enumerate()
+        {
+            for dr in mixed_traffic_roads(&requests[idx], &itineraries, &map) {
+                mixed_traffic.entry(dr).or_insert_with(Vec::new).push(idx);
+            }
+        }
+        let (corridor, affected) = mixed_traffic
+            .into_iter()
+            .max_by_key(|(_, reqs)| reqs.len())
+            .expect("Nobody chose to bike in the first place");
+        let affected: Vec<TripPlanRequest> = affected
+            .into_iter()
+            .map(|idx| requests[idx].clone())
+            .collect();
+        let before: f64 = bike_share(&sim, &map, &affected).into_iter().sum();
+
+        // Paint a bike lane there
+        let mut edits = map.get_edits().clone();
+        let road = map.get_r(corridor.id);
+        let lanes = if corridor.forwards {
+            &road.children_forwards
+        } else {
+            &road.children_backwards
+        };
+        for (l, lt) in lanes {
+            if *lt == LaneType::Driving {
+                edits.lane_overrides.insert(*l, LaneType::Biking);
+            }
+        }
+        map.apply_edits(edits, &mut Timer::throwaway());
+
+        let after: f64 = bike_share(&sim, &map, &affected).into_iter().sum();
+        assert!(
+            after > before,
+            "A bike lane on {:?} didn't encourage the {} trips using it to bike: {} expected bike \
+             trips before, {} after",
+            corridor,
+            affected.len(),
+            before,
+            after
+        );
+    });
+
+    t.run_slow("crowds_slow_walkers_down", |_| {
+        let (map, _, _) =
+            SimFlags::for_test("crowds_slow_walkers_down").load(None, &mut Timer::throwaway());
+        // Everybody appears on a sidewalk a little before a crosswalk at a stop sign, and crosses
+        // to a building on the other side.
+        let (start, goal, crosswalk) = map
+            .all_turns()
+            .values()
+            .filter_map(|t| {
+                if t.turn_type != TurnType::Crosswalk
+                    || map.maybe_get_stop_sign(t.id.parent).is_none()
+                {
+                    return None;
+                }
+                let src = map.get_l(t.id.src);
+                let dst = map.get_l(t.id.dst);
+                if src.length() < Distance::meters(30.0) || dst.building_paths.is_empty() {
+                    return None;
+                }
+                let dist = if src.dst_i == t.id.parent {
+                    src.length() - Distance::meters(15.0)
+                } else {
+                    Distance::meters(15.0)
+                };
+                let start = SidewalkSpot::suddenly_appear(src.id, dist, &map);
+                let goal = SidewalkSpot::building(dst.building_paths[0], &map);
+                let path = map.pathfind(PathRequest {
+                    start: start.sidewalk_pos,
+                    end: goal.sidewalk_pos,
+                    can_use_bike_lanes: false,
+                    can_use_bus_lanes: false,
+                })?;
+                if path.get_steps().contains(&PathStep::Turn(t.id)) {
+                    Some((start, goal, t.id))
+                } else {
+                    None
+                }
+            })
+            .next()
+            .expect("No crosswalk with room to gather in front of it");
+
+        let (alone, _) = walk_together(&map, 1, &start, &goal, crosswalk.parent);
+        let (crowd, max_waiting) = walk_together(&map, 100, &start, &goal, crosswalk.parent);
+        assert_eq!(crowd.len(), 100);
+
+        // Walking in a crowd is never faster, and usually a lot slower.
+        let alone = &alone[0];
+        assert!(crowd
+            .iter()
+            .all(|t| t.duration() + Duration::seconds(0.1) >= alone.duration()));
+        let slowest = crowd.iter().map(|t| t.duration()).max().unwrap();
+        assert!(
+            slowest > alone.duration() * 1.5,
+            "Alone took {}, but the slowest in a crowd only took {}",
+            alone.duration(),
+            slowest
+        );
+        // The crosswalk filled up, so some people had to wait at the corner for a turn to cross.
+        assert!(max_waiting > 0);
+        assert!(crowd
+            .iter()
+            .any(|t| t.intersection_delay > alone.intersection_delay));
+    });
+}
+
+// Also returns the most people ever waiting at the intersection at once
+fn walk_together(
+    map: &Map,
+    count: usize,
+    start: &SidewalkSpot,
+    goal: &SidewalkSpot,
+    i: IntersectionID,
+) -> (Vec<FinishedTrip>, usize) {
+    let mut sim = Sim::new(map, format!("walk_together_{}", count), None);
+    for _ in 0..count {
+        sim.schedule_trip(
+            Duration::ZERO,
+            TripSpec::JustWalking {
+                start: start.clone(),
+                goal: goal.clone(),
+                ped_speed: Speed::meters_per_second(1.2),
+            },
+            map,
+        )
+        .0
+        .unwrap();
+    }
+    sim.spawn_all_trips(map, &mut Timer::throwaway(), false);
+
+    let mut max_waiting = 0;
+    while !sim.is_done() {
+        assert!(
+            sim.time() < Duration::minutes(30),
+            "{} people never made it",
+            count
+        );
+        sim.step(map, Duration::seconds(1.0));
+        let (_, waiting) = sim.get_pedestrian_crowds(map);
+        max_waiting = max_waiting.max(waiting.get(&i).cloned().unwrap_or(0));
+    }
+    (sim.get_finished_trips(), max_waiting)
+}
+
+// For each request, how likely it is to bike
+fn bike_share(sim: &Sim, map: &Map, requests: &[TripPlanRequest]) -> Vec<f64> {
+    sim.plan_trips(requests.to_vec(), map, &mut Timer::throwaway())
+        .into_iter()
+        .map(|itineraries| {
+            itineraries
+                .iter()
+                .zip(choice_probabilities(&itineraries))
+                .filter(|(itinerary, _)| itinerary.modes().contains(&TripMode::Bike))
+                .map(|(_, p)| p)
+                .sum()
+        })
+        .collect()
+}
+
+// The roads where the biking option for this trip goes through driving lanes
+fn mixed_traffic_roads(
+    req: &TripPlanRequest,
+    itineraries: &[Itinerary],
+    map: &Map,
+) -> BTreeSet<DirectedRoadID> {
+    let mut roads = BTreeSet::new();
+    let goal = match itineraries
+        .iter()
+        .flat_map(|i| i.legs.iter())
+        .find_map(|leg| match leg {
+            PlannedLeg::Bike(_, goal) => Some(goal),
+            _ => None,
+        }) {
+        Some(goal) => goal,
+        None => {
+            return roads;
+        }
+    };
+    let start = match SidewalkSpot::bike_rack(req.start.sidewalk_pos.lane(), map)
+        .map(|spot| spot.connection)
+    {
+        Some(SidewalkPOI::BikeRack(pos)) => pos,
+        _ => {
+            return roads;
+        }
+    };
+    if let Some(path) = map.pathfind(PathRequest {
+        start,
+        end: goal.goal_pos(map),
+        can_use_bike_lanes: true,
+        can_use_bus_lanes: false,
+    }) {
+        for step in path.get_steps() {
+            if let PathStep::Lane(l) = step {
+                let lane = map.get_l(*l);
+                if lane.lane_type == LaneType::Driving {
+                    roads.insert(lane.get_directed_parent(map));
+                }
+            }
+        }
+    }
+    roads
 }